@@ -0,0 +1,551 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::config::Config;
+use crate::process::{self, ProcessSpec};
+use crate::retry::Backoff;
+use crate::{
+    CheckInstanceParams, CheckInstanceResult, InstanceResult, InstancesUpdate, JsonRpcRequest,
+    JsonRpcResponse, ListInstancesParams, ManagerError, ManagerStatus, PendingRequests,
+    PinInstanceParams, PruneResult, QueryInstanceParams, RegisterInstanceParams,
+    RenameInstanceParams, SetInstanceCwdParams, TagInstanceParams, Tombstone, TouchInstanceParams,
+    UnregisterInstanceParams, WatchInstancesParams,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// マネージャーの起動・停止を待つ間隔と上限 (最大 5 秒)
+const MANAGER_STARTUP: Backoff =
+    Backoff::exponential(Duration::from_millis(50), Duration::from_millis(500))
+        .deadline(Duration::from_secs(5));
+
+/// [`InstanceWatcher`] が 1 回の `watch_instances` で変わるまで待つ時間
+const WATCH_WAIT: Duration = Duration::from_secs(60);
+
+/// マネージャーとの接続 (TCP または Unix ソケット)
+trait ManagerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ManagerStream for T {}
+
+/// マネージャーの JSON-RPC クライアント
+///
+/// 未起動なら同じディレクトリにある `neovim-instance-manager` を起動してから送る
+#[derive(Debug, Clone)]
+pub struct ManagerClient {
+    /// 表示用の接続先 (Unix ソケットならそのパス)
+    pub addr: String,
+    /// 指定されていれば TCP の代わりにこのソケットに接続する
+    pub socket: Option<PathBuf>,
+    /// 未起動のときに自動で起動するか
+    pub autostart: bool,
+    /// 起動するマネージャーに渡すポート
+    pub port: Option<u16>,
+    /// 送受信の内容を標準エラー出力に出す
+    pub debug: bool,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl ManagerClient {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            addr: config.manager.address(),
+            socket: None,
+            autostart: true,
+            port: None,
+            debug: config.control.debug.value,
+            timeout: config.control.timeout.value,
+            retries: config.control.retries.value,
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<Box<dyn ManagerStream>> {
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket {
+            return Ok(Box::new(tokio::net::UnixStream::connect(socket).await?));
+        }
+
+        Ok(Box::new(TcpStream::connect(&self.addr).await?))
+    }
+
+    pub async fn is_manager_running(&self) -> bool {
+        self.connect().await.is_ok()
+    }
+
+    /// マネージャーが起動 (`running` が false なら停止) するまで最大 5 秒待つ
+    pub async fn wait_for_manager(&self, running: bool) -> Result<()> {
+        let reached = MANAGER_STARTUP
+            .poll(|| async { (self.is_manager_running().await == running).then_some(()) })
+            .await;
+        if reached.is_some() {
+            return Ok(());
+        }
+
+        if running {
+            Err(anyhow!("Manager not responding after startup"))
+        } else {
+            Err(anyhow!("Manager still running after shutdown request"))
+        }
+    }
+
+    pub async fn ensure_manager_running(&self) -> Result<()> {
+        // まず接続を試行
+        if self.connect().await.is_ok() {
+            return Ok(());
+        }
+        if !self.autostart {
+            return Err(ManagerError::Unreachable(format!(
+                "Cannot connect to manager at {}",
+                self.addr
+            ))
+            .into());
+        }
+
+        // マネージャーを起動
+        self.start_manager()?;
+
+        // 起動を待つ（最大5秒）
+        if self.debug {
+            eprintln!("Starting manager, waiting for startup...");
+        }
+        let started = MANAGER_STARTUP
+            .poll(|| async { self.connect().await.ok() })
+            .await;
+        if started.is_some() {
+            return Ok(());
+        }
+
+        Err(ManagerError::Unreachable(format!(
+            "Manager not responding at {} after startup",
+            self.addr
+        ))
+        .into())
+    }
+
+    pub fn manager_path() -> Result<PathBuf> {
+        // まず現在の実行可能ファイルのパスから推測
+        let current_exe = std::env::current_exe()?;
+        Ok(current_exe
+            .parent()
+            .ok_or_else(|| anyhow!("Cannot determine executable directory"))?
+            .join("neovim-instance-manager"))
+    }
+
+    /// 接続先に合わせて引数・環境変数を設定したマネージャーの起動コマンド
+    pub fn manager_process(&self) -> Result<ProcessSpec> {
+        let mut spec = ProcessSpec::new(Self::manager_path()?);
+        if let Some(socket) = &self.socket {
+            spec = spec.arg("--socket").arg(socket.to_string_lossy());
+        }
+        if let Some(port) = self.port {
+            spec = spec.env("NEOVIM_MANAGER_PORT", port.to_string());
+        }
+
+        Ok(spec)
+    }
+
+    pub fn start_manager(&self) -> Result<()> {
+        process::spawn(&self.manager_process()?)?;
+        Ok(())
+    }
+
+    pub async fn send_request(&self, method: &str, params: Value) -> Result<JsonRpcResponse> {
+        self.ensure_manager_running().await?;
+        self.send_request_direct(method, params).await
+    }
+
+    /// マネージャーの自動起動を行わずにリクエストを送る
+    ///
+    /// 接続できない・時間内に応答がない場合は `retries` 回まで間隔を空けて再試行する
+    pub async fn send_request_direct(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<JsonRpcResponse> {
+        let backoff = Backoff::exponential(Duration::from_millis(400), Duration::from_millis(6400))
+            .max_attempts(self.retries.saturating_add(1))
+            .jitter(0.2);
+
+        backoff
+            .retry_if(
+                |attempt| {
+                    if attempt > 1 && self.debug {
+                        eprintln!("Retrying ({}/{})...", attempt - 1, self.retries);
+                    }
+                    let params = params.clone();
+                    async move {
+                        tokio::time::timeout(self.timeout, self.exchange(method, params))
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(ManagerError::Unreachable(format!(
+                                    "Manager at {} did not respond within {:?}",
+                                    self.addr, self.timeout
+                                ))
+                                .into())
+                            })
+                    }
+                },
+                |e: &anyhow::Error| {
+                    matches!(
+                        e.downcast_ref::<ManagerError>(),
+                        Some(ManagerError::Unreachable(_))
+                    )
+                },
+            )
+            .await
+    }
+
+    /// 複数のリクエストを 1 本の接続で続けて送り、応答をリクエストと同じ順に返す
+    ///
+    /// 通知 (ID なし) には応答が来ないので、戻り値には含まれない
+    pub async fn send_batch(&self, requests: &[JsonRpcRequest]) -> Result<Vec<JsonRpcResponse>> {
+        self.ensure_manager_running().await?;
+
+        tokio::time::timeout(self.timeout, self.exchange_batch(requests))
+            .await
+            .unwrap_or_else(|_| {
+                Err(ManagerError::Unreachable(format!(
+                    "Manager at {} did not respond within {:?}",
+                    self.addr, self.timeout
+                ))
+                .into())
+            })
+    }
+
+    async fn exchange_batch(&self, requests: &[JsonRpcRequest]) -> Result<Vec<JsonRpcResponse>> {
+        let (reader, mut writer) = tokio::io::split(self.open_stream().await?);
+
+        let mut pending = PendingRequests::new();
+        let mut payload = String::new();
+        for (index, request) in requests.iter().enumerate() {
+            pending.insert(request, index);
+            payload.push_str(&serde_json::to_string(request)?);
+            payload.push('\n');
+        }
+        if self.debug {
+            eprint!("Sending requests:\n{payload}");
+        }
+
+        // 応答を読みながら書き込まないと、大量に送ったときに詰まる
+        let write = async {
+            writer.write_all(payload.as_bytes()).await?;
+            writer.flush().await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        let read = async {
+            let mut responses: Vec<Option<JsonRpcResponse>> = vec![None; requests.len()];
+            let mut lines = BufReader::new(reader).lines();
+            while !pending.is_empty() {
+                let line = lines.next_line().await?.ok_or_else(|| {
+                    ManagerError::Unreachable("Connection closed by manager".to_string())
+                })?;
+                if self.debug {
+                    eprintln!("Received: {}", line.trim());
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let response: JsonRpcResponse = serde_json::from_str(line.trim())
+                    .map_err(|e| anyhow!("Failed to parse response '{}': {}", line.trim(), e))?;
+                if let Some(index) = pending.resolve(&response) {
+                    responses[index] = Some(response);
+                }
+            }
+            Ok::<_, anyhow::Error>(responses.into_iter().flatten().collect())
+        };
+
+        let ((), responses) = tokio::try_join!(write, read)?;
+        Ok(responses)
+    }
+
+    /// 接続する。失敗は [`ManagerError::Unreachable`] にする
+    async fn open_stream(&self) -> Result<Box<dyn ManagerStream>> {
+        if self.debug {
+            eprintln!("Connecting to manager at {}", self.addr);
+        }
+        match tokio::time::timeout(CONNECT_TIMEOUT, self.connect()).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => Err(ManagerError::Unreachable(format!(
+                "Cannot connect to manager at {}: {e}",
+                self.addr
+            ))
+            .into()),
+            Err(_) => Err(ManagerError::Unreachable(format!(
+                "Connecting to manager at {} timed out",
+                self.addr
+            ))
+            .into()),
+        }
+    }
+
+    async fn exchange(&self, method: &str, params: Value) -> Result<JsonRpcResponse> {
+        let debug = self.debug;
+        let mut stream = self.open_stream().await?;
+
+        let request = JsonRpcRequest::new(method, params);
+
+        let request_json = serde_json::to_string(&request)?;
+        if debug {
+            eprintln!("Sending request: {request_json}");
+        }
+
+        stream.write_all(request_json.as_bytes()).await?;
+        stream.write_all(b"\n").await?;
+        stream.flush().await?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        if debug {
+            eprintln!("Waiting for response...");
+        }
+        let bytes_read = reader.read_line(&mut line).await?;
+        if debug {
+            eprintln!("Read {} bytes: '{}'", bytes_read, line.trim());
+        }
+
+        if bytes_read == 0 {
+            return Err(
+                ManagerError::Unreachable("Connection closed by manager".to_string()).into(),
+            );
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return Err(anyhow!("Empty response from manager"));
+        }
+
+        let response: JsonRpcResponse = serde_json::from_str(trimmed)
+            .map_err(|e| anyhow!("Failed to parse response '{}': {}", trimmed, e))?;
+
+        Ok(response)
+    }
+
+    /// リクエストを送り、結果を型付きで返す
+    ///
+    /// エラーレスポンスや接続できない場合は [`ManagerError`] を返す (`anyhow::Error` から downcast できる)
+    pub async fn call<P: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<T> {
+        let response = self
+            .send_request(method, serde_json::to_value(params)?)
+            .await?;
+
+        if let Some(error) = response.error {
+            return Err(ManagerError::from(error).into());
+        }
+
+        Ok(serde_json::from_value(
+            response.result.unwrap_or(Value::Null),
+        )?)
+    }
+
+    pub async fn query(&self, identifier: &str) -> Result<Option<InstanceResult>> {
+        self.call(
+            "query_instance",
+            QueryInstanceParams {
+                identifier: identifier.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// 登録済みインスタンスの一覧 (`tag` を指定すればそのタグを持つものだけ)
+    pub async fn list(&self, tag: Option<&str>) -> Result<Vec<InstanceResult>> {
+        self.call(
+            "list_instances",
+            ListInstancesParams {
+                tag: tag.map(str::to_string),
+            },
+        )
+        .await
+    }
+
+    pub async fn register(&self, params: RegisterInstanceParams) -> Result<()> {
+        self.call::<_, Value>("register_instance", params).await?;
+        Ok(())
+    }
+
+    pub async fn unregister(&self, identifier: &str) -> Result<()> {
+        self.call::<_, Value>(
+            "unregister_instance",
+            UnregisterInstanceParams {
+                identifier: identifier.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn rename(&self, identifier: &str, new_identifier: &str) -> Result<()> {
+        self.call::<_, Value>(
+            "rename_instance",
+            RenameInstanceParams {
+                identifier: identifier.to_string(),
+                new_identifier: new_identifier.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn touch(&self, identifier: &str) -> Result<()> {
+        self.call::<_, Value>(
+            "touch_instance",
+            TouchInstanceParams {
+                identifier: identifier.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn pin(&self, identifier: &str, pinned: bool) -> Result<()> {
+        self.call::<_, Value>(
+            "pin_instance",
+            PinInstanceParams {
+                identifier: identifier.to_string(),
+                pinned,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn tag(&self, identifier: &str, tag: &str, tagged: bool) -> Result<()> {
+        self.call::<_, Value>(
+            "tag_instance",
+            TagInstanceParams {
+                identifier: identifier.to_string(),
+                tag: tag.to_string(),
+                tagged,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_cwd(&self, identifier: &str, cwd: &str) -> Result<()> {
+        self.call::<_, Value>(
+            "set_instance_cwd",
+            SetInstanceCwdParams {
+                identifier: identifier.to_string(),
+                cwd: cwd.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn check(&self, identifier: &str) -> Result<CheckInstanceResult> {
+        self.call(
+            "check_instance",
+            CheckInstanceParams {
+                identifier: identifier.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// 登録内容が `generation` から変わるまで (最大 `wait`) 待ち、その時点の一覧を返す
+    pub async fn watch(&self, generation: Option<u64>, wait: Duration) -> Result<InstancesUpdate> {
+        // 待っている間に応答の待ち時間を超えないように
+        let mut client = self.clone();
+        client.timeout = self.timeout + wait;
+        client
+            .call(
+                "watch_instances",
+                WatchInstancesParams {
+                    generation,
+                    timeout_secs: Some(wait.as_secs()),
+                },
+            )
+            .await
+    }
+
+    pub async fn prune(&self) -> Result<PruneResult> {
+        self.call("prune_instances", json!({})).await
+    }
+
+    pub async fn recent(&self) -> Result<Vec<Tombstone>> {
+        self.call("recent_instances", json!({})).await
+    }
+
+    pub async fn status(&self) -> Result<ManagerStatus> {
+        self.call("status", json!({})).await
+    }
+
+    pub async fn shutdown(&self) -> Result<()> {
+        self.call::<_, Value>("shutdown", json!({})).await?;
+        Ok(())
+    }
+}
+
+/// 登録内容が変わるたびに一覧を受け取る (`watch_instances`)
+///
+/// `watch_instances` のない古いマネージャーには `interval` ごとに `list_instances` で問い合わせる。
+/// 接続できなかった後も `interval` だけ空けてから問い合わせ直す
+pub struct InstanceWatcher {
+    client: ManagerClient,
+    interval: Duration,
+    generation: Option<u64>,
+    /// 古いマネージャーなので問い合わせ続ける
+    polling: bool,
+    /// 次の問い合わせの前に `interval` だけ待つ
+    delay: bool,
+}
+
+impl InstanceWatcher {
+    pub fn new(client: ManagerClient, interval: Duration) -> Self {
+        Self {
+            client,
+            interval,
+            generation: None,
+            polling: false,
+            delay: false,
+        }
+    }
+
+    /// 最初はすぐに、以降は登録内容が変わったときに一覧を返す
+    pub async fn next(&mut self) -> Result<Vec<InstanceResult>> {
+        if std::mem::take(&mut self.delay) {
+            tokio::time::sleep(self.interval).await;
+        }
+        if self.polling {
+            self.delay = true;
+            return self.client.list(None).await;
+        }
+
+        match self.client.watch(self.generation, WATCH_WAIT).await {
+            Ok(update) => {
+                self.generation = Some(update.generation);
+                Ok(update.instances)
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(ManagerError::MethodNotFound)) => {
+                self.polling = true;
+                self.delay = true;
+                self.client.list(None).await
+            }
+            Err(e) => {
+                self.generation = None;
+                self.delay = true;
+                Err(e)
+            }
+        }
+    }
+
+    /// 次の [`next`](Self::next) を待たずに返させる (操作の結果をすぐに反映するとき)
+    pub fn refresh(&mut self) {
+        self.generation = None;
+        self.delay = false;
+    }
+}
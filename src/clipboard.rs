@@ -0,0 +1,159 @@
+//! 新しく起動する headless の nvim のクリップボード
+//!
+//! WSL や SSH 越しに起動した nvim は、Neovim が自分で探すクリップボードのコマンド (xclip・wl-copy など) を
+//! 使えないか、使えても GUI のある側に届かない。launcher は `launcher.clipboard` に従って
+//! `--cmd` で `g:clipboard` を設定してから nvim を起動する。
+
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::utils;
+use crate::wsl;
+
+/// 使うクリップボード (`launcher.clipboard`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardProvider {
+    /// WSL なら win32yank (なければ clip.exe と PowerShell)、SSH 越しなら lemonade (なければ OSC 52)、
+    /// それ以外は設定しない
+    #[default]
+    Auto,
+    /// 設定しない (Neovim が自分で探す)
+    None,
+    /// 端末のエスケープシーケンス (Neovim 0.10 以降の `vim.ui.clipboard.osc52`)
+    Osc52,
+    /// WSL から Windows のクリップボードを使う `win32yank.exe`
+    Win32yank,
+    /// SSH のポートフォワードで手元のクリップボードを使う `lemonade`
+    Lemonade,
+}
+
+impl ClipboardProvider {
+    pub const ALL: [ClipboardProvider; 5] = [
+        ClipboardProvider::Auto,
+        ClipboardProvider::None,
+        ClipboardProvider::Osc52,
+        ClipboardProvider::Win32yank,
+        ClipboardProvider::Lemonade,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClipboardProvider::Auto => "auto",
+            ClipboardProvider::None => "none",
+            ClipboardProvider::Osc52 => "osc52",
+            ClipboardProvider::Win32yank => "win32yank",
+            ClipboardProvider::Lemonade => "lemonade",
+        }
+    }
+
+    /// nvim に渡す引数 (`--cmd "lua vim.g.clipboard = ..."`)。設定しない場合は空
+    pub fn nvim_args(self) -> Vec<String> {
+        match self.lua() {
+            Some(lua) => vec!["--cmd".to_string(), format!("lua vim.g.clipboard = {lua}")],
+            None => Vec::new(),
+        }
+    }
+
+    /// `vim.g.clipboard` に入れる Lua の値
+    fn lua(self) -> Option<String> {
+        match self {
+            ClipboardProvider::Auto => auto_lua(),
+            ClipboardProvider::None => None,
+            ClipboardProvider::Osc52 => Some(
+                "{ name = 'OSC 52', \
+                 copy = { ['+'] = require('vim.ui.clipboard.osc52').copy('+'), \
+                 ['*'] = require('vim.ui.clipboard.osc52').copy('*') }, \
+                 paste = { ['+'] = require('vim.ui.clipboard.osc52').paste('+'), \
+                 ['*'] = require('vim.ui.clipboard.osc52').paste('*') } }"
+                    .to_string(),
+            ),
+            ClipboardProvider::Win32yank => Some(commands(
+                "win32yank",
+                &["win32yank.exe", "-i", "--crlf"],
+                &["win32yank.exe", "-o", "--lf"],
+            )),
+            ClipboardProvider::Lemonade => Some(commands(
+                "lemonade",
+                &["lemonade", "copy"],
+                &["lemonade", "paste"],
+            )),
+        }
+    }
+}
+
+/// `auto` のときの `vim.g.clipboard`
+fn auto_lua() -> Option<String> {
+    if wsl::is_wsl() {
+        if utils::find_in_path("win32yank.exe").is_some() {
+            return ClipboardProvider::Win32yank.lua();
+        }
+        // Windows に付属のコマンドだけで済ませる (`:help clipboard-wsl` と同じ)
+        return Some(commands(
+            "WslClipboard",
+            &["clip.exe"],
+            &[
+                "powershell.exe",
+                "-NoLogo",
+                "-NoProfile",
+                "-c",
+                "[Console]::Out.Write($(Get-Clipboard -Raw).tostring().replace(\"`r\", \"\"))",
+            ],
+        ));
+    }
+    if is_ssh_session() {
+        return if utils::find_in_path("lemonade").is_some() {
+            ClipboardProvider::Lemonade.lua()
+        } else {
+            ClipboardProvider::Osc52.lua()
+        };
+    }
+    None
+}
+
+/// SSH でログインした先で、手元の画面がない (X11 のフォワードもない) か
+fn is_ssh_session() -> bool {
+    let set = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    (set("SSH_CONNECTION") || set("SSH_TTY")) && !set("DISPLAY") && !set("WAYLAND_DISPLAY")
+}
+
+/// コピーとペーストのコマンドから `g:clipboard` を作る
+fn commands(name: &str, copy: &[&str], paste: &[&str]) -> String {
+    let list = |args: &[&str]| {
+        let quoted: Vec<_> = args.iter().map(|arg| format!("'{arg}'")).collect();
+        format!("{{ {} }}", quoted.join(", "))
+    };
+    format!(
+        "{{ name = '{name}', copy = {{ ['+'] = {copy}, ['*'] = {copy} }}, \
+         paste = {{ ['+'] = {paste}, ['*'] = {paste} }}, cache_enabled = 0 }}",
+        copy = list(copy),
+        paste = list(paste)
+    )
+}
+
+impl fmt::Display for ClipboardProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ClipboardProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        ClipboardProvider::ALL
+            .into_iter()
+            .find(|provider| provider.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = ClipboardProvider::ALL
+                    .iter()
+                    .map(|provider| provider.name())
+                    .collect();
+                format!(
+                    "unknown clipboard provider '{s}' (expected one of: {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
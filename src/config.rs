@@ -0,0 +1,736 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use directories::BaseDirs;
+
+use crate::clipboard::ClipboardProvider;
+use crate::identifier::PathMapping;
+use crate::notify::NotifyLevel;
+use crate::{duration, gui, DEFAULT_BIND_ADDR, DEFAULT_PORT};
+
+pub const CONFIG_ENV: &str = "NEOVIM_MANAGER_CONFIG";
+
+/// 設定値がどこから来たか
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "file {}", path.display()),
+            Origin::Env(name) => write!(f, "env {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+impl<T> Setting<T> {
+    fn new(default: T) -> Self {
+        Self {
+            value: default,
+            origin: Origin::Default,
+        }
+    }
+
+    pub(crate) fn apply_file(&mut self, value: Option<T>, path: &Path) {
+        if let Some(value) = value {
+            self.value = value;
+            self.origin = Origin::File(path.to_path_buf());
+        }
+    }
+
+    fn apply_env_with(&mut self, name: &'static str, parse: impl FnOnce(&str) -> Option<T>) {
+        if let Some(value) = std::env::var(name).ok().and_then(|raw| parse(&raw)) {
+            self.value = value;
+            self.origin = Origin::Env(name);
+        }
+    }
+
+    fn apply_env(&mut self, name: &'static str)
+    where
+        T: FromStr,
+    {
+        self.apply_env_with(name, |raw| raw.trim().parse().ok());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ManagerConfig {
+    pub port: Setting<u16>,
+    pub bind_address: Setting<String>,
+    pub health_check_interval: Setting<Duration>,
+    pub log_file: Setting<Option<PathBuf>>,
+    /// 登録内容の写し ([`crate::StateMirror`]) を書き出し続けるファイル。None なら書き出さない
+    pub state_file: Setting<Option<PathBuf>>,
+    /// どの重要度からデスクトップ通知を出すか
+    pub notify: Setting<NotifyLevel>,
+    /// コンテナの中のパスとホスト側のパスの対応 (identifier はホスト側にそろえる)
+    pub path_mappings: Setting<Vec<PathMapping>>,
+    pub hooks: ManagerHooks,
+}
+
+/// マネージャーの出来事で実行するシェルコマンド (`[manager.hooks]`)
+#[derive(Debug, Clone)]
+pub struct ManagerHooks {
+    /// インスタンスを登録した後
+    pub on_register: Setting<Option<String>>,
+    /// インスタンスが登録から外れた後 (登録解除・応答しなくなって削除)
+    pub on_unregister: Setting<Option<String>>,
+    /// 応答していたインスタンスがヘルスチェックに失敗した後 (ピン留めしたものも)
+    pub on_health_lost: Setting<Option<String>>,
+}
+
+impl ManagerConfig {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind_address.value, self.port.value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LauncherConfig {
+    pub neovide_command: Setting<String>,
+    pub neovide_args: Setting<Vec<String>>,
+    /// PATH より先に GUI を探すディレクトリ
+    pub gui_search_paths: Setting<Vec<PathBuf>>,
+    /// 新しく起動する nvim に `direnv export json` の環境を加える
+    pub direnv: Setting<bool>,
+    /// 新しく起動する nvim の `g:clipboard` (WSL や SSH 越しで使えるもの)
+    pub clipboard: Setting<ClipboardProvider>,
+    /// 起動・接続の回数とセッションの長さを状態ディレクトリに記録する (`neovim-control stats`)
+    pub stats: Setting<bool>,
+    /// Neovide のウィンドウの大きさと位置をプロジェクトごとに覚え、次の起動で戻す
+    pub remember_geometry: Setting<bool>,
+    /// `--toggle-scratch` で出し入れするスクラッチのインスタンスのディレクトリ
+    pub scratch_dir: Setting<Option<PathBuf>>,
+    /// 新しく起動する nvim の `NVIM_APPNAME`。None なら launcher の環境のまま
+    pub nvim_appname: Setting<Option<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlConfig {
+    pub debug: Setting<bool>,
+    pub timeout: Setting<Duration>,
+    pub retries: Setting<u32>,
+}
+
+/// デフォルト値・設定ファイル・環境変数の順に上書きした実効設定
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub file: Option<PathBuf>,
+    pub manager: ManagerConfig,
+    pub launcher: LauncherConfig,
+    pub control: ControlConfig,
+    /// 名前付きのワークスペース (`[workspace.<name>]`、設定ファイルでのみ定義できる)
+    pub workspaces: BTreeMap<String, Workspace>,
+}
+
+/// まとめて開くプロジェクトの組 (`neovim-launcher --workspace <name>`)
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /// プロジェクトのディレクトリ (先頭の `~` は展開済み)
+    pub projects: Vec<PathBuf>,
+    pub origin: Origin,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FileConfig {
+    manager: ManagerFileConfig,
+    launcher: LauncherFileConfig,
+    control: ControlFileConfig,
+    workspace: BTreeMap<String, WorkspaceFileConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceFileConfig {
+    projects: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ManagerFileConfig {
+    port: Option<u16>,
+    bind_address: Option<String>,
+    /// 従来の `health_check_interval_secs` (秒数) も受け付ける
+    #[serde(
+        alias = "health_check_interval_secs",
+        deserialize_with = "duration::deserialize_option"
+    )]
+    health_check_interval: Option<Duration>,
+    log_file: Option<PathBuf>,
+    /// 空文字列なら書き出さない
+    state_file: Option<PathBuf>,
+    notify: Option<NotifyLevel>,
+    path_mappings: Option<Vec<PathMapping>>,
+    hooks: ManagerHooksFileConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ManagerHooksFileConfig {
+    on_register: Option<String>,
+    on_unregister: Option<String>,
+    on_health_lost: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LauncherFileConfig {
+    neovide_command: Option<String>,
+    neovide_args: Option<Vec<String>>,
+    gui_search_paths: Option<Vec<PathBuf>>,
+    direnv: Option<bool>,
+    clipboard: Option<ClipboardProvider>,
+    stats: Option<bool>,
+    remember_geometry: Option<bool>,
+    scratch_dir: Option<String>,
+    nvim_appname: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ControlFileConfig {
+    debug: Option<bool>,
+    /// 従来の `timeout_secs` (秒数) も受け付ける
+    #[serde(
+        alias = "timeout_secs",
+        deserialize_with = "duration::deserialize_option"
+    )]
+    timeout: Option<Duration>,
+    retries: Option<u32>,
+}
+
+/// 設定していないフックは `(none)` と表示する
+fn display_hook(command: &Option<String>) -> String {
+    match command {
+        Some(command) => format!("{command:?}"),
+        None => "(none)".to_string(),
+    }
+}
+
+/// `NVIM_APPNAME` の値。空なら設定しない (launcher の環境のまま)
+pub fn appname(raw: impl Into<String>) -> Option<String> {
+    Some(raw.into()).filter(|appname| !appname.trim().is_empty())
+}
+
+/// ホスト側のパスの先頭の `~` をホームディレクトリにする
+fn expand_host_home(mut mapping: PathMapping) -> PathMapping {
+    mapping.host = expand_home(&mapping.host);
+    mapping
+}
+
+/// パスの先頭の `~` をホームディレクトリにする (`~user` はそのまま)
+fn expand_home(path: &str) -> String {
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"));
+    if let (Some(rest), Ok(home)) = (path.strip_prefix('~'), home) {
+        if rest.is_empty() || rest.starts_with(['/', '\\']) {
+            return format!("{home}{rest}");
+        }
+    }
+    path.to_string()
+}
+
+/// 基準ディレクトリを決める
+///
+/// XDG の環境変数があればどの OS でもそれを使う。次に従来の `~/<legacy>` が既にあればそれを使い、
+/// なければ OS ごとの標準の場所 (`directories` crate) にする
+fn base_dir(
+    xdg_var: &str,
+    legacy: &str,
+    platform: impl FnOnce(&BaseDirs) -> Option<&Path>,
+) -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(xdg_var).filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+    let base_dirs = BaseDirs::new();
+    match base_dirs.as_ref().and_then(platform) {
+        Some(dir) if !home.as_ref().is_some_and(|home| home.join(legacy).is_dir()) => {
+            Some(dir.to_path_buf())
+        }
+        _ => home.map(|home| home.join(legacy)),
+    }
+}
+
+/// 設定ファイルを置くディレクトリ (Linux では `~/.config/neovim-manager`)
+pub fn config_dir() -> Option<PathBuf> {
+    config_home().map(|dir| dir.join("neovim-manager"))
+}
+
+/// 各アプリケーションの設定を置くディレクトリ (Linux では `~/.config`)
+pub fn config_home() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config", |dirs| Some(dirs.config_dir()))
+}
+
+/// デスクトップエントリなど、他のアプリケーションから見えるデータを置くディレクトリ (Linux では `~/.local/share`)
+pub fn data_home() -> Option<PathBuf> {
+    base_dir("XDG_DATA_HOME", ".local/share", |dirs| {
+        Some(dirs.data_dir())
+    })
+}
+
+/// ログやセッションなど、消えても困らない状態を置くディレクトリ
+/// (Linux では `~/.cache/neovim-instance-manager`)
+pub fn state_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache", |dirs| Some(dirs.cache_dir()))
+        .map(|dir| dir.join("neovim-instance-manager"))
+}
+
+/// ソケットなど、ログアウトまでしか要らないものを置くディレクトリ
+///
+/// `$XDG_RUNTIME_DIR/neovim-manager`。ない環境では一時ディレクトリの下にユーザーごとに作る
+pub fn runtime_dir() -> PathBuf {
+    if let Some(dir) = BaseDirs::new().and_then(|dirs| dirs.runtime_dir().map(Path::to_path_buf)) {
+        return dir.join("neovim-manager");
+    }
+
+    match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        Ok(user) if !user.is_empty() => std::env::temp_dir().join(format!("neovim-manager-{user}")),
+        _ => std::env::temp_dir().join("neovim-manager"),
+    }
+}
+
+/// 実際に使われる既定のログファイル
+pub fn manager_log_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("manager.log"))
+}
+
+/// マネージャーが登録内容の写しを書き出す既定のファイル
+pub fn manager_state_path() -> PathBuf {
+    runtime_dir().join("state.json")
+}
+
+/// `--toggle-scratch` の既定のディレクトリ
+pub fn scratch_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("scratch"))
+}
+
+/// `control snapshot` の既定の保存先
+pub fn session_dir() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("sessions"))
+}
+
+/// 設定ファイルのパス (`$NEOVIM_MANAGER_CONFIG` または [`config_dir`] の `config.toml`)
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let mut config = Self::defaults();
+
+        if let Some(path) = config_path().filter(|path| path.is_file()) {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Cannot read config file {}: {e}", path.display()))?;
+            let file: FileConfig = toml::from_str(&content)
+                .map_err(|e| anyhow!("Invalid config file {}: {e}", path.display()))?;
+            config.apply_file(file, &path);
+            config.file = Some(path);
+        }
+
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// 名前付きのワークスペース。定義されていなければ定義済みの名前を添えたエラー
+    pub fn workspace(&self, name: &str) -> Result<&Workspace> {
+        self.workspaces.get(name).ok_or_else(|| {
+            if self.workspaces.is_empty() {
+                anyhow!("Unknown workspace '{name}' (no [workspace.<name>] in the config file)")
+            } else {
+                let names: Vec<_> = self.workspaces.keys().map(String::as_str).collect();
+                anyhow!("Unknown workspace '{name}' (defined: {})", names.join(", "))
+            }
+        })
+    }
+
+    fn defaults() -> Self {
+        Self {
+            file: None,
+            manager: ManagerConfig {
+                port: Setting::new(DEFAULT_PORT),
+                bind_address: Setting::new(DEFAULT_BIND_ADDR.to_string()),
+                health_check_interval: Setting::new(Duration::from_secs(5)),
+                log_file: Setting::new(manager_log_path()),
+                state_file: Setting::new(Some(manager_state_path())),
+                notify: Setting::new(NotifyLevel::default()),
+                path_mappings: Setting::new(Vec::new()),
+                hooks: ManagerHooks {
+                    on_register: Setting::new(None),
+                    on_unregister: Setting::new(None),
+                    on_health_lost: Setting::new(None),
+                },
+            },
+            launcher: LauncherConfig {
+                neovide_command: Setting::new(gui::default_program().to_string()),
+                neovide_args: Setting::new(gui::default_args()),
+                gui_search_paths: Setting::new(Vec::new()),
+                direnv: Setting::new(false),
+                clipboard: Setting::new(ClipboardProvider::default()),
+                stats: Setting::new(false),
+                remember_geometry: Setting::new(true),
+                scratch_dir: Setting::new(scratch_path()),
+                nvim_appname: Setting::new(None),
+            },
+            control: ControlConfig {
+                debug: Setting::new(false),
+                timeout: Setting::new(Duration::from_secs(10)),
+                retries: Setting::new(0),
+            },
+            workspaces: BTreeMap::new(),
+        }
+    }
+
+    fn apply_file(&mut self, file: FileConfig, path: &Path) {
+        let manager = &mut self.manager;
+        manager.port.apply_file(file.manager.port, path);
+        manager
+            .bind_address
+            .apply_file(file.manager.bind_address, path);
+        manager
+            .health_check_interval
+            .apply_file(file.manager.health_check_interval, path);
+        manager
+            .log_file
+            .apply_file(file.manager.log_file.map(Some), path);
+        manager.state_file.apply_file(
+            file.manager
+                .state_file
+                .map(|file| Some(file).filter(|file| !file.as_os_str().is_empty())),
+            path,
+        );
+        manager.notify.apply_file(file.manager.notify, path);
+        manager.path_mappings.apply_file(
+            file.manager
+                .path_mappings
+                .map(|mappings| mappings.into_iter().map(expand_host_home).collect()),
+            path,
+        );
+        let hooks = &mut manager.hooks;
+        hooks
+            .on_register
+            .apply_file(file.manager.hooks.on_register.map(Some), path);
+        hooks
+            .on_unregister
+            .apply_file(file.manager.hooks.on_unregister.map(Some), path);
+        hooks
+            .on_health_lost
+            .apply_file(file.manager.hooks.on_health_lost.map(Some), path);
+
+        let launcher = &mut self.launcher;
+        launcher
+            .neovide_command
+            .apply_file(file.launcher.neovide_command, path);
+        launcher
+            .neovide_args
+            .apply_file(file.launcher.neovide_args, path);
+        launcher
+            .gui_search_paths
+            .apply_file(file.launcher.gui_search_paths, path);
+        launcher.direnv.apply_file(file.launcher.direnv, path);
+        launcher.clipboard.apply_file(file.launcher.clipboard, path);
+        launcher.stats.apply_file(file.launcher.stats, path);
+        launcher
+            .remember_geometry
+            .apply_file(file.launcher.remember_geometry, path);
+        launcher.scratch_dir.apply_file(
+            file.launcher
+                .scratch_dir
+                .map(|dir| Some(PathBuf::from(expand_home(&dir)))),
+            path,
+        );
+        launcher
+            .nvim_appname
+            .apply_file(file.launcher.nvim_appname.map(appname), path);
+
+        let control = &mut self.control;
+        control.debug.apply_file(file.control.debug, path);
+        control.timeout.apply_file(file.control.timeout, path);
+        control.retries.apply_file(file.control.retries, path);
+
+        self.workspaces = file
+            .workspace
+            .into_iter()
+            .map(|(name, workspace)| {
+                let workspace = Workspace {
+                    projects: workspace
+                        .projects
+                        .iter()
+                        .map(|project| PathBuf::from(expand_home(project)))
+                        .collect(),
+                    origin: Origin::File(path.to_path_buf()),
+                };
+                (name, workspace)
+            })
+            .collect();
+    }
+
+    fn apply_env(&mut self) {
+        let manager = &mut self.manager;
+        manager.port.apply_env("NEOVIM_MANAGER_PORT");
+        manager.bind_address.apply_env("NEOVIM_MANAGER_BIND_ADDR");
+        manager
+            .health_check_interval
+            .apply_env_with("NEOVIM_MANAGER_HEALTH_CHECK_INTERVAL", |raw| {
+                duration::parse(raw).ok()
+            });
+        manager
+            .log_file
+            .apply_env_with("NEOVIM_MANAGER_LOG_FILE", |raw| {
+                Some(Some(PathBuf::from(raw)))
+            });
+        // 空にすると書き出さない
+        manager
+            .state_file
+            .apply_env_with("NEOVIM_MANAGER_STATE_FILE", |raw| {
+                Some(Some(PathBuf::from(raw)).filter(|_| !raw.is_empty()))
+            });
+        manager.notify.apply_env("NEOVIM_MANAGER_NOTIFY");
+        manager
+            .path_mappings
+            .apply_env_with("NEOVIM_MANAGER_PATH_MAPPINGS", |raw| {
+                raw.split(';')
+                    .filter(|mapping| !mapping.trim().is_empty())
+                    .map(|mapping| mapping.parse().ok().map(expand_host_home))
+                    .collect()
+            });
+        // 空にすると設定ファイルのフックを無効にできる
+        let hook =
+            |raw: &str| Some(Some(raw.to_string()).filter(|command| !command.trim().is_empty()));
+        let hooks = &mut manager.hooks;
+        hooks
+            .on_register
+            .apply_env_with("NEOVIM_MANAGER_ON_REGISTER", hook);
+        hooks
+            .on_unregister
+            .apply_env_with("NEOVIM_MANAGER_ON_UNREGISTER", hook);
+        hooks
+            .on_health_lost
+            .apply_env_with("NEOVIM_MANAGER_ON_HEALTH_LOST", hook);
+
+        let launcher = &mut self.launcher;
+        launcher.neovide_command.apply_env("NEOVIM_MANAGER_NEOVIDE");
+        launcher
+            .neovide_args
+            .apply_env_with("NEOVIM_MANAGER_NEOVIDE_ARGS", |raw| {
+                Some(raw.split_whitespace().map(str::to_string).collect())
+            });
+        launcher
+            .gui_search_paths
+            .apply_env_with("NEOVIM_MANAGER_GUI_PATH", |raw| {
+                Some(std::env::split_paths(raw).collect())
+            });
+        launcher.direnv.apply_env("NEOVIM_MANAGER_DIRENV");
+        launcher.clipboard.apply_env("NEOVIM_MANAGER_CLIPBOARD");
+        launcher.stats.apply_env("NEOVIM_MANAGER_STATS");
+        launcher
+            .remember_geometry
+            .apply_env("NEOVIM_MANAGER_REMEMBER_GEOMETRY");
+        launcher
+            .scratch_dir
+            .apply_env_with("NEOVIM_MANAGER_SCRATCH_DIR", |raw| {
+                Some(Some(PathBuf::from(expand_home(raw))))
+            });
+        launcher
+            .nvim_appname
+            .apply_env_with("NEOVIM_MANAGER_NVIM_APPNAME", |raw| Some(appname(raw)));
+
+        // 従来どおり、値に関係なく設定されていれば有効
+        let control = &mut self.control;
+        control
+            .debug
+            .apply_env_with("NEOVIM_MANAGER_DEBUG", |_| Some(true));
+        control
+            .timeout
+            .apply_env_with("NEOVIM_MANAGER_TIMEOUT", |raw| duration::parse(raw).ok());
+        control.retries.apply_env("NEOVIM_MANAGER_RETRIES");
+    }
+
+    /// `(セクション, キー, 表示用の値, 由来)` の一覧
+    pub fn entries(&self) -> Vec<(&'static str, &'static str, String, &Origin)> {
+        let manager = &self.manager;
+        let launcher = &self.launcher;
+        let control = &self.control;
+
+        vec![
+            (
+                "manager",
+                "port",
+                manager.port.value.to_string(),
+                &manager.port.origin,
+            ),
+            (
+                "manager",
+                "bind_address",
+                format!("{:?}", manager.bind_address.value),
+                &manager.bind_address.origin,
+            ),
+            (
+                "manager",
+                "health_check_interval",
+                format!(
+                    "{:?}",
+                    duration::format(manager.health_check_interval.value)
+                ),
+                &manager.health_check_interval.origin,
+            ),
+            (
+                "manager",
+                "log_file",
+                match &manager.log_file.value {
+                    Some(path) => format!("{:?}", path.display().to_string()),
+                    None => "(none)".to_string(),
+                },
+                &manager.log_file.origin,
+            ),
+            (
+                "manager",
+                "state_file",
+                match &manager.state_file.value {
+                    Some(path) => format!("{:?}", path.display().to_string()),
+                    None => "(none)".to_string(),
+                },
+                &manager.state_file.origin,
+            ),
+            (
+                "manager",
+                "notify",
+                format!("{:?}", manager.notify.value.name()),
+                &manager.notify.origin,
+            ),
+            (
+                "manager",
+                "path_mappings",
+                format!(
+                    "{:?}",
+                    manager
+                        .path_mappings
+                        .value
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                ),
+                &manager.path_mappings.origin,
+            ),
+            (
+                "manager.hooks",
+                "on_register",
+                display_hook(&manager.hooks.on_register.value),
+                &manager.hooks.on_register.origin,
+            ),
+            (
+                "manager.hooks",
+                "on_unregister",
+                display_hook(&manager.hooks.on_unregister.value),
+                &manager.hooks.on_unregister.origin,
+            ),
+            (
+                "manager.hooks",
+                "on_health_lost",
+                display_hook(&manager.hooks.on_health_lost.value),
+                &manager.hooks.on_health_lost.origin,
+            ),
+            (
+                "launcher",
+                "neovide_command",
+                format!("{:?}", launcher.neovide_command.value),
+                &launcher.neovide_command.origin,
+            ),
+            (
+                "launcher",
+                "neovide_args",
+                format!("{:?}", launcher.neovide_args.value),
+                &launcher.neovide_args.origin,
+            ),
+            (
+                "launcher",
+                "gui_search_paths",
+                format!("{:?}", launcher.gui_search_paths.value),
+                &launcher.gui_search_paths.origin,
+            ),
+            (
+                "launcher",
+                "direnv",
+                launcher.direnv.value.to_string(),
+                &launcher.direnv.origin,
+            ),
+            (
+                "launcher",
+                "clipboard",
+                format!("{:?}", launcher.clipboard.value.name()),
+                &launcher.clipboard.origin,
+            ),
+            (
+                "launcher",
+                "stats",
+                launcher.stats.value.to_string(),
+                &launcher.stats.origin,
+            ),
+            (
+                "launcher",
+                "remember_geometry",
+                launcher.remember_geometry.value.to_string(),
+                &launcher.remember_geometry.origin,
+            ),
+            (
+                "launcher",
+                "scratch_dir",
+                match &launcher.scratch_dir.value {
+                    Some(dir) => format!("{:?}", dir.display().to_string()),
+                    None => "(none)".to_string(),
+                },
+                &launcher.scratch_dir.origin,
+            ),
+            (
+                "launcher",
+                "nvim_appname",
+                match &launcher.nvim_appname.value {
+                    Some(appname) => format!("{appname:?}"),
+                    None => "(none)".to_string(),
+                },
+                &launcher.nvim_appname.origin,
+            ),
+            (
+                "control",
+                "debug",
+                control.debug.value.to_string(),
+                &control.debug.origin,
+            ),
+            (
+                "control",
+                "timeout",
+                format!("{:?}", duration::format(control.timeout.value)),
+                &control.timeout.origin,
+            ),
+            (
+                "control",
+                "retries",
+                control.retries.value.to_string(),
+                &control.retries.origin,
+            ),
+        ]
+    }
+}
@@ -0,0 +1,68 @@
+-- neovim-instance-manager との連携 (`neovim-instance-manager-control init-lua` で生成)
+-- init.lua に貼り付けるか、このまま dofile() で読み込む
+do
+  local control = @CONTROL@
+  local heartbeat_ms = @HEARTBEAT_MS@
+  -- launcher・control restore が起動した nvim にだけ設定される
+  -- control rename で書き換わるので、使うたびに読む
+  local function identifier()
+    return vim.env.NEOVIM_MANAGER_IDENTIFIER
+  end
+  local uv = vim.uv or vim.loop
+
+  local function run(args)
+    vim.fn.jobstart(vim.list_extend({ control }, args))
+  end
+
+  if identifier() and identifier() ~= '' then
+    local group = vim.api.nvim_create_augroup('NeovimInstanceManager', { clear = true })
+
+    -- ハートビート: 使われている間は最終使用時刻を更新し続ける
+    local focused = true
+    local timer = uv.new_timer()
+    timer:start(heartbeat_ms, heartbeat_ms, vim.schedule_wrap(function()
+      if focused then
+        run({ 'touch', identifier() })
+      end
+    end))
+    vim.api.nvim_create_autocmd('FocusGained', {
+      group = group,
+      callback = function()
+        focused = true
+        run({ 'touch', identifier() })
+      end,
+    })
+    vim.api.nvim_create_autocmd('FocusLost', {
+      group = group,
+      callback = function()
+        focused = false
+      end,
+    })
+
+    -- :cd したら記録している cwd も更新する
+    vim.api.nvim_create_autocmd('DirChanged', {
+      group = group,
+      pattern = 'global',
+      callback = function()
+        run({ 'cd', identifier(), vim.fn.getcwd(-1, -1), '--record-only' })
+      end,
+    })
+
+    -- 終了時はヘルスチェックを待たずに登録を外す (終わるまで待つ)
+    vim.api.nvim_create_autocmd('VimLeavePre', {
+      group = group,
+      callback = function()
+        timer:stop()
+        vim.fn.system({ control, 'unregister', identifier() })
+      end,
+    })
+  end
+
+  -- フォーカス要求 (`execute('NeovideFocus')`) が GUI なしでも失敗しないようにする
+  -- 後から同じ名前のコマンドを定義すればそちらが使われる
+  if vim.fn.exists(':NeovideFocus') ~= 2 then
+    vim.api.nvim_create_user_command('NeovideFocus', function() end, {
+      desc = 'Raise the Neovide window (no-op fallback from neovim-instance-manager)',
+    })
+  end
+end
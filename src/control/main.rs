@@ -1,264 +1,4053 @@
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
+use neovim_manager::client::{InstanceWatcher, ManagerClient};
+use neovim_manager::clock::{Clock, SystemClock};
+use neovim_manager::config::{self, Config};
+use neovim_manager::direnv;
+use neovim_manager::gui::GuiCommand;
+use neovim_manager::identifier::PathMapping;
+use neovim_manager::process::{self, ChildProcess, ProcessSpec};
+use neovim_manager::report;
+use neovim_manager::retry::Backoff;
+use neovim_manager::stats;
+use neovim_manager::tmux::{self, TmuxMode};
+use neovim_manager::tunnel;
+use neovim_manager::wsl;
 use neovim_manager::{
-    JsonRpcRequest, JsonRpcResponse, QueryInstanceParams, RegisterInstanceParams,
-    UnregisterInstanceParams, DEFAULT_BIND_ADDR, DEFAULT_PORT,
+    duration, errors, identifier, utils, CloseReason, HealthStatus, InstanceResult,
+    InstancesUpdate, JsonRpcError, JsonRpcRequest, JsonRpcResponse, ManagerError, ManagerStatus,
+    PinInstanceParams, QueryInstanceParams, RegisterInstanceParams, RegistrySnapshot,
+    ServerAddress, SessionEntry, SessionManifest, StateMirror, TagInstanceParams, Tombstone,
+    TouchInstanceParams, UnregisterInstanceParams, WatchInstancesParams, PROTOCOL_VERSION,
 };
 use serde_json::{json, Value};
-use std::process::Command;
-use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
+use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::sleep;
-use uuid::Uuid;
+
+mod service;
+mod top;
+mod tui;
 
 #[derive(Parser)]
 #[command(name = "neovim-instance-manager-control")]
 #[command(about = "Control client for neovim-instance-manager")]
 struct Cli {
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration::parse,
+        help = "Give up waiting for a manager response after this long (default: 10s)"
+    )]
+    timeout: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Retry this many times when the manager cannot be reached"
+    )]
+    retries: Option<u32>,
+
+    #[arg(
+        long,
+        visible_alias = "porcelain",
+        help = "On failure, print the JSON-RPC error object to stdout instead of a message"
+    )]
+    json_errors: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["address", "socket"],
+        help = "Talk to the manager on this port of the configured bind address"
+    )]
+    port: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        conflicts_with = "socket",
+        help = "Talk to the manager at this address (never auto-started)"
+    )]
+    address: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Talk to a manager listening on this Unix socket [env: NEOVIM_MANAGER_SOCKET]"
+    )]
+    socket: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// スクリプト向けの終了コード (2 は clap の引数エラー)
+mod exit_code {
+    pub const FAILURE: i32 = 1;
+    pub const UNREACHABLE: i32 = 3;
+    pub const NOT_FOUND: i32 = 4;
+    pub const RPC_ERROR: i32 = 5;
+
+    pub fn for_rpc_error(error: &super::JsonRpcError) -> i32 {
+        if error.code == super::errors::INSTANCE_NOT_FOUND {
+            NOT_FOUND
+        } else {
+            RPC_ERROR
+        }
+    }
+}
+
+/// エラーを表示する。`json` なら JSON-RPC のエラーオブジェクトをそのまま標準出力に出す
+fn print_error(error: &JsonRpcError, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string(error).unwrap_or_default());
+    } else {
+        eprintln!("error: {} (code: {})", error.message, error.code);
+        if let Some(hint) = report::hint_for(&ManagerError::from(error.clone())) {
+            eprintln!("  hint: {hint}");
+        }
+    }
+}
+
+#[derive(Args, Clone)]
+struct OutputArgs {
+    #[arg(long, conflicts_with_all = ["jsonl", "format"], help = "Print the result as JSON")]
+    json: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "format",
+        help = "Print one JSON object per line"
+    )]
+    jsonl: bool,
+
+    #[arg(
+        long,
+        help = "Print each instance using a template, e.g. '{identifier}\\t{health}'. \
+                Fields: identifier, address, health, age, registered_at, last_used, \
+                last_health_check, pinned, cwd, pid, tags, appname"
+    )]
+    format: Option<String>,
+}
+
+#[derive(Args, Clone)]
+struct ListFilterArgs {
+    #[arg(
+        long,
+        value_parser = ["mru", "age", "identifier"],
+        default_value = "identifier",
+        help = "Sort order: mru (most recently used first), age (oldest first) or identifier"
+    )]
+    sort: String,
+
+    #[arg(long, help = "Only show instances that passed their last health check")]
+    healthy_only: bool,
+
+    #[arg(long, help = "Only show instances with this tag")]
+    tag: Option<String>,
+
+    #[arg(
+        long = "match",
+        value_name = "SUBSTR",
+        help = "Only show instances whose identifier contains this string"
+    )]
+    pattern: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum ManagerCommands {
+    Start {
+        #[arg(long, help = "Run in the foreground, logging to stderr")]
+        foreground: bool,
+    },
+    Stop {
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    Restart {
+        #[arg(long, help = "Run in the foreground, logging to stderr")]
+        foreground: bool,
+    },
+    Status,
+}
+
+#[derive(Subcommand)]
+enum WorkspaceCommands {
+    List,
+    Status {
+        #[arg(add = ArgValueCandidates::new(complete_workspaces))]
+        name: String,
+        #[arg(long, help = "Output as JSON")]
+        json: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Query {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
         identifier: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    List {
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        filter: ListFilterArgs,
+        #[arg(long, help = "Also show each instance's open file buffers")]
+        buffers: bool,
+    },
+    Buffers {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        #[arg(long, help = "Output as JSON")]
+        json: bool,
+    },
+    Env {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        #[arg(help = "Variables to show (default: PATH, VIRTUAL_ENV, NVIM_APPNAME)")]
+        names: Vec<String>,
+        #[arg(long, conflicts_with = "names", help = "Show every variable")]
+        all: bool,
+        #[arg(long, help = "Output as JSON")]
+        json: bool,
     },
-    List,
     Register {
         identifier: String,
-        server_address: String,
+        server_address: ServerAddress,
+        #[arg(long, help = "Working directory of the instance")]
+        cwd: Option<String>,
+        #[arg(long, help = "PID of the nvim server process")]
+        pid: Option<u32>,
+    },
+    Adopt {
+        server_address: ServerAddress,
+        #[arg(long, help = "Identifier to register (default: the server's cwd)")]
+        identifier: Option<String>,
     },
     Unregister {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+    },
+    Rename {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        new_identifier: String,
+        #[arg(
+            long,
+            help = "Treat the new identifier as a path and canonicalize it (for moved directories)"
+        )]
+        follow_path: bool,
+    },
+    Health {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+    },
+    Cd {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        dir: String,
+        #[arg(long, help = "Use :tcd to change only the current tab's directory")]
+        tab: bool,
+        #[arg(
+            long,
+            conflicts_with = "tab",
+            help = "Only record the directory without changing Neovim's (used by init-lua)"
+        )]
+        record_only: bool,
+    },
+    Kill {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    Pin {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+    },
+    Unpin {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+    },
+    Tag {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        tag: String,
+    },
+    Untag {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        tag: String,
+    },
+    Attach {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+        #[arg(
+            long,
+            value_name = "MODE",
+            help = "Attach in a tmux window or split pane (window, split)"
+        )]
+        tmux: Option<TmuxMode>,
+    },
+    Proxy {
+        #[arg(
+            add = ArgValueCandidates::new(complete_identifiers),
+            required_unless_present = "manager"
+        )]
+        identifier: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "identifier",
+            help = "Bridge to the manager's JSON-RPC port instead of an instance"
+        )]
+        manager: bool,
+    },
+    Touch {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
+        identifier: String,
+    },
+    Watch {
+        #[arg(
+            long,
+            default_value = "2s",
+            value_parser = duration::parse,
+            help = "Retry interval while the manager is unreachable, and the polling interval for managers without watch_instances (e.g. 2s, 500ms)"
+        )]
+        interval: Duration,
+        #[arg(
+            long,
+            help = "Print one JSON object per change (added, removed, changed) instead of a table"
+        )]
+        jsonl: bool,
+    },
+    Prune {
+        #[arg(long, help = "Also kill headless nvim servers that are not registered")]
+        kill_orphans: bool,
+        #[arg(short, long, help = "Do not ask before killing orphans")]
+        yes: bool,
+    },
+    Gc {
+        #[arg(long, help = "Also kill headless nvim servers that are not registered")]
+        kill_orphans: bool,
+        #[arg(long, help = "Only report what would be cleaned up")]
+        dry_run: bool,
+        #[arg(short, long, help = "Do not ask before killing orphans")]
+        yes: bool,
+    },
+    Wait {
+        #[arg(add = ArgValueCandidates::new(complete_identifiers))]
         identifier: String,
+        #[arg(
+            long,
+            conflicts_with = "healthy",
+            help = "Wait until the instance disappears"
+        )]
+        gone: bool,
+        #[arg(long, help = "Wait until the instance is healthy")]
+        healthy: bool,
+        #[arg(
+            long,
+            value_parser = duration::parse,
+            help = "Give up after this long (e.g. 30s, 5m)"
+        )]
+        timeout: Option<Duration>,
+    },
+    Recent {
+        #[arg(long, help = "Print the records as JSON")]
+        json: bool,
+        #[arg(
+            long,
+            value_name = "N",
+            help = "Reopen the N-th entry (1 = most recently closed) with the launcher"
+        )]
+        relaunch: Option<usize>,
+    },
+    Stats {
+        #[arg(
+            long,
+            value_parser = duration::parse,
+            help = "Only count events newer than this (e.g. 7d, 30d)"
+        )]
+        since: Option<Duration>,
+        #[arg(long, help = "Print the statistics as JSON")]
+        json: bool,
+    },
+    Export,
+    Snapshot {
+        #[arg(long, help = "Directory to write sessions to (default: cache dir)")]
+        dir: Option<String>,
+    },
+    Restore {
+        #[arg(long, help = "Directory written by `snapshot` (default: cache dir)")]
+        dir: Option<String>,
+        #[arg(long, help = "Do not attach a GUI client to restored instances")]
+        headless: bool,
+    },
+    Import {
+        #[arg(help = "Snapshot file written by `export` (use - for stdin)")]
+        file: String,
+    },
+    QuitAll {
+        #[arg(
+            long,
+            help = "Only quit instances whose identifier is inside this directory"
+        )]
+        group: Option<String>,
+        #[arg(long, help = "Only quit instances with this tag")]
+        tag: Option<String>,
+        #[arg(long, help = "Discard unsaved changes")]
+        force: bool,
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    UnregisterAll {
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    Doctor,
+    Drift {
+        #[arg(long, help = "Print the findings as JSON")]
+        json: bool,
+    },
+    Foreach {
+        #[arg(
+            help = "Shell command; {identifier}, {address}, {cwd}, ... are replaced with quoted values"
+        )]
+        command: String,
+        #[arg(short, long, help = "Run the commands concurrently")]
+        parallel: bool,
+        #[arg(long, help = "Only run for instances with this tag")]
+        tag: Option<String>,
+    },
+    Resolve {
+        path: String,
+        #[arg(long, help = "Show the result of every matching strategy")]
+        explain: bool,
+    },
+    Select {
+        #[arg(
+            long,
+            value_parser = ["rofi", "dmenu", "fzf"],
+            help = "Picker to use (default: fzf in a terminal, otherwise rofi or dmenu)"
+        )]
+        menu: Option<String>,
+        #[arg(long, help = "Print the selected identifier instead of focusing it")]
+        print: bool,
+    },
+    Tui,
+    Top {
+        #[arg(
+            long,
+            value_parser = ["cpu", "mem", "uptime", "identifier"],
+            default_value = "cpu",
+            help = "Initial sort column"
+        )]
+        sort: String,
+    },
+    Manager {
+        #[command(subcommand)]
+        command: ManagerCommands,
+    },
+    Workspace {
+        #[command(subcommand)]
+        command: WorkspaceCommands,
+    },
+    Status,
+    Version {
+        #[arg(long, help = "Output as JSON")]
+        json: bool,
+    },
+    Ping {
+        #[arg(
+            short,
+            long,
+            default_value_t = 4,
+            help = "Number of round trips to measure"
+        )]
+        count: u32,
+        #[arg(
+            long,
+            help = "Also measure the response time of each registered instance"
+        )]
+        instances: bool,
+    },
+    Metrics {
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Atomically write to this node_exporter textfile instead of stdout"
+        )]
+        textfile: Option<String>,
+    },
+    Bench {
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 20,
+            help = "Iterations per measurement"
+        )]
+        iterations: u32,
+        #[arg(
+            long,
+            requires = "instance",
+            help = "Also measure opening this file remotely"
+        )]
+        open: Option<String>,
+        #[arg(
+            long,
+            add = ArgValueCandidates::new(complete_identifiers),
+            help = "Instance to open the file in"
+        )]
+        instance: Option<String>,
+    },
+    Config {
+        #[arg(long, help = "Show where each value comes from")]
+        show_origin: bool,
+    },
+    Logs {
+        #[arg(short, long, help = "Keep printing new log lines as they are written")]
+        follow: bool,
+        #[arg(
+            long,
+            value_parser = duration::parse,
+            help = "Only show entries newer than this (e.g. 30s, 10m, 2h, 1d)"
+        )]
+        since: Option<Duration>,
+    },
+    Shutdown {
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    InstallService {
+        #[arg(
+            long,
+            help = "Print the unit files (or command) instead of installing them"
+        )]
+        print: bool,
+    },
+    UninstallService,
+    Raw {
+        method: String,
+        #[arg(help = "JSON params (default: {}), `-` reads them from stdin")]
+        params: Option<String>,
+    },
+    Completions {
+        #[arg(value_parser = ["bash", "zsh", "fish", "elvish", "powershell"])]
+        shell: String,
+    },
+    Prompt {
+        #[arg(long, help = "Print without the icon, for a starship custom module")]
+        starship: bool,
+        #[arg(
+            long,
+            conflicts_with = "starship",
+            help = "Print zsh prompt escapes, for a powerlevel10k custom segment"
+        )]
+        p10k: bool,
+    },
+    InitLua {
+        #[arg(
+            long,
+            default_value = "1m",
+            value_parser = duration::parse,
+            help = "How often to mark the instance as used while it has focus"
+        )]
+        heartbeat: Duration,
     },
-    Shutdown,
 }
 
-struct ManagerClient {
-    addr: String,
+struct Control {
+    client: ManagerClient,
+    json_errors: bool,
+    /// 経過時間の表示や記録する時刻の取得元
+    clock: Arc<dyn Clock>,
+    /// マネージャーが書き出す登録内容の写し (`prompt` が TCP の代わりに読む)
+    state_file: Option<PathBuf>,
+    /// 写しを引くときに identifier をマネージャーと同じくそろえるため
+    path_mappings: Vec<PathMapping>,
 }
 
-impl ManagerClient {
-    fn new() -> Self {
-        let port = std::env::var("NEOVIM_MANAGER_PORT")
-            .unwrap_or_else(|_| DEFAULT_PORT.to_string())
-            .parse::<u16>()
-            .unwrap_or(DEFAULT_PORT);
-
+impl Control {
+    fn new(config: &Config) -> Self {
         Self {
-            addr: format!("{DEFAULT_BIND_ADDR}:{port}"),
+            client: ManagerClient::new(config),
+            json_errors: false,
+            clock: Arc::new(SystemClock),
+            state_file: config.manager.state_file.value.clone(),
+            path_mappings: config.manager.path_mappings.value.clone(),
         }
     }
 
-    async fn ensure_manager_running(&self) -> Result<()> {
-        // まず接続を試行
-        if TcpStream::connect(&self.addr).await.is_ok() {
+    /// JSON-RPC エラーを表示し、エラーコードに応じた終了コードで終了する
+    fn exit_rpc_error(&self, error: &JsonRpcError) -> ! {
+        print_error(error, self.json_errors);
+        std::process::exit(exit_code::for_rpc_error(error));
+    }
+
+    async fn query_instance(&self, identifier: &str, output: OutputArgs) -> Result<()> {
+        let params = serde_json::to_value(QueryInstanceParams {
+            identifier: identifier.to_string(),
+        })?;
+
+        let response = self.client.send_request("query_instance", params).await?;
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        let result = response.result.unwrap_or(Value::Null);
+        if output.json || output.jsonl {
+            println!("{}", serde_json::to_string(&result)?);
             return Ok(());
         }
 
-        // マネージャーを起動
-        self.start_manager()?;
+        match serde_json::from_value::<Option<InstanceResult>>(result)? {
+            Some(instance) => match &output.format {
+                Some(template) => println!(
+                    "{}",
+                    render_template(template, &instance, self.clock.now())?
+                ),
+                None => print_instance_table(&[instance], None, self.clock.now()),
+            },
+            None if output.format.is_some() => {}
+            None => println!("No instance registered for {identifier}"),
+        }
 
-        // 起動を待つ（最大5秒）
-        for i in 0..10 {
-            sleep(Duration::from_millis(500)).await;
-            if TcpStream::connect(&self.addr).await.is_ok() {
-                return Ok(());
+        Ok(())
+    }
+
+    async fn list_instances(
+        &self,
+        output: OutputArgs,
+        filter: ListFilterArgs,
+        buffers: bool,
+    ) -> Result<()> {
+        // タグはマネージャー側で絞り込み、残りはこちらで行う
+        let mut instances: Vec<InstanceResult> = self
+            .fetch_tagged_instances(filter.tag.as_deref())
+            .await?
+            .into_iter()
+            .filter(|instance| !filter.healthy_only || instance.health_status.is_healthy())
+            .filter(|instance| {
+                filter
+                    .pattern
+                    .as_deref()
+                    .is_none_or(|pattern| instance.identifier.contains(pattern))
+            })
+            .collect();
+        match filter.sort.as_str() {
+            "mru" => instances.sort_by_key(|instance| std::cmp::Reverse(instance.last_used)),
+            "age" => instances.sort_by_key(|instance| instance.registered_at),
+            _ => {}
+        }
+
+        // 各インスタンスに問い合わせる。応答しないものは None
+        let mut buffer_lists: Vec<Option<Vec<utils::NvimBuffer>>> = Vec::new();
+        if buffers {
+            for instance in &instances {
+                buffer_lists.push(
+                    utils::list_nvim_buffers(&instance.server_address)
+                        .await
+                        .ok(),
+                );
+            }
+        }
+
+        if output.json || output.jsonl {
+            let values = instances
+                .iter()
+                .enumerate()
+                .map(|(index, instance)| {
+                    let mut value = serde_json::to_value(instance)?;
+                    if let Some(buffers) = buffer_lists.get(index) {
+                        value["buffers"] = json!(buffers);
+                    }
+                    Ok(value)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if output.json {
+                println!("{}", serde_json::to_string_pretty(&values)?);
+            } else {
+                for value in &values {
+                    println!("{}", serde_json::to_string(value)?);
+                }
             }
-            if i == 0 && std::env::var("NEOVIM_MANAGER_DEBUG").is_ok() {
-                eprintln!("Starting manager, waiting for startup...");
+        } else if let Some(template) = &output.format {
+            for instance in &instances {
+                println!("{}", render_template(template, instance, self.clock.now())?);
             }
+        } else if buffers {
+            let cells: Vec<String> = buffer_lists
+                .iter()
+                .map(|buffers| match buffers {
+                    Some(buffers) => format_buffer_names(buffers),
+                    None => "?".to_string(),
+                })
+                .collect();
+            print_instance_table(&instances, Some(&cells), self.clock.now());
+        } else {
+            print_instance_table(&instances, None, self.clock.now());
         }
 
-        Err(anyhow!("Manager not responding after startup"))
+        Ok(())
     }
 
-    fn start_manager(&self) -> Result<()> {
-        use std::process::Stdio;
+    async fn buffers(&self, identifier: &str, json: bool) -> Result<()> {
+        let instance = self.fetch_instance(identifier).await?;
+        let buffers = utils::list_nvim_buffers(&instance.server_address).await?;
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&buffers)?);
+            return Ok(());
+        }
 
-        // まず現在の実行可能ファイルのパスから推測
-        let current_exe = std::env::current_exe()?;
-        let manager_path = current_exe
-            .parent()
-            .ok_or_else(|| anyhow!("Cannot determine executable directory"))?
-            .join("neovim-instance-manager");
+        for buffer in &buffers {
+            println!(
+                "{:>4} {} {}",
+                buffer.bufnr,
+                if buffer.modified { "+" } else { " " },
+                buffer.name
+            );
+        }
 
-        Command::new(&manager_path)
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?;
         Ok(())
     }
 
-    async fn send_request(&self, method: &str, params: Value) -> Result<JsonRpcResponse> {
-        self.ensure_manager_running().await?;
+    async fn env(&self, identifier: &str, names: &[String], all: bool, json: bool) -> Result<()> {
+        const DEFAULT_NAMES: [&str; 3] = ["PATH", "VIRTUAL_ENV", "NVIM_APPNAME"];
 
-        let debug = std::env::var("NEOVIM_MANAGER_DEBUG").is_ok();
+        let instance = self.fetch_instance(identifier).await?;
 
-        if debug {
-            eprintln!("Connecting to manager at {}", self.addr);
-        }
-        let mut stream = TcpStream::connect(&self.addr).await?;
+        // OS から読めなければ Neovim 自身に environ() を尋ねる
+        let from_os = instance
+            .pid
+            .ok_or_else(|| anyhow!("no PID recorded"))
+            .and_then(utils::process_environment);
+        let environment = match from_os {
+            Ok(environment) => environment,
+            Err(e) => {
+                eprintln!("Warning: {e}, asking Neovim instead");
+                let output = utils::eval_in_nvim_instance(
+                    &instance.server_address,
+                    "json_encode(environ())",
+                )
+                .await?;
+                serde_json::from_str::<std::collections::BTreeMap<String, String>>(&output)?
+                    .into_iter()
+                    .collect()
+            }
+        };
 
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: method.to_string(),
-            params,
-            id: json!(Uuid::new_v4().to_string()),
+        let selected: Vec<(String, Option<String>)> = if all {
+            environment
+                .into_iter()
+                .map(|(name, value)| (name, Some(value)))
+                .collect()
+        } else {
+            let names: Vec<&str> = if names.is_empty() {
+                DEFAULT_NAMES.to_vec()
+            } else {
+                names.iter().map(String::as_str).collect()
+            };
+            names
+                .into_iter()
+                .map(|name| {
+                    let value = environment
+                        .iter()
+                        .find(|(candidate, _)| candidate == name)
+                        .map(|(_, value)| value.clone());
+                    (name.to_string(), value)
+                })
+                .collect()
         };
 
-        let request_json = serde_json::to_string(&request)?;
-        if debug {
-            eprintln!("Sending request: {request_json}");
+        if json {
+            let object: serde_json::Map<String, Value> = selected
+                .into_iter()
+                .map(|(name, value)| (name, json!(value)))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&object)?);
+            return Ok(());
         }
 
-        stream.write_all(request_json.as_bytes()).await?;
-        stream.write_all(b"\n").await?;
-        stream.flush().await?;
+        for (name, value) in selected {
+            match value {
+                Some(value) => println!("{name}={value}"),
+                None => println!("{name} (unset)"),
+            }
+        }
 
-        let (reader, _) = stream.into_split();
-        let mut reader = BufReader::new(reader);
-        let mut line = String::new();
+        Ok(())
+    }
 
-        if debug {
-            eprintln!("Waiting for response...");
-        }
-        let bytes_read = reader.read_line(&mut line).await?;
-        if debug {
-            eprintln!("Read {} bytes: '{}'", bytes_read, line.trim());
+    async fn register_instance(&self, params: RegisterInstanceParams) -> Result<()> {
+        let params = serde_json::to_value(params)?;
+
+        let response = self
+            .client
+            .send_request("register_instance", params)
+            .await?;
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
         }
 
-        if bytes_read == 0 {
-            return Err(anyhow!("Connection closed by manager"));
+        if let Some(result) = response.result {
+            println!("Success: {}", result.as_str().unwrap_or("registered"));
         }
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            return Err(anyhow!("Empty response from manager"));
+        Ok(())
+    }
+
+    async fn adopt_instance(
+        &self,
+        server_address: &ServerAddress,
+        identifier: Option<&str>,
+    ) -> Result<()> {
+        if !utils::check_nvim_instance(server_address).await? {
+            return Err(anyhow!(
+                "No Neovim server is responding at {server_address}"
+            ));
         }
 
-        let response: JsonRpcResponse = serde_json::from_str(trimmed)
-            .map_err(|e| anyhow!("Failed to parse response '{}': {}", trimmed, e))?;
+        // launcher と同じく、正規化したカレントディレクトリを identifier にする
+        let cwd = utils::eval_in_nvim_instance(server_address, "getcwd()").await?;
+        let cwd = identifier::from_path_lossy(&cwd);
+        let identifier = identifier.map_or_else(|| cwd.clone(), identifier::normalize);
+        let pid = utils::eval_in_nvim_instance(server_address, "getpid()")
+            .await
+            .ok()
+            .and_then(|pid| pid.parse().ok());
+        let appname = utils::eval_in_nvim_instance(server_address, "$NVIM_APPNAME")
+            .await
+            .ok()
+            .filter(|appname| !appname.is_empty());
 
-        Ok(response)
+        println!("Adopting {server_address} as {identifier}");
+        self.register_instance(RegisterInstanceParams {
+            identifier,
+            server_address: server_address.clone(),
+            cwd: Some(cwd),
+            pid,
+            tags: Vec::new(),
+            appname,
+            pinned: false,
+        })
+        .await
     }
 
-    async fn query_instance(&self, identifier: &str) -> Result<()> {
-        let params = serde_json::to_value(QueryInstanceParams {
+    async fn unregister_instance(&self, identifier: &str) -> Result<()> {
+        let params = serde_json::to_value(UnregisterInstanceParams {
             identifier: identifier.to_string(),
         })?;
 
-        let response = self.send_request("query_instance", params).await?;
+        let response = self
+            .client
+            .send_request("unregister_instance", params)
+            .await?;
 
         if let Some(error) = response.error {
-            eprintln!("Error: {} (code: {})", error.message, error.code);
-            std::process::exit(1);
+            self.exit_rpc_error(&error);
         }
 
         if let Some(result) = response.result {
-            println!("{}", serde_json::to_string(&result)?);
+            println!("Success: {}", result.as_str().unwrap_or("unregistered"));
+        }
+
+        Ok(())
+    }
+
+    async fn rename_instance(
+        &self,
+        identifier: &str,
+        new_identifier: &str,
+        follow_path: bool,
+    ) -> Result<()> {
+        let new_dir = if follow_path {
+            Some(
+                identifier::canonical_path(std::path::Path::new(new_identifier))
+                    .map_err(|e| anyhow!("Cannot resolve path '{new_identifier}': {e}"))?,
+            )
         } else {
-            println!("null");
+            None
+        };
+        let new_identifier = match &new_dir {
+            Some(dir) => identifier::from_path_lossy(&dir.to_string_lossy()),
+            None => new_identifier.to_string(),
+        };
+
+        self.client.rename(identifier, &new_identifier).await?;
+
+        println!("Renamed: {identifier} -> {new_identifier}");
+
+        // nvim はまだ移動前のディレクトリにいるので、移動先に :cd する
+        if let Some(dir) = new_dir {
+            let instance = self.fetch_instance(&new_identifier).await?;
+            match utils::change_nvim_directory(
+                &instance.server_address,
+                &dir.to_string_lossy(),
+                false,
+            )
+            .await
+            {
+                Ok(cwd) => self.client.set_cwd(&new_identifier, &cwd).await?,
+                Err(e) => eprintln!("Warning: Failed to change the directory: {e}"),
+            }
         }
 
         Ok(())
     }
 
-    async fn list_instances(&self) -> Result<()> {
-        let response = self.send_request("list_instances", json!({})).await?;
+    async fn change_directory(&self, identifier: &str, dir: &str, tab: bool) -> Result<()> {
+        let instance = self.fetch_instance(identifier).await?;
+
+        // 相対パスはこのプロセスのカレントディレクトリ基準で解決してから渡す
+        let dir = std::path::Path::new(dir);
+        let dir = identifier::canonical_path(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let cwd =
+            utils::change_nvim_directory(&instance.server_address, &dir.to_string_lossy(), tab)
+                .await?;
+
+        self.client.set_cwd(identifier, &cwd).await?;
+
+        println!("{identifier}: cwd is now {cwd}");
+
+        Ok(())
+    }
+
+    /// nvim 側で変わった作業ディレクトリを記録する (nvim には何もしない)
+    async fn record_directory(&self, identifier: &str, dir: &str) -> Result<()> {
+        self.client
+            .set_cwd(identifier, &identifier::from_path_lossy(dir))
+            .await
+    }
+
+    async fn pin_instance(&self, identifier: &str, pinned: bool) -> Result<()> {
+        let params = serde_json::to_value(PinInstanceParams {
+            identifier: identifier.to_string(),
+            pinned,
+        })?;
+        let response = self.client.send_request("pin_instance", params).await?;
 
         if let Some(error) = response.error {
-            eprintln!("Error: {} (code: {})", error.message, error.code);
-            std::process::exit(1);
+            self.exit_rpc_error(&error);
         }
 
         if let Some(result) = response.result {
-            println!("{}", serde_json::to_string_pretty(&result)?);
+            println!("Success: {}", result.as_str().unwrap_or(""));
         }
 
         Ok(())
     }
 
-    async fn register_instance(&self, identifier: &str, server_address: &str) -> Result<()> {
-        let params = serde_json::to_value(RegisterInstanceParams {
+    async fn tag_instance(&self, identifier: &str, tag: &str, tagged: bool) -> Result<()> {
+        let tag = tag.trim();
+        if tag.is_empty() || tag.contains(',') {
+            return Err(anyhow!(
+                "Invalid tag '{tag}' (must be non-empty without commas)"
+            ));
+        }
+
+        let params = serde_json::to_value(TagInstanceParams {
             identifier: identifier.to_string(),
-            server_address: server_address.to_string(),
+            tag: tag.to_string(),
+            tagged,
         })?;
-
-        let response = self.send_request("register_instance", params).await?;
+        let response = self.client.send_request("tag_instance", params).await?;
 
         if let Some(error) = response.error {
-            eprintln!("Error: {} (code: {})", error.message, error.code);
-            std::process::exit(1);
+            self.exit_rpc_error(&error);
         }
 
         if let Some(result) = response.result {
-            println!("Success: {}", result.as_str().unwrap_or("registered"));
+            println!("Success: {}", result.as_str().unwrap_or(""));
         }
 
         Ok(())
     }
 
-    async fn unregister_instance(&self, identifier: &str) -> Result<()> {
-        let params = serde_json::to_value(UnregisterInstanceParams {
-            identifier: identifier.to_string(),
-        })?;
+    /// カレントディレクトリのインスタンスの状態をプロンプト用に 1 行で出す
+    ///
+    /// プロンプトを遅くしないよう、マネージャーを起動せず再試行もしない。
+    /// インスタンスがない・マネージャーに接続できない場合は何も出さない
+    async fn prompt(&self, style: PromptStyle) {
+        let Ok(identifier) = identifier::from_target(None) else {
+            return;
+        };
 
-        let response = self.send_request("unregister_instance", params).await?;
+        // 写しがあれば TCP で問い合わせずにそれを読む
+        if let Some(state) = self.read_state_file() {
+            let identifier = identifier::normalize_mapped(&identifier, &self.path_mappings);
+            if let Some(instance) = state
+                .instances
+                .iter()
+                .find(|instance| instance.identifier == identifier)
+            {
+                println!(
+                    "{}",
+                    style.render(&instance.identifier, &instance.health_status)
+                );
+            }
+            return;
+        }
 
-        if let Some(error) = response.error {
-            eprintln!("Error: {} (code: {})", error.message, error.code);
-            std::process::exit(1);
+        let mut client = self.client.clone();
+        client.autostart = false;
+        client.retries = 0;
+        client.timeout = client.timeout.min(PROMPT_TIMEOUT);
+
+        if let Ok(Some(instance)) = client.query(&identifier).await {
+            println!(
+                "{}",
+                style.render(&instance.identifier, &instance.health_status)
+            );
         }
+    }
 
-        if let Some(result) = response.result {
-            println!("Success: {}", result.as_str().unwrap_or("unregistered"));
+    /// 登録内容の写し。ない・読めない・書き出したマネージャーがもういない場合は None
+    fn read_state_file(&self) -> Option<StateMirror> {
+        let content = std::fs::read(self.state_file.as_ref()?).ok()?;
+        let state: StateMirror = serde_json::from_slice(&content).ok()?;
+        (utils::process_exists(state.manager_pid) != Some(false)).then_some(state)
+    }
+
+    async fn attach(&self, identifier: &str, tmux_mode: Option<TmuxMode>) -> Result<()> {
+        let instance = self.fetch_instance(identifier).await?;
+        self.touch_instance(identifier).await?;
+
+        if let Some(mode) = tmux_mode {
+            tmux::open(mode, identifier, &instance.server_address)?;
+            return Ok(());
+        }
+
+        let code = utils::attach_nvim_instance(&instance.server_address)?;
+        if code != Some(0) {
+            std::process::exit(code.unwrap_or(1));
         }
 
         Ok(())
     }
 
-    async fn shutdown(&self) -> Result<()> {
-        let response = self.send_request("shutdown", json!({})).await?;
+    async fn proxy(&self, identifier: Option<&str>, manager: bool) -> Result<()> {
+        let address = match identifier {
+            Some(identifier) if !manager => self
+                .fetch_instance(identifier)
+                .await?
+                .server_address
+                .to_string(),
+            _ => self.client.addr.clone(),
+        };
+        let address = tunnel::local_address_async(&address).await?;
+
+        // 標準入力の読み込みは中断できないので、tokio ではなくスレッドで中継する
+        tokio::task::spawn_blocking(move || proxy_socket(&address)).await?
+    }
+
+    async fn kill_instance(&self, identifier: &str, yes: bool) -> Result<()> {
+        let instance = self.fetch_instance(identifier).await?;
 
-        if let Some(error) = response.error {
-            eprintln!("Error: {} (code: {})", error.message, error.code);
+        if !yes
+            && !confirm(&format!(
+                "Kill {} ({})? Unsaved changes will be lost",
+                instance.identifier, instance.server_address
+            ))?
+        {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        // 記録された PID がなければ listen アドレスからプロセスを探す
+        let pid = match instance.pid {
+            Some(pid) => Some(pid),
+            None => utils::find_headless_nvim_servers()?
+                .into_iter()
+                .find(|server| server.listen_address == instance.server_address)
+                .map(|server| server.pid),
+        };
+
+        match pid {
+            Some(pid) => match utils::kill_process(pid) {
+                Ok(()) => println!("Killed pid {pid} ({})", instance.server_address),
+                Err(e) => eprintln!("Warning: {e}"),
+            },
+            None => eprintln!(
+                "Warning: could not find the process listening on {}, unregistering only",
+                instance.server_address
+            ),
+        }
+
+        self.unregister_instance(identifier).await
+    }
+
+    async fn foreach(&self, template: &str, parallel: bool, tag: Option<&str>) -> Result<()> {
+        let instances = self.fetch_tagged_instances(tag).await?;
+
+        let spawn = |instance: &InstanceResult| {
+            let command = render_shell_template(template, instance, self.clock.now());
+            let spec = if cfg!(windows) {
+                ProcessSpec::new("cmd").args(["/C", &command])
+            } else {
+                ProcessSpec::new("sh").args(["-c", &command])
+            };
+            process::spawn(&spec.inherit_stdio())
+                .map_err(|e| anyhow!("Failed to run `{command}`: {e:#}"))
+        };
+
+        let mut failed = Vec::new();
+        if parallel {
+            let children = instances
+                .iter()
+                .map(|instance| Ok((instance, spawn(instance)?)))
+                .collect::<Result<Vec<_>>>()?;
+            for (instance, mut child) in children {
+                if child.wait()? != Some(0) {
+                    failed.push(&instance.identifier);
+                }
+            }
+        } else {
+            for instance in &instances {
+                if spawn(instance)?.wait()? != Some(0) {
+                    failed.push(&instance.identifier);
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            for identifier in &failed {
+                eprintln!("Command failed for {identifier}");
+            }
             std::process::exit(1);
         }
 
-        println!("Manager shutdown requested");
         Ok(())
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let client = ManagerClient::new();
+    async fn resolve(&self, path: &str, explain: bool) -> Result<()> {
+        let path = std::path::Path::new(path);
+        let identifier = identifier::from_target(Some(path))?;
+        let instances = self.fetch_instances().await?;
+        let find = |identifier: &str| {
+            instances
+                .iter()
+                .find(|instance| instance.identifier == identifier)
+        };
 
-    match cli.command {
-        Commands::Query { identifier } => {
-            client.query_instance(&identifier).await?;
+        // launcher が実際に使うのは exact のみ
+        let exact = find(&identifier);
+        let absolute = identifier::canonical_path(path).unwrap_or_else(|_| path.to_path_buf());
+        let git_root = utils::git_root(&absolute);
+        let by_git_root = git_root
+            .as_ref()
+            .and_then(|root| find(&identifier::normalize(&root.to_string_lossy())));
+        let by_containment = instances
+            .iter()
+            .filter_map(|instance| {
+                let dir = instance.cwd.as_deref().unwrap_or(&instance.identifier);
+                let dir = std::path::Path::new(dir);
+                (dir.is_absolute() && absolute.starts_with(dir))
+                    .then_some((dir.components().count(), instance))
+            })
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, instance)| instance);
+
+        let describe = |instance: Option<&InstanceResult>| match instance {
+            Some(instance) => format!("{}\t{}", instance.identifier, instance.server_address),
+            None => "none".to_string(),
+        };
+
+        if !explain {
+            println!("{}", describe(exact));
+            return Ok(());
         }
-        Commands::List => {
-            client.list_instances().await?;
+
+        let describe = |instance: Option<&InstanceResult>| match instance {
+            Some(instance) => format!("{} @ {}", instance.identifier, instance.server_address),
+            None => "none".to_string(),
+        };
+
+        println!("identifier:   {identifier}");
+        println!("exact:        {}", describe(exact));
+        println!(
+            "git-root:     {} (repository: {})",
+            describe(by_git_root),
+            git_root.map_or_else(
+                || "no repository".to_string(),
+                |root| root.display().to_string()
+            )
+        );
+        println!("containment:  {}", describe(by_containment));
+        println!("launcher:     uses exact");
+
+        Ok(())
+    }
+
+    async fn select(&self, menu: Option<&str>, print: bool) -> Result<()> {
+        let instances = self.fetch_instances().await?;
+        if instances.is_empty() {
+            eprintln!("No instances registered");
+            std::process::exit(1);
         }
-        Commands::Register {
-            identifier,
-            server_address,
-        } => {
-            client
-                .register_instance(&identifier, &server_address)
-                .await?;
+
+        let menu = match menu {
+            Some(menu) => menu,
+            None if std::io::stdin().is_terminal() && utils::find_in_path("fzf").is_some() => "fzf",
+            None if utils::find_in_path("rofi").is_some() => "rofi",
+            None => "dmenu",
+        };
+        let spec = match menu {
+            "rofi" => ProcessSpec::new("rofi").args(["-dmenu", "-i", "-p", "nvim"]),
+            "dmenu" => ProcessSpec::new("dmenu").args(["-i", "-p", "nvim"]),
+            _ => ProcessSpec::new("fzf").args(["--prompt", "nvim> "]),
+        };
+        let input: String = instances
+            .iter()
+            .map(|instance| format!("{}\n", instance.identifier))
+            .collect();
+        // fzf は端末に画面を出すので、標準エラー出力は引き継ぐ
+        let output = process::output(&spec.stdin(input).inherit_stdio())
+            .map_err(|e| anyhow!("Failed to run {menu}: {e:#}"))?;
+
+        // キャンセルされた場合は何も出力せずに失敗とする
+        let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let Some(instance) = instances
+            .iter()
+            .find(|instance| instance.identifier == selected)
+        else {
+            std::process::exit(1);
+        };
+
+        if print {
+            println!("{}", instance.identifier);
+            return Ok(());
         }
-        Commands::Unregister { identifier } => {
-            client.unregister_instance(&identifier).await?;
+
+        utils::focus_nvim_instance(&instance.server_address).await?;
+        self.touch_instance(&instance.identifier).await
+    }
+
+    async fn touch_instance(&self, identifier: &str) -> Result<()> {
+        let params = serde_json::to_value(TouchInstanceParams {
+            identifier: identifier.to_string(),
+        })?;
+
+        let response = self.client.send_request("touch_instance", params).await?;
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        Ok(())
+    }
+
+    /// 登録内容が変わるたびに表を描き直す
+    ///
+    /// `interval` は古いマネージャーに問い合わせる間隔と、接続できなかった後に待つ時間
+    async fn watch_instances(&self, interval: Duration, jsonl: bool) -> Result<()> {
+        let interval = interval.max(Duration::from_millis(100));
+
+        if jsonl {
+            return self.watch_events(interval).await;
+        }
+
+        let mut watcher = self.instance_watcher(interval);
+        loop {
+            let instances = match watcher.next().await {
+                Ok(instances) => instances,
+                Err(e) => {
+                    eprintln!("Warning: {e:#}");
+                    continue;
+                }
+            };
+
+            // 画面をクリアしてから表を描画する
+            print!("\x1b[2J\x1b[H");
+            println!(
+                "{} instance(s)    {}",
+                instances.len(),
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            );
+            println!();
+            print_instance_table(&instances, None, self.clock.now());
+        }
+    }
+
+    /// 前回の一覧との差分を 1 行 1 イベントの JSON で出し続ける (初回は全件 added)
+    async fn watch_events(&self, interval: Duration) -> Result<()> {
+        // 毎回変わるヘルスチェック時刻だけの変化は changed にしない
+        let differs = |before: &Value, after: &Value| {
+            let strip = |value: &Value| {
+                let mut value = value.clone();
+                if let Some(object) = value.as_object_mut() {
+                    object.remove("last_health_check");
+                }
+                value
+            };
+            strip(before) != strip(after)
+        };
+        let mut previous: HashMap<String, Value> = HashMap::new();
+
+        let mut watcher = self.instance_watcher(interval);
+        loop {
+            // 接続できない間は前回の一覧を保ち、つながり直したときに差分だけを出す
+            let instances = match watcher.next().await {
+                Ok(instances) => instances,
+                Err(e) => {
+                    eprintln!("Warning: {e:#}");
+                    continue;
+                }
+            };
+            let at = self.clock.now();
+            let mut current = HashMap::new();
+
+            let emit = |event: &str, instance: &Value| -> Result<()> {
+                let line = json!({"event": event, "at": at, "instance": instance});
+                println!("{}", serde_json::to_string(&line)?);
+                Ok(())
+            };
+
+            for instance in &instances {
+                let value = serde_json::to_value(instance)?;
+                match previous.remove(&instance.identifier) {
+                    None => emit("added", &value)?,
+                    Some(before) if differs(&before, &value) => emit("changed", &value)?,
+                    Some(_) => {}
+                }
+                current.insert(instance.identifier.clone(), value);
+            }
+            for instance in previous.values() {
+                emit("removed", instance)?;
+            }
+            previous = current;
+        }
+    }
+
+    /// 見張るだけなのでマネージャーは起動しない (止まっている間は接続し直し続ける)
+    fn instance_watcher(&self, interval: Duration) -> InstanceWatcher {
+        let mut client = self.client.clone();
+        client.autostart = false;
+        InstanceWatcher::new(client, interval)
+    }
+
+    async fn check_instance(&self, identifier: &str) -> Result<()> {
+        let result = self.client.check(identifier).await?;
+
+        if result.healthy {
+            println!(
+                "{} ({}): healthy, latency {}ms",
+                result.identifier, result.server_address, result.latency_ms
+            );
+        } else {
+            println!(
+                "{} ({}): not responding, latency {}ms{}",
+                result.identifier,
+                result.server_address,
+                result.latency_ms,
+                if result.removed { ", removed" } else { "" }
+            );
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn prune_instances(&self, kill_orphans: bool, yes: bool) -> Result<()> {
+        let result = self.client.prune().await?;
+
+        for instance in &result.removed {
+            println!(
+                "Removed: {} ({})",
+                instance.identifier, instance.server_address
+            );
+        }
+        println!(
+            "Pruned {} instance(s), {} remaining",
+            result.removed.len(),
+            result.remaining
+        );
+
+        if kill_orphans {
+            self.kill_orphans(yes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn kill_orphans(&self, yes: bool) -> Result<()> {
+        let instances = self.fetch_instances().await?;
+        let orphans: Vec<_> = utils::find_headless_nvim_servers()?
+            .into_iter()
+            .filter(|server| {
+                !instances
+                    .iter()
+                    .any(|instance| instance.server_address == server.listen_address)
+            })
+            .collect();
+
+        if orphans.is_empty() {
+            println!("No orphaned servers");
+            return Ok(());
+        }
+        if !yes && !confirm_kill_orphans(&orphans)? {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        let mut killed = 0;
+        for server in &orphans {
+            match utils::kill_process(server.pid) {
+                Ok(()) => {
+                    println!(
+                        "Killed orphan: pid {} ({})",
+                        server.pid, server.listen_address
+                    );
+                    killed += 1;
+                }
+                Err(e) => eprintln!("Failed to kill pid {}: {e}", server.pid),
+            }
+        }
+        println!("Killed {killed} orphaned server(s)");
+
+        Ok(())
+    }
+
+    async fn gc(&self, kill_orphans: bool, dry_run: bool, yes: bool) -> Result<()> {
+        // 登録済みだが応答しないインスタンス
+        let removed = if dry_run {
+            let mut dead = Vec::new();
+            for instance in self.fetch_instances().await? {
+                if !instance.pinned
+                    && !utils::check_nvim_instance(&instance.server_address)
+                        .await
+                        .unwrap_or(false)
+                {
+                    dead.push(instance);
+                }
+            }
+            dead
+        } else {
+            self.client.prune().await?.removed
+        };
+        for instance in &removed {
+            println!(
+                "Unregister dead: {} ({})",
+                instance.identifier, instance.server_address
+            );
+        }
+
+        // 誰も追跡していない headless サーバー
+        let instances = self.fetch_instances().await?;
+        let orphans: Vec<_> = utils::find_headless_nvim_servers()?
+            .into_iter()
+            .filter(|server| {
+                !instances
+                    .iter()
+                    .any(|instance| instance.server_address == server.listen_address)
+            })
+            .collect();
+        // 確認を断られた場合は未登録サーバーを報告するだけにする
+        let kill = kill_orphans
+            && !dry_run
+            && !orphans.is_empty()
+            && (yes || confirm_kill_orphans(&orphans)?);
+        let mut killed = 0;
+        for server in &orphans {
+            if !kill {
+                println!(
+                    "Untracked server: pid {} ({})",
+                    server.pid, server.listen_address
+                );
+                continue;
+            }
+
+            match utils::kill_process(server.pid) {
+                Ok(()) => {
+                    println!(
+                        "Killed orphan: pid {} ({})",
+                        server.pid, server.listen_address
+                    );
+                    killed += 1;
+                }
+                Err(e) => eprintln!("Failed to kill pid {}: {e}", server.pid),
+            }
+        }
+
+        // 残ったソケットファイル
+        let candidates: Vec<String> = removed
+            .iter()
+            .map(|instance| instance.server_address.to_string())
+            .collect();
+        let mut deleted = 0;
+        for socket in utils::find_stale_nvim_sockets(&candidates) {
+            if dry_run {
+                println!("Stale socket: {}", socket.display());
+                continue;
+            }
+
+            match std::fs::remove_file(&socket) {
+                Ok(()) => {
+                    println!("Removed stale socket: {}", socket.display());
+                    deleted += 1;
+                }
+                Err(e) => eprintln!("Failed to remove {}: {e}", socket.display()),
+            }
+        }
+
+        if dry_run {
+            println!("Dry run, nothing was changed");
+        } else {
+            println!(
+                "Unregistered {} dead instance(s), killed {killed} orphan(s), removed {deleted} stale socket(s)",
+                removed.len()
+            );
+            if !kill && !orphans.is_empty() {
+                println!(
+                    "{} untracked server(s) left running, pass --kill-orphans to stop them",
+                    orphans.len()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn wait_instance(
+        &self,
+        identifier: &str,
+        gone: bool,
+        healthy: bool,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        // すぐに変わることが多いので短い間隔から始め、長く待つときは問い合わせを減らす
+        let mut backoff = Backoff::exponential(Duration::from_millis(100), Duration::from_secs(2));
+        if let Some(timeout) = timeout {
+            backoff = backoff.deadline(timeout);
+        }
+
+        let settled = backoff
+            .poll(|| async {
+                match self.instance_settled(identifier, gone, healthy).await {
+                    Ok(true) => Some(Ok(())),
+                    Ok(false) => None,
+                    Err(e) => Some(Err(e)),
+                }
+            })
+            .await;
+
+        match settled {
+            Some(result) => result,
+            None => {
+                eprintln!("Timed out waiting for {identifier}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// `wait` の条件を満たしたか
+    async fn instance_settled(&self, identifier: &str, gone: bool, healthy: bool) -> Result<bool> {
+        let params = serde_json::to_value(QueryInstanceParams {
+            identifier: identifier.to_string(),
+        })?;
+        let response = self.client.send_request("query_instance", params).await?;
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        let instance: Option<InstanceResult> =
+            serde_json::from_value(response.result.unwrap_or(Value::Null))?;
+        Ok(match instance {
+            None => gone,
+            Some(_) if gone => false,
+            Some(instance) if healthy => instance.health_status.is_healthy(),
+            Some(_) => true,
+        })
+    }
+
+    async fn export_registry(&self) -> Result<()> {
+        let snapshot = RegistrySnapshot {
+            exported_at: self.clock.now(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            instances: self.fetch_instances().await?,
+        };
+
+        println!("{}", serde_json::to_string_pretty(&snapshot)?);
+        Ok(())
+    }
+
+    async fn snapshot(&self, dir: Option<&str>) -> Result<()> {
+        let dir = session_dir_or(dir)?;
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+
+        let mut sessions = Vec::new();
+        for instance in self.fetch_instances().await? {
+            if !instance.health_status.is_healthy() {
+                println!("Skipped (not healthy): {}", instance.identifier);
+                continue;
+            }
+
+            // identifier はパスのことが多いので、ファイル名に使えない文字を置き換える
+            let stem: String = instance
+                .identifier
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let mut session_file = format!("{stem}.vim");
+            let mut suffix = 1;
+            while sessions
+                .iter()
+                .any(|entry: &SessionEntry| entry.session_file == session_file)
+            {
+                suffix += 1;
+                session_file = format!("{stem}-{suffix}.vim");
+            }
+
+            let path = dir.join(&session_file);
+            let expr = format!(
+                "execute('mksession! ' . fnameescape({}))",
+                utils::vim_string_literal(&path.to_string_lossy())
+            );
+            match utils::eval_in_nvim_instance(&instance.server_address, &expr).await {
+                Ok(_) => {
+                    println!("Saved: {} -> {}", instance.identifier, path.display());
+                    sessions.push(SessionEntry {
+                        session_file,
+                        instance,
+                    });
+                }
+                Err(e) => eprintln!("Failed to save {}: {e}", instance.identifier),
+            }
+        }
+
+        let manifest = SessionManifest {
+            created_at: self.clock.now(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            sessions,
+        };
+        let manifest_path = dir.join("manifest.json");
+        std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+        println!(
+            "Saved {} session(s) to {}",
+            manifest.sessions.len(),
+            manifest_path.display()
+        );
+
+        Ok(())
+    }
+
+    async fn restore(&self, config: &Config, dir: Option<&str>, headless: bool) -> Result<()> {
+        let dir = session_dir_or(dir)?;
+        let manifest_path = dir.join("manifest.json");
+        let content = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| anyhow!("Cannot read {}: {e}", manifest_path.display()))?;
+        let manifest: SessionManifest = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid manifest {}: {e}", manifest_path.display()))?;
+
+        let registered = self.fetch_instances().await?;
+        let (mut restored, mut skipped) = (0, 0);
+        for entry in manifest.sessions {
+            let instance = entry.instance;
+            if registered
+                .iter()
+                .any(|running| running.identifier == instance.identifier)
+            {
+                println!("Skipped (already running): {}", instance.identifier);
+                skipped += 1;
+                continue;
+            }
+
+            match self
+                .restore_session(config, &dir, &entry.session_file, &instance, headless)
+                .await
+            {
+                Ok(server_address) => {
+                    println!("Restored: {} ({server_address})", instance.identifier);
+                    restored += 1;
+                }
+                Err(e) => {
+                    eprintln!("Failed to restore {}: {e}", instance.identifier);
+                    skipped += 1;
+                }
+            }
+        }
+        println!("Restored {restored} instance(s), skipped {skipped}");
+
+        Ok(())
+    }
+
+    async fn restore_session(
+        &self,
+        config: &Config,
+        dir: &std::path::Path,
+        session_file: &str,
+        instance: &InstanceResult,
+        headless: bool,
+    ) -> Result<ServerAddress> {
+        let cwd = instance
+            .cwd
+            .as_deref()
+            .map(std::path::Path::new)
+            .filter(|cwd| cwd.is_dir());
+        let session = dir.join(session_file);
+        let session_args = ["-S".to_string(), session.to_string_lossy().to_string()];
+        let env = match cwd {
+            Some(cwd) => direnv::load(&config.launcher, cwd).unwrap_or_else(|e| {
+                eprintln!(
+                    "Restoring {} without the direnv environment: {e:#}",
+                    instance.identifier
+                );
+                direnv::EnvChanges::new()
+            }),
+            None => direnv::EnvChanges::new(),
+        };
+        let (child, server_address) = utils::start_nvim_server(|server_address| {
+            utils::spawn_headless_nvim(
+                &instance.identifier,
+                server_address,
+                cwd,
+                &session_args,
+                &env,
+                instance.appname.as_deref(),
+            )
+        })
+        .await?;
+
+        self.client
+            .register(RegisterInstanceParams {
+                identifier: instance.identifier.clone(),
+                server_address: server_address.clone(),
+                cwd: instance.cwd.clone(),
+                pid: Some(child.id()),
+                tags: instance.tags.clone(),
+                appname: instance.appname.clone(),
+                pinned: instance.pinned,
+            })
+            .await?;
+
+        if !headless {
+            GuiCommand::resolve(&config.launcher).spawn(&server_address)?;
+        }
+
+        Ok(server_address)
+    }
+
+    async fn import_registry(&self, file: &str) -> Result<()> {
+        let content = if file == "-" {
+            std::io::read_to_string(std::io::stdin())?
+        } else {
+            std::fs::read_to_string(file).map_err(|e| anyhow!("Cannot read {file}: {e}"))?
+        };
+        let snapshot: RegistrySnapshot = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid snapshot file {file}: {e}"))?;
+
+        let (mut imported, mut skipped) = (0, 0);
+        for instance in snapshot.instances {
+            // スナップショット取得後に終了したインスタンスは登録しない
+            if !utils::check_nvim_instance(&instance.server_address)
+                .await
+                .unwrap_or(false)
+            {
+                println!(
+                    "Skipped (not responding): {} ({})",
+                    instance.identifier, instance.server_address
+                );
+                skipped += 1;
+                continue;
+            }
+
+            let result = self
+                .client
+                .register(RegisterInstanceParams {
+                    identifier: instance.identifier.clone(),
+                    server_address: instance.server_address.clone(),
+                    cwd: instance.cwd.clone(),
+                    pid: instance.pid,
+                    tags: instance.tags.clone(),
+                    appname: instance.appname.clone(),
+                    pinned: instance.pinned,
+                })
+                .await;
+
+            match result {
+                Err(e)
+                    if e.downcast_ref::<ManagerError>().is_some_and(|e| {
+                        matches!(e, ManagerError::InstanceAlreadyExists { .. })
+                    }) =>
+                {
+                    println!("Skipped ({e}): {}", instance.identifier);
+                    skipped += 1;
+                }
+                Err(e) => return Err(e),
+                Ok(()) => {
+                    println!(
+                        "Imported: {} ({})",
+                        instance.identifier, instance.server_address
+                    );
+                    imported += 1;
+                }
+            }
+        }
+        println!("Imported {imported} instance(s), skipped {skipped}");
+
+        Ok(())
+    }
+
+    /// 登録済みインスタンスを 1 つ取得する。未登録なら終了する
+    async fn fetch_instance(&self, identifier: &str) -> Result<InstanceResult> {
+        match self.client.query(identifier).await? {
+            Some(instance) => Ok(instance),
+            None => self.exit_rpc_error(
+                &ManagerError::InstanceNotFound {
+                    identifier: identifier.to_string(),
+                }
+                .into(),
+            ),
+        }
+    }
+
+    async fn fetch_instances(&self) -> Result<Vec<InstanceResult>> {
+        self.fetch_tagged_instances(None).await
+    }
+
+    /// `tag` を指定するとそのタグを持つインスタンスだけを取得する
+    async fn fetch_tagged_instances(&self, tag: Option<&str>) -> Result<Vec<InstanceResult>> {
+        let mut instances = self.client.list(tag).await?;
+        instances.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+
+        Ok(instances)
+    }
+
+    async fn quit_all(
+        &self,
+        group: Option<&str>,
+        tag: Option<&str>,
+        force: bool,
+        yes: bool,
+    ) -> Result<()> {
+        // identifier と同じく正規化して比べる (Windows では大文字小文字を区別しない)
+        let group = group
+            .map(|group| {
+                identifier::from_path(std::path::Path::new(group))
+                    .map_err(|e| anyhow!("Cannot resolve group directory '{group}': {e}"))
+            })
+            .transpose()?;
+
+        let instances: Vec<InstanceResult> = self
+            .fetch_tagged_instances(tag)
+            .await?
+            .into_iter()
+            .filter(|instance| {
+                group
+                    .as_ref()
+                    .is_none_or(|group| identifier::contains(group, &instance.identifier))
+            })
+            .collect();
+
+        if instances.is_empty() {
+            println!("No instances to quit");
+            return Ok(());
+        }
+
+        for instance in &instances {
+            println!("  {}", instance.identifier);
+        }
+        let prompt = if force {
+            format!(
+                "Quit {} instance(s), discarding unsaved changes?",
+                instances.len()
+            )
+        } else {
+            format!("Quit {} instance(s)?", instances.len())
+        };
+        if !yes && !confirm(&prompt)? {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for instance in &instances {
+            match utils::quit_all_nvim_instance(&instance.server_address, force).await {
+                Ok(true) => {
+                    println!("Quit: {}", instance.identifier);
+                    // 次のヘルスチェックを待たずに登録も解除しておく
+                    let params = serde_json::to_value(UnregisterInstanceParams {
+                        identifier: instance.identifier.clone(),
+                    })?;
+                    self.client
+                        .send_request("unregister_instance", params)
+                        .await?;
+                }
+                Ok(false) => {
+                    eprintln!(
+                        "Failed to quit {} (unsaved changes? retry with --force)",
+                        instance.identifier
+                    );
+                    failed += 1;
+                }
+                Err(e) => {
+                    eprintln!("Failed to quit {}: {e}", instance.identifier);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn unregister_all(&self, yes: bool) -> Result<()> {
+        let instances = self.fetch_instances().await?;
+
+        if instances.is_empty() {
+            println!("No instances registered");
+            return Ok(());
+        }
+
+        for instance in &instances {
+            println!("  {}", instance.identifier);
+        }
+        if !yes
+            && !confirm(&format!(
+                "Unregister {} instance(s)? (the Neovim processes keep running)",
+                instances.len()
+            ))?
+        {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        // 1 本の接続でまとめて送る
+        let requests = instances
+            .iter()
+            .map(|instance| {
+                Ok(JsonRpcRequest::new(
+                    "unregister_instance",
+                    serde_json::to_value(UnregisterInstanceParams {
+                        identifier: instance.identifier.clone(),
+                    })?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let responses = self.client.send_batch(&requests).await?;
+
+        for (instance, response) in instances.iter().zip(responses) {
+            match response.error {
+                Some(error) => eprintln!(
+                    "Failed to unregister {}: {}",
+                    instance.identifier,
+                    ManagerError::from(error)
+                ),
+                None => println!("Unregistered: {}", instance.identifier),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn recent(&self, json: bool, relaunch: Option<usize>) -> Result<()> {
+        let tombstones = self.client.recent().await?;
+
+        if let Some(n) = relaunch {
+            let tombstone = n
+                .checked_sub(1)
+                .and_then(|index| tombstones.get(index))
+                .ok_or_else(|| anyhow!("No entry #{n} ({} recorded)", tombstones.len()))?;
+            return relaunch_tombstone(tombstone);
+        }
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&tombstones)?);
+            return Ok(());
+        }
+
+        if tombstones.is_empty() {
+            println!("No recently closed instances");
+            return Ok(());
+        }
+
+        let rows: Vec<[String; 4]> = tombstones
+            .iter()
+            .enumerate()
+            .map(|(index, tombstone)| {
+                [
+                    (index + 1).to_string(),
+                    tombstone.instance.identifier.clone(),
+                    format!(
+                        "{} ago",
+                        format_elapsed(tombstone.closed_at, self.clock.now())
+                    ),
+                    match tombstone.reason {
+                        CloseReason::Unregistered => "closed".to_string(),
+                        CloseReason::Unresponsive => "unresponsive".to_string(),
+                    },
+                ]
+            })
+            .collect();
+        print_table(&["#", "IDENTIFIER", "CLOSED", "REASON"], &rows);
+
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<()> {
+        // 到達性を確認したいので、未起動でもマネージャーは起動しない
+        let response = match self.client.send_request_direct("status", json!({})).await {
+            Ok(response) => response,
+            Err(e) => {
+                println!(
+                    "Manager:        not reachable at {} ({e})",
+                    self.client.addr
+                );
+                std::process::exit(exit_code::UNREACHABLE);
+            }
+        };
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        let status: ManagerStatus = serde_json::from_value(
+            response
+                .result
+                .ok_or_else(|| anyhow!("Empty result from manager"))?,
+        )?;
+        let stats = &status.health_checks;
+
+        println!("Manager:        reachable at {}", self.client.addr);
+        println!(
+            "Version:        {} (protocol {})",
+            status.version, status.protocol_version
+        );
+        println!("PID:            {}", status.pid);
+        println!(
+            "Uptime:         {} (since {})",
+            format_elapsed(status.started_at, self.clock.now()),
+            status
+                .started_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        );
+        println!("Bind address:   {}", status.bind_address);
+        println!(
+            "Instances:      {} ({} healthy)",
+            status.instance_count, status.healthy_count
+        );
+        println!(
+            "Health checks:  {} runs, {} checks, {} failures, {} removed",
+            stats.runs, stats.checks, stats.failures, stats.removed
+        );
+        match (stats.last_run_at, stats.last_run_duration_ms) {
+            (Some(at), Some(duration_ms)) => println!(
+                "Last check run: {} ago, took {duration_ms}ms",
+                format_elapsed(at, self.clock.now())
+            ),
+            _ => println!("Last check run: never"),
+        }
+        if let Some(at) = stats.last_resume_at {
+            println!(
+                "Resumes:        {} (last {} ago)",
+                stats.resumes,
+                format_elapsed(at, self.clock.now())
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn version(&self, json: bool) -> Result<()> {
+        // 未起動なら新しく起動したものと比べても意味がないので起動しない
+        let manager = match self.client.send_request_direct("status", json!({})).await {
+            Ok(response) => response
+                .result
+                .and_then(|result| serde_json::from_value::<ManagerStatus>(result).ok()),
+            Err(_) => None,
+        };
+        let mismatch = manager.as_ref().is_some_and(|status| {
+            status.version != env!("CARGO_PKG_VERSION")
+                || status.protocol_version != PROTOCOL_VERSION
+        });
+
+        if json {
+            let output = json!({
+                "client": {
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "protocol_version": PROTOCOL_VERSION,
+                },
+                "manager": manager.as_ref().map(|status| json!({
+                    "version": status.version,
+                    "protocol_version": status.protocol_version,
+                    "pid": status.pid,
+                })),
+                "mismatch": mismatch,
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        } else {
+            println!(
+                "Client:   {} (protocol {PROTOCOL_VERSION})",
+                env!("CARGO_PKG_VERSION")
+            );
+            match &manager {
+                Some(status) => println!(
+                    "Manager:  {} (protocol {}, pid {})",
+                    status.version, status.protocol_version, status.pid
+                ),
+                None => println!("Manager:  not running at {}", self.client.addr),
+            }
+        }
+
+        if mismatch {
+            eprintln!();
+            eprintln!("WARNING: the running manager does not match this client.");
+            eprintln!("         It was probably started before an upgrade and may misunderstand requests.");
+            eprintln!(
+                "         Run `neovim-instance-manager-control manager restart` to replace it."
+            );
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn metrics(&self, textfile: Option<&str>) -> Result<()> {
+        // cron などから定期実行される想定なので、マネージャーは起動しない
+        let status = match self.client.send_request_direct("status", json!({})).await {
+            Ok(response) => response
+                .result
+                .and_then(|result| serde_json::from_value::<ManagerStatus>(result).ok()),
+            Err(_) => None,
+        };
+        let instances = match &status {
+            Some(_) => {
+                let response = self
+                    .client
+                    .send_request_direct("list_instances", json!({}))
+                    .await?;
+                match response.error {
+                    Some(error) => self.exit_rpc_error(&error),
+                    None => serde_json::from_value::<Vec<InstanceResult>>(
+                        response.result.unwrap_or_else(|| json!([])),
+                    )?,
+                }
+            }
+            None => Vec::new(),
+        };
+
+        let output = render_metrics(status.as_ref(), &instances, self.clock.now());
+        match textfile {
+            Some(path) => {
+                // node_exporter が書きかけを読まないよう、一時ファイルに書いてから置き換える
+                let path = std::path::Path::new(path);
+                let tmp = path.with_extension(format!("prom.{}.tmp", std::process::id()));
+                std::fs::write(&tmp, output)
+                    .map_err(|e| anyhow!("Cannot write {}: {e}", tmp.display()))?;
+                std::fs::rename(&tmp, path)
+                    .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+            }
+            None => print!("{output}"),
+        }
+
+        Ok(())
+    }
+
+    async fn bench(
+        &self,
+        iterations: u32,
+        open: Option<&str>,
+        instance: Option<&str>,
+    ) -> Result<()> {
+        let iterations = iterations.max(1);
+        self.client.ensure_manager_running().await?;
+
+        let mut samples = Vec::new();
+        for _ in 0..iterations {
+            let started = Instant::now();
+            let response = self.client.send_request_direct("ping", json!({})).await?;
+            if let Some(error) = response.error {
+                self.exit_rpc_error(&error);
+            }
+            samples.push(started.elapsed());
+        }
+        print_latency_summary("manager rpc (ping)", &mut samples);
+
+        for instance in self.fetch_instances().await? {
+            let mut samples = Vec::new();
+            for _ in 0..iterations {
+                let started = Instant::now();
+                utils::check_nvim_instance(&instance.server_address).await?;
+                samples.push(started.elapsed());
+            }
+            print_latency_summary(&format!("health {}", instance.identifier), &mut samples);
+        }
+
+        if let (Some(file), Some(identifier)) = (open, instance) {
+            let instance = self.fetch_instance(identifier).await?;
+            let file = std::path::Path::new(file);
+            let file = identifier::canonical_path(file).unwrap_or_else(|_| file.to_path_buf());
+
+            let mut samples = Vec::new();
+            for _ in 0..iterations {
+                let started = Instant::now();
+                utils::open_file_in_nvim_instance(
+                    &instance.server_address,
+                    &file.to_string_lossy(),
+                    &Default::default(),
+                )
+                .await?;
+                samples.push(started.elapsed());
+            }
+            print_latency_summary(&format!("open {}", instance.identifier), &mut samples);
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&self, count: u32, instances: bool) -> Result<()> {
+        self.client.ensure_manager_running().await?;
+
+        let mut latencies = Vec::new();
+        for seq in 1..=count.max(1) {
+            let started = Instant::now();
+            let response = self.client.send_request_direct("ping", json!({})).await?;
+            let elapsed = started.elapsed();
+
+            if let Some(error) = response.error {
+                self.exit_rpc_error(&error);
+            }
+
+            println!(
+                "manager {}: seq={seq} time={:.2}ms",
+                self.client.addr,
+                as_millis_f64(elapsed)
+            );
+            latencies.push(elapsed);
+        }
+
+        let min = latencies.iter().min().copied().unwrap_or_default();
+        let max = latencies.iter().max().copied().unwrap_or_default();
+        let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        println!(
+            "round-trip min/avg/max = {:.2}/{:.2}/{:.2} ms",
+            as_millis_f64(min),
+            as_millis_f64(avg),
+            as_millis_f64(max)
+        );
+
+        if instances {
+            let response = self
+                .client
+                .send_request_direct("list_instances", json!({}))
+                .await?;
+            let instances: Vec<InstanceResult> =
+                serde_json::from_value(response.result.unwrap_or_else(|| json!([])))?;
+
+            // マネージャーを経由せず、このクライアントから直接 nvim に疎通確認する
+            for instance in instances {
+                let started = Instant::now();
+                let healthy = utils::check_nvim_instance(&instance.server_address)
+                    .await
+                    .unwrap_or(false);
+                let elapsed = started.elapsed();
+                println!(
+                    "{} ({}): {} time={:.2}ms",
+                    instance.identifier,
+                    instance.server_address,
+                    if healthy { "ok" } else { "no response" },
+                    as_millis_f64(elapsed)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// マネージャーを起動する。`foreground` の場合はログを標準エラー出力に流す子プロセスを返す
+    async fn manager_start(&self, foreground: bool) -> Result<Option<Box<dyn ChildProcess>>> {
+        if self.client.is_manager_running().await {
+            println!("Manager already running at {}", self.client.addr);
+            return Ok(None);
+        }
+
+        let child = if foreground {
+            Some(process::spawn(
+                &self
+                    .client
+                    .manager_process()?
+                    .arg("--foreground")
+                    .inherit_stdio(),
+            )?)
+        } else {
+            self.client.start_manager()?;
+            None
+        };
+
+        self.client.wait_for_manager(true).await?;
+        println!("Manager started at {}", self.client.addr);
+
+        Ok(child)
+    }
+
+    async fn manager_stop(&self) -> Result<()> {
+        if !self.client.is_manager_running().await {
+            println!("Manager not running at {}", self.client.addr);
+            return Ok(());
+        }
+
+        let response = self
+            .client
+            .send_request_direct("shutdown", json!({}))
+            .await?;
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        self.client.wait_for_manager(false).await?;
+        println!("Manager stopped");
+
+        Ok(())
+    }
+
+    async fn manager_restart(&self, foreground: bool) -> Result<Option<Box<dyn ChildProcess>>> {
+        // 永続化層がないので、停止前の登録内容を控えておき再起動後に登録し直す
+        let instances = if self.client.is_manager_running().await {
+            self.fetch_instances().await?
+        } else {
+            Vec::new()
+        };
+
+        self.manager_stop().await?;
+        let child = self.manager_start(foreground).await?;
+
+        let mut restored = 0;
+        for instance in &instances {
+            if !utils::check_nvim_instance(&instance.server_address)
+                .await
+                .unwrap_or(false)
+            {
+                continue;
+            }
+
+            let params = serde_json::to_value(RegisterInstanceParams {
+                identifier: instance.identifier.clone(),
+                server_address: instance.server_address.clone(),
+                cwd: instance.cwd.clone(),
+                pid: instance.pid,
+                tags: instance.tags.clone(),
+                appname: instance.appname.clone(),
+                pinned: instance.pinned,
+            })?;
+            if self
+                .client
+                .send_request_direct("register_instance", params)
+                .await?
+                .error
+                .is_none()
+            {
+                restored += 1;
+            }
+        }
+        if !instances.is_empty() {
+            println!("Restored {restored} of {} registration(s)", instances.len());
+        }
+
+        Ok(child)
+    }
+
+    /// ワークスペースのプロジェクトごとにインスタンスの状態を表示する。動いていないものがあれば終了コード 1
+    async fn workspace_status(&self, config: &Config, name: &str, json: bool) -> Result<()> {
+        let workspace = config.workspace(name)?;
+        let instances = self.client.list(None).await?;
+        let projects: Vec<_> = workspace
+            .projects
+            .iter()
+            .map(|dir| {
+                let identifier = identifier::from_path(dir).ok();
+                let instance = identifier.as_ref().and_then(|identifier| {
+                    instances
+                        .iter()
+                        .find(|instance| instance.identifier == *identifier)
+                });
+                (dir, identifier, instance)
+            })
+            .collect();
+
+        if json {
+            let projects: Vec<_> = projects
+                .iter()
+                .map(|(dir, identifier, instance)| {
+                    json!({
+                        "path": dir,
+                        "identifier": identifier,
+                        "instance": instance,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&projects)?);
+        } else {
+            let rows: Vec<[String; 3]> = projects
+                .iter()
+                .map(|(dir, identifier, instance)| match (identifier, instance) {
+                    (None, _) => [
+                        dir.display().to_string(),
+                        "missing".to_string(),
+                        String::new(),
+                    ],
+                    (Some(identifier), None) => {
+                        [identifier.clone(), "not running".to_string(), String::new()]
+                    }
+                    (Some(identifier), Some(instance)) => [
+                        identifier.clone(),
+                        instance.health_status.to_string(),
+                        instance.server_address.to_string(),
+                    ],
+                })
+                .collect();
+            print_table(&["PROJECT", "STATUS", "ADDRESS"], &rows);
+        }
+
+        if projects.iter().any(|(_, _, instance)| instance.is_none()) {
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+
+    async fn manager_command(&self, command: ManagerCommands) -> Result<()> {
+        let child = match command {
+            ManagerCommands::Start { foreground } => self.manager_start(foreground).await?,
+            ManagerCommands::Stop { yes } => {
+                if !yes && !self.confirm_shutdown().await? {
+                    println!("Aborted");
+                    return Ok(());
+                }
+                self.manager_stop().await?;
+                None
+            }
+            ManagerCommands::Restart { foreground } => self.manager_restart(foreground).await?,
+            ManagerCommands::Status => {
+                self.status().await?;
+                None
+            }
+        };
+
+        if let Some(mut child) = child {
+            let code = child.wait()?;
+            std::process::exit(code.unwrap_or(1));
+        }
+
+        Ok(())
+    }
+
+    /// 登録内容と実際に動いているプロセスのずれを報告する (変更はしない)
+    async fn drift(&self, json: bool) -> Result<()> {
+        // 応答しないものは一覧取得時にマネージャーが削除するので、
+        // ここで見つかるのは主にピン留めされたもの
+        let instances = self.fetch_instances().await?;
+        let servers = utils::find_headless_nvim_servers()?;
+
+        let mut unresponsive = Vec::new();
+        let mut pid_mismatch = Vec::new();
+        for instance in &instances {
+            if !utils::check_nvim_instance(&instance.server_address)
+                .await
+                .unwrap_or(false)
+            {
+                unresponsive.push(instance);
+                continue;
+            }
+
+            let listening = servers
+                .iter()
+                .find(|server| server.listen_address == instance.server_address);
+            if let (Some(pid), Some(server)) = (instance.pid, listening) {
+                if server.pid != pid {
+                    pid_mismatch.push((instance, server));
+                }
+            }
+        }
+        let untracked: Vec<_> = servers
+            .iter()
+            .filter(|server| {
+                !instances
+                    .iter()
+                    .any(|instance| instance.server_address == server.listen_address)
+            })
+            .collect();
+
+        let drifted = !unresponsive.is_empty() || !pid_mismatch.is_empty() || !untracked.is_empty();
+
+        if json {
+            let report = json!({
+                "unresponsive": unresponsive
+                    .iter()
+                    .map(|instance| json!({
+                        "identifier": instance.identifier,
+                        "server_address": instance.server_address,
+                        "pinned": instance.pinned,
+                    }))
+                    .collect::<Vec<_>>(),
+                "pid_mismatch": pid_mismatch
+                    .iter()
+                    .map(|(instance, server)| json!({
+                        "identifier": instance.identifier,
+                        "server_address": instance.server_address,
+                        "registered_pid": instance.pid,
+                        "actual_pid": server.pid,
+                    }))
+                    .collect::<Vec<_>>(),
+                "untracked": untracked
+                    .iter()
+                    .map(|server| json!({
+                        "pid": server.pid,
+                        "listen_address": server.listen_address,
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for instance in &unresponsive {
+                println!(
+                    "Registered but not answering: {} ({})",
+                    instance.identifier, instance.server_address
+                );
+                if instance.pinned {
+                    println!(
+                        "  fix: control unpin {0} && control unregister {0}",
+                        shell_quote(&instance.identifier)
+                    );
+                } else {
+                    println!(
+                        "  fix: control unregister {}  (or control gc)",
+                        shell_quote(&instance.identifier)
+                    );
+                }
+            }
+            for (instance, server) in &pid_mismatch {
+                println!(
+                    "PID mismatch: {} is registered with pid {} but {} is served by pid {}",
+                    instance.identifier,
+                    instance.pid.unwrap_or_default(),
+                    instance.server_address,
+                    server.pid
+                );
+                println!(
+                    "  fix: control unregister {0} && control adopt {1} --identifier {0}",
+                    shell_quote(&instance.identifier),
+                    shell_quote(&instance.server_address)
+                );
+            }
+            for server in &untracked {
+                println!(
+                    "Running but not registered: pid {} ({})",
+                    server.pid, server.listen_address
+                );
+                println!(
+                    "  fix: control adopt {}  (or control gc --kill-orphans)",
+                    shell_quote(&server.listen_address)
+                );
+            }
+            if !drifted {
+                println!(
+                    "No drift: {} registered instance(s) match the running servers",
+                    instances.len()
+                );
+            }
+        }
+
+        if drifted {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn doctor(&self, config: &Config) -> Result<()> {
+        let mut report = DoctorReport::default();
+
+        // nvim
+        match utils::find_in_path("nvim") {
+            Some(path) => match utils::nvim_version() {
+                Ok(version) => report.ok(format!("nvim: {} ({version})", path.display())),
+                Err(e) => report.error(
+                    format!("nvim: {} does not run: {e}", path.display()),
+                    "reinstall Neovim or fix the binary on PATH",
+                ),
+            },
+            None => report.error(
+                "nvim: not found on PATH",
+                "install Neovim; the manager uses `nvim --server` for health checks",
+            ),
+        }
+
+        // GUI
+        let gui = GuiCommand::resolve(&config.launcher);
+        if gui.is_found() {
+            report.ok(format!("GUI: {} ({})", gui.program.display(), gui.source));
+        } else {
+            report.error(
+                format!("GUI: {} not found", gui.program.display()),
+                "install Neovide, or set launcher.neovide_command / NEOVIM_MANAGER_NEOVIDE \
+                 or launcher.gui_search_paths / NEOVIM_MANAGER_GUI_PATH",
+            );
+        }
+
+        // WSL
+        match wsl::version() {
+            Some(version) => {
+                let networking = wsl::networking_mode()
+                    .map(|mode| mode.to_string())
+                    .unwrap_or_default();
+                report.ok(format!(
+                    "WSL: {version} ({networking} networking), \
+                     the Windows build of Neovide (neovide.exe) is used"
+                ));
+            }
+            None => report.ok("WSL: not detected"),
+        }
+
+        // manager
+        match self.client.send_request_direct("status", json!({})).await {
+            Ok(response) => match response
+                .result
+                .and_then(|result| serde_json::from_value::<ManagerStatus>(result).ok())
+            {
+                Some(status)
+                    if status.version == env!("CARGO_PKG_VERSION")
+                        && status.protocol_version == PROTOCOL_VERSION =>
+                {
+                    report.ok(format!(
+                        "manager: reachable at {}, version {}",
+                        self.client.addr, status.version
+                    ));
+                }
+                Some(status) => report.warn(
+                    format!(
+                        "manager: version {} (protocol {}) differs from this client ({}, protocol {PROTOCOL_VERSION})",
+                        status.version,
+                        status.protocol_version,
+                        env!("CARGO_PKG_VERSION")
+                    ),
+                    "restart it with `neovim-instance-manager-control manager restart`",
+                ),
+                None => report.warn(
+                    format!("manager: {} answered but did not report its status", self.client.addr),
+                    "the manager is probably outdated; restart it",
+                ),
+            },
+            Err(_) if self.client.socket.is_some() || !self.client.autostart => report.error(
+                format!("manager: not reachable at {}", self.client.addr),
+                "check that the manager (or the tunnel to it) is running",
+            ),
+            Err(_) => match std::net::TcpListener::bind(&self.client.addr) {
+                Ok(_) => report.ok(format!(
+                    "manager: not running, {} is free (it starts on demand)",
+                    self.client.addr
+                )),
+                Err(e) => report.error(
+                    format!("manager: not reachable and {} cannot be bound: {e}", self.client.addr),
+                    "another program may be using the port; set NEOVIM_MANAGER_PORT to another port",
+                ),
+            },
+        }
+
+        // config
+        match &config.file {
+            Some(path) => report.ok(format!("config: {}", path.display())),
+            None => report.ok("config: no config file, using defaults and environment"),
+        }
+
+        // log directory
+        match &config.manager.log_file.value {
+            Some(log_file) => match check_writable_dir(log_file.parent()) {
+                Ok(()) => report.ok(format!("log file: {}", log_file.display())),
+                Err(e) => report.error(
+                    format!("log file: {} is not writable: {e}", log_file.display()),
+                    "fix the directory permissions or set NEOVIM_MANAGER_LOG_FILE",
+                ),
+            },
+            None => report.warn(
+                "log file: cannot determine a location, manager logs are discarded",
+                "set HOME or NEOVIM_MANAGER_LOG_FILE",
+            ),
+        }
+
+        // ソケット・SSH トンネル・nvim の標準エラー出力・ワークスペースのロックを置く
+        let runtime_dir = config::runtime_dir();
+        match check_writable_dir(Some(&runtime_dir)) {
+            Ok(()) => report.ok(format!("runtime dir: {}", runtime_dir.display())),
+            Err(e) => report.error(
+                format!(
+                    "runtime dir: {} is not writable: {e}",
+                    runtime_dir.display()
+                ),
+                "fix the directory permissions, or on Linux point XDG_RUNTIME_DIR to a writable directory",
+            ),
+        }
+
+        // 信頼したプロジェクト・統計・セッション・最近のプロジェクトを置く
+        match config::state_dir() {
+            Some(state_dir) => match check_writable_dir(Some(&state_dir)) {
+                Ok(()) => report.ok(format!("state dir: {}", state_dir.display())),
+                Err(e) => report.error(
+                    format!("state dir: {} is not writable: {e}", state_dir.display()),
+                    "fix the directory permissions or point XDG_CACHE_HOME to a writable directory",
+                ),
+            },
+            None => report.warn(
+                "state dir: cannot determine a location, trusted projects, stats and sessions are not saved",
+                "set HOME or XDG_CACHE_HOME",
+            ),
+        }
+
+        if report.has_errors {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn raw(&self, method: &str, params: Option<&str>) -> Result<()> {
+        let params = match params {
+            Some("-") => {
+                let mut input = String::new();
+                std::io::stdin().read_to_string(&mut input)?;
+                input
+            }
+            Some(params) => params.to_string(),
+            None => "{}".to_string(),
+        };
+        let params: Value =
+            serde_json::from_str(&params).map_err(|e| anyhow!("Invalid JSON params: {e}"))?;
+
+        let response = self.client.send_request(method, params).await?;
+        println!("{}", serde_json::to_string_pretty(&response)?);
+
+        if let Some(error) = &response.error {
+            std::process::exit(exit_code::for_rpc_error(error));
+        }
+
+        Ok(())
+    }
+
+    /// 登録数を示して確認する (マネージャーに届かなければ件数なしで聞く)
+    async fn confirm_shutdown(&self) -> Result<bool> {
+        let prompt = match self
+            .client
+            .send_request_direct("list_instances", json!({}))
+            .await
+        {
+            Ok(JsonRpcResponse {
+                result: Some(result),
+                ..
+            }) => {
+                let count = result.as_array().map_or(0, Vec::len);
+                format!("Shut down the manager? {count} registered instance(s) will no longer be tracked")
+            }
+            _ => "Shut down the manager?".to_string(),
+        };
+
+        confirm(&prompt)
+    }
+
+    async fn shutdown(&self, yes: bool) -> Result<()> {
+        if !yes && !self.confirm_shutdown().await? {
+            println!("Aborted");
+            return Ok(());
+        }
+
+        let response = self.client.send_request("shutdown", json!({})).await?;
+
+        if let Some(error) = response.error {
+            self.exit_rpc_error(&error);
+        }
+
+        println!("Manager shutdown requested");
+        Ok(())
+    }
+}
+
+/// `prompt` がマネージャーの応答を待つ上限
+const PROMPT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Neovim のアイコン (Nerd Fonts の nf-custom-vim)
+const PROMPT_ICON: &str = "\u{e62b}";
+
+#[derive(Debug, Clone, Copy)]
+enum PromptStyle {
+    /// アイコン付きの文字列 (tmux の status line など)
+    Plain,
+    /// アイコンなし (starship の `symbol` で付ける)
+    Starship,
+    /// zsh のプロンプトのエスケープで色を付ける
+    P10k,
+}
+
+impl PromptStyle {
+    /// `~/s/proj` のように縮めた identifier に、状態の印を付ける
+    /// (起動中は `…`、応答しない場合は `!`)
+    fn render(self, identifier: &str, health_status: &HealthStatus) -> String {
+        let name = identifier::abbreviate(identifier);
+        let (mark, color) = match health_status {
+            HealthStatus::Healthy => ("", "green"),
+            HealthStatus::Starting => ("…", "yellow"),
+            _ => ("!", "red"),
+        };
+
+        match self {
+            PromptStyle::Plain => format!("{PROMPT_ICON} {name}{mark}"),
+            PromptStyle::Starship => format!("{name}{mark}"),
+            PromptStyle::P10k => format!(
+                "%F{{{color}}}{PROMPT_ICON} {}{mark}%f",
+                name.replace('%', "%%")
+            ),
+        }
+    }
+}
+
+const COMPLETE_VAR: &str = "COMPLETE";
+const BIN_NAME: &str = "neovim-instance-manager-control";
+
+/// `--port` / `--address` / `--socket` (どれもなければ `NEOVIM_MANAGER_SOCKET`) を接続先に反映する
+fn apply_connection_args(
+    client: &mut ManagerClient,
+    config: &Config,
+    port: Option<u16>,
+    address: Option<String>,
+    socket: Option<PathBuf>,
+) -> Result<()> {
+    let socket = socket.or_else(|| {
+        (port.is_none() && address.is_none())
+            .then(|| std::env::var_os(SOCKET_ENV).filter(|socket| !socket.is_empty()))
+            .flatten()
+            .map(PathBuf::from)
+    });
+    if let Some(port) = port {
+        client.addr = format!("{}:{port}", config.manager.bind_address.value);
+        client.port = Some(port);
+    }
+    if let Some(address) = address {
+        client.addr = address;
+        client.autostart = false;
+    }
+    if let Some(socket) = socket {
+        if cfg!(not(unix)) {
+            return Err(anyhow!("--socket is only supported on Unix"));
+        }
+        client.addr = socket.display().to_string();
+        client.socket = Some(socket);
+    }
+    Ok(())
+}
+
+/// `--socket` を省略したときのソケット
+const SOCKET_ENV: &str = "NEOVIM_MANAGER_SOCKET";
+
+/// 補完中のコマンドラインから `--name <value>` / `--name=<value>` の値を取り出す
+fn completing_flag(args: &[String], name: &str) -> Option<String> {
+    args.iter().enumerate().find_map(|(index, arg)| {
+        let rest = arg.strip_prefix("--")?.strip_prefix(name)?;
+        match rest.strip_prefix('=') {
+            Some(value) => Some(value.to_string()),
+            None if rest.is_empty() => args.get(index + 1).cloned(),
+            None => None,
+        }
+    })
+}
+
+/// 補完中に呼ばれるため、マネージャーの自動起動は行わずブロッキングで一覧を取得する
+///
+/// 接続先は補完中のコマンドラインの `--port` / `--address` / `--socket` を実行時と同じく反映する
+fn fetch_identifiers_blocking() -> Result<Vec<InstanceResult>> {
+    let config = Config::load()?;
+    let args: Vec<String> = std::env::args().collect();
+    let mut client = ManagerClient::new(&config);
+    apply_connection_args(
+        &mut client,
+        &config,
+        completing_flag(&args, "port").and_then(|port| port.parse().ok()),
+        completing_flag(&args, "address"),
+        completing_flag(&args, "socket").map(PathBuf::from),
+    )?;
+
+    let timeout = Duration::from_millis(500);
+    #[cfg(unix)]
+    if let Some(socket) = &client.socket {
+        let stream = std::os::unix::net::UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(timeout))?;
+        return list_instances_over(stream);
+    }
+    // --address にはホスト名も書ける
+    let mut last_error = None;
+    for addr in client.addr.to_socket_addrs()? {
+        match std::net::TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                return list_instances_over(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => e.into(),
+        None => anyhow!("Cannot resolve {}", client.addr),
+    })
+}
+
+/// ヘルスチェックを待たずに済むよう `watch_instances` で今の一覧を受け取る (古いマネージャーには `list_instances`)
+fn list_instances_over(mut stream: impl Read + Write) -> Result<Vec<InstanceResult>> {
+    let params = WatchInstancesParams {
+        generation: None,
+        timeout_secs: None,
+    };
+    let response = request_over(
+        &mut stream,
+        "watch_instances",
+        serde_json::to_value(params)?,
+    )?;
+    if let Some(result) = response.result {
+        let update: InstancesUpdate = serde_json::from_value(result)?;
+        return Ok(update.instances);
+    }
+    if response.error.map(|error| error.code) != Some(errors::METHOD_NOT_FOUND) {
+        return Ok(Vec::new());
+    }
+
+    let response = request_over(&mut stream, "list_instances", json!({}))?;
+    Ok(serde_json::from_value(
+        response.result.unwrap_or_else(|| json!([])),
+    )?)
+}
+
+fn request_over(
+    mut stream: impl Read + Write,
+    method: &str,
+    params: Value,
+) -> Result<JsonRpcResponse> {
+    let request = JsonRpcRequest::new(method, params);
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+
+    let mut line = String::new();
+    std::io::BufReader::new(&mut stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(line.trim())?)
+}
+
+fn complete_identifiers() -> Vec<CompletionCandidate> {
+    fetch_identifiers_blocking()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|instance| {
+            CompletionCandidate::new(instance.identifier)
+                .help(Some(instance.server_address.to_string().into()))
+        })
+        .collect()
+}
+
+fn complete_workspaces() -> Vec<CompletionCandidate> {
+    Config::load()
+        .map(|config| config.workspaces.into_keys().collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// 設定ファイルのワークスペースとプロジェクトを表示する
+fn list_workspaces(config: &Config) {
+    if config.workspaces.is_empty() {
+        println!("No workspaces defined (add [workspace.<name>] with `projects = [...]` to the config file)");
+        return;
+    }
+    for (name, workspace) in &config.workspaces {
+        println!("{name}");
+        for project in &workspace.projects {
+            println!("  {}", project.display());
+        }
+    }
+}
+
+fn print_completions(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .ok_or_else(|| anyhow!("Unsupported shell: {shell}"))?;
+
+    let mut stdout = std::io::stdout();
+    completer.write_registration(COMPLETE_VAR, BIN_NAME, BIN_NAME, BIN_NAME, &mut stdout)?;
+
+    Ok(())
+}
+
+/// nvim の設定に入れる Lua (`init.lua`) に、このバイナリのパスとハートビートの間隔を埋め込む
+fn init_lua(heartbeat: Duration) -> Result<String> {
+    let control = std::env::current_exe()?;
+    let control = control.to_string_lossy();
+    Ok(include_str!("init.lua")
+        .replace(
+            "@CONTROL@",
+            &format!("'{}'", control.replace('\\', "\\\\").replace('\'', "\\'")),
+        )
+        .replace(
+            "@HEARTBEAT_MS@",
+            &heartbeat.as_millis().max(1000).to_string(),
+        ))
+}
+
+#[derive(Default)]
+struct DoctorReport {
+    has_errors: bool,
+}
+
+impl DoctorReport {
+    fn ok(&mut self, message: impl std::fmt::Display) {
+        println!("[ok]    {message}");
+    }
+
+    fn warn(&mut self, message: impl std::fmt::Display, hint: &str) {
+        println!("[warn]  {message}");
+        println!("        -> {hint}");
+    }
+
+    fn error(&mut self, message: impl std::fmt::Display, hint: &str) {
+        println!("[error] {message}");
+        println!("        -> {hint}");
+        self.has_errors = true;
+    }
+}
+
+fn check_writable_dir(dir: Option<&std::path::Path>) -> Result<()> {
+    let dir = dir.ok_or_else(|| anyhow!("no parent directory"))?;
+    std::fs::create_dir_all(dir)?;
+
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)?;
+
+    Ok(())
+}
+
+fn session_dir_or(dir: Option<&str>) -> Result<std::path::PathBuf> {
+    match dir {
+        Some(dir) => Ok(std::path::PathBuf::from(dir)),
+        None => config::session_dir().ok_or_else(|| anyhow!("Cannot determine cache directory")),
+    }
+}
+
+/// 確認プロンプトを表示する。端末から実行されていない場合は確認できないのでエラーにする (終了コード 1)
+fn confirm(prompt: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Refusing to continue without confirmation (use --yes)"
+        ));
+    }
+
+    eprint!("{prompt} [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// 中継に使うソケット (書き込み側だけ閉じられるもの)
+trait ProxySocket: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+    fn shutdown_write(&self);
+}
+
+impl ProxySocket for std::net::TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        std::net::TcpStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+#[cfg(unix)]
+impl ProxySocket for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+#[cfg(windows)]
+impl ProxySocket for std::fs::File {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        std::fs::File::try_clone(self)
+    }
+
+    // 名前付きパイプは片側だけ閉じられない
+    fn shutdown_write(&self) {}
+}
+
+/// `host:port` なら TCP、それ以外は Unix ソケット (Windows では名前付きパイプ) として接続する
+fn proxy_socket(address: &str) -> Result<()> {
+    if utils::is_tcp_address(address) {
+        return bridge_stdio(std::net::TcpStream::connect(address)?);
+    }
+
+    #[cfg(unix)]
+    return bridge_stdio(
+        std::os::unix::net::UnixStream::connect(address)
+            .map_err(|e| anyhow!("Cannot connect to {address}: {e}"))?,
+    );
+
+    #[cfg(windows)]
+    return bridge_stdio(
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(address)
+            .map_err(|e| anyhow!("Cannot connect to {address}: {e}"))?,
+    );
+}
+
+/// 標準入力をソケットへ、ソケットからの受信を標準出力へ流す。相手が閉じたら終わる
+fn bridge_stdio<S: ProxySocket>(socket: S) -> Result<()> {
+    let mut writer = socket.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin().lock(), &mut writer);
+        writer.shutdown_write();
+    });
+
+    // msgpack には改行がないので、受け取るたびに flush する
+    let mut reader = socket;
+    let mut stdout = std::io::stdout().lock();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(());
+        }
+        stdout.write_all(&buffer[..n])?;
+        stdout.flush()?;
+    }
+}
+
+/// 閉じたインスタンスの作業ディレクトリをランチャーで開き直す
+fn relaunch_tombstone(tombstone: &Tombstone) -> Result<()> {
+    let instance = &tombstone.instance;
+    let target = instance.cwd.as_deref().unwrap_or(&instance.identifier);
+    if !std::path::Path::new(target).is_dir() {
+        return Err(anyhow!(
+            "Cannot relaunch {}: {target} is not a directory",
+            instance.identifier
+        ));
+    }
+
+    let launcher = std::env::current_exe()?
+        .parent()
+        .ok_or_else(|| anyhow!("Cannot determine executable directory"))?
+        .join("neovim-launcher");
+    let code = process::status(&ProcessSpec::new(&launcher).arg(target))
+        .map_err(|e| anyhow!("Cannot run {}: {e:#}", launcher.display()))?;
+    if code != Some(0) {
+        std::process::exit(code.unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+fn confirm_kill_orphans(orphans: &[utils::NvimServerProcess]) -> Result<bool> {
+    for server in orphans {
+        println!("  pid {} ({})", server.pid, server.listen_address);
+    }
+    confirm(&format!(
+        "Kill {} untracked headless server(s)? Unsaved changes will be lost",
+        orphans.len()
+    ))
+}
+
+fn as_millis_f64(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Prometheus のテキスト形式で出力する
+fn render_metrics(
+    status: Option<&ManagerStatus>,
+    instances: &[InstanceResult],
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    use std::fmt::Write as _;
+
+    let label = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let mut output = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(output, "{name}{labels} {value}");
+        }
+    };
+
+    metric(
+        "neovim_manager_up",
+        "gauge",
+        "Whether the manager answered the status request.",
+        vec![(String::new(), u8::from(status.is_some()).to_string())],
+    );
+    let Some(status) = status else {
+        return output;
+    };
+
+    metric(
+        "neovim_manager_uptime_seconds",
+        "gauge",
+        "Seconds since the manager started.",
+        vec![(String::new(), status.uptime_secs.to_string())],
+    );
+    metric(
+        "neovim_manager_instances",
+        "gauge",
+        "Number of registered instances.",
+        vec![(String::new(), instances.len().to_string())],
+    );
+
+    metric(
+        "neovim_manager_instances_by_health",
+        "gauge",
+        "Number of registered instances per health status.",
+        ["Starting", "Healthy", "Unhealthy", "Dead"]
+            .into_iter()
+            .map(|name| {
+                let count = instances
+                    .iter()
+                    .filter(|instance| instance.health_status.name() == name)
+                    .count();
+                (format!("{{health=\"{name}\"}}"), count.to_string())
+            })
+            .collect(),
+    );
+
+    let stats = &status.health_checks;
+    for (name, help, value) in [
+        ("runs", "Health check passes run.", stats.runs),
+        ("checks", "Individual instance health checks.", stats.checks),
+        ("failures", "Failed instance health checks.", stats.failures),
+        (
+            "removed",
+            "Instances removed after failing a health check.",
+            stats.removed,
+        ),
+        (
+            "resumes",
+            "Suspend/resume or clock jumps that triggered a re-check.",
+            stats.resumes,
+        ),
+    ] {
+        metric(
+            &format!("neovim_manager_health_check_{name}_total"),
+            "counter",
+            help,
+            vec![(String::new(), value.to_string())],
+        );
+    }
+
+    let per_instance = |value: &dyn Fn(&InstanceResult) -> String| {
+        instances
+            .iter()
+            .map(|instance| {
+                (
+                    format!("{{identifier=\"{}\"}}", label(&instance.identifier)),
+                    value(instance),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "neovim_manager_instance_healthy",
+        "gauge",
+        "Whether the instance passed its last health check.",
+        per_instance(&|instance| u8::from(instance.health_status.is_healthy()).to_string()),
+    );
+    metric(
+        "neovim_manager_instance_age_seconds",
+        "gauge",
+        "Seconds since the instance was registered.",
+        per_instance(&|instance| {
+            (now - instance.registered_at)
+                .num_seconds()
+                .max(0)
+                .to_string()
+        }),
+    );
+    metric(
+        "neovim_manager_instance_idle_seconds",
+        "gauge",
+        "Seconds since the instance was last used.",
+        per_instance(&|instance| (now - instance.last_used).num_seconds().max(0).to_string()),
+    );
+
+    output
+}
+
+/// `label: n=.. min=.. p50=.. p90=.. p99=.. max=..` の形式で表示する
+fn print_latency_summary(label: &str, samples: &mut [Duration]) {
+    if samples.is_empty() {
+        return;
+    }
+    samples.sort();
+
+    // 最近傍順位法でのパーセンタイル
+    let percentile = |p: f64| {
+        let rank = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+        as_millis_f64(samples[rank.clamp(1, samples.len()) - 1])
+    };
+    println!(
+        "{label}: n={} min={:.2}ms p50={:.2}ms p90={:.2}ms p99={:.2}ms max={:.2}ms",
+        samples.len(),
+        as_millis_f64(samples[0]),
+        percentile(50.0),
+        percentile(90.0),
+        percentile(99.0),
+        as_millis_f64(samples[samples.len() - 1])
+    );
+}
+
+/// `[2024-01-01T00:00:00.000Z INFO ...]` 形式のログ行からタイムスタンプを取り出す
+fn log_line_timestamp(line: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    let timestamp = line.strip_prefix('[')?.split_whitespace().next()?;
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|t| t.with_timezone(&chrono::Utc))
+}
+
+async fn show_logs(
+    config: &Config,
+    follow: bool,
+    since: Option<Duration>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<()> {
+    use tokio::io::AsyncSeekExt;
+
+    let path = config
+        .manager
+        .log_file
+        .value
+        .clone()
+        .ok_or_else(|| anyhow!("Cannot determine manager log file"))?;
+    let cutoff = since
+        .map(chrono::Duration::from_std)
+        .transpose()?
+        .map(|since| now - since);
+
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| anyhow!("Cannot open manager log {}: {e}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    // 継続行 (タイムスタンプを持たない行) は直前のエントリの判定に従う
+    let mut show = cutoff.is_none();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            if !follow {
+                break;
+            }
+
+            // ログがローテートされて短くなった場合は先頭から読み直す
+            let position = reader.stream_position().await?;
+            if tokio::fs::metadata(&path).await?.len() < position {
+                reader.seek(std::io::SeekFrom::Start(0)).await?;
+            }
+            sleep(Duration::from_millis(500)).await;
+            continue;
+        }
+
+        if let Some(cutoff) = cutoff {
+            if let Some(timestamp) = log_line_timestamp(&line) {
+                show = timestamp >= cutoff;
+            }
+        }
+
+        if show {
+            print!("{line}");
+        }
+    }
+
+    Ok(())
+}
+
+/// `launcher.stats` で記録した使用状況を identifier ごとに表示する (マネージャーには問い合わせない)
+fn show_stats(
+    config: &Config,
+    since: Option<Duration>,
+    json: bool,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<()> {
+    let cutoff = since
+        .map(chrono::Duration::from_std)
+        .transpose()?
+        .map(|since| now - since);
+    let projects = stats::summarize(&stats::load(cutoff)?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&projects)?);
+        return Ok(());
+    }
+
+    if projects.is_empty() {
+        if config.launcher.stats.value {
+            println!("No usage statistics recorded yet");
+        } else {
+            println!(
+                "Usage statistics are disabled (set `stats = true` in [launcher] to record them)"
+            );
+        }
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 6]> = projects
+        .iter()
+        .map(|project| {
+            // 秒までは細かすぎるので、1 分以上は分に丸める
+            let secs = match project.total_duration_secs {
+                secs @ 0..=59 => secs,
+                secs => secs - secs % 60,
+            };
+            [
+                project.identifier.clone(),
+                project.launches.to_string(),
+                format!("{:.0}%", project.attach_ratio() * 100.0),
+                project.sessions.to_string(),
+                duration::format(Duration::from_secs(secs)),
+                project
+                    .last_used
+                    .map(|at| format!("{} ago", format_elapsed(at, now)))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "IDENTIFIER",
+            "LAUNCHES",
+            "ATTACH",
+            "SESSIONS",
+            "TIME",
+            "LAST",
+        ],
+        &rows,
+    );
+
+    Ok(())
+}
+
+fn print_config(config: &Config, show_origin: bool) {
+    match &config.file {
+        Some(path) => println!("# config file: {}", path.display()),
+        None => println!("# config file: (none)"),
+    }
+    let display = |dir: Option<PathBuf>| {
+        dir.map_or_else(|| "(unknown)".to_string(), |dir| dir.display().to_string())
+    };
+    println!("# config dir: {}", display(config::config_dir()));
+    println!("# state dir: {}", display(config::state_dir()));
+    println!("# runtime dir: {}", config::runtime_dir().display());
+
+    let mut current_section = "";
+    for (section, key, value, origin) in config.entries() {
+        if section != current_section {
+            println!();
+            println!("[{section}]");
+            current_section = section;
+        }
+
+        if show_origin {
+            println!("{key} = {value}  # {origin}");
+        } else {
+            println!("{key} = {value}");
+        }
+    }
+
+    for (name, workspace) in &config.workspaces {
+        println!();
+        println!("[workspace.{name}]");
+        let projects: Vec<_> = workspace
+            .projects
+            .iter()
+            .map(|project| project.display().to_string())
+            .collect();
+        if show_origin {
+            println!("projects = {projects:?}  # {}", workspace.origin);
+        } else {
+            println!("projects = {projects:?}");
+        }
+    }
+}
+
+fn instance_field(
+    instance: &InstanceResult,
+    field: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<String> {
+    let value = match field {
+        "identifier" => instance.identifier.clone(),
+        "address" | "server_address" => instance.server_address.to_string(),
+        "health" | "health_status" => instance.health_status.to_string(),
+        "age" => format_elapsed(instance.registered_at, now),
+        "registered_at" => instance.registered_at.to_rfc3339(),
+        "last_used" => instance.last_used.to_rfc3339(),
+        "last_health_check" => instance.last_health_check.to_rfc3339(),
+        "pinned" => instance.pinned.to_string(),
+        "tags" => instance.tags.join(","),
+        "cwd" => instance.cwd.clone().unwrap_or_default(),
+        "pid" => instance.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+        "appname" => instance.appname.clone().unwrap_or_default(),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// `{field}` をインスタンスの値に置き換える。シェルの単一引用符内でも使えるよう `\t` `\n` も展開する
+fn render_template(
+    template: &str,
+    instance: &InstanceResult,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<String> {
+    let mut output = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let field: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let value = instance_field(instance, field.trim(), now)
+                    .ok_or_else(|| anyhow!("Unknown field in --format: {{{field}}}"))?;
+                output.push_str(&value);
+            }
+            '\\' => match chars.next() {
+                Some('t') => output.push('\t'),
+                Some('n') => output.push('\n'),
+                Some(other) => {
+                    output.push('\\');
+                    output.push(other);
+                }
+                None => output.push('\\'),
+            },
+            _ => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+/// シェルの単語として安全に使えるようクォートする
+fn shell_quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// `{field}` をクォート済みの値に置き換える。`${HOME}` のように既知のフィールドでないものはそのまま残す
+fn render_shell_template(
+    template: &str,
+    instance: &InstanceResult,
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| {
+            instance_field(instance, after[..end].trim(), now).map(|value| (end, value))
+        }) {
+            Some((end, value)) => {
+                output.push_str(&shell_quote(&value));
+                rest = &after[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+
+    output
+}
+
+fn format_elapsed(
+    since: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let secs = (now - since).num_seconds().max(0);
+
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// `prompt` と同じく、起動中は黄色、応答しない (`Unhealthy (n)` / `Dead`) ものは赤
+fn health_color(health: &str) -> &'static str {
+    match health {
+        "Healthy" => "\x1b[32m",
+        "Starting" => "\x1b[33m",
+        _ => "\x1b[31m",
+    }
+}
+
+/// バッファ名をファイル名だけにして並べる (変更ありは末尾に `+`)
+fn format_buffer_names(buffers: &[utils::NvimBuffer]) -> String {
+    buffers
+        .iter()
+        .map(|buffer| {
+            let name = std::path::Path::new(&buffer.name).file_name().map_or_else(
+                || buffer.name.clone(),
+                |name| name.to_string_lossy().to_string(),
+            );
+            if buffer.modified {
+                format!("{name}+")
+            } else {
+                name
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `buffers` を渡すと各インスタンスのバッファ一覧を BUFFERS 列として追加する
+fn print_instance_table(
+    instances: &[InstanceResult],
+    buffers: Option<&[String]>,
+    now: chrono::DateTime<chrono::Utc>,
+) {
+    const HEALTH_COLUMN: usize = 2;
+
+    let use_color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    // 先頭列はピン留めの印 (ピン留めがなければ列ごと省く)
+    let show_pin = instances.iter().any(|instance| instance.pinned);
+    let health_column = if show_pin {
+        HEALTH_COLUMN
+    } else {
+        HEALTH_COLUMN - 1
+    };
+    let mut headers = vec!["", "IDENTIFIER", "HEALTH", "ADDRESS", "AGE", "LAST USED"];
+    if !show_pin {
+        headers.remove(0);
+    }
+    if buffers.is_some() {
+        headers.push("BUFFERS");
+    }
+    let rows: Vec<Vec<String>> = instances
+        .iter()
+        .enumerate()
+        .map(|(index, instance)| {
+            let mut row = vec![
+                if instance.pinned { "*" } else { "" }.to_string(),
+                instance.identifier.clone(),
+                instance.health_status.to_string(),
+                instance.server_address.to_string(),
+                format_elapsed(instance.registered_at, now),
+                format!("{} ago", format_elapsed(instance.last_used, now)),
+            ];
+            if let Some(buffers) = buffers {
+                row.push(buffers.get(index).cloned().unwrap_or_default());
+            }
+            if !show_pin {
+                row.remove(0);
+            }
+            row
+        })
+        .collect();
+
+    print_colored_table(&headers, &rows, |column, cell| {
+        (use_color && column == health_column).then(|| health_color(cell))
+    });
+}
+
+/// 列の幅をそろえて表を出す (列の間は空白 2 つ)
+fn print_table(headers: &[&str], rows: &[impl AsRef<[String]>]) {
+    print_colored_table(headers, rows, |_, _| None);
+}
+
+/// `color` が返したエスケープシーケンスでセル (見出しを除く) に色を付ける
+fn print_colored_table(
+    headers: &[&str],
+    rows: &[impl AsRef<[String]>],
+    color: impl Fn(usize, &str) -> Option<&'static str>,
+) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.as_ref()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &[&str], is_header: bool| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                // 色付けのエスケープシーケンスは幅に含めないよう、パディング後に付与する
+                let padded = format!("{cell:<width$}");
+                match color(column, cell).filter(|_| !is_header) {
+                    Some(escape) => format!("{escape}{padded}\x1b[0m"),
+                    None => padded,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(headers, true);
+    for row in rows {
+        print_row(
+            &row.as_ref().iter().map(String::as_str).collect::<Vec<_>>(),
+            false,
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // ライブラリの警告 (nvim の起動のやり直しなど) は RUST_LOG で表示する
+    env_logger::init();
+
+    // COMPLETE=<shell> で呼ばれた場合は補完候補を出力して終了する
+    CompleteEnv::with_factory(Cli::command)
+        .var(COMPLETE_VAR)
+        .complete();
+
+    let matches = Cli::command().get_matches();
+    let command = matches.subcommand_name().unwrap_or_default().to_string();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let json_errors = cli.json_errors;
+
+    if let Err(e) = run(cli).await {
+        let code = match e.downcast_ref::<ManagerError>() {
+            Some(ManagerError::Unreachable(_)) => exit_code::UNREACHABLE,
+            // マネージャーが返したエラーはそのまま表示し、エラーコードに応じて終了する
+            Some(error) => {
+                let error = JsonRpcError::from(error.clone());
+                print_error(&error, json_errors);
+                std::process::exit(exit_code::for_rpc_error(&error));
+            }
+            None => exit_code::FAILURE,
+        };
+
+        if json_errors {
+            // クライアント側の失敗は INTERNAL_ERROR として終了コードを添える
+            print_error(
+                &JsonRpcError {
+                    code: errors::INTERNAL_ERROR,
+                    message: format!("{e:#}"),
+                    data: Some(json!({"exit_code": code})),
+                },
+                true,
+            );
+        } else {
+            report::eprint(&e.context(format!("`{command}` failed")));
+        }
+        std::process::exit(code);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let config = Config::load()?;
+    let mut control = Control::new(&config);
+    if let Some(timeout) = cli.timeout {
+        control.client.timeout = timeout;
+    }
+    if let Some(retries) = cli.retries {
+        control.client.retries = retries;
+    }
+    control.json_errors = cli.json_errors;
+    apply_connection_args(
+        &mut control.client,
+        &config,
+        cli.port,
+        cli.address,
+        cli.socket,
+    )?;
+
+    match cli.command {
+        Commands::Query { identifier, output } => {
+            control.query_instance(&identifier, output).await?;
+        }
+        Commands::List {
+            output,
+            filter,
+            buffers,
+        } => {
+            control.list_instances(output, filter, buffers).await?;
+        }
+        Commands::Buffers { identifier, json } => {
+            control.buffers(&identifier, json).await?;
+        }
+        Commands::Env {
+            identifier,
+            names,
+            all,
+            json,
+        } => {
+            control.env(&identifier, &names, all, json).await?;
+        }
+        Commands::Adopt {
+            server_address,
+            identifier,
+        } => {
+            control
+                .adopt_instance(&server_address, identifier.as_deref())
+                .await?;
+        }
+        Commands::Register {
+            identifier,
+            server_address,
+            cwd,
+            pid,
+        } => {
+            control
+                .register_instance(RegisterInstanceParams {
+                    identifier,
+                    server_address,
+                    cwd,
+                    pid,
+                    tags: Vec::new(),
+                    appname: None,
+                    pinned: false,
+                })
+                .await?;
+        }
+        Commands::Unregister { identifier } => {
+            control.unregister_instance(&identifier).await?;
+        }
+        Commands::Rename {
+            identifier,
+            new_identifier,
+            follow_path,
+        } => {
+            control
+                .rename_instance(&identifier, &new_identifier, follow_path)
+                .await?;
+        }
+        Commands::Health { identifier } => {
+            control.check_instance(&identifier).await?;
+        }
+        Commands::Cd {
+            identifier,
+            dir,
+            tab: _,
+            record_only: true,
+        } => {
+            control.record_directory(&identifier, &dir).await?;
+        }
+        Commands::Cd {
+            identifier,
+            dir,
+            tab,
+            record_only: false,
+        } => {
+            control.change_directory(&identifier, &dir, tab).await?;
+        }
+        Commands::Kill { identifier, yes } => {
+            control.kill_instance(&identifier, yes).await?;
+        }
+        Commands::Pin { identifier } => {
+            control.pin_instance(&identifier, true).await?;
+        }
+        Commands::Unpin { identifier } => {
+            control.pin_instance(&identifier, false).await?;
+        }
+        Commands::Tag { identifier, tag } => {
+            control.tag_instance(&identifier, &tag, true).await?;
+        }
+        Commands::Untag { identifier, tag } => {
+            control.tag_instance(&identifier, &tag, false).await?;
+        }
+        Commands::Attach { identifier, tmux } => {
+            control.attach(&identifier, tmux).await?;
+        }
+        Commands::Proxy {
+            identifier,
+            manager,
+        } => {
+            control.proxy(identifier.as_deref(), manager).await?;
+        }
+        Commands::Touch { identifier } => {
+            control.touch_instance(&identifier).await?;
+        }
+        Commands::Watch { interval, jsonl } => {
+            control.watch_instances(interval, jsonl).await?;
+        }
+        Commands::Prune { kill_orphans, yes } => {
+            control.prune_instances(kill_orphans, yes).await?;
+        }
+        Commands::Gc {
+            kill_orphans,
+            dry_run,
+            yes,
+        } => {
+            control.gc(kill_orphans, dry_run, yes).await?;
+        }
+        Commands::Wait {
+            identifier,
+            gone,
+            healthy,
+            timeout,
+        } => {
+            control
+                .wait_instance(&identifier, gone, healthy, timeout)
+                .await?;
+        }
+        Commands::Recent { json, relaunch } => {
+            control.recent(json, relaunch).await?;
+        }
+        Commands::Stats { since, json } => {
+            show_stats(&config, since, json, control.clock.now())?;
+        }
+        Commands::Export => {
+            control.export_registry().await?;
+        }
+        Commands::Snapshot { dir } => {
+            control.snapshot(dir.as_deref()).await?;
+        }
+        Commands::Restore { dir, headless } => {
+            control.restore(&config, dir.as_deref(), headless).await?;
+        }
+        Commands::Import { file } => {
+            control.import_registry(&file).await?;
+        }
+        Commands::QuitAll {
+            group,
+            tag,
+            force,
+            yes,
+        } => {
+            control
+                .quit_all(group.as_deref(), tag.as_deref(), force, yes)
+                .await?;
+        }
+        Commands::UnregisterAll { yes } => {
+            control.unregister_all(yes).await?;
+        }
+        Commands::Manager { command } => {
+            control.manager_command(command).await?;
+        }
+        Commands::Workspace { command } => match command {
+            WorkspaceCommands::List => list_workspaces(&config),
+            WorkspaceCommands::Status { name, json } => {
+                control.workspace_status(&config, &name, json).await?;
+            }
+        },
+        Commands::Doctor => {
+            control.doctor(&config).await?;
+        }
+        Commands::Drift { json } => {
+            control.drift(json).await?;
+        }
+        Commands::Foreach {
+            command,
+            parallel,
+            tag,
+        } => {
+            control.foreach(&command, parallel, tag.as_deref()).await?;
+        }
+        Commands::Resolve { path, explain } => {
+            control.resolve(&path, explain).await?;
+        }
+        Commands::Select { menu, print } => {
+            control.select(menu.as_deref(), print).await?;
+        }
+        Commands::Tui => {
+            tui::run(&control.client, Arc::clone(&control.clock)).await?;
+        }
+        Commands::Top { sort } => {
+            top::run(&control.client, top::SortKey::parse(&sort)).await?;
+        }
+        Commands::Status => {
+            control.status().await?;
+        }
+        Commands::Version { json } => {
+            control.version(json).await?;
+        }
+        Commands::Metrics { textfile } => {
+            control.metrics(textfile.as_deref()).await?;
+        }
+        Commands::Bench {
+            iterations,
+            open,
+            instance,
+        } => {
+            control
+                .bench(iterations, open.as_deref(), instance.as_deref())
+                .await?;
+        }
+        Commands::Ping { count, instances } => {
+            control.ping(count, instances).await?;
+        }
+        Commands::Config { show_origin } => {
+            print_config(&config, show_origin);
+        }
+        Commands::Logs { follow, since } => {
+            show_logs(&config, follow, since, control.clock.now()).await?;
+        }
+        Commands::Shutdown { yes } => {
+            control.shutdown(yes).await?;
+        }
+        Commands::InstallService { print } => {
+            service::install(&config, print)?;
+        }
+        Commands::UninstallService => {
+            service::uninstall()?;
+        }
+        Commands::Raw { method, params } => {
+            control.raw(&method, params.as_deref()).await?;
+        }
+        Commands::Completions { shell } => {
+            print_completions(&shell)?;
+        }
+        Commands::Prompt { starship, p10k } => {
+            let style = if starship {
+                PromptStyle::Starship
+            } else if p10k {
+                PromptStyle::P10k
+            } else {
+                PromptStyle::Plain
+            };
+            control.prompt(style).await;
         }
-        Commands::Shutdown => {
-            client.shutdown().await?;
+        Commands::InitLua { heartbeat } => {
+            print!("{}", init_lua(heartbeat)?);
         }
     }
 
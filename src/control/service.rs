@@ -0,0 +1,348 @@
+//! ログイン時にマネージャーを起動するサービスの登録 (`install-service` / `uninstall-service`)
+//!
+//! 最初に接続したクライアントの環境を引き継いで起動されないよう、OS のサービス管理に任せる。
+//! Linux は systemd のユーザーユニット (ソケットアクティベーション)、macOS は launchd の LaunchAgent、
+//! Windows はタスク スケジューラのログオン時のタスクにする。
+
+use anyhow::{anyhow, Result};
+use neovim_manager::client::ManagerClient;
+use neovim_manager::config::{self, Config};
+use neovim_manager::process::{self, ProcessSpec};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// systemd のユニット名 (`.socket` と `.service`)
+const UNIT_NAME: &str = "neovim-instance-manager";
+/// launchd のラベル (plist のファイル名にもする)
+const LAUNCHD_LABEL: &str = "io.github.statiolake.neovim-instance-manager";
+/// タスク スケジューラのタスク名
+const TASK_NAME: &str = "Neovim Instance Manager";
+
+/// systemctl などを待つ上限
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// 既にマネージャーが動いているか確かめるときの接続の上限
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// サービスに書き込む環境変数 (PATH と設定の `NEOVIM_MANAGER_*`)
+///
+/// サービスはログインセッションの環境で起動されるので、フックなどが同じコマンドを見つけられるよう今の値を残す
+fn service_env() -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| name == "PATH" || name.starts_with("NEOVIM_MANAGER_"))
+        .collect();
+    env.sort();
+    env
+}
+
+fn manager_path() -> Result<PathBuf> {
+    let path = ManagerClient::manager_path()?;
+    let path = if cfg!(windows) {
+        path.with_extension("exe")
+    } else {
+        path
+    };
+    if !path.is_file() {
+        return Err(anyhow!("Cannot find the manager at {}", path.display()));
+    }
+    Ok(path)
+}
+
+/// 設定のアドレスで既にマネージャー (など) が待ち受けているか
+fn is_listening(address: &str) -> bool {
+    address
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .is_some_and(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok())
+}
+
+pub fn install(config: &Config, print: bool) -> Result<()> {
+    let manager = manager_path()?;
+    let env = service_env();
+    if cfg!(windows) {
+        install_task(config, &manager, print)
+    } else if cfg!(target_os = "macos") {
+        install_launchd(config, &manager, &env, print)
+    } else {
+        install_systemd(config, &manager, &env, print)
+    }
+}
+
+pub fn uninstall() -> Result<()> {
+    if cfg!(windows) {
+        run("schtasks", &["/Delete", "/TN", TASK_NAME, "/F"])?;
+        println!("Removed the scheduled task \"{TASK_NAME}\"");
+        return Ok(());
+    }
+
+    let files = if cfg!(target_os = "macos") {
+        let plist = launch_agents_dir()?.join(format!("{LAUNCHD_LABEL}.plist"));
+        if plist.is_file() {
+            // 動いているマネージャーも止まる
+            if let Err(e) = run("launchctl", &["unload", "-w", &plist.to_string_lossy()]) {
+                eprintln!("Skipped unloading the agent: {e:#}");
+            }
+        }
+        vec![plist]
+    } else {
+        let dir = systemd_user_dir()?;
+        let socket = format!("{UNIT_NAME}.socket");
+        // 動いているマネージャーは止めない (登録が消えるので `control shutdown` に任せる)
+        if let Err(e) = run("systemctl", &["--user", "disable", "--now", &socket]) {
+            eprintln!("Skipped disabling {socket}: {e:#}");
+        }
+        vec![dir.join(&socket), dir.join(format!("{UNIT_NAME}.service"))]
+    };
+    for file in files.iter().filter(|file| file.is_file()) {
+        std::fs::remove_file(file).map_err(|e| anyhow!("Cannot remove {}: {e}", file.display()))?;
+        println!("Removed {}", file.display());
+    }
+    if cfg!(not(target_os = "macos")) {
+        run("systemctl", &["--user", "daemon-reload"])?;
+    }
+    Ok(())
+}
+
+/// `~/.config/systemd/user`
+fn systemd_user_dir() -> Result<PathBuf> {
+    Ok(config::config_home()
+        .ok_or_else(|| anyhow!("Cannot determine the config directory (set XDG_CONFIG_HOME)"))?
+        .join("systemd/user"))
+}
+
+/// `~/Library/LaunchAgents`
+fn launch_agents_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+    Ok(PathBuf::from(home).join("Library/LaunchAgents"))
+}
+
+/// ソケットは設定のアドレスで systemd が待ち受け、最初の接続でマネージャーを起動する
+fn install_systemd(
+    config: &Config,
+    manager: &Path,
+    env: &[(String, String)],
+    print: bool,
+) -> Result<()> {
+    let address = config.manager.address();
+    let socket = format!("{UNIT_NAME}.socket");
+    let units = [
+        (socket.clone(), socket_unit(&address)),
+        (format!("{UNIT_NAME}.service"), service_unit(manager, env)),
+    ];
+    if print {
+        for (name, content) in &units {
+            println!("# {name}\n{content}");
+        }
+        return Ok(());
+    }
+
+    let dir = systemd_user_dir()?;
+    write_files(&dir, &units)?;
+    run("systemctl", &["--user", "daemon-reload"])?;
+
+    // 自動起動されたマネージャーが動いていると、ソケットを待ち受けられない
+    if is_listening(&address) {
+        run("systemctl", &["--user", "enable", &socket])?;
+        println!("Enabled {socket}; it starts listening at the next login");
+        println!(
+            "A manager is already running at {address}. To switch now, run `{} shutdown` \
+             and then `systemctl --user start {socket}`",
+            super::BIN_NAME
+        );
+    } else {
+        run("systemctl", &["--user", "enable", "--now", &socket])?;
+        println!("Enabled and started {socket}, listening at {address}");
+    }
+    Ok(())
+}
+
+fn socket_unit(address: &str) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Neovim Instance Manager socket\n\
+         \n\
+         [Socket]\n\
+         ListenStream={address}\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n"
+    )
+}
+
+fn service_unit(manager: &Path, env: &[(String, String)]) -> String {
+    let environment: String = env
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "Environment={}\n",
+                systemd_quote(&format!("{name}={value}"))
+            )
+        })
+        .collect();
+    format!(
+        "[Unit]\n\
+         Description=Neovim Instance Manager\n\
+         Requires={UNIT_NAME}.socket\n\
+         After={UNIT_NAME}.socket\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         {environment}\
+         Restart=on-failure\n",
+        systemd_quote(&manager.to_string_lossy())
+    )
+}
+
+/// systemd のユニットファイルの値として引用する (`%` は指定子になるので重ねる)
+fn systemd_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{escaped}\"")
+}
+
+/// ログイン時 (と登録した時点) にマネージャーを起動し、異常終了したら起動し直す
+///
+/// `control shutdown` での終了 (終了コード 0) では起動し直さない
+fn install_launchd(
+    config: &Config,
+    manager: &Path,
+    env: &[(String, String)],
+    print: bool,
+) -> Result<()> {
+    let content = launchd_plist(manager, env);
+    if print {
+        println!("{content}");
+        return Ok(());
+    }
+
+    let dir = launch_agents_dir()?;
+    let name = format!("{LAUNCHD_LABEL}.plist");
+    write_files(&dir, &[(name.clone(), content)])?;
+
+    let address = config.manager.address();
+    if is_listening(&address) {
+        println!("The agent starts the manager at the next login");
+        println!(
+            "A manager is already running at {address}. To switch now, run `{} shutdown` \
+             and then `launchctl load -w {}`",
+            super::BIN_NAME,
+            dir.join(&name).display()
+        );
+    } else {
+        run(
+            "launchctl",
+            &["load", "-w", &dir.join(&name).to_string_lossy()],
+        )?;
+        println!("Loaded {LAUNCHD_LABEL}");
+    }
+    Ok(())
+}
+
+fn launchd_plist(manager: &Path, env: &[(String, String)]) -> String {
+    let environment: String = env
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "        <key>{}</key>\n        <string>{}</string>\n",
+                xml_escape(name),
+                xml_escape(value)
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n    \
+             <key>Label</key>\n    \
+             <string>{LAUNCHD_LABEL}</string>\n    \
+             <key>ProgramArguments</key>\n    \
+             <array>\n        \
+                 <string>{}</string>\n    \
+             </array>\n    \
+             <key>EnvironmentVariables</key>\n    \
+             <dict>\n\
+         {environment}    \
+             </dict>\n    \
+             <key>RunAtLoad</key>\n    \
+             <true/>\n    \
+             <key>KeepAlive</key>\n    \
+             <dict>\n        \
+                 <key>SuccessfulExit</key>\n        \
+                 <false/>\n    \
+             </dict>\n    \
+             <key>ProcessType</key>\n    \
+             <string>Background</string>\n\
+         </dict>\n\
+         </plist>",
+        xml_escape(&manager.to_string_lossy())
+    )
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// ログオン時に起動するタスクを作る (既にあれば置き換える)。環境はログオンしたユーザーのものになる
+fn install_task(config: &Config, manager: &Path, print: bool) -> Result<()> {
+    let command = format!("\"{}\"", manager.display());
+    let args = [
+        "/Create", "/TN", TASK_NAME, "/TR", &command, "/SC", "ONLOGON", "/RL", "LIMITED", "/F",
+    ];
+    if print {
+        let args: Vec<String> = args.iter().map(|arg| quote_arg(arg)).collect();
+        println!("schtasks {}", args.join(" "));
+        return Ok(());
+    }
+
+    run("schtasks", &args)?;
+    println!("Created the scheduled task \"{TASK_NAME}\" (runs at logon)");
+    if !is_listening(&config.manager.address()) {
+        run("schtasks", &["/Run", "/TN", TASK_NAME])?;
+        println!("Started the manager");
+    }
+    Ok(())
+}
+
+/// 表示用に、空白や引用符を含む引数を `"` で囲む
+fn quote_arg(arg: &str) -> String {
+    if arg.contains([' ', '"']) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+fn write_files(dir: &Path, files: &[(String, String)]) -> Result<()> {
+    std::fs::create_dir_all(dir).map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    for (name, content) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, content)
+            .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = process::output(
+        &ProcessSpec::new(program)
+            .args(args.iter().copied())
+            .timeout(COMMAND_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "{program} {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,348 @@
+use anyhow::Result;
+use neovim_manager::utils::{self, ProcessTreeStats};
+use neovim_manager::InstanceResult;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::tui::{focus, quit};
+use neovim_manager::client::ManagerClient;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Cpu,
+    Memory,
+    Uptime,
+    Identifier,
+}
+
+impl SortKey {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "mem" => SortKey::Memory,
+            "uptime" => SortKey::Uptime,
+            "identifier" => SortKey::Identifier,
+            _ => SortKey::Cpu,
+        }
+    }
+}
+
+struct Entry {
+    instance: InstanceResult,
+    stats: Option<ProcessTreeStats>,
+    /// 前回の取得からの CPU 使用率 (初回は不明)
+    cpu_percent: Option<f64>,
+}
+
+struct App {
+    entries: Vec<Entry>,
+    table_state: TableState,
+    sort: SortKey,
+    confirm_quit: bool,
+    message: String,
+    last_refresh: Option<Instant>,
+    /// PID ごとの前回の累積 CPU 時間と取得時刻
+    previous: HashMap<u32, (f64, Instant)>,
+}
+
+impl App {
+    fn selected(&self) -> Option<&Entry> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.entries.get(index))
+    }
+
+    fn select_next(&mut self) {
+        if !self.entries.is_empty() {
+            let next = self
+                .table_state
+                .selected()
+                .map_or(0, |index| (index + 1).min(self.entries.len() - 1));
+            self.table_state.select(Some(next));
+        }
+    }
+
+    fn select_previous(&mut self) {
+        if !self.entries.is_empty() {
+            let previous = self
+                .table_state
+                .selected()
+                .map_or(0, |index| index.saturating_sub(1));
+            self.table_state.select(Some(previous));
+        }
+    }
+
+    fn sort_entries(&mut self) {
+        let selected = self
+            .selected()
+            .map(|entry| entry.instance.identifier.clone());
+
+        match self.sort {
+            SortKey::Cpu => self.entries.sort_by(|a, b| {
+                let cpu = |entry: &Entry| entry.cpu_percent.unwrap_or(-1.0);
+                cpu(b).total_cmp(&cpu(a))
+            }),
+            SortKey::Memory => self.entries.sort_by_key(|entry| {
+                std::cmp::Reverse(entry.stats.as_ref().map_or(0, |stats| stats.rss_kib))
+            }),
+            SortKey::Uptime => self.entries.sort_by_key(|entry| {
+                std::cmp::Reverse(entry.stats.as_ref().map_or(0, |stats| stats.uptime_secs))
+            }),
+            SortKey::Identifier => self
+                .entries
+                .sort_by(|a, b| a.instance.identifier.cmp(&b.instance.identifier)),
+        }
+
+        // 並べ替え後も同じインスタンスを選択し続ける
+        let index = selected
+            .and_then(|identifier| {
+                self.entries
+                    .iter()
+                    .position(|entry| entry.instance.identifier == identifier)
+            })
+            .or_else(|| (!self.entries.is_empty()).then_some(0));
+        self.table_state.select(index);
+    }
+}
+
+pub async fn run(client: &ManagerClient, sort: SortKey) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, client, sort).await;
+    ratatui::restore();
+    result
+}
+
+async fn refresh(app: &mut App, client: &ManagerClient) -> Result<()> {
+    let instances = client.list(None).await?;
+    let pids: Vec<u32> = instances
+        .iter()
+        .filter_map(|instance| instance.pid)
+        .collect();
+    let mut stats = utils::process_tree_stats(&pids)?;
+    let now = Instant::now();
+
+    let mut previous = HashMap::new();
+    app.entries = instances
+        .into_iter()
+        .map(|instance| {
+            let stats = instance.pid.and_then(|pid| stats.remove(&pid));
+            let cpu_percent = match (instance.pid, &stats) {
+                (Some(pid), Some(stats)) => {
+                    let cpu_percent = app.previous.get(&pid).map(|(cpu_secs, at)| {
+                        let elapsed = now.duration_since(*at).as_secs_f64();
+                        ((stats.cpu_secs - cpu_secs) / elapsed * 100.0).max(0.0)
+                    });
+                    previous.insert(pid, (stats.cpu_secs, now));
+                    cpu_percent
+                }
+                _ => None,
+            };
+
+            Entry {
+                instance,
+                stats,
+                cpu_percent,
+            }
+        })
+        .collect();
+    app.previous = previous;
+    app.sort_entries();
+    app.last_refresh = Some(now);
+
+    Ok(())
+}
+
+async fn run_app(
+    terminal: &mut DefaultTerminal,
+    client: &ManagerClient,
+    sort: SortKey,
+) -> Result<()> {
+    let mut app = App {
+        entries: Vec::new(),
+        table_state: TableState::default(),
+        sort,
+        confirm_quit: false,
+        message: String::new(),
+        last_refresh: None,
+        previous: HashMap::new(),
+    };
+
+    loop {
+        if app
+            .last_refresh
+            .is_none_or(|last| last.elapsed() >= REFRESH_INTERVAL)
+        {
+            if let Err(e) = refresh(&mut app, client).await {
+                app.message = format!("Refresh failed: {e}");
+                app.last_refresh = Some(Instant::now());
+            }
+        }
+
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if app.confirm_quit {
+            app.confirm_quit = false;
+            if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                if let Some(entry) = app.selected() {
+                    let instance = entry.instance.clone();
+                    app.message = match quit(client, &instance).await {
+                        Ok(()) => format!("Quit {}", instance.identifier),
+                        Err(e) => format!("Quit failed: {e}"),
+                    };
+                    app.last_refresh = None;
+                }
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => app.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => app.select_previous(),
+            KeyCode::Char('c') => {
+                app.sort = SortKey::Cpu;
+                app.sort_entries();
+            }
+            KeyCode::Char('m') => {
+                app.sort = SortKey::Memory;
+                app.sort_entries();
+            }
+            KeyCode::Char('u') => {
+                app.sort = SortKey::Uptime;
+                app.sort_entries();
+            }
+            KeyCode::Char('n') => {
+                app.sort = SortKey::Identifier;
+                app.sort_entries();
+            }
+            KeyCode::Char('f') | KeyCode::Enter => {
+                if let Some(entry) = app.selected() {
+                    let instance = entry.instance.clone();
+                    app.message = match focus(client, &instance).await {
+                        Ok(()) => format!("Focused {}", instance.identifier),
+                        Err(e) => format!("Focus failed: {e}"),
+                    };
+                }
+            }
+            KeyCode::Char('x') if app.selected().is_some() => app.confirm_quit = true,
+            _ => {}
+        }
+    }
+}
+
+fn format_memory(kib: u64) -> String {
+    match kib {
+        0..=1023 => format!("{kib}K"),
+        1024..=1048575 => format!("{:.1}M", kib as f64 / 1024.0),
+        _ => format!("{:.1}G", kib as f64 / 1048576.0),
+    }
+}
+
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..=3599 => format!("{}:{:02}", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [table_area, status_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+
+    // 並べ替えに使っている列の見出しに印を付ける
+    let mark = |key: SortKey, title: &'static str| {
+        if app.sort == key {
+            format!("{title}▼")
+        } else {
+            title.to_string()
+        }
+    };
+    let header = Row::new(vec![
+        mark(SortKey::Identifier, "IDENTIFIER"),
+        "PID".to_string(),
+        mark(SortKey::Cpu, "CPU%"),
+        mark(SortKey::Memory, "MEM"),
+        "PROCS".to_string(),
+        mark(SortKey::Uptime, "UPTIME"),
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows = app.entries.iter().map(|entry| {
+        let pid = entry
+            .instance
+            .pid
+            .map_or_else(|| "-".to_string(), |pid| pid.to_string());
+        match &entry.stats {
+            Some(stats) => Row::new(vec![
+                entry.instance.identifier.clone(),
+                pid,
+                entry
+                    .cpu_percent
+                    .map_or_else(|| "-".to_string(), |cpu| format!("{cpu:.1}")),
+                format_memory(stats.rss_kib),
+                stats.processes.to_string(),
+                format_uptime(stats.uptime_secs),
+            ]),
+            // PID が記録されていない・プロセスが見つからないもの
+            None => Row::new(vec![
+                entry.instance.identifier.clone(),
+                pid,
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+            ])
+            .style(Style::default().add_modifier(Modifier::DIM)),
+        }
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(7),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(8),
+        ],
+    )
+    .header(header)
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered().title(format!(" Neovim processes ({}) ", app.entries.len())));
+    frame.render_stateful_widget(table, table_area, &mut app.table_state);
+
+    let status = if app.confirm_quit {
+        format!(
+            "Quit {}? [y/N]",
+            app.selected()
+                .map(|entry| entry.instance.identifier.as_str())
+                .unwrap_or("")
+        )
+    } else {
+        let keys = "sort: c cpu  m mem  u uptime  n name  |  enter/f focus  x quit  q exit";
+        if app.message.is_empty() {
+            keys.to_string()
+        } else {
+            format!("{}  |  {keys}", app.message)
+        }
+    };
+    frame.render_widget(Paragraph::new(status).block(Block::bordered()), status_area);
+}
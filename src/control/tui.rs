@@ -0,0 +1,333 @@
+use anyhow::{anyhow, Result};
+use neovim_manager::clock::Clock;
+use neovim_manager::{identifier, utils, HealthStatus, InstanceResult};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::format_elapsed;
+use neovim_manager::client::ManagerClient;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+enum InputAction {
+    OpenFile,
+    Rename,
+}
+
+enum Mode {
+    Normal,
+    Input { action: InputAction, buffer: String },
+    ConfirmQuit,
+}
+
+struct App {
+    instances: Vec<InstanceResult>,
+    table_state: TableState,
+    mode: Mode,
+    message: String,
+    last_refresh: Option<Instant>,
+    /// 経過時間の表示に使う
+    clock: Arc<dyn Clock>,
+}
+
+impl App {
+    fn selected(&self) -> Option<&InstanceResult> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.instances.get(index))
+    }
+
+    fn select_next(&mut self) {
+        if !self.instances.is_empty() {
+            let next = self
+                .table_state
+                .selected()
+                .map_or(0, |index| (index + 1).min(self.instances.len() - 1));
+            self.table_state.select(Some(next));
+        }
+    }
+
+    fn select_previous(&mut self) {
+        if !self.instances.is_empty() {
+            let previous = self
+                .table_state
+                .selected()
+                .map_or(0, |index| index.saturating_sub(1));
+            self.table_state.select(Some(previous));
+        }
+    }
+}
+
+pub async fn run(client: &ManagerClient, clock: Arc<dyn Clock>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, client, clock).await;
+    ratatui::restore();
+    result
+}
+
+async fn refresh(app: &mut App, client: &ManagerClient) -> Result<()> {
+    let selected = app.selected().map(|instance| instance.identifier.clone());
+
+    let mut instances = client.list(None).await?;
+    instances.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    app.instances = instances;
+
+    // 再取得後も同じインスタンスを選択し続ける
+    let index = selected
+        .and_then(|identifier| {
+            app.instances
+                .iter()
+                .position(|instance| instance.identifier == identifier)
+        })
+        .or_else(|| (!app.instances.is_empty()).then_some(0))
+        .map(|index| index.min(app.instances.len().saturating_sub(1)));
+    app.table_state.select(index);
+    app.last_refresh = Some(Instant::now());
+
+    Ok(())
+}
+
+async fn run_app(
+    terminal: &mut DefaultTerminal,
+    client: &ManagerClient,
+    clock: Arc<dyn Clock>,
+) -> Result<()> {
+    let mut app = App {
+        instances: Vec::new(),
+        table_state: TableState::default(),
+        mode: Mode::Normal,
+        message: String::new(),
+        last_refresh: None,
+        clock,
+    };
+
+    loop {
+        if app
+            .last_refresh
+            .is_none_or(|last| last.elapsed() >= REFRESH_INTERVAL)
+        {
+            if let Err(e) = refresh(&mut app, client).await {
+                app.message = format!("Refresh failed: {e}");
+                app.last_refresh = Some(Instant::now());
+            }
+        }
+
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match &mut app.mode {
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => app.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => app.select_previous(),
+                KeyCode::Char('g') => app.last_refresh = None,
+                KeyCode::Char('f') | KeyCode::Enter => {
+                    if let Some(instance) = app.selected().cloned() {
+                        app.message = match focus(client, &instance).await {
+                            Ok(()) => format!("Focused {}", instance.identifier),
+                            Err(e) => format!("Focus failed: {e}"),
+                        };
+                    }
+                }
+                KeyCode::Char('o') if app.selected().is_some() => {
+                    app.mode = Mode::Input {
+                        action: InputAction::OpenFile,
+                        buffer: String::new(),
+                    };
+                }
+                KeyCode::Char('r') => {
+                    if let Some(instance) = app.selected() {
+                        app.mode = Mode::Input {
+                            action: InputAction::Rename,
+                            buffer: instance.identifier.clone(),
+                        };
+                    }
+                }
+                KeyCode::Char('x') if app.selected().is_some() => {
+                    app.mode = Mode::ConfirmQuit;
+                }
+                KeyCode::Char('p') => {
+                    if let Some(instance) = app.selected().cloned() {
+                        let pinned = !instance.pinned;
+                        app.message = match client.pin(&instance.identifier, pinned).await {
+                            Ok(()) => format!(
+                                "{}: {}",
+                                instance.identifier,
+                                if pinned { "pinned" } else { "unpinned" }
+                            ),
+                            Err(e) => format!("Pin failed: {e}"),
+                        };
+                        app.last_refresh = None;
+                    }
+                }
+                _ => {}
+            },
+            Mode::Input { action, buffer } => match key.code {
+                KeyCode::Esc => app.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Char(c) => buffer.push(c),
+                KeyCode::Enter => {
+                    let input = buffer.trim().to_string();
+                    let is_rename = matches!(action, InputAction::Rename);
+                    app.mode = Mode::Normal;
+
+                    if let Some(instance) = app.selected().cloned() {
+                        app.message = if is_rename {
+                            match rename(client, &instance, &input).await {
+                                Ok(()) => format!("Renamed to {input}"),
+                                Err(e) => format!("Rename failed: {e}"),
+                            }
+                        } else {
+                            match open_file(client, &instance, &input).await {
+                                Ok(()) => format!("Opened {input}"),
+                                Err(e) => format!("Open failed: {e}"),
+                            }
+                        };
+                        app.last_refresh = None;
+                    }
+                }
+                _ => {}
+            },
+            Mode::ConfirmQuit => {
+                if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                    if let Some(instance) = app.selected().cloned() {
+                        app.message = match quit(client, &instance).await {
+                            Ok(()) => format!("Quit {}", instance.identifier),
+                            Err(e) => format!("Quit failed: {e}"),
+                        };
+                        app.last_refresh = None;
+                    }
+                }
+                app.mode = Mode::Normal;
+            }
+        }
+    }
+}
+
+pub(crate) async fn focus(client: &ManagerClient, instance: &InstanceResult) -> Result<()> {
+    utils::focus_nvim_instance(&instance.server_address).await?;
+
+    client.touch(&instance.identifier).await
+}
+
+async fn open_file(client: &ManagerClient, instance: &InstanceResult, path: &str) -> Result<()> {
+    if path.is_empty() {
+        return Err(anyhow!("No file given"));
+    }
+
+    // 相対パスはこのプロセスのカレントディレクトリ基準で解決してから渡す
+    let path = std::path::Path::new(path);
+    let path = identifier::canonical_path(path).unwrap_or_else(|_| path.to_path_buf());
+    utils::open_file_in_nvim_instance(
+        &instance.server_address,
+        &path.to_string_lossy(),
+        &Default::default(),
+    )
+    .await?;
+    focus(client, instance).await
+}
+
+async fn rename(
+    client: &ManagerClient,
+    instance: &InstanceResult,
+    new_identifier: &str,
+) -> Result<()> {
+    if new_identifier.is_empty() || new_identifier == instance.identifier {
+        return Err(anyhow!("Identifier unchanged"));
+    }
+
+    client.rename(&instance.identifier, new_identifier).await
+}
+
+pub(crate) async fn quit(client: &ManagerClient, instance: &InstanceResult) -> Result<()> {
+    if !utils::quit_all_nvim_instance(&instance.server_address, false).await? {
+        return Err(anyhow!("Neovim refused to quit (unsaved changes?)"));
+    }
+
+    client.unregister(&instance.identifier).await
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [table_area, status_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+
+    let header = Row::new(["", "IDENTIFIER", "HEALTH", "ADDRESS", "AGE", "LAST USED"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let now = app.clock.now();
+    let rows = app.instances.iter().map(|instance| {
+        let health_style = match instance.health_status {
+            HealthStatus::Healthy => Style::default().fg(Color::Green),
+            HealthStatus::Starting => Style::default().fg(Color::Yellow),
+            HealthStatus::Unhealthy { .. } | HealthStatus::Dead => Style::default().fg(Color::Red),
+        };
+
+        Row::new(vec![
+            Line::from(if instance.pinned { "*" } else { "" }),
+            Line::from(instance.identifier.clone()),
+            Line::styled(instance.health_status.to_string(), health_style),
+            Line::from(instance.server_address.to_string()),
+            Line::from(format_elapsed(instance.registered_at, now)),
+            Line::from(format!("{} ago", format_elapsed(instance.last_used, now))),
+        ])
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(1),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(21),
+            Constraint::Length(5),
+            Constraint::Length(10),
+        ],
+    )
+    .header(header)
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered().title(format!(" Neovim instances ({}) ", app.instances.len())));
+    frame.render_stateful_widget(table, table_area, &mut app.table_state);
+
+    let status = match &app.mode {
+        Mode::Normal => {
+            let keys = "enter/f focus  o open file  r rename  p pin  x quit  g refresh  q exit";
+            if app.message.is_empty() {
+                keys.to_string()
+            } else {
+                format!("{}  |  {keys}", app.message)
+            }
+        }
+        Mode::Input {
+            action: InputAction::OpenFile,
+            buffer,
+        } => format!("Open file: {buffer}_"),
+        Mode::Input {
+            action: InputAction::Rename,
+            buffer,
+        } => format!("New identifier: {buffer}_"),
+        Mode::ConfirmQuit => format!(
+            "Quit {}? [y/N]",
+            app.selected()
+                .map(|instance| instance.identifier.as_str())
+                .unwrap_or("")
+        ),
+    };
+    frame.render_widget(Paragraph::new(status).block(Block::bordered()), status_area);
+}
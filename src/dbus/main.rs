@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
+use log::info;
+use neovim_manager::client::ManagerClient;
+use neovim_manager::config::{self, Config};
+use neovim_manager::{duration, report, utils, InstanceResult};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+mod manager;
+mod search;
+
+/// セッションバスで名乗る名前
+const BUS_NAME: &str = "io.github.statiolake.NeovimManager";
+/// マネージャーの操作 (`io.github.statiolake.NeovimManager1`)
+const MANAGER_PATH: &str = "/io/github/statiolake/NeovimManager";
+/// GNOME Shell の検索プロバイダー (`org.gnome.Shell.SearchProvider2`)
+const SEARCH_PROVIDER_PATH: &str = "/io/github/statiolake/NeovimManager/SearchProvider";
+/// KRunner のランナー (`org.kde.krunner1`)
+const KRUNNER_PATH: &str = "/io/github/statiolake/NeovimManager/KRunner";
+/// GNOME Shell の検索結果の元になるアプリケーション (`neovim-launcher install-desktop` が書き出すもの)
+const DESKTOP_ID: &str = "neovim-launcher.desktop";
+
+#[derive(Parser)]
+#[command(name = "neovim-manager-dbus")]
+#[command(
+    about = "D-Bus service exposing the Neovim instances to desktop search (GNOME Shell / KRunner) and other tools"
+)]
+struct Cli {
+    #[arg(
+        long,
+        default_value = "2s",
+        value_parser = duration::parse,
+        help = "How long to wait before retrying when the manager cannot be reached, \
+                or how often to refresh when it is too old to report changes"
+    )]
+    interval: Duration,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Install the D-Bus service file and the GNOME Shell / KRunner search provider files
+    Install {
+        #[arg(long, help = "Print the files instead of installing them")]
+        print: bool,
+    },
+}
+
+/// 各オブジェクトが共有する状態
+pub struct State {
+    client: ManagerClient,
+    /// `manager::watch` が受け取った最新の一覧 (検索はキー入力ごとに呼ばれるので、マネージャーに問い合わせない)
+    instances: RwLock<Vec<InstanceResult>>,
+}
+
+impl State {
+    /// 今のインスタンスの一覧 (マネージャーに接続できなければ空)
+    async fn instances(&self) -> Vec<InstanceResult> {
+        self.instances.read().await.clone()
+    }
+
+    /// identifier のインスタンスにフォーカスし、最終使用時刻を更新する
+    async fn focus(&self, identifier: &str) -> Result<()> {
+        let instance = self
+            .client
+            .query(identifier)
+            .await?
+            .ok_or_else(|| anyhow!("No instance is registered as {identifier}"))?;
+        info!("Focusing {identifier}");
+        utils::focus_nvim_instance(&instance.server_address).await?;
+        self.client.touch(identifier).await
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Install { print }) => {
+            install(print).context("Failed to install the D-Bus service")
+        }
+        None => serve(cli.interval)
+            .await
+            .context("The D-Bus service stopped with an error"),
+    };
+    if let Err(e) = result {
+        report::exit(1, &e);
+    }
+}
+
+async fn serve(interval: Duration) -> Result<()> {
+    let config = Config::load()?;
+    // 問い合わせのたびにマネージャーを起動しないようにする (Open はランチャーが起動する)
+    let mut client = ManagerClient::new(&config);
+    client.autostart = false;
+    let state = Arc::new(State {
+        client,
+        instances: RwLock::new(Vec::new()),
+    });
+
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(MANAGER_PATH, manager::Manager::new(state.clone()))?
+        .serve_at(
+            SEARCH_PROVIDER_PATH,
+            search::GnomeSearchProvider::new(state.clone()),
+        )?
+        .serve_at(KRUNNER_PATH, search::KRunner::new(state.clone()))?
+        .build()
+        .await
+        .with_context(|| format!("Cannot register {BUS_NAME} on the session bus"))?;
+    info!("Serving {BUS_NAME} on the session bus");
+
+    let emitter = zbus::object_server::SignalEmitter::new(&connection, MANAGER_PATH)?;
+    tokio::select! {
+        _ = manager::watch(state, emitter, interval) => {}
+        result = tokio::signal::ctrl_c() => result?,
+    }
+    Ok(())
+}
+
+/// 書き出すファイル (パス、内容)
+fn files() -> Result<Vec<(PathBuf, String)>> {
+    let data_home = config::data_home()
+        .ok_or_else(|| anyhow!("Cannot determine the data directory (set XDG_DATA_HOME)"))?;
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_string_lossy();
+    let exec = if exe.contains([' ', '"', '\'']) {
+        format!("\"{}\"", exe.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        exe.to_string()
+    };
+
+    Ok(vec![
+        // 呼ばれたときに D-Bus が起動する
+        (
+            data_home
+                .join("dbus-1/services")
+                .join(format!("{BUS_NAME}.service")),
+            format!("[D-BUS Service]\nName={BUS_NAME}\nExec={exec}\n"),
+        ),
+        (
+            data_home
+                .join("gnome-shell/search-providers")
+                .join(format!("{BUS_NAME}.search-provider.ini")),
+            format!(
+                "[Shell Search Provider]\n\
+                 DesktopId={DESKTOP_ID}\n\
+                 BusName={BUS_NAME}\n\
+                 ObjectPath={SEARCH_PROVIDER_PATH}\n\
+                 Version=2\n"
+            ),
+        ),
+        (
+            data_home.join("krunner/dbusplugins/neovim-manager.desktop"),
+            format!(
+                "[Desktop Entry]\n\
+                 Type=Service\n\
+                 Name=Neovim instances\n\
+                 Comment=Switch to a running Neovim instance\n\
+                 Icon=nvim\n\
+                 X-KDE-ServiceTypes=Plasma/Runner\n\
+                 X-KDE-PluginInfo-Name=neovim-manager\n\
+                 X-KDE-PluginInfo-EnabledByDefault=true\n\
+                 X-Plasma-API=DBus\n\
+                 X-Plasma-DBusRunner-Service={BUS_NAME}\n\
+                 X-Plasma-DBusRunner-Path={KRUNNER_PATH}\n"
+            ),
+        ),
+    ])
+}
+
+fn install(print: bool) -> Result<()> {
+    if cfg!(any(windows, target_os = "macos")) {
+        return Err(anyhow!(
+            "The D-Bus service is for Linux and BSD desktops only"
+        ));
+    }
+
+    let files = files()?;
+    if print {
+        for (path, content) in &files {
+            println!("# {}\n{content}", path.display());
+        }
+        return Ok(());
+    }
+
+    for (path, content) in &files {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+        }
+        std::fs::write(path, content)
+            .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    println!(
+        "GNOME Shell reads search providers only from the system data directories: \
+         copy the .search-provider.ini to /usr/share/gnome-shell/search-providers/ \
+         and run `neovim-launcher install-desktop` so that {DESKTOP_ID} exists"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! マネージャーの操作をセッションバスに出す (`io.github.statiolake.NeovimManager1`)
+//!
+//! `watch_instances` で登録内容が変わるたびに一覧を受け取り、差分を
+//! `InstanceRegistered` / `InstanceUnregistered` として送る。
+
+use log::{info, warn};
+use neovim_manager::client::InstanceWatcher;
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::{HealthStatus, InstanceResult};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Str};
+
+use super::State;
+
+/// ランチャーが開き終わるまで待つ時間
+const OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "io.github.statiolake.NeovimManager1.Error")]
+pub enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    /// その identifier のインスタンスは登録されていない
+    NotFound(String),
+    Failed(String),
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Failed(format!("{e:#}"))
+    }
+}
+
+fn health(status: &HealthStatus) -> &'static str {
+    match status {
+        HealthStatus::Starting => "starting",
+        HealthStatus::Healthy => "healthy",
+        HealthStatus::Unhealthy { .. } => "unhealthy",
+        HealthStatus::Dead => "dead",
+    }
+}
+
+/// インスタンスを `a{sv}` で表す (`cwd` と `pid` は分かるときだけ)
+fn instance_dict(instance: &InstanceResult) -> HashMap<String, OwnedValue> {
+    let string = |value: &str| OwnedValue::from(Str::from(value.to_string()));
+    let mut dict = HashMap::from([
+        ("identifier".to_string(), string(&instance.identifier)),
+        (
+            "server_address".to_string(),
+            string(instance.server_address.as_str()),
+        ),
+        (
+            "health".to_string(),
+            string(health(&instance.health_status)),
+        ),
+        (
+            "registered_at".to_string(),
+            OwnedValue::from(instance.registered_at.timestamp()),
+        ),
+        (
+            "last_used".to_string(),
+            OwnedValue::from(instance.last_used.timestamp()),
+        ),
+        ("pinned".to_string(), OwnedValue::from(instance.pinned)),
+        (
+            "tags".to_string(),
+            OwnedValue::try_from(zbus::zvariant::Value::from(instance.tags.clone()))
+                .expect("an array of strings has no file descriptors"),
+        ),
+    ]);
+    if let Some(cwd) = &instance.cwd {
+        dict.insert("cwd".to_string(), string(cwd));
+    }
+    if let Some(pid) = instance.pid {
+        dict.insert("pid".to_string(), OwnedValue::from(pid));
+    }
+    dict
+}
+
+/// `io.github.statiolake.NeovimManager1`
+pub struct Manager {
+    state: Arc<State>,
+}
+
+impl Manager {
+    pub fn new(state: Arc<State>) -> Self {
+        Self { state }
+    }
+}
+
+#[zbus::interface(name = "io.github.statiolake.NeovimManager1")]
+impl Manager {
+    /// インスタンスの一覧 (`tag` が空でなければそのタグが付いたものだけ)
+    async fn list(&self, tag: String) -> Result<Vec<HashMap<String, OwnedValue>>, Error> {
+        let tag = (!tag.is_empty()).then_some(tag.as_str());
+        let instances = self.state.client.list(tag).await?;
+        Ok(instances.iter().map(instance_dict).collect())
+    }
+
+    async fn query(&self, identifier: String) -> Result<HashMap<String, OwnedValue>, Error> {
+        match self.state.client.query(&identifier).await? {
+            Some(instance) => Ok(instance_dict(&instance)),
+            None => Err(Error::NotFound(format!(
+                "No instance is registered as {identifier}"
+            ))),
+        }
+    }
+
+    /// NeovideFocus を実行し、最終使用時刻を更新する
+    async fn focus(&self, identifier: String) -> Result<(), Error> {
+        if self.state.client.query(&identifier).await?.is_none() {
+            return Err(Error::NotFound(format!(
+                "No instance is registered as {identifier}"
+            )));
+        }
+        Ok(self.state.focus(&identifier).await?)
+    }
+
+    /// `neovim-launcher --handler <path>` と同じように開く
+    /// (含むインスタンスがあればそこで開き、なければ起動する)
+    async fn open(&self, path: String) -> Result<(), Error> {
+        if !Path::new(&path).is_absolute() {
+            return Err(Error::Failed(format!("{path} is not an absolute path")));
+        }
+
+        let launcher = std::env::current_exe()
+            .map_err(anyhow::Error::from)?
+            .with_file_name("neovim-launcher");
+        let spec = ProcessSpec::new(launcher)
+            .args(["--handler", &path])
+            .timeout(OPEN_TIMEOUT);
+        info!("Opening {path}");
+        let output = process::output_async(&spec).await?;
+        if !output.success() {
+            return Err(Error::Failed(format!(
+                "Failed to open {path}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn instance_registered(
+        emitter: &SignalEmitter<'_>,
+        identifier: &str,
+        server_address: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn instance_unregistered(
+        emitter: &SignalEmitter<'_>,
+        identifier: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// 登録内容が変わるたびに一覧を受け取り、検索用の一覧を更新して、増えたものと減ったものをシグナルで送る
+///
+/// マネージャーに接続できないときは検索用の一覧を空にし、シグナルのための前回の一覧は保ったまま、
+/// `interval` だけ空けて接続し直す
+pub async fn watch(state: Arc<State>, emitter: SignalEmitter<'_>, interval: Duration) {
+    let mut known: Option<HashMap<String, String>> = None;
+    let mut watcher = InstanceWatcher::new(state.client.clone(), interval);
+
+    loop {
+        let Ok(instances) = watcher.next().await else {
+            state.instances.write().await.clear();
+            continue;
+        };
+        *state.instances.write().await = instances.clone();
+        let current: HashMap<String, String> = instances
+            .into_iter()
+            .map(|instance| (instance.identifier, instance.server_address.to_string()))
+            .collect();
+
+        // 起動直後の一覧は通知しない
+        if let Some(known) = &known {
+            for (identifier, server_address) in &current {
+                // 同じ identifier で登録し直されたものは解除と登録の両方を送る
+                match known.get(identifier) {
+                    Some(previous) if previous == server_address => continue,
+                    Some(_) => {
+                        if let Err(e) = Manager::instance_unregistered(&emitter, identifier).await {
+                            warn!("Cannot emit InstanceUnregistered: {e}");
+                        }
+                    }
+                    None => {}
+                }
+                if let Err(e) =
+                    Manager::instance_registered(&emitter, identifier, server_address).await
+                {
+                    warn!("Cannot emit InstanceRegistered: {e}");
+                }
+            }
+            for identifier in known.keys().filter(|id| !current.contains_key(*id)) {
+                if let Err(e) = Manager::instance_unregistered(&emitter, identifier).await {
+                    warn!("Cannot emit InstanceUnregistered: {e}");
+                }
+            }
+        }
+        known = Some(current);
+    }
+}
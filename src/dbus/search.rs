@@ -0,0 +1,197 @@
+//! デスクトップの検索 (GNOME Shell のアクティビティ画面と KRunner) にインスタンスを出す
+//!
+//! 入力した語をすべて含む identifier のインスタンスを「Switch to Neovim: ~/work/foo」として返し、
+//! 選ばれたらフォーカスする。一覧は問い合わせのたびにマネージャーから取り直す。
+
+use log::warn;
+use neovim_manager::{identifier, InstanceResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+use zbus::fdo;
+use zbus::zvariant::{OwnedValue, Str};
+
+use super::State;
+
+/// 結果に付けるアイコン
+const ICON: &str = "nvim";
+
+/// `terms` をすべて (大文字小文字を区別せずに) 含むインスタンス。最近使った順
+fn matching(instances: Vec<InstanceResult>, terms: &[String]) -> Vec<InstanceResult> {
+    let terms: Vec<String> = terms.iter().map(|term| term.to_lowercase()).collect();
+    let mut matched: Vec<InstanceResult> = instances
+        .into_iter()
+        .filter(|instance| {
+            let haystack = format!(
+                "{} {}",
+                identifier::display(&instance.identifier),
+                instance.tags.join(" ")
+            )
+            .to_lowercase();
+            terms.iter().all(|term| haystack.contains(term))
+        })
+        .collect();
+    matched.sort_by_key(|instance| std::cmp::Reverse(instance.last_used));
+    matched
+}
+
+fn title(instance: &InstanceResult) -> String {
+    format!(
+        "Switch to Neovim: {}",
+        identifier::display(&instance.identifier)
+    )
+}
+
+/// 結果の説明 (応答しないものとタグを示す)
+fn description(instance: &InstanceResult) -> String {
+    let mut description = if instance.health_status.is_healthy() {
+        "Running".to_string()
+    } else {
+        "Not responding".to_string()
+    };
+    if !instance.tags.is_empty() {
+        description.push_str(&format!(" ({})", instance.tags.join(", ")));
+    }
+    description
+}
+
+fn string_value(value: impl Into<String>) -> OwnedValue {
+    OwnedValue::from(Str::from(value.into()))
+}
+
+/// `org.gnome.Shell.SearchProvider2` (結果の ID は identifier)
+pub struct GnomeSearchProvider {
+    state: Arc<State>,
+}
+
+impl GnomeSearchProvider {
+    pub fn new(state: Arc<State>) -> Self {
+        Self { state }
+    }
+
+    async fn search(&self, terms: &[String]) -> Vec<String> {
+        matching(self.state.instances().await, terms)
+            .into_iter()
+            .map(|instance| instance.identifier)
+            .collect()
+    }
+}
+
+#[zbus::interface(name = "org.gnome.Shell.SearchProvider2")]
+impl GnomeSearchProvider {
+    async fn get_initial_result_set(&self, terms: Vec<String>) -> Vec<String> {
+        self.search(&terms).await
+    }
+
+    async fn get_subsearch_result_set(
+        &self,
+        _previous_results: Vec<String>,
+        terms: Vec<String>,
+    ) -> Vec<String> {
+        // 一覧は変わりうるので、前の結果を絞り込まずに検索し直す
+        self.search(&terms).await
+    }
+
+    async fn get_result_metas(&self, identifiers: Vec<String>) -> Vec<HashMap<String, OwnedValue>> {
+        let instances = self.state.instances().await;
+        identifiers
+            .iter()
+            .filter_map(|id| instances.iter().find(|instance| &instance.identifier == id))
+            .map(|instance| {
+                HashMap::from([
+                    ("id".to_string(), string_value(&instance.identifier)),
+                    ("name".to_string(), string_value(title(instance))),
+                    (
+                        "description".to_string(),
+                        string_value(description(instance)),
+                    ),
+                    ("gicon".to_string(), string_value(ICON)),
+                ])
+            })
+            .collect()
+    }
+
+    async fn activate_result(&self, identifier: String, _terms: Vec<String>, _timestamp: u32) {
+        if let Err(e) = self.state.focus(&identifier).await {
+            warn!("{e:#}");
+        }
+    }
+
+    /// 「もっと見る」は開くアプリケーションがないので何もしない
+    async fn launch_search(&self, _terms: Vec<String>, _timestamp: u32) {}
+}
+
+/// `org.kde.krunner1` の一致の種類 (`Plasma::QueryMatch::Type`)
+const KRUNNER_EXACT_MATCH: i32 = 100;
+const KRUNNER_POSSIBLE_MATCH: i32 = 30;
+
+/// KRunner の結果 (ID, 表示, アイコン, 種類, 関連度, プロパティ)
+type KRunnerMatch = (
+    String,
+    String,
+    String,
+    i32,
+    f64,
+    HashMap<String, OwnedValue>,
+);
+
+/// `org.kde.krunner1` (結果の ID は identifier)
+pub struct KRunner {
+    state: Arc<State>,
+}
+
+impl KRunner {
+    pub fn new(state: Arc<State>) -> Self {
+        Self { state }
+    }
+}
+
+#[zbus::interface(name = "org.kde.krunner1")]
+impl KRunner {
+    /// 結果ごとの追加の操作はない
+    async fn actions(&self) -> Vec<(String, String, String)> {
+        Vec::new()
+    }
+
+    #[zbus(name = "Match")]
+    async fn find(&self, query: String) -> Vec<KRunnerMatch> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_string).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        matching(self.state.instances().await, &terms)
+            .into_iter()
+            .map(|instance| {
+                // ディレクトリ名そのものを入力したら一番上に出す
+                let name = instance
+                    .identifier
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let (kind, relevance) = if name == query.trim().to_lowercase() {
+                    (KRUNNER_EXACT_MATCH, 1.0)
+                } else {
+                    (KRUNNER_POSSIBLE_MATCH, 0.7)
+                };
+                let properties =
+                    HashMap::from([("subtext".to_string(), string_value(description(&instance)))]);
+                (
+                    instance.identifier.clone(),
+                    title(&instance),
+                    ICON.to_string(),
+                    kind,
+                    relevance,
+                    properties,
+                )
+            })
+            .collect()
+    }
+
+    async fn run(&self, identifier: String, _action_id: String) -> fdo::Result<()> {
+        self.state
+            .focus(&identifier)
+            .await
+            .map_err(|e| fdo::Error::Failed(format!("{e:#}")))
+    }
+}
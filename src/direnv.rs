@@ -0,0 +1,71 @@
+//! direnv の環境を新しく起動する nvim に渡す
+//!
+//! GUI から起動した launcher はシェルのフックを通らないので、プロジェクトの `.envrc` で設定したツールチェーンが
+//! 見えない。`launcher.direnv` が有効なら、起動する前に `direnv export json` を実行してその差分を nvim の環境に加える。
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::LauncherConfig;
+use crate::process::{self, ProcessSpec};
+
+/// `direnv export json` を待つ上限 (nix などでは最初の評価に時間がかかる)
+const DIRENV_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 環境変数の変更。値が None のものは取り除く
+pub type EnvChanges = BTreeMap<String, Option<String>>;
+
+/// `dir` かその上にある `.envrc`
+pub fn find_envrc(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(".envrc"))
+        .find(|envrc| envrc.is_file())
+}
+
+/// `dir` で `direnv export json` を実行し、今の環境との差分を返す
+///
+/// `.envrc` がなければ direnv を実行せずに空を返す。許可されていない (`direnv allow` していない) 場合はエラー
+pub fn export(dir: &Path) -> Result<EnvChanges> {
+    if find_envrc(dir).is_none() {
+        return Ok(EnvChanges::new());
+    }
+
+    let output = process::output(
+        &ProcessSpec::new("direnv")
+            .args(["export", "json"])
+            .current_dir(dir)
+            .timeout(DIRENV_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "direnv export failed in {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // 変更がなければ何も出力しない
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(EnvChanges::new());
+    }
+    serde_json::from_str(&stdout).map_err(|e| anyhow!("Invalid output of direnv export: {e}"))
+}
+
+/// `launcher.direnv` が有効なときだけ [`export`] する
+pub fn load(config: &LauncherConfig, dir: &Path) -> Result<EnvChanges> {
+    if !config.direnv.value {
+        return Ok(EnvChanges::new());
+    }
+    export(dir)
+}
+
+/// `changes` を起動するプロセスの環境に加える
+pub fn apply(spec: ProcessSpec, changes: &EnvChanges) -> ProcessSpec {
+    changes.iter().fold(spec, |spec, (key, value)| match value {
+        Some(value) => spec.env(key, value),
+        None => spec.env_remove(key),
+    })
+}
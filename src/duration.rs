@@ -0,0 +1,100 @@
+//! 時間の長さの表記
+//!
+//! 設定ファイル・環境変数・コマンドライン引数の間隔やタイムアウトは、すべて `30s`・`5m`・`2h`・`1h30m`・`500ms`
+//! の形で書ける。単位を省いた数字は従来どおり秒とみなす。
+
+use std::time::Duration;
+
+/// 時間の長さとして読めない文字列
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid duration: {0:?} (use e.g. 500ms, 30s, 5m, 2h, 1d or 1h30m)")]
+pub struct InvalidDuration(pub String);
+
+/// `30s`・`1h30m`・`1h 30m` などを読む。単位のない数字は秒
+pub fn parse(s: &str) -> Result<Duration, InvalidDuration> {
+    let invalid = || InvalidDuration(s.to_string());
+    let trimmed = s.trim();
+    if trimmed.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = trimmed.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = trimmed;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit = unit_from_str(&rest[..unit_len]).ok_or_else(invalid)?;
+        rest = rest[unit_len..].trim_start();
+
+        total = unit
+            .checked_mul(u32::try_from(value).map_err(|_| invalid())?)
+            .and_then(|value| total.checked_add(value))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(total)
+}
+
+/// [`parse`] で読み戻せる形 (`1h30m`・`500ms`・`0s`) にする
+pub fn format(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis == 0 {
+        return "0s".to_string();
+    }
+    if duration.subsec_millis() != 0 {
+        return format!("{millis}ms");
+    }
+
+    let mut secs = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, unit_secs) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if secs >= unit_secs {
+            formatted.push_str(&format!("{}{unit}", secs / unit_secs));
+            secs %= unit_secs;
+        }
+    }
+    formatted
+}
+
+fn unit_from_str(unit: &str) -> Option<Duration> {
+    Some(match unit {
+        "ms" => Duration::from_millis(1),
+        "s" | "sec" | "secs" => Duration::from_secs(1),
+        "m" | "min" | "mins" => Duration::from_secs(60),
+        "h" | "hr" | "hour" | "hours" => Duration::from_secs(3600),
+        "d" | "day" | "days" => Duration::from_secs(86400),
+        _ => return None,
+    })
+}
+
+/// 設定ファイルの値。秒数 (整数) と文字列 (`"30s"`) のどちらも受け付ける
+#[cfg(feature = "client")]
+pub(crate) fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Secs(u64),
+        Text(String),
+    }
+
+    match Option::<Raw>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Raw::Secs(secs)) => Ok(Some(Duration::from_secs(secs))),
+        Some(Raw::Text(text)) => parse(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
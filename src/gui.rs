@@ -0,0 +1,631 @@
+//! GUI (Neovide など) の実行ファイルを探す
+//!
+//! 探す順番:
+//! 1. `launcher.neovide_command` / `NEOVIM_MANAGER_NEOVIDE` がパスならそれをそのまま使う
+//! 2. `launcher.gui_search_paths` / `NEOVIM_MANAGER_GUI_PATH` のディレクトリ
+//! 3. PATH
+//! 4. macOS の .app バンドル (`/Applications` と `~/Applications`)
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{LauncherConfig, Origin};
+use crate::process::{self, ProcessSpec};
+use crate::{tunnel, utils, wsl};
+
+/// GUI をどこで見つけたか
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuiSource {
+    /// 設定や環境変数でパスが指定された
+    Configured(Origin),
+    /// `launcher.gui_search_paths` のディレクトリ
+    SearchPath,
+    Path,
+    AppBundle,
+    /// 見つからなかった (名前のまま起動を試みる)
+    NotFound,
+}
+
+impl fmt::Display for GuiSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuiSource::Configured(origin) => write!(f, "configured by {origin}"),
+            GuiSource::SearchPath => write!(f, "found in gui_search_paths"),
+            GuiSource::Path => write!(f, "found on PATH"),
+            GuiSource::AppBundle => write!(f, "found in an app bundle"),
+            GuiSource::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+/// サーバーに接続する GUI の起動方法
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuiCommand {
+    pub program: PathBuf,
+    /// `--server <addr>` の後に渡す引数 (`launcher.neovide_args`)
+    pub args: Vec<String>,
+    pub source: GuiSource,
+}
+
+impl GuiCommand {
+    pub fn resolve(config: &LauncherConfig) -> Self {
+        let name = config.neovide_command.value.as_str();
+        let args = config.neovide_args.value.clone();
+        let command = |program: PathBuf, source: GuiSource| GuiCommand {
+            program,
+            args: args.clone(),
+            source,
+        };
+
+        // 区切り文字を含むならパスとして扱い、探さない
+        if Path::new(name).components().count() > 1 {
+            return command(
+                PathBuf::from(name),
+                GuiSource::Configured(config.neovide_command.origin.clone()),
+            );
+        }
+
+        if let Some(path) = config
+            .gui_search_paths
+            .value
+            .iter()
+            .find_map(|dir| find_in_dir(dir, name))
+        {
+            return command(path, GuiSource::SearchPath);
+        }
+        if let Some(path) = utils::find_in_path(name) {
+            return command(path, GuiSource::Path);
+        }
+        if let Some(path) = app_bundle_candidates(name)
+            .into_iter()
+            .find(|path| path.is_file())
+        {
+            return command(path, GuiSource::AppBundle);
+        }
+
+        command(PathBuf::from(name), GuiSource::NotFound)
+    }
+
+    /// Neovide か (`--grid` などの Neovide の引数を渡してよいか)
+    pub fn is_neovide(&self) -> bool {
+        self.program
+            .file_stem()
+            .is_some_and(|stem| stem.eq_ignore_ascii_case("neovide"))
+    }
+
+    /// 実行ファイルが存在するか
+    pub fn is_found(&self) -> bool {
+        match self.source {
+            GuiSource::Configured(_) => self.program.is_file(),
+            GuiSource::NotFound => false,
+            _ => true,
+        }
+    }
+
+    pub fn spec(&self, server_address: &str) -> ProcessSpec {
+        ProcessSpec::new(&self.program)
+            .args(["--server", server_address])
+            .args(self.args.iter().cloned())
+    }
+
+    /// サーバーに接続する形で起動する (終了は待たない)。`ssh://` はローカルフォワードに接続する
+    pub fn spawn(&self, server_address: &str) -> Result<()> {
+        process::spawn(&self.spec(&tunnel::local_address(server_address)?))?;
+        Ok(())
+    }
+}
+
+/// 既定の GUI の名前 (WSL では Windows 版を使う)
+pub fn default_program() -> &'static str {
+    if wsl::is_wsl() || cfg!(windows) {
+        "neovide.exe"
+    } else {
+        "neovide"
+    }
+}
+
+/// 既定で GUI に渡す引数
+pub fn default_args() -> Vec<String> {
+    let mut args = vec![];
+
+    if cfg!(target_os = "macos") {
+        args.push("--no-vsync".to_string());
+    }
+
+    args
+}
+
+/// osascript などを待つ上限
+const ACTIVATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// デスクトップから起動されたプロセスに渡される xdg-activation のトークン (Wayland)
+pub const ACTIVATION_TOKEN_ENV: &str = "XDG_ACTIVATION_TOKEN";
+/// X11 の起動通知の ID (Wayland でもトークンとして渡す環境がある)
+pub const STARTUP_ID_ENV: &str = "DESKTOP_STARTUP_ID";
+
+/// Hyprland でウィンドウを隠す特殊ワークスペース (`special:<name>`)
+const HYPRLAND_HIDDEN_WORKSPACE: &str = "neovim-scratch";
+
+/// フォーカスを移す・ウィンドウを隠す・位置を読み書きするためのコンポジターの IPC (Wayland)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compositor {
+    /// `swaymsg [pid=<pid>] focus`
+    Sway,
+    /// `hyprctl dispatch focuswindow pid:<pid>`
+    Hyprland,
+    /// `niri msg action focus-window --id <id>`
+    Niri,
+}
+
+impl Compositor {
+    /// 今のセッションのコンポジター (それぞれが設定する環境変数で判定する)。Wayland でなければ None
+    pub fn detect() -> Option<Self> {
+        std::env::var_os("WAYLAND_DISPLAY")?;
+        if std::env::var_os("SWAYSOCK").is_some() {
+            Some(Compositor::Sway)
+        } else if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            Some(Compositor::Hyprland)
+        } else if std::env::var_os("NIRI_SOCKET").is_some() {
+            Some(Compositor::Niri)
+        } else {
+            None
+        }
+    }
+
+    /// `pid` のウィンドウにフォーカスを移す
+    pub fn focus(self, pid: u32) -> Result<()> {
+        let spec = match self {
+            Compositor::Sway => ProcessSpec::new("swaymsg").arg(format!("[pid={pid}] focus")),
+            Compositor::Hyprland => ProcessSpec::new("hyprctl")
+                .args(["dispatch", "focuswindow"])
+                .arg(format!("pid:{pid}")),
+            Compositor::Niri => {
+                let id = niri_window_id(pid)?
+                    .ok_or_else(|| anyhow!("niri has no window of process {pid}"))?;
+                ProcessSpec::new("niri").args(["msg", "action", "focus-window", "--id", &id])
+            }
+        }
+        .timeout(ACTIVATE_TIMEOUT);
+        checked_output(&spec)?;
+        Ok(())
+    }
+
+    /// `pid` のウィンドウの左上の位置。niri は位置を持たないので常に None
+    pub fn window_position(self, pid: u32) -> Result<Option<(i32, i32)>> {
+        match self {
+            Compositor::Sway => {
+                let tree = sway_tree()?;
+                Ok(
+                    sway_find(&tree, &|node| node["pid"].as_u64() == Some(u64::from(pid)))
+                        .and_then(|node| {
+                            let rect = &node["rect"];
+                            Some((
+                                i32::try_from(rect["x"].as_i64()?).ok()?,
+                                i32::try_from(rect["y"].as_i64()?).ok()?,
+                            ))
+                        }),
+                )
+            }
+            Compositor::Hyprland => Ok(hyprland_client(pid)?.and_then(|client| {
+                Some((
+                    i32::try_from(client["at"][0].as_i64()?).ok()?,
+                    i32::try_from(client["at"][1].as_i64()?).ok()?,
+                ))
+            })),
+            Compositor::Niri => Ok(None),
+        }
+    }
+
+    /// フォーカスのあるウィンドウのプロセス ID
+    pub fn focused_pid(self) -> Result<Option<u32>> {
+        let window: serde_json::Value = match self {
+            Compositor::Sway => {
+                let tree = sway_tree()?;
+                return Ok(
+                    sway_find(&tree, &|node| node["focused"].as_bool() == Some(true))
+                        .and_then(|node| u32::try_from(node["pid"].as_u64()?).ok()),
+                );
+            }
+            Compositor::Hyprland => serde_json::from_slice(&checked_output(
+                &ProcessSpec::new("hyprctl")
+                    .args(["activewindow", "-j"])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?)
+            // ウィンドウがなければ JSON ではなく "Invalid" を返す
+            .unwrap_or_default(),
+            Compositor::Niri => serde_json::from_slice(&checked_output(
+                &ProcessSpec::new("niri")
+                    .args(["msg", "--json", "focused-window"])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?)?,
+        };
+        Ok(window["pid"]
+            .as_u64()
+            .and_then(|pid| u32::try_from(pid).ok()))
+    }
+
+    /// `pid` のウィンドウを隠す ([`Compositor::focus`] で戻る)
+    ///
+    /// sway はスクラッチパッドに、Hyprland は特殊ワークスペースに移す。
+    /// niri はウィンドウを隠せないので、前のウィンドウにフォーカスを戻すだけにする
+    pub fn hide(self, pid: u32) -> Result<()> {
+        let spec = match self {
+            Compositor::Sway => {
+                ProcessSpec::new("swaymsg").arg(format!("[pid={pid}] move scratchpad"))
+            }
+            Compositor::Hyprland => {
+                let workspace = hyprland_client(pid)?
+                    .and_then(|client| client["workspace"]["name"].as_str().map(str::to_string));
+                match workspace
+                    .as_deref()
+                    .and_then(|name| name.strip_prefix("special:"))
+                {
+                    // 特殊ワークスペースを開いて戻したものは、特殊ワークスペースごと閉じる
+                    Some(name) => ProcessSpec::new("hyprctl").args([
+                        "dispatch",
+                        "togglespecialworkspace",
+                        name,
+                    ]),
+                    None => ProcessSpec::new("hyprctl")
+                        .args(["dispatch", "movetoworkspacesilent"])
+                        .arg(format!("special:{HYPRLAND_HIDDEN_WORKSPACE},pid:{pid}")),
+                }
+            }
+            Compositor::Niri => {
+                ProcessSpec::new("niri").args(["msg", "action", "focus-window-previous"])
+            }
+        };
+        checked_output(&spec.timeout(ACTIVATE_TIMEOUT))?;
+        Ok(())
+    }
+
+    /// `pid` のウィンドウを `(x, y)` に移す (sway と Hyprland ではフローティングのウィンドウだけ動く)
+    pub fn move_window(self, pid: u32, (x, y): (i32, i32)) -> Result<()> {
+        let spec = match self {
+            Compositor::Sway => ProcessSpec::new("swaymsg")
+                .arg(format!("[pid={pid}] move absolute position {x} {y}")),
+            Compositor::Hyprland => ProcessSpec::new("hyprctl")
+                .args(["dispatch", "movewindowpixel"])
+                .arg(format!("exact {x} {y},pid:{pid}")),
+            Compositor::Niri => return Err(anyhow!("niri does not support moving windows")),
+        };
+        checked_output(&spec.timeout(ACTIVATE_TIMEOUT))?;
+        Ok(())
+    }
+}
+
+/// 成功したときの標準出力。失敗したら標準エラー出力をエラーにする
+fn checked_output(spec: &ProcessSpec) -> Result<Vec<u8>> {
+    let output = process::output(spec)?;
+    if !output.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            spec.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn sway_tree() -> Result<serde_json::Value> {
+    Ok(serde_json::from_slice(&checked_output(
+        &ProcessSpec::new("swaymsg")
+            .args(["-t", "get_tree"])
+            .timeout(ACTIVATE_TIMEOUT),
+    )?)?)
+}
+
+/// sway のツリーから `matches` に合うノードを探す (フローティングのウィンドウは `floating_nodes` にある)
+fn sway_find<'a>(
+    node: &'a serde_json::Value,
+    matches: &dyn Fn(&serde_json::Value) -> bool,
+) -> Option<&'a serde_json::Value> {
+    if matches(node) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .into_iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(|child| sway_find(child, matches))
+}
+
+/// Hyprland の `pid` のウィンドウ (`hyprctl clients -j` の要素)
+fn hyprland_client(pid: u32) -> Result<Option<serde_json::Value>> {
+    let clients: Vec<serde_json::Value> = serde_json::from_slice(&checked_output(
+        &ProcessSpec::new("hyprctl")
+            .args(["clients", "-j"])
+            .timeout(ACTIVATE_TIMEOUT),
+    )?)?;
+    Ok(clients
+        .into_iter()
+        .find(|client| client["pid"].as_u64() == Some(u64::from(pid))))
+}
+
+/// デスクトップから渡された xdg-activation のトークン (`XDG_ACTIVATION_TOKEN`、なければ `DESKTOP_STARTUP_ID`)
+pub fn activation_token() -> Option<String> {
+    [ACTIVATION_TOKEN_ENV, STARTUP_ID_ENV]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok().filter(|token| !token.is_empty()))
+}
+
+/// `server_address` に接続している GUI のウィンドウを前面に出す (macOS と Wayland のみ)
+///
+/// フォーカスを奪う制限で `NeovideFocus` が効かないことが多いので、GUI のプロセスを探して
+/// OS (macOS) やコンポジター (Wayland の sway / Hyprland / niri) に前面に出させる。
+/// 対応していない環境や、接続している GUI が見つからなければ何もしない
+pub fn activate(server_address: &str) -> Result<()> {
+    let compositor = Compositor::detect();
+    if !cfg!(target_os = "macos") && compositor.is_none() {
+        return Ok(());
+    }
+    // GUI は `ssh://` ではなくローカルフォワードのアドレスで接続している
+    let server_address = tunnel::local_address(server_address)?;
+    let Some(&pid) = find_gui_processes(&server_address)?.last() else {
+        return Ok(());
+    };
+    match compositor {
+        Some(compositor) => compositor.focus(pid),
+        None => activate_process(pid),
+    }
+}
+
+/// `pid` のウィンドウの左上の位置 (macOS と、位置を持つ Wayland のコンポジターのみ)
+pub fn window_position(pid: u32) -> Result<Option<(i32, i32)>> {
+    match Compositor::detect() {
+        Some(compositor) => compositor.window_position(pid),
+        None if cfg!(target_os = "macos") => {
+            let output = checked_output(
+                &ProcessSpec::new("osascript")
+                    .args([
+                        "-e",
+                        &format!(
+                            "tell application \"System Events\" to get position of \
+                         first window of (first process whose unix id is {pid})"
+                        ),
+                    ])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?;
+            // "x, y" の形で返る
+            let output = String::from_utf8_lossy(&output);
+            Ok(output
+                .trim()
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?))))
+        }
+        None => Ok(None),
+    }
+}
+
+/// `pid` のウィンドウを `position` に移す (macOS ではアクセシビリティの許可が要る)
+pub fn move_window(pid: u32, position: (i32, i32)) -> Result<()> {
+    match Compositor::detect() {
+        Some(compositor) => compositor.move_window(pid, position),
+        None if cfg!(target_os = "macos") => {
+            let (x, y) = position;
+            checked_output(
+                &ProcessSpec::new("osascript")
+                    .args([
+                        "-e",
+                        &format!(
+                            "tell application \"System Events\" to set position of \
+                         first window of (first process whose unix id is {pid}) to {{{x}, {y}}}"
+                        ),
+                    ])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?;
+            Ok(())
+        }
+        None => Err(anyhow!(
+            "Moving windows is not supported in this environment"
+        )),
+    }
+}
+
+/// [`focused_process`]・[`hide`]・[`show`] を使える環境か (macOS と sway・Hyprland・niri)
+pub fn can_toggle() -> bool {
+    cfg!(target_os = "macos") || Compositor::detect().is_some()
+}
+
+/// フォーカスのあるウィンドウのプロセス ID (macOS と Wayland のみ)
+pub fn focused_process() -> Result<Option<u32>> {
+    match Compositor::detect() {
+        Some(compositor) => compositor.focused_pid(),
+        None if cfg!(target_os = "macos") => {
+            let output = checked_output(
+                &ProcessSpec::new("osascript")
+                    .args([
+                        "-e",
+                        "tell application \"System Events\" to get unix id of \
+                         first process whose frontmost is true",
+                    ])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?;
+            Ok(String::from_utf8_lossy(&output).trim().parse().ok())
+        }
+        None => Err(unsupported_toggle()),
+    }
+}
+
+/// `pid` のウィンドウを隠す (macOS ではアプリケーションを隠す)
+pub fn hide(pid: u32) -> Result<()> {
+    match Compositor::detect() {
+        Some(compositor) => compositor.hide(pid),
+        None if cfg!(target_os = "macos") => {
+            checked_output(
+                &ProcessSpec::new("osascript")
+                    .args([
+                        "-e",
+                        &format!(
+                            "tell application \"System Events\" to set visible of \
+                         (first process whose unix id is {pid}) to false"
+                        ),
+                    ])
+                    .timeout(ACTIVATE_TIMEOUT),
+            )?;
+            Ok(())
+        }
+        None => Err(unsupported_toggle()),
+    }
+}
+
+/// [`hide`] で隠したウィンドウを戻して前面に出す
+pub fn show(pid: u32) -> Result<()> {
+    match Compositor::detect() {
+        Some(compositor) => compositor.focus(pid),
+        None if cfg!(target_os = "macos") => activate_process(pid),
+        None => Err(unsupported_toggle()),
+    }
+}
+
+fn unsupported_toggle() -> anyhow::Error {
+    anyhow!("Showing and hiding windows is only supported on macOS and sway, Hyprland or niri")
+}
+
+/// `--server <server_address>` で接続している GUI のプロセス ID (`ps` で探す。nvim 自身は除く)
+pub fn find_gui_processes(server_address: &str) -> Result<Vec<u32>> {
+    let output = process::output(
+        &ProcessSpec::new("ps")
+            .args(["-eo", "pid=,args="])
+            .timeout(ACTIVATE_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!("Failed to list processes"));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pids = stdout
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let pid = parts.next()?.parse::<u32>().ok()?;
+            let args: Vec<&str> = parts.collect();
+
+            let program = Path::new(args.first()?).file_stem()?.to_str()?;
+            let connected = args
+                .windows(2)
+                .any(|pair| pair == ["--server", server_address])
+                || args.contains(&format!("--server={server_address}").as_str());
+            (program != "nvim" && connected).then_some(pid)
+        })
+        .collect();
+    Ok(pids)
+}
+
+/// macOS で `pid` のアプリケーションを前面に出す
+///
+/// 1. `NSRunningApplication` の `activateWithOptions` (JXA 経由。アクセシビリティの許可は要らない)
+/// 2. System Events で `frontmost` にする (アクセシビリティの許可が要る)
+/// 3. .app バンドルの中の実行ファイルなら `open -a <bundle>`
+pub fn activate_process(pid: u32) -> Result<()> {
+    let jxa = format!(
+        "ObjC.import('AppKit'); \
+         $.NSRunningApplication.runningApplicationWithProcessIdentifier({pid})\
+         .activateWithOptions($.NSApplicationActivateIgnoringOtherApps)"
+    );
+    let apple_script = format!(
+        "tell application \"System Events\" to set frontmost of \
+         (first process whose unix id is {pid}) to true"
+    );
+    let mut errors = Vec::new();
+    for spec in [
+        ProcessSpec::new("osascript").args(["-l", "JavaScript", "-e", &jxa]),
+        ProcessSpec::new("osascript").args(["-e", &apple_script]),
+    ] {
+        match process::output(&spec.timeout(ACTIVATE_TIMEOUT)) {
+            // activateWithOptions は前面に出せなければ false を返す
+            Ok(output)
+                if output.success()
+                    && String::from_utf8_lossy(&output.stdout).trim() != "false" =>
+            {
+                return Ok(());
+            }
+            Ok(output) => errors.push(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    if let Some(bundle) = app_bundle_of(pid) {
+        let output = process::output(
+            &ProcessSpec::new("open")
+                .arg("-a")
+                .arg(bundle.to_string_lossy())
+                .timeout(ACTIVATE_TIMEOUT),
+        )?;
+        if output.success() {
+            return Ok(());
+        }
+        errors.push(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    Err(anyhow!(
+        "Cannot activate process {pid}: {}",
+        errors.join("; ")
+    ))
+}
+
+/// niri の `pid` のウィンドウの ID
+fn niri_window_id(pid: u32) -> Result<Option<String>> {
+    let output = process::output(
+        &ProcessSpec::new("niri")
+            .args(["msg", "--json", "windows"])
+            .timeout(ACTIVATE_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "niri msg windows failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let windows: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+    Ok(windows
+        .iter()
+        .find(|window| window["pid"].as_u64() == Some(u64::from(pid)))
+        .map(|window| window["id"].to_string()))
+}
+
+/// `pid` の実行ファイルを含む .app バンドル
+fn app_bundle_of(pid: u32) -> Option<PathBuf> {
+    let output = process::output(
+        &ProcessSpec::new("ps")
+            .args(["-o", "comm=", "-p", &pid.to_string()])
+            .timeout(ACTIVATE_TIMEOUT),
+    )
+    .ok()?;
+    let program = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    program
+        .ancestors()
+        .find(|dir| dir.extension().is_some_and(|ext| ext == "app"))
+        .map(Path::to_path_buf)
+}
+
+fn find_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    let candidates = if cfg!(windows) && Path::new(name).extension().is_none() {
+        vec![dir.join(format!("{name}.exe")), dir.join(name)]
+    } else {
+        vec![dir.join(name)]
+    };
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+/// `neovide` なら `Neovide.app/Contents/MacOS/neovide`
+fn app_bundle_candidates(name: &str) -> Vec<PathBuf> {
+    if !cfg!(target_os = "macos") {
+        return Vec::new();
+    }
+
+    let mut bundle: String = name.chars().take(1).flat_map(char::to_uppercase).collect();
+    bundle.extend(name.chars().skip(1));
+    let relative = Path::new("Applications")
+        .join(format!("{bundle}.app"))
+        .join("Contents/MacOS")
+        .join(name);
+
+    let mut candidates = vec![Path::new("/").join(&relative)];
+    if let Some(home) = std::env::var_os("HOME") {
+        candidates.push(PathBuf::from(home).join(&relative));
+    }
+    candidates
+}
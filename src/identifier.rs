@@ -0,0 +1,242 @@
+//! identifier の導出と正規化
+//!
+//! 同じディレクトリがシンボリックリンク経由・末尾の区切り文字・大文字小文字 (Windows) の違いで
+//! 別の identifier にならないように、パスから identifier を作るときは必ずここを通す。
+
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// パスとして扱う identifier の区切り文字
+#[cfg(windows)]
+const SEPARATORS: &[char] = &['\\', '/'];
+#[cfg(not(windows))]
+const SEPARATORS: &[char] = &['/'];
+
+/// launcher と同じ規則で、開く対象から identifier を作る
+///
+/// - ディレクトリ: そのディレクトリ
+/// - ファイル: カレントディレクトリ
+/// - 存在しないパス: 親ディレクトリ
+/// - 指定なし: カレントディレクトリ
+pub fn from_target(target: Option<&Path>) -> io::Result<String> {
+    let dir = match target {
+        Some(path) if path.is_file() => std::env::current_dir()?,
+        Some(path) if path.is_dir() => path.to_path_buf(),
+        Some(path) => path
+            .parent()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Cannot determine parent directory",
+                )
+            })?
+            .to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+
+    from_path(&dir)
+}
+
+/// 存在するパスの identifier (シンボリックリンクを解決して正規化する)
+pub fn from_path(path: &Path) -> io::Result<String> {
+    Ok(normalize(&canonical_path(path)?.to_string_lossy()))
+}
+
+/// [`from_path`] と同じだが、解決できないパス (別のマシンの nvim の getcwd() など) は字句的に正規化する
+pub fn from_path_lossy(path: &str) -> String {
+    from_path(Path::new(path)).unwrap_or_else(|_| normalize(path))
+}
+
+/// シンボリックリンクを解決した絶対パス。Windows の `\\?\` 接頭辞は付けない
+pub fn canonical_path(path: &Path) -> io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    Ok(match path.to_str() {
+        Some(s) => PathBuf::from(strip_verbatim_prefix(s).as_ref()),
+        None => path,
+    })
+}
+
+/// identifier を字句的に正規化する (ファイルシステムは見ない)
+///
+/// 絶対パスの形をしたものだけが対象で、`project` のような名前はそのまま返す。
+/// - `\\?\C:\dir` → `C:\dir`、`\\?\UNC\server\share` → `\\server\share`
+/// - 末尾の区切り文字を取り除く (ルートは除く)
+/// - Windows では小文字にそろえる
+pub fn normalize(identifier: &str) -> String {
+    let stripped = strip_verbatim_prefix(identifier);
+    let stripped = stripped.as_ref();
+    if !is_path(stripped) {
+        return identifier.to_string();
+    }
+
+    let trimmed = stripped.trim_end_matches(SEPARATORS);
+    let normalized = if trimmed.is_empty() || trimmed.ends_with(':') {
+        // `/` や `C:\` はルートなので区切り文字を 1 つ残す
+        &stripped[..trimmed.len() + 1]
+    } else {
+        trimmed
+    };
+
+    if cfg!(windows) {
+        normalized.to_lowercase()
+    } else {
+        normalized.to_string()
+    }
+}
+
+/// 表示用に、ホームディレクトリの下なら `~` で始める (`/home/me/proj` → `~/proj`)
+pub fn display(identifier: &str) -> String {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .unwrap_or_default();
+    match identifier.strip_prefix(home.as_str()) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with(SEPARATORS)) => {
+            format!("~{rest}")
+        }
+        _ => identifier.to_string(),
+    }
+}
+
+/// [`display`] の最後以外の要素を 1 文字に縮める (`~/src/neovim-manager` → `~/s/neovim-manager`)
+///
+/// `.config` のように `.` で始まる要素は 2 文字残す (fish の `prompt_pwd` と同じ)
+pub fn abbreviate(identifier: &str) -> String {
+    let displayed = display(identifier);
+    let Some(last) = displayed.rfind(SEPARATORS) else {
+        return displayed;
+    };
+
+    let (parents, name) = displayed.split_at(last);
+    let mut abbreviated = String::new();
+    for (i, part) in parents.split(SEPARATORS).enumerate() {
+        if i > 0 {
+            abbreviated.push(name.chars().next().unwrap_or('/'));
+        }
+        let keep = if part.starts_with('.') { 2 } else { 1 };
+        abbreviated.extend(part.chars().take(keep));
+    }
+    abbreviated.push_str(name);
+    abbreviated
+}
+
+/// `path` が `dir` そのものか、その下にあるか (どちらも [`normalize`] 済みとして字句的に比べる)
+pub fn contains(dir: &str, path: &str) -> bool {
+    if !is_path(dir) {
+        return false;
+    }
+    match path.strip_prefix(dir) {
+        Some(rest) => rest.is_empty() || dir.ends_with(SEPARATORS) || rest.starts_with(SEPARATORS),
+        None => false,
+    }
+}
+
+/// 絶対パスの形をした identifier か
+pub fn is_path(identifier: &str) -> bool {
+    let identifier = strip_verbatim_prefix(identifier);
+    Path::new(identifier.as_ref()).is_absolute() || is_windows_absolute(&identifier)
+}
+
+/// コンテナの中と外で同じディレクトリを指すパスの組 (`/workspaces/foo` ⇄ `~/src/foo`)
+///
+/// devcontainer の中の nvim が登録した identifier をホスト側のパスにそろえ、
+/// ホスト側で開くファイルをコンテナの中のパスに直すのに使う
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PathMapping {
+    /// コンテナの中のパス
+    pub container: String,
+    /// ホスト側のパス
+    pub host: String,
+}
+
+impl PathMapping {
+    /// `path` がコンテナの中のパスなら、対応するホスト側のパス
+    pub fn to_host(&self, path: &str) -> Option<String> {
+        let rest = strip_dir(&self.container, path, false)?;
+        Some(join(&self.host, rest))
+    }
+
+    /// `path` がホスト側のパスなら、対応するコンテナの中のパス
+    pub fn to_container(&self, path: &str) -> Option<String> {
+        let rest = strip_dir(&self.host, path, cfg!(windows))?;
+        Some(join(&self.container, rest))
+    }
+}
+
+/// `CONTAINER=HOST` の形
+impl std::str::FromStr for PathMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((container, host)) if !container.trim().is_empty() && !host.trim().is_empty() => {
+                Ok(Self {
+                    container: container.trim().to_string(),
+                    host: host.trim().to_string(),
+                })
+            }
+            _ => Err(format!(
+                "Invalid path mapping {s:?} (expected CONTAINER=HOST)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PathMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.container, self.host)
+    }
+}
+
+/// [`normalize`] したうえで、コンテナの中のパスならホスト側のパスに直す (最初に当てはまった組を使う)
+pub fn normalize_mapped(identifier: &str, mappings: &[PathMapping]) -> String {
+    let normalized = normalize(identifier);
+    mappings
+        .iter()
+        .find_map(|mapping| mapping.to_host(&normalized))
+        .map(|host| normalize(&host))
+        .unwrap_or(normalized)
+}
+
+/// `path` が `dir` そのものかその下なら、残りの部分 (空か区切り文字で始まる)。
+/// コンテナの中のパスはどの OS から見ても `/` 区切りなので、[`is_path`] では判定しない
+fn strip_dir<'a>(dir: &str, path: &'a str, ignore_case: bool) -> Option<&'a str> {
+    let dir = dir.trim_end_matches(['/', '\\']);
+    let head = path.get(..dir.len())?;
+    let matched = if ignore_case {
+        head.to_lowercase() == dir.to_lowercase()
+    } else {
+        head == dir
+    };
+    let rest = &path[dir.len()..];
+    (matched && (rest.is_empty() || rest.starts_with(['/', '\\']))).then_some(rest)
+}
+
+/// `base` に [`strip_dir`] の残りをつなぐ。区切り文字は `base` に合わせる
+fn join(base: &str, rest: &str) -> String {
+    let base = base.trim_end_matches(['/', '\\']);
+    if base.contains('\\') && !base.contains('/') {
+        format!("{base}{}", rest.replace('/', "\\"))
+    } else {
+        format!("{base}{}", rest.replace('\\', "/"))
+    }
+}
+
+/// `C:\` や `\\server\share` の形か (どの OS でも判定できるようにする)
+fn is_windows_absolute(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    let drive = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/');
+    drive || path.starts_with(r"\\")
+}
+
+fn strip_verbatim_prefix(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix(r"\\?\UNC\") {
+        Some(rest) => Cow::Owned(format!(r"\\{rest}")),
+        None => Cow::Borrowed(path.strip_prefix(r"\\?\").unwrap_or(path)),
+    }
+}
@@ -0,0 +1,212 @@
+//! デスクトップエントリ (`.desktop`) のインストール (`neovim-launcher install-desktop`)
+//!
+//! ファイルを開く `neovim-launcher.desktop` (`--handler %F`) と、`nvim://` の URL を開く
+//! `neovim-launcher-url.desktop` (`--handler %u`) を書き出す。`%F` にはファイル以外の URL が渡されないので分けている。
+
+use anyhow::{anyhow, Result};
+use neovim_manager::config;
+use neovim_manager::process::{self, ProcessSpec};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// ファイルを開くエントリ
+pub const DESKTOP_FILE: &str = "neovim-launcher.desktop";
+/// `nvim://` を開くエントリ
+pub const URL_DESKTOP_FILE: &str = "neovim-launcher-url.desktop";
+/// 開く URL のスキーム (`nvim://file/<path>:<line>:<column>`)
+pub const URL_SCHEME_MIME: &str = "x-scheme-handler/nvim";
+
+/// 開けるファイルの種類 (nvim.desktop のものにいくつか加えたもの)
+pub const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain",
+    "text/english",
+    "text/markdown",
+    "text/x-makefile",
+    "text/x-c",
+    "text/x-c++",
+    "text/x-csrc",
+    "text/x-chdr",
+    "text/x-c++src",
+    "text/x-c++hdr",
+    "text/x-java",
+    "text/x-moc",
+    "text/x-pascal",
+    "text/x-tcl",
+    "text/x-tex",
+    "text/x-python",
+    "text/x-rust",
+    "text/x-go",
+    "text/x-lua",
+    "text/x-csharp",
+    "text/x-log",
+    "text/css",
+    "text/csv",
+    "application/x-shellscript",
+    "application/json",
+    "application/toml",
+    "application/x-yaml",
+    "application/xml",
+];
+
+/// xdg-mime などを待つ上限
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `install-desktop` の指定
+pub struct InstallOptions {
+    /// 書き出すディレクトリ (既定は `$XDG_DATA_HOME/applications`)
+    pub dir: Option<PathBuf>,
+    pub icon: String,
+    /// 既定のアプリケーションにする MIME タイプ (`all` はエントリのすべて)
+    pub defaults: Vec<String>,
+    /// 書き出さずに内容を表示する
+    pub print: bool,
+}
+
+pub fn install(options: &InstallOptions) -> Result<()> {
+    if cfg!(any(windows, target_os = "macos")) {
+        return Err(anyhow!(
+            "install-desktop writes freedesktop.org desktop entries and is only for Linux and BSD \
+             (use install-windows on Windows)"
+        ));
+    }
+
+    let exec = exec_path()?;
+    let entries = [
+        (DESKTOP_FILE, desktop_entry(&exec, &options.icon)),
+        (URL_DESKTOP_FILE, url_desktop_entry(&exec, &options.icon)),
+    ];
+    if options.print {
+        for (name, content) in &entries {
+            println!("# {name}\n{content}");
+        }
+        return Ok(());
+    }
+
+    let dir = match &options.dir {
+        Some(dir) => dir.clone(),
+        None => config::data_home()
+            .ok_or_else(|| anyhow!("Cannot determine the data directory (set XDG_DATA_HOME)"))?
+            .join("applications"),
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    for (name, content) in &entries {
+        let path = dir.join(name);
+        std::fs::write(&path, content)
+            .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+
+    // キャッシュの更新はなくても動くので、失敗しても続ける
+    if let Err(e) = run("update-desktop-database", &[&dir.to_string_lossy()]) {
+        eprintln!("Skipped updating the desktop database: {e:#}");
+    }
+
+    // URL は既定のハンドラーになっていないと開かれないので、常に登録する
+    run("xdg-mime", &["default", URL_DESKTOP_FILE, URL_SCHEME_MIME])?;
+    println!("Registered {URL_DESKTOP_FILE} for {URL_SCHEME_MIME}");
+
+    let defaults = default_types(&options.defaults);
+    if !defaults.is_empty() {
+        let mut args = vec!["default", DESKTOP_FILE];
+        args.extend(defaults.iter().map(String::as_str));
+        run("xdg-mime", &args)?;
+        println!("Registered {DESKTOP_FILE} for {}", defaults.join(", "));
+    }
+
+    Ok(())
+}
+
+/// `all` をエントリの MIME タイプすべてに展開する
+fn default_types(requested: &[String]) -> Vec<String> {
+    let mut types: Vec<String> = Vec::new();
+    for mime in requested {
+        let expanded = if mime == "all" {
+            TEXT_MIME_TYPES
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect()
+        } else {
+            vec![mime.clone()]
+        };
+        for mime in expanded {
+            if !types.contains(&mime) {
+                types.push(mime);
+            }
+        }
+    }
+    types
+}
+
+fn desktop_entry(exec: &str, icon: &str) -> String {
+    let mime_types: String = TEXT_MIME_TYPES
+        .iter()
+        .map(|mime| format!("{mime};"))
+        .collect();
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Neovim (Instance Manager)\n\
+         GenericName=Text Editor\n\
+         Comment=Open files in the running Neovim instance of their project\n\
+         Exec={exec} --handler %F\n\
+         Icon={icon}\n\
+         Terminal=false\n\
+         Categories=Utility;TextEditor;Development;\n\
+         Keywords=Text;Editor;Neovim;Vim;\n\
+         StartupNotify=false\n\
+         MimeType={mime_types}\n"
+    )
+}
+
+fn url_desktop_entry(exec: &str, icon: &str) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Neovim (Instance Manager) URL Handler\n\
+         Exec={exec} --handler %u\n\
+         Icon={icon}\n\
+         Terminal=false\n\
+         NoDisplay=true\n\
+         StartupNotify=false\n\
+         MimeType={URL_SCHEME_MIME};\n"
+    )
+}
+
+/// Exec に書く実行ファイルのパス (空白などを含めば引用符で囲む)
+fn exec_path() -> Result<String> {
+    let path = std::env::current_exe()?;
+    let exe = path
+        .to_str()
+        .ok_or_else(|| anyhow!("The launcher path is not valid UTF-8: {}", path.display()))?;
+
+    let needs_quote = exe
+        .chars()
+        .any(|c| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c));
+    if !needs_quote {
+        return Ok(exe.to_string());
+    }
+    // 引用符の中では `"` `` ` `` `$` `\` をエスケープし、さらに文字列の値として `\` を重ねる
+    let mut quoted = String::new();
+    for c in exe.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    Ok(format!("\"{}\"", quoted.replace('\\', "\\\\")))
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = process::output(
+        &ProcessSpec::new(program)
+            .args(args.iter().copied())
+            .timeout(COMMAND_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
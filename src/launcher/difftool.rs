@@ -0,0 +1,249 @@
+//! git の difftool / mergetool として使う
+//!
+//! プロジェクトのインスタンスに新しいタブで差分を開き、見終わる (マージ結果のバッファを閉じる) まで待つ。
+//! インスタンスがなければ git の nvimdiff と同じように、端末で `nvim -d` を実行する。
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use neovim_manager::identifier::PathMapping;
+use neovim_manager::nvim::{NvimClient, Value};
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::utils;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// バッファが閉じられたかを確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 左から順に並べて差分を取り、`bottom` があれば下に全幅で開く。
+/// 待つバッファ (`bottom` があればそれだけ) と、新しく作ったバッファを返す
+const OPEN_LUA: &str = r#"
+local paths, bottom = ...
+if bottom == vim.NIL then
+  bottom = nil
+end
+local function find(path)
+  local full = vim.fn.fnamemodify(path, ':p')
+  for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+    if vim.api.nvim_buf_get_name(buf) == full then
+      return buf
+    end
+  end
+end
+local created, watch = {}, {}
+local function open(cmd, path, mods)
+  local existed = find(path) ~= nil
+  vim.cmd({ cmd = cmd, args = { path }, mods = mods or {} })
+  vim.cmd.diffthis()
+  local buf = vim.api.nvim_get_current_buf()
+  if not existed then
+    table.insert(created, buf)
+  end
+  return buf
+end
+for i, path in ipairs(paths) do
+  local buf = i == 1 and open('tabedit', path) or open('vsplit', path, { split = 'belowright' })
+  if bottom == nil then
+    table.insert(watch, buf)
+  end
+end
+if bottom ~= nil then
+  table.insert(watch, open('split', bottom, { split = 'botright' }))
+end
+return { watch = watch, created = created }
+"#;
+
+/// 待っているバッファのどれかがウィンドウに表示されているか、と未保存の変更があるか
+const POLL_LUA: &str = r#"
+local watch = ...
+local shown, modified = false, false
+for _, buf in ipairs(watch) do
+  if vim.api.nvim_buf_is_valid(buf) then
+    shown = shown or #vim.fn.win_findbuf(buf) > 0
+    modified = modified or vim.bo[buf].modified
+  end
+end
+return { shown = shown, modified = modified }
+"#;
+
+/// 開いたバッファのうち、表示されておらず変更もないものを消す (git が消す一時ファイルなので残さない)
+const CLEANUP_LUA: &str = r#"
+for _, buf in ipairs(...) do
+  if vim.api.nvim_buf_is_valid(buf) and #vim.fn.win_findbuf(buf) == 0 and not vim.bo[buf].modified then
+    pcall(vim.api.nvim_buf_delete, buf, {})
+  end
+end
+"#;
+
+#[derive(Debug, Deserialize)]
+struct Opened {
+    watch: Vec<i64>,
+    created: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Poll {
+    shown: bool,
+    modified: bool,
+}
+
+/// `git difftool` / `git mergetool` から渡されたファイル
+#[derive(Debug, Clone)]
+pub enum DiffTool {
+    Diff {
+        local: PathBuf,
+        remote: PathBuf,
+    },
+    Merge {
+        local: PathBuf,
+        base: PathBuf,
+        remote: PathBuf,
+        merged: PathBuf,
+    },
+}
+
+impl DiffTool {
+    /// 横に並べるファイルと、下に置くファイル (マージ結果)
+    fn layout(&self) -> (Vec<&Path>, Option<&Path>) {
+        match self {
+            DiffTool::Diff { local, remote } => (vec![local, remote], None),
+            DiffTool::Merge {
+                local,
+                base,
+                remote,
+                merged,
+            } => {
+                // 追加同士の衝突では共通の祖先がなく、BASE は空か存在しない
+                let mut paths = vec![local.as_path()];
+                if base.metadata().is_ok_and(|meta| meta.len() > 0) {
+                    paths.push(base);
+                }
+                paths.push(remote);
+                (paths, Some(merged))
+            }
+        }
+    }
+
+    /// インスタンスで開いて閉じられるまで待ち、git に返す終了コードを返す
+    ///
+    /// mergetool では、未保存のまま閉じた・衝突の印が残っている場合に 1 (git は解決しなかったとみなす)
+    /// `paths`・`bottom` は `layout()` をインスタンスから見たパスに直したもの
+    fn run_in_instance(
+        &self,
+        server_address: &str,
+        paths: Vec<String>,
+        bottom: Option<String>,
+    ) -> Result<i32> {
+        let mut nvim = NvimClient::connect(server_address)?;
+
+        let paths = paths.into_iter().map(Value::from).collect();
+        let bottom = bottom.map(Value::from).unwrap_or(Value::Nil);
+        let opened: Opened = nvim.request_as(
+            "nvim_exec_lua",
+            vec![
+                Value::from(OPEN_LUA),
+                Value::Array(vec![Value::Array(paths), bottom]),
+            ],
+        )?;
+        info!("Opened {self:?} in {server_address}, waiting for it to close");
+
+        let watch = Value::Array(opened.watch.iter().copied().map(Value::from).collect());
+        let modified = loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let poll: Poll = nvim
+                .request_as(
+                    "nvim_exec_lua",
+                    vec![Value::from(POLL_LUA), Value::Array(vec![watch.clone()])],
+                )
+                .map_err(|e| anyhow!("Lost the Neovim instance while waiting: {e}"))?;
+            if !poll.shown {
+                break poll.modified;
+            }
+        };
+
+        let created = Value::Array(opened.created.iter().copied().map(Value::from).collect());
+        nvim.request(
+            "nvim_exec_lua",
+            vec![Value::from(CLEANUP_LUA), Value::Array(vec![created])],
+        )?;
+
+        Ok(match self {
+            DiffTool::Diff { .. } => 0,
+            DiffTool::Merge { merged, .. } => {
+                if modified {
+                    eprintln!("{} was closed without saving", merged.display());
+                    1
+                } else if has_conflict_markers(merged) {
+                    eprintln!("{} still has conflict markers", merged.display());
+                    1
+                } else {
+                    0
+                }
+            }
+        })
+    }
+
+    /// インスタンスがない場合に、端末で `nvim -d` を実行する (git の nvimdiff と同じ並び)
+    pub fn run_in_terminal(&self) -> Result<i32> {
+        let (paths, bottom) = self.layout();
+        let mut spec = ProcessSpec::new("nvim").arg("-d").inherit_stdio();
+        if let Some(bottom) = bottom {
+            spec = spec.arg(bottom.to_string_lossy());
+        }
+        spec = spec.args(paths.iter().map(|path| path.to_string_lossy().to_string()));
+        if bottom.is_some() {
+            // 最初に開いた結果のウィンドウを一番下に移す
+            spec = spec.args(["-c", "1wincmd w", "-c", "wincmd J"]);
+        }
+
+        let code = process::status(&spec)?;
+        Ok(code.unwrap_or(1))
+    }
+}
+
+/// 行頭の `<<<<<<< ` / `>>>>>>> ` が残っているか
+fn has_conflict_markers(path: &Path) -> bool {
+    std::fs::read(path).is_ok_and(|content| {
+        String::from_utf8_lossy(&content)
+            .lines()
+            .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> "))
+    })
+}
+
+/// インスタンスにフォーカスしてから開く
+///
+/// コンテナの中のインスタンスにはコンテナの中のパスで渡す
+pub async fn run(
+    tool: DiffTool,
+    server_address: Option<String>,
+    path_mappings: &[PathMapping],
+) -> Result<i32> {
+    match server_address {
+        Some(server_address) => {
+            if let Err(e) = utils::focus_nvim_instance(&server_address).await {
+                warn!("{e:#}");
+            }
+            let (paths, bottom) = tool.layout();
+            let mut remote = Vec::new();
+            for path in paths {
+                remote.push(
+                    utils::remote_path(&server_address, &path.to_string_lossy(), path_mappings)
+                        .await,
+                );
+            }
+            let bottom = match bottom {
+                Some(path) => Some(
+                    utils::remote_path(&server_address, &path.to_string_lossy(), path_mappings)
+                        .await,
+                ),
+                None => None,
+            };
+            tokio::task::spawn_blocking(move || {
+                tool.run_in_instance(&server_address, remote, bottom)
+            })
+            .await?
+        }
+        None => tokio::task::spawn_blocking(move || tool.run_in_terminal()).await?,
+    }
+}
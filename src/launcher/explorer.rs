@@ -0,0 +1,284 @@
+//! Windows のエクスプローラーへの登録 (`neovim-launcher install-windows` / `uninstall-windows`)
+//!
+//! 管理者権限が要らないように、すべて `HKEY_CURRENT_USER\Software\Classes` の下に `reg.exe` で書き込む。
+//! - 右クリックメニューの「Open with Neovim Manager」(ファイル・フォルダー・フォルダーの背景)
+//! - 拡張子の「プログラムから開く」(`OpenWithProgids`。既定のアプリケーションは Windows の設定画面で選ぶ)
+//! - `nvim://` の URL プロトコル
+
+use anyhow::{anyhow, Result};
+use neovim_manager::process::{self, ProcessSpec};
+use std::time::Duration;
+
+const CLASSES: &str = r"HKCU\Software\Classes";
+/// ファイルの関連付けに使う ProgID
+const PROG_ID: &str = "NeovimManager.File";
+/// 右クリックメニューのキー名
+const VERB: &str = "NeovimManager";
+const MENU_TEXT: &str = "Open with Neovim Manager";
+/// ProgID に記録する、関連付けた拡張子 (`;` 区切り。uninstall-windows が読む)
+const EXTENSIONS_VALUE: &str = "NeovimManagerExtensions";
+
+/// `--extension` を指定しない場合に関連付ける拡張子
+pub const TEXT_EXTENSIONS: &[&str] = &[
+    ".txt",
+    ".md",
+    ".markdown",
+    ".log",
+    ".csv",
+    ".ini",
+    ".cfg",
+    ".conf",
+    ".toml",
+    ".yaml",
+    ".yml",
+    ".json",
+    ".xml",
+    ".lua",
+    ".vim",
+    ".rs",
+    ".py",
+    ".go",
+    ".c",
+    ".h",
+    ".cpp",
+    ".hpp",
+    ".cs",
+    ".java",
+    ".js",
+    ".ts",
+    ".sh",
+    ".ps1",
+    ".bat",
+    ".cmd",
+];
+
+/// reg.exe を待つ上限
+const REG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 書き込む値
+struct RegValue {
+    key: String,
+    /// None は既定の値
+    name: Option<&'static str>,
+    /// None は値を持たない REG_NONE (`OpenWithProgids` の項目)
+    data: Option<String>,
+}
+
+impl RegValue {
+    fn new(key: impl Into<String>, name: Option<&'static str>, data: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            name,
+            data: Some(data.into()),
+        }
+    }
+
+    /// `reg add` の引数
+    fn args(&self) -> Vec<&str> {
+        let mut args = vec!["add", &self.key];
+        match self.name {
+            Some(name) => args.extend(["/v", name]),
+            None => args.push("/ve"),
+        }
+        match &self.data {
+            Some(data) => args.extend(["/t", "REG_SZ", "/d", data]),
+            None => args.extend(["/t", "REG_NONE"]),
+        }
+        args.push("/f");
+        args
+    }
+}
+
+pub fn install(extensions: &[String], print: bool) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let exe = exe.to_string_lossy();
+    let extensions: Vec<String> = if extensions.is_empty() {
+        TEXT_EXTENSIONS.iter().map(|ext| ext.to_string()).collect()
+    } else {
+        extensions
+            .iter()
+            .map(|ext| normalize_extension(ext))
+            .collect()
+    };
+
+    let values = values(&exe, &extensions);
+    if print {
+        for value in &values {
+            let args: Vec<String> = value.args().into_iter().map(quote).collect();
+            println!("reg {}", args.join(" "));
+        }
+        return Ok(());
+    }
+    ensure_windows("install-windows")?;
+
+    for value in &values {
+        reg(&value.args())?;
+    }
+    println!(
+        "Registered the context menu, {} file type(s) and the nvim:// protocol",
+        extensions.len()
+    );
+    println!("Choose \"{MENU_TEXT}\" as the default app in Settings > Apps > Default apps if you want to");
+
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    ensure_windows("uninstall-windows")?;
+
+    // 関連付けた拡張子は ProgID に記録してある
+    let extensions = reg(&[
+        "query",
+        &format!(r"{CLASSES}\{PROG_ID}"),
+        "/v",
+        EXTENSIONS_VALUE,
+    ])
+    .ok()
+    .and_then(|output| {
+        output
+            .lines()
+            .find(|line| line.trim_start().starts_with(EXTENSIONS_VALUE))
+            .and_then(|line| line.split_whitespace().nth(2).map(str::to_string))
+    })
+    .unwrap_or_default();
+    for extension in extensions.split(';').filter(|ext| !ext.is_empty()) {
+        delete(
+            &format!(r"{CLASSES}\{extension}\OpenWithProgids"),
+            Some(PROG_ID),
+        )?;
+    }
+
+    for key in [
+        format!(r"{CLASSES}\*\shell\{VERB}"),
+        format!(r"{CLASSES}\Directory\shell\{VERB}"),
+        format!(r"{CLASSES}\Directory\Background\shell\{VERB}"),
+        format!(r"{CLASSES}\{PROG_ID}"),
+        format!(r"{CLASSES}\nvim"),
+    ] {
+        delete(&key, None)?;
+    }
+    println!("Removed the Explorer integration");
+
+    Ok(())
+}
+
+fn values(exe: &str, extensions: &[String]) -> Vec<RegValue> {
+    let open = |arg: &str| format!("\"{exe}\" --handler \"{arg}\"");
+    let icon = format!("\"{exe}\",0");
+
+    let mut values = Vec::new();
+    // 右クリックメニュー (フォルダーの背景では %V が開いているフォルダー)
+    for (key, arg) in [
+        (format!(r"{CLASSES}\*\shell\{VERB}"), "%1"),
+        (format!(r"{CLASSES}\Directory\shell\{VERB}"), "%1"),
+        (
+            format!(r"{CLASSES}\Directory\Background\shell\{VERB}"),
+            "%V",
+        ),
+    ] {
+        values.push(RegValue::new(&key, None, MENU_TEXT));
+        values.push(RegValue::new(&key, Some("Icon"), &icon));
+        values.push(RegValue::new(format!(r"{key}\command"), None, open(arg)));
+    }
+
+    // ファイルの関連付け
+    let prog_id = format!(r"{CLASSES}\{PROG_ID}");
+    values.push(RegValue::new(&prog_id, None, "Text file (Neovim Manager)"));
+    values.push(RegValue::new(
+        &prog_id,
+        Some(EXTENSIONS_VALUE),
+        extensions.join(";"),
+    ));
+    values.push(RegValue::new(
+        format!(r"{prog_id}\DefaultIcon"),
+        None,
+        &icon,
+    ));
+    values.push(RegValue::new(
+        format!(r"{prog_id}\shell\open\command"),
+        None,
+        open("%1"),
+    ));
+    for extension in extensions {
+        values.push(RegValue {
+            key: format!(r"{CLASSES}\{extension}\OpenWithProgids"),
+            name: Some(PROG_ID),
+            data: None,
+        });
+    }
+
+    // nvim:// の URL
+    let protocol = format!(r"{CLASSES}\nvim");
+    values.push(RegValue::new(&protocol, None, "URL:Neovim Manager"));
+    values.push(RegValue::new(&protocol, Some("URL Protocol"), ""));
+    values.push(RegValue::new(
+        format!(r"{protocol}\DefaultIcon"),
+        None,
+        &icon,
+    ));
+    values.push(RegValue::new(
+        format!(r"{protocol}\shell\open\command"),
+        None,
+        open("%1"),
+    ));
+
+    values
+}
+
+/// `txt` → `.txt`
+fn normalize_extension(extension: &str) -> String {
+    let extension = extension.trim().to_lowercase();
+    if extension.starts_with('.') {
+        extension
+    } else {
+        format!(".{extension}")
+    }
+}
+
+fn ensure_windows(command: &str) -> Result<()> {
+    if cfg!(windows) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{command} edits the Windows registry and is only for Windows"
+        ))
+    }
+}
+
+/// コマンドプロンプトに貼り付けられるように引数を囲む
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '"']) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('"', "\\\""))
+}
+
+/// reg.exe を実行して標準出力を返す
+fn reg<S: AsRef<str>>(args: &[S]) -> Result<String> {
+    let output = process::output(
+        &ProcessSpec::new("reg")
+            .args(args.iter().map(|arg| arg.as_ref().to_string()))
+            .timeout(REG_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "reg {} failed: {}",
+            args.first().map(AsRef::as_ref).unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// キー (`name` があればその値) を削除する。もともとない場合は何もしない
+fn delete(key: &str, name: Option<&str>) -> Result<()> {
+    let mut args = vec![key];
+    if let Some(name) = name {
+        args.extend(["/v", name]);
+    }
+    if reg(&[&["query"], args.as_slice()].concat()).is_err() {
+        return Ok(());
+    }
+    reg(&[&["delete"], args.as_slice(), &["/f"]].concat())?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! Neovide のウィンドウの大きさと位置をプロジェクトごとに覚える (`launcher.remember_geometry`)
+//!
+//! 新しく起動したインスタンスを監視している間、Neovide のグリッドの大きさ (`nvim_list_uis()`) と、
+//! GUI のプロセス ID からコンポジターや OS に問い合わせたウィンドウの位置を定期的に読み、
+//! 状態ディレクトリの `window-geometry.json` に identifier ごとに記録する。
+//! 次にそのプロジェクトで Neovide を起動するときは `--grid <列>x<行>` を渡し、ウィンドウが現れたら元の位置に移す。
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use neovim_manager::config::{self, LauncherConfig};
+use neovim_manager::gui::{self, GuiCommand};
+use neovim_manager::nvim::NvimClient;
+use neovim_manager::tunnel;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// 大きさと位置を読む間隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// 位置を戻すために GUI のウィンドウが現れるのを待つ間隔と上限
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const WINDOW_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 大きさを指定する Neovide の引数 (`launcher.neovide_args` にあれば覚えた大きさは渡さない)
+const SIZE_ARGS: [&str; 3] = ["--grid", "--size", "--maximized"];
+
+/// 1 つのプロジェクトのウィンドウ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Geometry {
+    /// グリッドの列数と行数
+    pub columns: u64,
+    pub lines: u64,
+    /// ウィンドウの左上の位置 (読めない環境では記録しない)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+}
+
+/// 記録のファイル (状態ディレクトリの `window-geometry.json`)
+fn path() -> Option<PathBuf> {
+    config::state_dir().map(|dir| dir.join("window-geometry.json"))
+}
+
+/// identifier からウィンドウへの対応。ファイルがない・読めない場合は空
+fn read_all() -> BTreeMap<String, Geometry> {
+    path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn load(identifier: &str) -> Option<Geometry> {
+    read_all().get(identifier).copied()
+}
+
+fn save(identifier: &str, geometry: Geometry) -> Result<()> {
+    let path = path().ok_or_else(|| anyhow!("Cannot determine the state directory"))?;
+    let mut all = read_all();
+    all.insert(identifier.to_string(), geometry);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    }
+    // 同時に動く launcher が書きかけを読まないよう、一時ファイルに書いてから置き換える
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    std::fs::write(&tmp, serde_json::to_string_pretty(&all)?)
+        .map_err(|e| anyhow!("Cannot write {}: {e}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))
+}
+
+fn enabled(config: &LauncherConfig, gui: &GuiCommand) -> bool {
+    config.remember_geometry.value && gui.is_neovide()
+}
+
+/// `identifier` の前回の大きさで起動する引数 (`--grid <列>x<行>`)。覚えていなければ空
+pub fn neovide_args(config: &LauncherConfig, gui: &GuiCommand, identifier: &str) -> Vec<String> {
+    let sized = gui.args.iter().any(|arg| {
+        SIZE_ARGS
+            .iter()
+            .any(|size| arg == size || arg.starts_with(&format!("{size}=")))
+    });
+    if !enabled(config, gui) || sized {
+        return Vec::new();
+    }
+    match load(identifier) {
+        Some(geometry) => vec![
+            "--grid".to_string(),
+            format!("{}x{}", geometry.columns, geometry.lines),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// 起動した Neovide のウィンドウを前回の位置に移し、大きさと位置の記録を始める
+///
+/// Neovide でなければ (tmux で接続した場合も) 何もしない。監視を終えたら返したタスクを止める
+pub fn track(
+    config: &LauncherConfig,
+    identifier: &str,
+    server_address: &str,
+) -> Option<JoinHandle<()>> {
+    if !enabled(config, &GuiCommand::resolve(config)) {
+        return None;
+    }
+    let identifier = identifier.to_string();
+    let server_address = server_address.to_string();
+
+    Some(tokio::spawn(async move {
+        let mut last = load(&identifier);
+        if let Some(position) = last.and_then(|geometry| geometry.position) {
+            let server_address = server_address.clone();
+            let restored =
+                tokio::task::spawn_blocking(move || restore_position(&server_address, position))
+                    .await;
+            if let Ok(Err(e)) = restored {
+                warn!("Cannot restore the window position: {e:#}");
+            }
+        }
+
+        loop {
+            sleep(SAMPLE_INTERVAL).await;
+            let sampled = {
+                let server_address = server_address.clone();
+                tokio::task::spawn_blocking(move || sample(&server_address)).await
+            };
+            let geometry = match sampled {
+                Ok(Ok(Some(geometry))) => geometry,
+                // まだ (もう) UI が接続していない
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    info!("Cannot read the window geometry: {e:#}");
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            // 位置を読めなかったときは前回の位置を残す
+            let geometry = Geometry {
+                position: geometry.position.or(last.and_then(|last| last.position)),
+                ..geometry
+            };
+            if last != Some(geometry) {
+                if let Err(e) = save(&identifier, geometry) {
+                    warn!("Cannot save the window geometry: {e:#}");
+                }
+                last = Some(geometry);
+            }
+        }
+    }))
+}
+
+/// `server_address` に接続した GUI のプロセス ID (複数あれば最後に起動したもの)
+fn gui_pid(server_address: &str) -> Result<Option<u32>> {
+    let server_address = tunnel::local_address(server_address)?;
+    Ok(gui::find_gui_processes(&server_address)?.last().copied())
+}
+
+/// GUI のウィンドウが現れるのを待ってから `position` に移す
+fn restore_position(server_address: &str, position: (i32, i32)) -> Result<()> {
+    let deadline = Instant::now() + WINDOW_TIMEOUT;
+    loop {
+        if let Some(pid) = gui_pid(server_address)? {
+            // プロセスが起動してもウィンドウができるまでは位置を読めない
+            if let Ok(Some(_)) = gui::window_position(pid) {
+                info!("Moving the window of process {pid} to {position:?}");
+                return gui::move_window(pid, position);
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow!("No window connected to {server_address} appeared"));
+        }
+        std::thread::sleep(WINDOW_POLL_INTERVAL);
+    }
+}
+
+/// 今のグリッドの大きさとウィンドウの位置。UI が接続していなければ None
+fn sample(server_address: &str) -> Result<Option<Geometry>> {
+    let mut nvim = NvimClient::connect(server_address)?;
+    let uis = nvim.attached_uis()?;
+    // Neovide は rgb の UI として接続する
+    let Some(ui) = uis.iter().find(|ui| ui.rgb).or(uis.first()) else {
+        return Ok(None);
+    };
+
+    // プロセスを探せない環境 (`ps` がないなど) でも大きさは記録する
+    let pid = gui_pid(server_address).unwrap_or_else(|e| {
+        info!("Cannot find the GUI process: {e:#}");
+        None
+    });
+    let position = match pid {
+        Some(pid) => gui::window_position(pid).unwrap_or_else(|e| {
+            info!("Cannot read the window position: {e:#}");
+            None
+        }),
+        None => None,
+    };
+    Ok(Some(Geometry {
+        columns: ui.width,
+        lines: ui.height,
+        position,
+    }))
+}
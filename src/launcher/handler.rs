@@ -0,0 +1,215 @@
+//! ファイルを開く既定のアプリケーションとして使う (`--handler %F`)
+//!
+//! ファイルを含むディレクトリのインスタンスを探して開き、フォーカスしたらすぐに終了する。
+//! 監視ループで待たないので、ファイルマネージャーなどの親プロセスを引き止めない。
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use neovim_manager::config::Config;
+use neovim_manager::direnv;
+use neovim_manager::gui;
+use neovim_manager::identifier;
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::stats::EventKind;
+use neovim_manager::utils::{self, OpenFileOptions};
+use neovim_manager::{HealthStatus, InstanceResult};
+use std::path::{Path, PathBuf};
+
+use super::{
+    focus_existing_instance, launch_client, nvim_env, parse_goto, record_recent, record_stats,
+    LauncherClient,
+};
+
+/// 開く URL の接頭辞 (`nvim://file/<path>[:<line>[:<column>]]`、VS Code の `vscode://file/...` と同じ形)
+const URL_PREFIX: &str = "nvim://";
+
+/// `files` をそれぞれ一番近いインスタンスで開く。ファイルがなければカレントディレクトリを開く
+pub async fn run(
+    client: &LauncherClient,
+    config: &Config,
+    files: &[PathBuf],
+    open_options: &OpenFileOptions,
+) -> Result<()> {
+    let targets = if files.is_empty() {
+        vec![(std::env::current_dir()?, *open_options)]
+    } else {
+        files
+            .iter()
+            .map(
+                |file| match file.to_str().and_then(|url| url.strip_prefix(URL_PREFIX)) {
+                    Some(rest) => {
+                        let (path, line, column) = parse_url(rest)?;
+                        Ok((
+                            path,
+                            OpenFileOptions {
+                                line,
+                                column,
+                                ..*open_options
+                            },
+                        ))
+                    }
+                    None => Ok((file.clone(), *open_options)),
+                },
+            )
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    // 前のファイルで起動したインスタンスに後のファイルも開けるよう、1 つずつ一覧を取り直す
+    for (target, open_options) in targets {
+        let open_options = &open_options;
+        let path = identifier::canonical_path(&target)
+            .map_err(|e| anyhow!("Cannot open {}: {e}", target.display()))?;
+        let (dir, file) = if path.is_dir() {
+            (path.clone(), None)
+        } else {
+            let dir = path
+                .parent()
+                .ok_or_else(|| anyhow!("Cannot determine parent directory of {}", path.display()))?
+                .to_path_buf();
+            (dir, Some(path.clone()))
+        };
+
+        let instances = client.list_instances().await?;
+        let key = identifier::normalize(&path.to_string_lossy());
+        match best_instance(&instances, &key) {
+            Some(instance) => {
+                info!("Opening {} in {}", path.display(), instance.identifier);
+                focus_existing_instance(
+                    &instance.identifier,
+                    &instance.server_address,
+                    None,
+                    file.as_ref(),
+                    open_options,
+                    &config.manager.path_mappings.value,
+                )
+                .await?;
+                if let Err(e) = client.touch_instance(&instance.identifier).await {
+                    warn!("{e:#}");
+                }
+                record_recent(&instance.identifier);
+                record_stats(
+                    &config.launcher,
+                    &instance.identifier,
+                    EventKind::Attach,
+                    None,
+                );
+            }
+            None => start_instance(client, config, &dir, file.as_deref(), open_options).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// `nvim://` の後ろを読む。パスはパーセントエンコードを戻してから `--goto` と同じ規則で行・列を分ける
+fn parse_url(rest: &str) -> Result<(PathBuf, Option<u32>, Option<u32>)> {
+    let url = format!("{URL_PREFIX}{rest}");
+    let path = rest
+        .strip_prefix("file/")
+        .ok_or_else(|| anyhow!("Unsupported URL {url:?} (expected {URL_PREFIX}file/<path>)"))?;
+    let path = percent_decode(path).ok_or_else(|| anyhow!("Invalid escape in URL {url:?}"))?;
+
+    // `nvim://file/C:/src/a.rs` のようなドライブ名の前には区切り文字を付けない
+    let is_drive =
+        path.as_bytes().get(1) == Some(&b':') && path.as_bytes()[0].is_ascii_alphabetic();
+    let path = if is_drive { path } else { format!("/{path}") };
+    parse_goto(&path)
+}
+
+/// `%XX` を戻す。UTF-8 として正しくなければ None
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// `path` を含むインスタンスのうち、一番深いディレクトリのもの (同じなら最近使ったもの)
+///
+/// identifier と作業ディレクトリ (`cwd`) のどちらかが `path` を含めば候補にする。応答しないものは除く
+fn best_instance<'a>(instances: &'a [InstanceResult], path: &str) -> Option<&'a InstanceResult> {
+    instances
+        .iter()
+        .filter(|instance| {
+            matches!(
+                instance.health_status,
+                HealthStatus::Starting | HealthStatus::Healthy
+            )
+        })
+        .filter_map(|instance| {
+            let depth = [Some(instance.identifier.as_str()), instance.cwd.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter(|dir| identifier::contains(dir, path))
+                .map(str::len)
+                .max()?;
+            Some((depth, instance.last_used, instance))
+        })
+        .max_by_key(|(depth, last_used, _)| (*depth, *last_used))
+        .map(|(_, _, instance)| instance)
+}
+
+/// `dir` のインスタンスを起動して登録し、GUI で接続する (終了は待たない)
+pub async fn start_instance(
+    client: &LauncherClient,
+    config: &Config,
+    dir: &Path,
+    file: Option<&Path>,
+    open_options: &OpenFileOptions,
+) -> Result<()> {
+    let identifier = identifier::from_path(dir)?;
+    info!(
+        "No instance contains {}, starting {identifier}",
+        dir.display()
+    );
+
+    let env = nvim_env(&config.launcher, dir);
+    let (nvim_process, server_address) = utils::start_nvim_server(|server_address| {
+        let mut spec = ProcessSpec::new("nvim")
+            .args(["--listen", server_address, "--headless"])
+            .args(config.launcher.clipboard.value.nvim_args())
+            .current_dir(dir)
+            .env(utils::IDENTIFIER_ENV, &identifier)
+            // xdg-activation のトークンは GUI にだけ渡す
+            .env_remove(gui::ACTIVATION_TOKEN_ENV)
+            .env_remove(gui::STARTUP_ID_ENV);
+        match file {
+            Some(file) => {
+                spec = spec.arg(file.to_string_lossy());
+                if let Some(line) = open_options.line {
+                    spec = spec.arg(format!(
+                        "+call setcursorcharpos({line}, {})",
+                        open_options.column.unwrap_or(1)
+                    ));
+                }
+            }
+            None => spec = spec.arg("."),
+        }
+        process::spawn(&direnv::apply(spec, &env))
+    })
+    .await?;
+
+    client
+        .register_instance(
+            &identifier,
+            &server_address,
+            Some(&identifier),
+            Some(nvim_process.id()),
+            config.launcher.nvim_appname.value.as_deref(),
+        )
+        .await?;
+    launch_client(&config.launcher, None, &identifier, &server_address)?;
+    record_recent(&identifier);
+    record_stats(&config.launcher, &identifier, EventKind::Create, None);
+
+    Ok(())
+}
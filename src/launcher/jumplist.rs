@@ -0,0 +1,106 @@
+//! タスクバーのジャンプリスト (Windows)
+//!
+//! launcher で開くたびにプロジェクトの履歴 (`neovim_manager::recent`) に記録し、
+//! ピン留めした launcher を右クリックしたときの「Recent projects」に `--handler "<dir>"` の項目として並べる。
+//! Windows 以外では履歴に記録するだけ。
+
+use anyhow::Result;
+use neovim_manager::recent;
+use std::path::Path;
+
+/// ジャンプリストのカテゴリー名
+#[cfg(windows)]
+const CATEGORY: &str = "Recent projects";
+
+/// `identifier` を履歴に記録し、ジャンプリストを作り直す
+pub fn record(identifier: &str) -> Result<()> {
+    let recent = recent::record(identifier)?;
+    // 消えたディレクトリは開けないので並べない
+    let projects: Vec<&str> = recent
+        .iter()
+        .map(String::as_str)
+        .filter(|dir| Path::new(dir).is_dir())
+        .collect();
+    update(&projects)
+}
+
+#[cfg(not(windows))]
+fn update(_projects: &[&str]) -> Result<()> {
+    Ok(())
+}
+
+/// `projects` を並べたジャンプリストに置き換える (表示できる数を超えた分は捨てる)
+#[cfg(windows)]
+fn update(projects: &[&str]) -> Result<()> {
+    use neovim_manager::identifier;
+    use windows::core::{Interface, HSTRING};
+    use windows::Win32::Storage::EnhancedStorage::PKEY_Title;
+    use windows::Win32::System::Com::StructuredStorage::{
+        PropVariantChangeType, PROPVARIANT, PVCHF_DEFAULT,
+    };
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER,
+        COINIT_APARTMENTTHREADED,
+    };
+    use windows::Win32::System::Variant::VT_LPWSTR;
+    use windows::Win32::UI::Shell::Common::{IObjectArray, IObjectCollection};
+    use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+    use windows::Win32::UI::Shell::{
+        DestinationList, EnumerableObjectCollection, ICustomDestinationList, IShellLinkW, ShellLink,
+    };
+
+    let exe = HSTRING::from(std::env::current_exe()?.as_os_str());
+
+    // SAFETY: COM はこのスレッドで初期化し、作ったオブジェクトはすべて CoUninitialize の前に解放する
+    unsafe {
+        CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
+        let result = (|| -> Result<()> {
+            let list: ICustomDestinationList =
+                CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)?;
+            let mut slots = 0u32;
+            // ユーザーがジャンプリストから外した項目 (今は並べ直すだけなので使わない)
+            let _removed: IObjectArray = list.BeginList(&mut slots)?;
+
+            let items: IObjectCollection =
+                CoCreateInstance(&EnumerableObjectCollection, None, CLSCTX_INPROC_SERVER)?;
+            for dir in projects.iter().take(slots as usize) {
+                let link: IShellLinkW = CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER)?;
+                link.SetPath(&exe)?;
+                link.SetArguments(&HSTRING::from(format!("--handler {}", quote(dir))))?;
+                link.SetDescription(&HSTRING::from(*dir))?;
+                link.SetIconLocation(&exe, 0)?;
+
+                // 表示名は PKEY_Title (VT_LPWSTR) で付ける
+                let mut title = PROPVARIANT::default();
+                PropVariantChangeType(
+                    &mut title,
+                    &PROPVARIANT::from(format!("Reopen {}", identifier::display(dir)).as_str()),
+                    PVCHF_DEFAULT,
+                    VT_LPWSTR,
+                )?;
+                let store: IPropertyStore = link.cast()?;
+                store.SetValue(&PKEY_Title, &title)?;
+                store.Commit()?;
+
+                items.AddObject(&link)?;
+            }
+
+            let items: IObjectArray = items.cast()?;
+            list.AppendCategory(&HSTRING::from(CATEGORY), &items)?;
+            list.CommitList()?;
+            Ok(())
+        })();
+        CoUninitialize();
+        result
+    }
+}
+
+/// コマンドラインの 1 つの引数として囲む (`C:\` のように `\` で終わる場合は `\"` にならないよう重ねる)
+#[cfg(windows)]
+fn quote(arg: &str) -> String {
+    if arg.ends_with('\\') {
+        format!("\"{arg}\\\"")
+    } else {
+        format!("\"{arg}\"")
+    }
+}
@@ -1,19 +1,58 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
 use log::{error, info, warn};
-use neovim_manager::{utils, HealthStatus, InstanceResult};
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use neovim_manager::client::ManagerClient;
+use neovim_manager::config::{self, Config, LauncherConfig};
+use neovim_manager::direnv::{self, EnvChanges};
+use neovim_manager::gui::{self, GuiCommand};
+use neovim_manager::identifier::{self, PathMapping};
+use neovim_manager::process::{self, ChildProcess, ProcessSpec};
+use neovim_manager::retry::Backoff;
+use neovim_manager::stats::{self, EventKind};
+use neovim_manager::tmux::{self, TmuxMode};
+use neovim_manager::utils::{self, OpenFileOptions, OpenMode};
+use neovim_manager::{duration, report, tunnel, wsl};
+use neovim_manager::{InstanceResult, RegisterInstanceParams, ServerAddress};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::signal;
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 
+mod desktop;
+mod difftool;
+mod explorer;
+mod geometry;
+mod handler;
+mod jumplist;
+mod project;
+mod scratch;
+mod sudo;
+mod workspace;
+
+use difftool::DiffTool;
+
+/// 起動やヘルスチェックの完了を待つ間隔 (上限は待つものごとに決める)
+const READINESS: Backoff =
+    Backoff::exponential(Duration::from_millis(100), Duration::from_millis(500));
+
+/// 起動してからこれより早く終了コード 2 で終了したものを、再起動の繰り返しとして数える
+const QUICK_EXIT: Duration = Duration::from_secs(10);
+/// この期間に `CRASH_LOOP_LIMIT` 回すぐに終了したら再起動をやめる
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(60);
+const CRASH_LOOP_LIMIT: usize = 3;
+/// 表示する nvim の標準エラー出力の行数 (最後から)
+const STDERR_TAIL_LINES: usize = 20;
+
 #[derive(Parser)]
 #[command(name = "neovim-launcher")]
 #[command(about = "High-level Neovim launcher with instance management")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<LauncherCommand>,
+
     #[arg(help = "File or directory to open")]
     target: Option<PathBuf>,
 
@@ -24,66 +63,237 @@ struct Cli {
     identifier: Option<String>,
 
     #[arg(long, help = "Remote server address (required for remote mode)")]
-    server: Option<String>,
+    server: Option<ServerAddress>,
+
+    #[arg(
+        long,
+        default_value_t = OpenMode::Drop,
+        help = "How to open the file in an existing instance (edit, drop, tab, split)"
+    )]
+    open_mode: OpenMode,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "Attach in a tmux window or split pane instead of launching Neovide (window, split)"
+    )]
+    tmux: Option<TmuxMode>,
+
+    #[arg(
+        long,
+        num_args = 4,
+        value_names = ["LOCAL", "BASE", "REMOTE", "MERGED"],
+        conflicts_with_all = ["target", "remote", "difftool"],
+        help = "Act as a git mergetool: open a merge layout and wait until MERGED is closed"
+    )]
+    mergetool: Option<Vec<PathBuf>>,
+
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["LOCAL", "REMOTE"],
+        conflicts_with_all = ["target", "remote"],
+        help = "Act as a git difftool: open a diff and wait until it is closed"
+    )]
+    difftool: Option<Vec<PathBuf>>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..,
+        conflicts_with_all = ["target", "remote", "tmux", "mergetool", "difftool"],
+        help = "Open FILEs in the instance whose directory contains them and exit without waiting \
+                (for use as the default application, e.g. `Exec=neovim-launcher --handler %F`)"
+    )]
+    handler: Option<Vec<PathBuf>>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["target", "remote", "tmux", "mergetool", "difftool", "handler"],
+        help = "Edit FILE as root like sudoedit: edit a copy in the project instance, \
+                write it back with sudo on every save and wait until it is closed"
+    )]
+    sudo: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = [
+            "target", "remote", "identifier", "server", "mergetool", "difftool", "handler", "sudo",
+            "line", "goto",
+        ],
+        help = "Launch or attach every project of the workspace defined as [workspace.NAME] \
+                in the config file"
+    )]
+    workspace: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "target", "remote", "identifier", "server", "tmux", "mergetool", "difftool", "handler",
+            "sudo", "workspace", "line", "goto",
+        ],
+        help = "Bring up the scratch instance (launching it if needed), \
+                or hide its window if it is already focused"
+    )]
+    toggle_scratch: bool,
+
+    #[arg(
+        long,
+        help = "Line to put the cursor on after opening the file (1-based)"
+    )]
+    line: Option<u32>,
+
+    #[arg(
+        short = 'g',
+        long,
+        requires = "target",
+        conflicts_with_all = ["line", "column", "remote", "handler", "mergetool", "difftool"],
+        help = "Read TARGET as FILE[:LINE[:COLUMN]] and put the cursor there (same as `code --goto`)"
+    )]
+    goto: bool,
+
+    #[arg(
+        long,
+        requires = "line",
+        help = "Column to put the cursor on (1-based, in characters)"
+    )]
+    column: Option<u32>,
+}
+
+#[derive(Subcommand)]
+enum LauncherCommand {
+    /// Install .desktop entries that open files and nvim:// URLs with `--handler`
+    InstallDesktop {
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Directory to write the entries to (default: $XDG_DATA_HOME/applications)"
+        )]
+        dir: Option<PathBuf>,
+        #[arg(long, default_value = "nvim", help = "Icon name or path")]
+        icon: String,
+        #[arg(
+            long = "default",
+            value_name = "MIME",
+            help = "Also make it the default application for MIME via xdg-mime \
+                    (repeatable; `all` for every type in the entry)"
+        )]
+        defaults: Vec<String>,
+        #[arg(long, help = "Print the entries instead of installing them")]
+        print: bool,
+    },
+    /// Register the Explorer context menu, file associations and the nvim:// protocol (per user)
+    InstallWindows {
+        #[arg(
+            long = "extension",
+            value_name = "EXT",
+            help = "File extension to associate (repeatable; default: common text and source files)"
+        )]
+        extensions: Vec<String>,
+        #[arg(long, help = "Print the reg commands instead of running them")]
+        print: bool,
+    },
+    /// Remove what install-windows registered
+    UninstallWindows,
+    /// Trust the .nvim-manager.toml of DIR without being asked (for launches outside a terminal)
+    Trust {
+        #[arg(help = "Project directory (default: current directory)")]
+        dir: Option<PathBuf>,
+    },
 }
 
 struct LauncherClient {
-    control_binary: String,
+    client: ManagerClient,
 }
 
 impl LauncherClient {
-    fn new() -> Result<Self> {
-        let current_exe = std::env::current_exe()?;
-        let control_path = current_exe
-            .parent()
-            .ok_or_else(|| anyhow!("Cannot determine executable directory"))?
-            .join("neovim-instance-manager-control");
-
-        Ok(Self {
-            control_binary: control_path.to_string_lossy().to_string(),
-        })
+    fn new(config: &Config) -> Self {
+        Self {
+            client: ManagerClient::new(config),
+        }
     }
 
     async fn query_instance(&self, identifier: &str) -> Result<Option<InstanceResult>> {
-        let output = Command::new(&self.control_binary)
-            .args(["query", identifier])
-            .output()?;
-
-        if !output.status.success() {
-            return Ok(None);
-        }
-
-        let stdout = String::from_utf8(output.stdout)?;
-        let trimmed = stdout.trim();
+        self.client.query(identifier).await
+    }
 
-        let result: Option<InstanceResult> = serde_json::from_str(trimmed)
-            .map_err(|e| anyhow!("Failed to parse JSON from stdout '{}': {}", trimmed, e))?;
+    async fn list_instances(&self) -> Result<Vec<InstanceResult>> {
+        self.client.list(None).await
+    }
 
-        Ok(result)
+    async fn register_instance(
+        &self,
+        identifier: &str,
+        server_address: &ServerAddress,
+        cwd: Option<&str>,
+        pid: Option<u32>,
+        appname: Option<&str>,
+    ) -> Result<()> {
+        self.client
+            .register(RegisterInstanceParams {
+                identifier: identifier.to_string(),
+                server_address: server_address.clone(),
+                cwd: cwd.map(str::to_string),
+                pid,
+                tags: Vec::new(),
+                appname: appname.map(str::to_string),
+                pinned: false,
+            })
+            .await
+            .context("Failed to register instance")
     }
 
-    async fn register_instance(&self, identifier: &str, server_address: &str) -> Result<()> {
-        let output = Command::new(&self.control_binary)
-            .args(["register", identifier, server_address])
-            .output()?;
+    async fn touch_instance(&self, identifier: &str) -> Result<()> {
+        self.client
+            .touch(identifier)
+            .await
+            .context("Failed to touch instance")
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8(output.stderr)?;
-            return Err(anyhow!("Failed to register instance: {}", stderr));
+    /// 見えなくなった identifier が control rename で付け替えられていれば、新しい identifier
+    ///
+    /// rename は nvim の `$NEOVIM_MANAGER_IDENTIFIER` も書き換えるので、それを読んで確かめる
+    async fn renamed_identifier(
+        &self,
+        identifier: &str,
+        server_address: &ServerAddress,
+    ) -> Option<String> {
+        let expr = format!("${}", utils::IDENTIFIER_ENV);
+        let renamed = utils::eval_in_nvim_instance(server_address, &expr)
+            .await
+            .ok()?;
+        if renamed.is_empty() || renamed == identifier {
+            return None;
+        }
+        match self.query_instance(&renamed).await {
+            Ok(Some(instance)) if instance.server_address == *server_address => {
+                info!("Instance {identifier} was renamed to {renamed}");
+                Some(renamed)
+            }
+            _ => None,
         }
-
-        Ok(())
     }
 
     async fn monitor_instance(&self, identifier: &str) -> Result<()> {
         info!("Monitoring instance: {identifier}");
 
+        let mut identifier = identifier.to_string();
+        let mut server_address = None;
         loop {
-            match self.query_instance(identifier).await {
-                Ok(Some(_)) => {
+            match self.query_instance(&identifier).await {
+                Ok(Some(instance)) => {
+                    server_address = Some(instance.server_address);
                     sleep(Duration::from_millis(500)).await;
                 }
                 Ok(None) => {
+                    if let Some(address) = &server_address {
+                        if let Some(renamed) = self.renamed_identifier(&identifier, address).await {
+                            identifier = renamed;
+                            continue;
+                        }
+                    }
                     info!("Instance {identifier} no longer exists, exiting");
                     break;
                 }
@@ -100,24 +310,33 @@ impl LauncherClient {
     async fn monitor_instance_with_exit_code(
         &self,
         identifier: &str,
-        nvim_process: Child,
+        nvim_process: Box<dyn ChildProcess>,
     ) -> Result<i32> {
         info!("Monitoring instance: {identifier}");
 
         let mut nvim_process = nvim_process;
 
+        let mut identifier = identifier.to_string();
+        let mut server_address = None;
         loop {
-            match self.query_instance(identifier).await {
-                Ok(Some(_)) => {
+            match self.query_instance(&identifier).await {
+                Ok(Some(instance)) => {
+                    server_address = Some(instance.server_address);
                     sleep(Duration::from_millis(500)).await;
                 }
                 Ok(None) => {
+                    if let Some(address) = &server_address {
+                        if let Some(renamed) = self.renamed_identifier(&identifier, address).await {
+                            identifier = renamed;
+                            continue;
+                        }
+                    }
                     info!("Instance {identifier} no longer exists, checking exit code");
 
                     // Neovimプロセスの終了を待機して終了コードを取得
                     match nvim_process.wait() {
-                        Ok(status) => {
-                            let exit_code = status.code().unwrap_or(-1);
+                        Ok(code) => {
+                            let exit_code = code.unwrap_or(-1);
                             info!("Neovim process exited with code: {exit_code}");
                             return Ok(exit_code);
                         }
@@ -136,30 +355,71 @@ impl LauncherClient {
     }
 }
 
-fn generate_identifier(target: Option<&PathBuf>) -> Result<String> {
-    let path = match target {
-        Some(path) => {
-            if path.is_dir() {
-                path.clone()
-            } else {
-                path.parent()
-                    .ok_or_else(|| anyhow!("Cannot determine parent directory"))?
-                    .to_path_buf()
-            }
+/// 終了コード 2 での再起動のうち、起動してすぐに終了したものを数える
+#[derive(Default)]
+struct CrashLoopDetector {
+    quick_exits: Vec<Instant>,
+}
+
+impl CrashLoopDetector {
+    /// `spawned_at` に起動したインスタンスが終了コード 2 で終了した。再起動をやめるべきなら true
+    ///
+    /// しばらく動いてから終了したもの (設定を読み直すための再起動など) があれば数え直す
+    fn record_restart(&mut self, spawned_at: Instant) -> bool {
+        let now = Instant::now();
+        if now.duration_since(spawned_at) >= QUICK_EXIT {
+            self.quick_exits.clear();
+            return false;
         }
-        None => std::env::current_dir()?,
+        self.quick_exits
+            .retain(|at| now.duration_since(*at) < CRASH_LOOP_WINDOW);
+        self.quick_exits.push(now);
+        self.quick_exits.len() >= CRASH_LOOP_LIMIT
+    }
+}
+
+/// headless の nvim の標準エラー出力を残すファイル (identifier ごと、起動するたびに空にする)
+fn nvim_stderr_path(identifier: &str) -> PathBuf {
+    config::runtime_dir()
+        .join("nvim-stderr")
+        .join(format!("{}.log", utils::stable_hash(identifier.as_bytes())))
+}
+
+/// 残した nvim の標準エラー出力の最後の部分を表示する (何もなければ表示しない)
+fn print_nvim_stderr(identifier: &str) {
+    print_log_tail("Neovim stderr", &nvim_stderr_path(identifier));
+}
+
+/// `path` に残した出力の最後の部分を表示する (何もなければ表示しない)
+fn print_log_tail(label: &str, path: &Path) {
+    let Ok(content) = std::fs::read(path) else {
+        return;
     };
+    let content = String::from_utf8_lossy(&content);
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
 
-    let canonical = path.canonicalize()?;
-    Ok(canonical.to_string_lossy().to_string())
+    eprintln!("--- {label} ({}):", path.display());
+    for line in &lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..] {
+        eprintln!("{line}");
+    }
+    eprintln!("---");
 }
 
 fn launch_neovim_server(
-    _identifier: &str,
+    identifier: &str,
     target_dir: Option<&PathBuf>,
     target_file: Option<&PathBuf>,
+    open_options: &OpenFileOptions,
+    env: &EnvChanges,
+    extra_args: &[String],
     server_address: &str,
-) -> Result<Child> {
+) -> Result<Box<dyn ChildProcess>> {
     let dir_arg = target_dir
         .map(|p| p.to_string_lossy().to_string())
         .unwrap_or_else(|| ".".to_string());
@@ -169,88 +429,146 @@ fn launch_neovim_server(
         server_address.to_string(),
         "--headless".to_string(),
     ];
+    // プロジェクトの設定 (.nvim-manager.toml) の引数は、開くファイルより前に置く
+    args.extend(extra_args.iter().cloned());
 
     // ファイルが指定されている場合はそれを引数として追加
     if let Some(file_path) = target_file {
         args.push(file_path.to_string_lossy().to_string());
+        if let Some(line) = open_options.line {
+            args.push(format!(
+                "+call setcursorcharpos({line}, {})",
+                open_options.column.unwrap_or(1)
+            ));
+        }
     } else {
         args.push(dir_arg);
     }
 
-    let args_str: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    // 一度しか使えない xdg-activation のトークンは、長く動く nvim (とその子プロセス) には渡さない
+    let spec = ProcessSpec::new("nvim")
+        .args(args)
+        .env(utils::IDENTIFIER_ENV, identifier)
+        .env_remove(gui::ACTIVATION_TOKEN_ENV)
+        .env_remove(gui::STARTUP_ID_ENV);
+    let spec = direnv::apply(spec, env);
+    // 起動に失敗したり終了し続けたりしたときに表示できるよう、標準エラー出力をファイルに残す
+    let stderr_path = nvim_stderr_path(identifier);
+    let spec = match stderr_path.parent().map(std::fs::create_dir_all) {
+        Some(Err(e)) => {
+            warn!(
+                "Cannot keep the Neovim stderr in {}: {e}",
+                stderr_path.display()
+            );
+            spec
+        }
+        _ => spec.stderr_file(stderr_path),
+    };
 
-    eprintln!("Executing: nvim {}", args.join(" "));
+    eprintln!("Executing: {}", spec.display());
     info!("Launching Neovim server: {server_address}");
 
-    let mut nvim_cmd = Command::new("nvim");
-    nvim_cmd.args(&args_str);
-
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        nvim_cmd.creation_flags(0x08000000);
-    }
-
-    #[cfg(not(windows))]
-    {
-        nvim_cmd
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
-    }
-
-    let nvim_child = nvim_cmd.spawn()?;
+    let nvim_child = process::spawn(&spec)?;
     eprintln!("Nvim server spawned with PID: {:?}", nvim_child.id());
-    std::thread::sleep(Duration::from_millis(1000));
 
     Ok(nvim_child)
 }
 
-fn launch_neovide_client(server_address: &str) -> Result<()> {
-    let neovide_cmd = utils::get_neovide_command();
-    let mut args = vec!["--server".to_string(), server_address.to_string()];
-    args.extend(utils::get_neovide_extra_args());
-
-    eprintln!("Executing: {} {}", neovide_cmd, args.join(" "));
-    info!("Launching Neovide client for server: {server_address}");
-
-    let mut cmd = Command::new(neovide_cmd);
-    cmd.args(args);
-
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        cmd.creation_flags(0x08000000);
+/// 新しく起動する nvim に加える環境変数
+///
+/// `launcher.direnv` が有効なら `dir` の direnv の環境を読む (読めなくても direnv なしで起動する)。
+/// `launcher.nvim_appname` があれば、.envrc の `NVIM_APPNAME` より優先する
+fn nvim_env(config: &LauncherConfig, dir: &Path) -> EnvChanges {
+    let mut env = direnv::load(config, dir).unwrap_or_else(|e| {
+        eprintln!("Starting without the direnv environment: {e:#}");
+        EnvChanges::new()
+    });
+    if let Some(appname) = &config.nvim_appname.value {
+        env.insert(utils::APPNAME_ENV.to_string(), Some(appname.clone()));
     }
+    env
+}
 
-    #[cfg(not(windows))]
-    {
-        cmd.stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+fn launch_neovide_client(
+    config: &LauncherConfig,
+    identifier: &str,
+    server_address: &str,
+) -> Result<()> {
+    let gui = GuiCommand::resolve(config);
+    if !gui.is_found() {
+        warn!("{} ({})", gui.program.display(), gui.source);
     }
+    let server_address = &tunnel::local_address(server_address)?;
+    // WSL から Windows 版の GUI を起動する場合は、Windows 側から接続できるアドレスにする
+    let is_windows_gui = gui
+        .program
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exe"));
+    let mut spec = if wsl::is_wsl() && is_windows_gui {
+        gui.spec(&wsl::windows_gui_address(server_address)?)
+    } else {
+        gui.spec(server_address)
+    };
+    // 前回のウィンドウの大きさで開く
+    spec = spec.args(geometry::neovide_args(config, &gui, identifier));
+    // デスクトップから渡された xdg-activation のトークンで、新しいウィンドウを前面に出させる (Wayland)
+    if let Some(token) = gui::activation_token() {
+        spec = spec.env(gui::ACTIVATION_TOKEN_ENV, token);
+    }
+
+    eprintln!("Executing: {}", spec.display());
+    info!("Launching Neovide client for server: {server_address}");
 
-    let _child = cmd.spawn()?;
+    process::spawn(&spec)?;
     eprintln!("Neovide client spawned successfully");
     std::thread::sleep(Duration::from_millis(500));
 
     Ok(())
 }
 
+/// 新しいインスタンスに接続する (Neovide または tmux)
+fn launch_client(
+    config: &LauncherConfig,
+    tmux_mode: Option<TmuxMode>,
+    identifier: &str,
+    server_address: &str,
+) -> Result<()> {
+    match tmux_mode {
+        Some(mode) => {
+            info!("Attaching in tmux ({mode}): {server_address}");
+            tmux::open(mode, identifier, server_address)?;
+            Ok(())
+        }
+        None => launch_neovide_client(config, identifier, server_address),
+    }
+}
+
 async fn focus_existing_instance(
+    identifier: &str,
     server_address: &str,
+    tmux_mode: Option<TmuxMode>,
     target_file: Option<&PathBuf>,
+    open_options: &OpenFileOptions,
+    path_mappings: &[PathMapping],
 ) -> Result<()> {
     info!("Focusing existing instance: {server_address}");
 
-    // CLAUDE.mdに従ってNeovideFocusコマンドを実行
-    utils::focus_nvim_instance(server_address)?;
+    match tmux_mode {
+        // tmux では接続しているウィンドウ・ペインへ移る (なければ作る)
+        Some(mode) => {
+            tmux::open(mode, identifier, server_address)?;
+        }
+        // CLAUDE.mdに従ってNeovideFocusコマンドを実行
+        None => utils::focus_nvim_instance(server_address).await?,
+    }
 
     // ファイルが指定されている場合は、そのファイルをリモートで開く
     if let Some(file_path) = target_file {
-        let file_str = file_path.to_string_lossy();
+        // コンテナの中のインスタンスにはコンテナの中のパスで渡す
+        let file_str =
+            utils::remote_path(server_address, &file_path.to_string_lossy(), path_mappings).await;
         info!("Opening file in existing instance: {file_str}");
-        utils::open_file_in_nvim_instance(server_address, &file_str)?;
+        utils::open_file_in_nvim_instance(server_address, &file_str, open_options).await?;
     }
 
     Ok(())
@@ -258,15 +576,196 @@ async fn focus_existing_instance(
 
 #[derive(Debug, Clone)]
 struct CleanupInfo {
-    server_address: Option<String>,
+    server_address: Option<ServerAddress>,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
 
-    let cli = Cli::parse();
-    let client = LauncherClient::new()?;
+    let mut cli = Cli::parse();
+    if let Some(command) = cli.command.take() {
+        if let Err(e) = run_command(command) {
+            report::exit(1, &e);
+        }
+        return;
+    }
+
+    if let Err(e) = run(cli).await {
+        report::exit(1, &e.context("Failed to launch Neovim"));
+    }
+}
+
+/// git は作業ツリーからの相対パスで渡すので、インスタンスの作業ディレクトリに左右されないよう絶対パスにする
+fn diff_tool(cli: &Cli) -> Result<Option<DiffTool>> {
+    if let Some([local, base, remote, merged]) = cli.mergetool.as_deref() {
+        return Ok(Some(DiffTool::Merge {
+            local: std::path::absolute(local)?,
+            base: std::path::absolute(base)?,
+            remote: std::path::absolute(remote)?,
+            merged: std::path::absolute(merged)?,
+        }));
+    }
+    if let Some([local, remote]) = cli.difftool.as_deref() {
+        return Ok(Some(DiffTool::Diff {
+            local: std::path::absolute(local)?,
+            remote: std::path::absolute(remote)?,
+        }));
+    }
+    Ok(None)
+}
+
+/// `code --goto` と同じ規則で `FILE[:LINE[:COLUMN]]` を分ける
+///
+/// `:` で区切った要素のうち、数字のものを順に行・列とし、残りは `:` でつなぎ直してパスにする
+/// (`C:\file.txt:12` のドライブ名のため)。空の要素は VS Code と同じく 0 とみなす。
+/// 行だけなら列は 1、0 は 1 にする
+fn parse_goto(raw: &str) -> Result<(PathBuf, Option<u32>, Option<u32>)> {
+    let mut path: Option<String> = None;
+    let (mut line, mut column) = (None, None);
+    for segment in raw.split(':') {
+        if !segment.bytes().all(|b| b.is_ascii_digit()) {
+            path = Some(match path {
+                Some(path) => format!("{path}:{segment}"),
+                None => segment.to_string(),
+            });
+            continue;
+        }
+        let number = match segment {
+            "" => 1,
+            digits => digits.parse::<u32>().unwrap_or(u32::MAX).max(1),
+        };
+        if line.is_none() {
+            line = Some(number);
+        } else if column.is_none() {
+            column = Some(number);
+        }
+    }
+
+    let path =
+        path.ok_or_else(|| anyhow!("Format for --goto should be FILE:LINE(:COLUMN), got {raw:?}"))?;
+    Ok((PathBuf::from(path), line, column.or(line.map(|_| 1))))
+}
+
+fn run_command(command: LauncherCommand) -> Result<()> {
+    match command {
+        LauncherCommand::InstallDesktop {
+            dir,
+            icon,
+            defaults,
+            print,
+        } => desktop::install(&desktop::InstallOptions {
+            dir,
+            icon,
+            defaults,
+            print,
+        })
+        .context("Failed to install the desktop entries"),
+        LauncherCommand::InstallWindows { extensions, print } => {
+            explorer::install(&extensions, print)
+                .context("Failed to register the Explorer integration")
+        }
+        LauncherCommand::UninstallWindows => {
+            explorer::uninstall().context("Failed to remove the Explorer integration")
+        }
+        LauncherCommand::Trust { dir } => {
+            let dir = match dir {
+                Some(dir) => dir,
+                None => std::env::current_dir()?,
+            };
+            project::trust(&dir).context("Failed to trust the project config")
+        }
+    }
+}
+
+/// 開いたプロジェクトを履歴とジャンプリストに加える (失敗しても起動は続ける)
+fn record_recent(identifier: &str) {
+    if let Err(e) = jumplist::record(identifier) {
+        warn!("Cannot update the recent projects: {e:#}");
+    }
+}
+
+/// `launcher.stats` が有効なら使用状況を記録する (失敗しても起動は続ける)
+fn record_stats(
+    config: &LauncherConfig,
+    identifier: &str,
+    kind: EventKind,
+    duration: Option<Duration>,
+) {
+    if !config.stats.value {
+        return;
+    }
+    if let Err(e) = stats::record(identifier, kind, duration) {
+        warn!("Cannot record usage statistics: {e:#}");
+    }
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    if cli.goto {
+        if let Some(target) = &cli.target {
+            let (path, line, column) = parse_goto(&target.to_string_lossy())?;
+            cli.target = Some(path);
+            cli.line = line;
+            cli.column = column;
+        }
+    }
+
+    let mut config = Config::load()?;
+    let client = LauncherClient::new(&config);
+
+    if let Some(tool) = diff_tool(&cli)? {
+        // git のカレントディレクトリ (作業ツリーの先頭) のインスタンスで開く
+        let identifier = identifier::from_target(None)?;
+        let instance = client.query_instance(&identifier).await?;
+        if instance.is_some() {
+            if let Err(e) = client.touch_instance(&identifier).await {
+                warn!("{e:#}");
+            }
+        }
+        let code = difftool::run(
+            tool,
+            instance.map(|instance| instance.server_address.into()),
+            &config.manager.path_mappings.value,
+        )
+        .await?;
+        std::process::exit(code);
+    }
+
+    let open_options = OpenFileOptions {
+        mode: cli.open_mode,
+        line: cli.line,
+        column: cli.column,
+    };
+
+    if let Some(file) = &cli.sudo {
+        // difftool と同じく、カレントディレクトリのインスタンスで開く
+        let identifier = identifier::from_target(None)?;
+        let instance = client.query_instance(&identifier).await?;
+        if instance.is_some() {
+            if let Err(e) = client.touch_instance(&identifier).await {
+                warn!("{e:#}");
+            }
+        }
+        let code = sudo::run(
+            file,
+            instance.map(|instance| instance.server_address.into()),
+            &open_options,
+        )
+        .await?;
+        std::process::exit(code);
+    }
+
+    if let Some(files) = &cli.handler {
+        return handler::run(&client, &config, files, &open_options).await;
+    }
+
+    if let Some(name) = &cli.workspace {
+        return workspace::run(&client, &config, name, cli.tmux).await;
+    }
+
+    if cli.toggle_scratch {
+        return scratch::run(&client, &config).await;
+    }
 
     // クリーンアップ情報を管理
     let cleanup_info = Arc::new(Mutex::new(CleanupInfo {
@@ -280,7 +779,7 @@ async fn main() -> Result<()> {
         match &cli.target {
             Some(path) if path.is_file() => {
                 // ファイルが指定された場合：ディレクトリは.(カレント)、ファイルを記録
-                let file_path = path.canonicalize()?;
+                let file_path = identifier::canonical_path(path)?;
                 (None, Some(file_path)) // target_dirはNoneにして常に"."を使用
             }
             _ => {
@@ -290,20 +789,52 @@ async fn main() -> Result<()> {
         }
     };
 
+    // プロジェクトの設定 (.nvim-manager.toml) はローカルモードだけで使う
+    let mut project = None;
     let identifier = if cli.remote {
         cli.identifier
             .ok_or_else(|| anyhow!("--identifier is required in remote mode"))?
     } else {
         // ファイル指定の場合でも現在のディレクトリをidentifierに使用
-        let identifier_target = if target_file.is_some() {
-            None // ファイル指定時は現在のディレクトリを使用
-        } else {
-            target_dir.as_ref()
-        };
-        generate_identifier(identifier_target)?
+        let identifier = identifier::from_target(cli.target.as_deref())?;
+        project = project::load(Path::new(&identifier))?;
+        match &project {
+            Some(project) => project.identifier(identifier)?,
+            None => identifier,
+        }
     };
+    let mut tmux_mode = cli.tmux;
+    if let Some(project) = &project {
+        project
+            .config
+            .apply(&mut config.launcher, &project.project.file);
+        if tmux_mode.is_none() {
+            tmux_mode = project
+                .config
+                .frontend
+                .and_then(|frontend| frontend.tmux_mode());
+            // 設定ファイルの tmux は tmux の外では使えないので GUI にする
+            if tmux_mode.is_some() && !tmux::in_tmux() {
+                eprintln!(
+                    "Not inside tmux, using the GUI instead of the frontend in {}",
+                    project.project.file.display()
+                );
+                tmux_mode = None;
+            }
+        }
+    }
 
     info!("Using identifier: {identifier}");
+    if !cli.remote {
+        record_recent(&identifier);
+    }
+
+    if tmux_mode.is_some() && !tmux::in_tmux() {
+        report::exit(
+            2,
+            &anyhow!("--tmux requires running inside a tmux session (TMUX is not set)"),
+        );
+    }
 
     // Ctrl+C ハンドラーを設定
     let cleanup_info_clone = Arc::clone(&cleanup_info);
@@ -318,7 +849,7 @@ async fn main() -> Result<()> {
 
         if let Some(server_address) = &cleanup.server_address {
             eprintln!("Cleaning up unused Neovim server: {server_address}");
-            if let Err(e) = utils::quit_nvim_instance_with_retry(server_address, 3) {
+            if let Err(e) = utils::quit_nvim_instance_with_retry(server_address, 3).await {
                 eprintln!("Failed to cleanup server: {e}");
             }
         }
@@ -343,13 +874,25 @@ async fn main() -> Result<()> {
                 }
 
                 // 既存インスタンスにフォーカス（CLAUDE.md仕様）
-                focus_existing_instance(&instance.server_address, None).await?;
+                focus_existing_instance(
+                    &identifier,
+                    &instance.server_address,
+                    tmux_mode,
+                    None,
+                    &open_options,
+                    &config.manager.path_mappings.value,
+                )
+                .await?;
+                if let Err(e) = client.touch_instance(&identifier).await {
+                    warn!("{e:#}");
+                }
+                record_stats(&config.launcher, &identifier, EventKind::Attach, None);
 
                 // 監視終了後、新規サーバーをクリーンアップ
                 let result = client.monitor_instance(&identifier).await;
 
                 eprintln!("Cleaning up unused Neovim server: {server_address}");
-                if let Err(e) = utils::quit_nvim_instance_with_retry(&server_address, 3) {
+                if let Err(e) = utils::quit_nvim_instance_with_retry(&server_address, 3).await {
                     eprintln!("Failed to cleanup server: {e}");
                 }
 
@@ -358,33 +901,51 @@ async fn main() -> Result<()> {
             None => {
                 info!("Registering new remote instance");
                 client
-                    .register_instance(&identifier, &server_address)
+                    .register_instance(&identifier, &server_address, None, None, None)
                     .await?;
 
                 // Neovimインスタンスが起動するまで待機
                 info!("Waiting for remote Neovim instance to start...");
-                let mut attempts = 0;
-                let max_attempts = 30; // 15秒間待機
-
-                loop {
-                    if utils::check_nvim_instance(&server_address).unwrap_or(false) {
-                        info!("Remote Neovim instance is ready");
-                        break;
-                    }
-
-                    attempts += 1;
-                    if attempts >= max_attempts {
-                        error!("Remote Neovim instance failed to start within 15 seconds");
-                        std::process::exit(3);
-                    }
-
-                    sleep(Duration::from_millis(500)).await;
+                let ready = READINESS
+                    .deadline(Duration::from_secs(15))
+                    .poll(|| async {
+                        utils::check_nvim_instance(&server_address)
+                            .await
+                            .unwrap_or(false)
+                            .then_some(())
+                    })
+                    .await;
+                if ready.is_none() {
+                    report::exit(
+                        3,
+                        &anyhow!("Remote Neovim instance failed to start within 15 seconds")
+                            .context(format!(
+                                "No Neovim server is responding at {server_address}"
+                            )),
+                    );
                 }
-
-                // 新規リモートインスタンスにNeovideクライアントで接続
-                launch_neovide_client(&server_address)?;
-
-                client.monitor_instance(&identifier).await?;
+                info!("Remote Neovim instance is ready");
+
+                // 新規リモートインスタンスにNeovideクライアント (または tmux) で接続
+                launch_client(&config.launcher, tmux_mode, &identifier, &server_address)?;
+                record_stats(&config.launcher, &identifier, EventKind::Create, None);
+                let started_at = Instant::now();
+                let geometry = tmux_mode
+                    .is_none()
+                    .then(|| geometry::track(&config.launcher, &identifier, &server_address))
+                    .flatten();
+
+                let monitored = client.monitor_instance(&identifier).await;
+                if let Some(geometry) = geometry {
+                    geometry.abort();
+                }
+                monitored?;
+                record_stats(
+                    &config.launcher,
+                    &identifier,
+                    EventKind::Exit,
+                    Some(started_at.elapsed()),
+                );
             }
         }
     } else {
@@ -392,46 +953,88 @@ async fn main() -> Result<()> {
         match client.query_instance(&identifier).await? {
             Some(instance) => {
                 info!("Found existing local instance");
-                focus_existing_instance(&instance.server_address, target_file.as_ref()).await?;
+                // 起動し直さないと NVIM_APPNAME は変わらない
+                if let Some(appname) = &config.launcher.nvim_appname.value {
+                    if instance.appname.as_ref() != Some(appname) {
+                        eprintln!(
+                            "{identifier} is already running with NVIM_APPNAME={}, not {appname}",
+                            instance.appname.as_deref().unwrap_or("(unset)")
+                        );
+                    }
+                }
+                focus_existing_instance(
+                    &identifier,
+                    &instance.server_address,
+                    tmux_mode,
+                    target_file.as_ref(),
+                    &open_options,
+                    &config.manager.path_mappings.value,
+                )
+                .await?;
+                if let Err(e) = client.touch_instance(&identifier).await {
+                    warn!("{e:#}");
+                }
+                record_stats(&config.launcher, &identifier, EventKind::Attach, None);
+                if let Some(project) = &project {
+                    project.run_hook(
+                        |hooks| &hooks.on_attach,
+                        &identifier,
+                        instance.server_address.as_str(),
+                    );
+                }
                 client.monitor_instance(&identifier).await?;
             }
             None => {
+                // 再起動しても 1 回の起動として数える
+                let mut started_at = None;
+                let mut crash_loop = CrashLoopDetector::default();
                 // 終了コード2の場合は再起動ループ
                 loop {
                     info!("Creating new local instance");
-                    let port = utils::get_random_port()?;
-                    let server_address = format!("127.0.0.1:{port}");
-
-                    // Neovimサーバーを起動
-                    let nvim_process = launch_neovim_server(
-                        &identifier,
-                        target_dir.as_ref(),
-                        target_file.as_ref(),
-                        &server_address,
-                    )?;
+                    // .envrc が変わっているかもしれないので、再起動のたびに読み直す
+                    let env = nvim_env(&config.launcher, Path::new(&identifier));
+                    // プロジェクトの nvim_args で上書きできるよう、クリップボードを先に設定する
+                    let mut extra_args = config.launcher.clipboard.value.nvim_args();
+                    if let Some(project) = &project {
+                        extra_args.extend(project.nvim_args(&identifier, target_file.as_deref()));
+                    }
 
-                    // Neovimインスタンスが起動するまで待機
+                    // Neovimサーバーを起動し、起動するまで待機 (ポートを取られたら別のポートでやり直す)
                     info!("Waiting for Neovim instance to start...");
-                    let mut attempts = 0;
-                    let max_attempts = 30; // 15秒間待機
-
-                    loop {
-                        if utils::check_nvim_instance(&server_address).unwrap_or(false) {
-                            info!("Neovim instance is ready");
-                            break;
-                        }
-
-                        attempts += 1;
-                        if attempts >= max_attempts {
-                            error!("Neovim instance failed to start within 15 seconds");
-                            std::process::exit(3);
+                    let started = utils::start_nvim_server(|server_address| {
+                        launch_neovim_server(
+                            &identifier,
+                            target_dir.as_ref(),
+                            target_file.as_ref(),
+                            &open_options,
+                            &env,
+                            &extra_args,
+                            server_address,
+                        )
+                    })
+                    .await;
+                    let (nvim_process, server_address) = match started {
+                        Ok(started) => started,
+                        Err(e) => {
+                            print_nvim_stderr(&identifier);
+                            report::exit(3, &e.context("Neovim instance failed to start"));
                         }
-
-                        sleep(Duration::from_millis(500)).await;
-                    }
+                    };
+                    info!("Neovim instance is ready");
+                    let spawned_at = Instant::now();
 
                     // インスタンスを登録
-                    match client.register_instance(&identifier, &server_address).await {
+                    // ローカルモードの identifier は作業ディレクトリそのもの
+                    match client
+                        .register_instance(
+                            &identifier,
+                            &server_address,
+                            Some(&identifier),
+                            Some(nvim_process.id()),
+                            config.launcher.nvim_appname.value.as_deref(),
+                        )
+                        .await
+                    {
                         Ok(()) => {
                             info!("Instance registered successfully");
 
@@ -441,66 +1044,108 @@ async fn main() -> Result<()> {
                                     info!("Instance registration confirmed");
 
                                     // ヘルスステータスがHealthyになるまで待機
-                                    if !matches!(instance.health_status, HealthStatus::Healthy) {
+                                    if !instance.health_status.is_healthy() {
                                         info!("Waiting for instance to become healthy...");
-                                        let mut attempts = 0;
-                                        let max_attempts = 60; // 30秒間待機（5秒間隔のヘルスチェック）
-
-                                        loop {
-                                            sleep(Duration::from_millis(500)).await;
-
-                                            match client.query_instance(&identifier).await? {
-                                                Some(updated_instance) => {
-                                                    if matches!(
-                                                        updated_instance.health_status,
-                                                        HealthStatus::Healthy
-                                                    ) {
-                                                        info!("Instance is now healthy");
-                                                        break;
+                                        // 30秒間待機（5秒間隔のヘルスチェック）
+                                        let healthy = READINESS
+                                            .deadline(Duration::from_secs(30))
+                                            .poll(|| async {
+                                                match client.query_instance(&identifier).await {
+                                                    Ok(Some(instance))
+                                                        if !instance.health_status.is_healthy() =>
+                                                    {
+                                                        None
                                                     }
+                                                    result => Some(result),
                                                 }
-                                                None => {
-                                                    error!("Instance disappeared during health check wait");
-                                                    std::process::exit(5);
-                                                }
-                                            }
-
-                                            attempts += 1;
-                                            if attempts >= max_attempts {
-                                                error!("Instance did not become healthy within 30 seconds");
-                                                std::process::exit(6);
-                                            }
+                                            })
+                                            .await;
+
+                                        match healthy.transpose()? {
+                                            Some(Some(_)) => info!("Instance is now healthy"),
+                                            Some(None) => report::exit(
+                                                5,
+                                                &anyhow!(
+                                                    "Instance disappeared during health check wait"
+                                                ),
+                                            ),
+                                            None => report::exit(
+                                                6,
+                                                &anyhow!("Instance did not become healthy within 30 seconds"),
+                                            ),
                                         }
                                     } else {
                                         info!("Instance is already healthy");
                                     }
 
-                                    // Neovide クライアントを起動
-                                    launch_neovide_client(&server_address)?;
-                                }
-                                None => {
-                                    error!("Instance not found immediately after registration - this should not happen");
-                                    std::process::exit(4);
+                                    // Neovide クライアント (または tmux) を起動
+                                    launch_client(&config.launcher, tmux_mode, &identifier, &server_address)?;
+                                    if let Some(project) = &project {
+                                        project.run_hook(|hooks| &hooks.on_start, &identifier, &server_address);
+                                    }
+                                    if started_at.is_none() {
+                                        record_stats(&config.launcher, &identifier, EventKind::Create, None);
+                                        started_at = Some(Instant::now());
+                                    }
                                 }
+                                None => report::exit(
+                                    4,
+                                    &anyhow!("Instance not found immediately after registration - this should not happen"),
+                                ),
                             }
 
-                            // 監視して終了コードを取得
+                            // 監視して終了コードを取得 (その間 Neovide のウィンドウの大きさと位置を記録する)
+                            let geometry = tmux_mode
+                                .is_none()
+                                .then(|| {
+                                    geometry::track(&config.launcher, &identifier, &server_address)
+                                })
+                                .flatten();
                             let exit_code = client
                                 .monitor_instance_with_exit_code(&identifier, nvim_process)
-                                .await?;
+                                .await;
+                            if let Some(geometry) = geometry {
+                                geometry.abort();
+                            }
+                            let exit_code = exit_code?;
+                            if let Some(project) = &project {
+                                project.run_hook(
+                                    |hooks| &hooks.on_exit,
+                                    &identifier,
+                                    &server_address,
+                                );
+                            }
 
-                            if exit_code == 2 {
+                            let crash_looping =
+                                exit_code == 2 && crash_loop.record_restart(spawned_at);
+                            if exit_code == 2 && !crash_looping {
                                 info!("Neovim exited with code 2, restarting...");
                                 continue; // 再起動ループを継続
-                            } else {
-                                info!("Neovim exited with code {exit_code}, ending");
-                                break; // ループを抜けて終了
                             }
+
+                            info!("Neovim exited with code {exit_code}, ending");
+                            record_stats(
+                                &config.launcher,
+                                &identifier,
+                                EventKind::Exit,
+                                started_at.map(|started_at| started_at.elapsed()),
+                            );
+                            if exit_code != 0 {
+                                print_nvim_stderr(&identifier);
+                            }
+                            if crash_looping {
+                                report::exit(
+                                    7,
+                                    &anyhow!(
+                                        "Neovim exited with code 2 within {} of starting {CRASH_LOOP_LIMIT} times in {}, not restarting it again",
+                                        duration::format(QUICK_EXIT),
+                                        duration::format(CRASH_LOOP_WINDOW)
+                                    ),
+                                );
+                            }
+                            break; // ループを抜けて終了
                         }
-                        Err(e) => {
-                            error!("Failed to register instance: {e}");
-                            std::process::exit(2);
-                        }
+                        Err(e) => report::exit(2, &e),
                     }
                 }
             }
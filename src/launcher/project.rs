@@ -0,0 +1,164 @@
+//! `.nvim-manager.toml` (プロジェクトごとの設定) を launcher に反映する
+//!
+//! 読み込みと信頼の記録はライブラリの `neovim_manager::project` が行い、ここでは信頼するかの確認・
+//! nvim に加える引数・フックの実行を行う。
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use neovim_manager::identifier;
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::project::{
+    self, Hooks, IdentifierStrategy, Project, ProjectConfig, SessionMode,
+};
+use neovim_manager::utils;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// 信頼した設定ファイルとその内容
+pub struct Loaded {
+    pub project: Project,
+    pub config: ProjectConfig,
+}
+
+/// `dir` の設定ファイルを読む。初めての内容なら端末で信頼するか尋ね、信頼しなければ使わない
+pub fn load(dir: &Path) -> Result<Option<Loaded>> {
+    let Some(project) = project::load(dir)? else {
+        return Ok(None);
+    };
+    // 書き間違いは尋ねる前に知らせる
+    let config = project.parse()?;
+
+    if !project.is_trusted() {
+        if !confirm_trust(&project)? {
+            eprintln!("Ignoring untrusted {}", project.file.display());
+            return Ok(None);
+        }
+        project.trust()?;
+    }
+    info!("Using project config {}", project.file.display());
+    Ok(Some(Loaded { project, config }))
+}
+
+/// `neovim-launcher trust [DIR]`: 端末から起動しない場合のために、尋ねずに信頼する
+pub fn trust(dir: &Path) -> Result<()> {
+    let project = project::load(dir)?
+        .ok_or_else(|| anyhow!("No {} found in {}", project::FILE_NAME, dir.display()))?;
+    project.parse()?;
+    project.trust()?;
+    println!("Trusted {}", project.file.display());
+    Ok(())
+}
+
+fn confirm_trust(project: &Project) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "{} is not trusted yet (run `neovim-launcher trust {}` to use it)",
+            project.file.display(),
+            project.root.display()
+        );
+        return Ok(false);
+    }
+
+    eprintln!("--- {}", project.file.display());
+    eprintln!("{}", project.content.trim_end());
+    eprintln!("---");
+    eprint!("This file can run commands when launching Neovim. Trust it? [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+impl Loaded {
+    /// `identifier = "git-root"` なら、`identifier` (開く対象のディレクトリ) を含む作業ツリーの先頭にする
+    pub fn identifier(&self, identifier: String) -> Result<String> {
+        match self.config.identifier {
+            IdentifierStrategy::Target => Ok(identifier),
+            IdentifierStrategy::GitRoot => match utils::git_root(Path::new(&identifier)) {
+                Some(root) => Ok(identifier::from_path(&root)?),
+                None => Ok(identifier),
+            },
+        }
+    }
+
+    /// headless の nvim の、開くファイルより前に加える引数
+    ///
+    /// `nvim_args` のあとに、作業ディレクトリを identifier にする (`git-root` のとき) ・
+    /// セッションを読み込んでから開くファイルを開き直す・終了するときにセッションを保存する、の順に加える
+    pub fn nvim_args(&self, identifier: &str, target_file: Option<&Path>) -> Vec<String> {
+        let mut args = self.config.nvim_args.clone();
+        if self.config.identifier == IdentifierStrategy::GitRoot {
+            args.extend([
+                "-c".to_string(),
+                format!(
+                    "execute 'cd ' . fnameescape({})",
+                    utils::vim_string_literal(identifier)
+                ),
+            ]);
+        }
+
+        if self.config.session == SessionMode::Auto {
+            let Some(session) = project::session_path(identifier) else {
+                warn!("Cannot determine the session directory");
+                return args;
+            };
+            if let Some(dir) = session.parent() {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    warn!("Cannot create {}: {e}", dir.display());
+                }
+            }
+            let session_literal = utils::vim_string_literal(&session.to_string_lossy());
+            if session.is_file() {
+                args.extend([
+                    "-c".to_string(),
+                    format!("execute 'source ' . fnameescape({session_literal})"),
+                ]);
+                // セッションでウィンドウが置き換わるので、開くファイルをもう一度表示する
+                if let Some(file) = target_file {
+                    args.extend([
+                        "-c".to_string(),
+                        format!(
+                            "execute 'drop ' . fnameescape({})",
+                            utils::vim_string_literal(&file.to_string_lossy())
+                        ),
+                    ]);
+                }
+            }
+            args.extend([
+                "--cmd".to_string(),
+                format!(
+                    "autocmd VimLeavePre * execute 'mksession! ' . fnameescape({session_literal})"
+                ),
+            ]);
+        }
+        args
+    }
+
+    /// フックを待たずに実行する (失敗しても起動は続ける)
+    ///
+    /// プロジェクトのディレクトリで、`NEOVIM_MANAGER_IDENTIFIER` と `NVIM` (サーバーアドレス) を設定して実行する
+    pub fn run_hook(
+        &self,
+        select: impl FnOnce(&Hooks) -> &Option<String>,
+        identifier: &str,
+        server_address: &str,
+    ) {
+        let Some(command) = select(&self.config.hooks) else {
+            return;
+        };
+        let shell = if cfg!(windows) {
+            ProcessSpec::new("cmd").args(["/C", command])
+        } else {
+            ProcessSpec::new("sh").args(["-c", command])
+        };
+        let spec = shell
+            .current_dir(&self.project.root)
+            .env(utils::IDENTIFIER_ENV, identifier)
+            .env("NVIM", server_address);
+        info!("Running hook: {command}");
+        if let Err(e) = process::spawn(&spec) {
+            eprintln!("Failed to run hook `{command}`: {e:#}");
+        }
+    }
+}
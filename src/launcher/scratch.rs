@@ -0,0 +1,102 @@
+//! いつでも出し入れできるスクラッチのインスタンス (`--toggle-scratch`)
+//!
+//! `launcher.scratch_dir` のインスタンスを、なければ起動し、GUI のウィンドウにフォーカスがあれば隠し、
+//! なければ (隠れていれば戻して) 前面に出す。ホットキーに割り当てて使うので、どの場合もすぐに終了する。
+//! GUI のウィンドウは接続しているプロセスの ID で探し、コンポジターや OS に出し入れさせる。
+//! 隠せない環境 (X11・Windows など) では、接続している UI があれば `NeovideFocus` で前面に出すだけにする。
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use neovim_manager::config::Config;
+use neovim_manager::gui;
+use neovim_manager::identifier;
+use neovim_manager::nvim::NvimClient;
+use neovim_manager::tunnel;
+use neovim_manager::utils::{self, OpenFileOptions};
+use std::path::Path;
+
+use super::{handler, launch_client, LauncherClient};
+
+/// 出し入れした結果
+enum Toggled {
+    Shown,
+    Hidden,
+    /// 接続している GUI がない (ウィンドウだけ閉じられた)
+    NoWindow,
+}
+
+pub async fn run(client: &LauncherClient, config: &Config) -> Result<()> {
+    let dir = config.launcher.scratch_dir.value.as_ref().ok_or_else(|| {
+        anyhow!("Cannot determine the scratch directory (set launcher.scratch_dir)")
+    })?;
+    std::fs::create_dir_all(dir).map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    let identifier = identifier::from_path(dir)?;
+
+    let Some(instance) = client.query_instance(&identifier).await? else {
+        eprintln!("Starting the scratch instance in {identifier}");
+        return handler::start_instance(
+            client,
+            config,
+            Path::new(&identifier),
+            None,
+            &OpenFileOptions::default(),
+        )
+        .await;
+    };
+
+    let server_address = instance.server_address.as_str().to_string();
+    let toggled = if gui::can_toggle() {
+        let server_address = server_address.clone();
+        tokio::task::spawn_blocking(move || toggle(&server_address)).await??
+    } else {
+        show(&server_address).await?
+    };
+    match toggled {
+        Toggled::Hidden => return Ok(()),
+        Toggled::Shown => {}
+        Toggled::NoWindow => {
+            info!("No GUI is attached to the scratch instance, launching one");
+            launch_client(&config.launcher, None, &identifier, &server_address)?;
+        }
+    }
+    if let Err(e) = client.touch_instance(&identifier).await {
+        warn!("{e:#}");
+    }
+    Ok(())
+}
+
+/// `server_address` に接続している GUI のウィンドウにフォーカスがあれば隠し、なければ前面に出す
+fn toggle(server_address: &str) -> Result<Toggled> {
+    // GUI は `ssh://` ではなくローカルフォワードのアドレスで接続している
+    let pids = gui::find_gui_processes(&tunnel::local_address(server_address)?)?;
+    let Some(&pid) = pids.last() else {
+        return Ok(Toggled::NoWindow);
+    };
+    match gui::focused_process()? {
+        Some(focused) if pids.contains(&focused) => {
+            info!("Hiding the window of process {focused}");
+            gui::hide(focused)?;
+            Ok(Toggled::Hidden)
+        }
+        _ => {
+            info!("Showing the window of process {pid}");
+            gui::show(pid)?;
+            Ok(Toggled::Shown)
+        }
+    }
+}
+
+/// 隠せない環境では、接続している UI があれば前面に出すだけにする
+async fn show(server_address: &str) -> Result<Toggled> {
+    let attached = {
+        let server_address = server_address.to_string();
+        tokio::task::spawn_blocking(move || NvimClient::connect(&server_address)?.attached_uis())
+            .await??
+    };
+    if attached.is_empty() {
+        return Ok(Toggled::NoWindow);
+    }
+    info!("Hiding windows is not supported here, only showing the scratch instance");
+    utils::focus_nvim_instance(server_address).await?;
+    Ok(Toggled::Shown)
+}
@@ -0,0 +1,273 @@
+//! `sudoedit` と同じ流れで、書き込み権限のないファイルを編集する (`--sudo <FILE>`)
+//!
+//! 元のファイルを `sudo cat` で自分だけが読める一時ディレクトリに写し、プロジェクトのインスタンスで開く。
+//! 保存されるたびに `sudo cp` で書き戻し、バッファが閉じられたら一時ファイルを消して終了する。
+//! インスタンスがなければ端末で `nvim` を実行し、終了してから書き戻す。
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use neovim_manager::identifier;
+use neovim_manager::nvim::{NvimClient, Value};
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::utils::{self, OpenFileOptions};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 保存・バッファが閉じられたかを確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// バッファがウィンドウに表示されているか、と未保存の変更があるか (消されていれば表示なし)
+const POLL_LUA: &str = r#"
+local buf = ...
+if not vim.api.nvim_buf_is_valid(buf) then
+  return { shown = false, modified = false }
+end
+return { shown = #vim.fn.win_findbuf(buf) > 0, modified = vim.bo[buf].modified }
+"#;
+
+/// 表示されておらず変更もなければバッファを消す (一時ファイルは後で消すので残さない)
+const CLEANUP_LUA: &str = r#"
+local buf = ...
+if vim.api.nvim_buf_is_valid(buf) and #vim.fn.win_findbuf(buf) == 0 and not vim.bo[buf].modified then
+  pcall(vim.api.nvim_buf_delete, buf, {})
+end
+"#;
+
+#[derive(Debug, Deserialize)]
+struct Poll {
+    shown: bool,
+    modified: bool,
+}
+
+/// 編集中の一時ファイルと、書き戻し先
+struct Session {
+    target: PathBuf,
+    dir: PathBuf,
+    copy: PathBuf,
+    /// 最後に書き戻した (または元の) 内容
+    written: Vec<u8>,
+    /// 最後に書き戻そうとして失敗した内容 (同じ内容で sudo を繰り返さない)
+    failed: Option<Vec<u8>>,
+}
+
+impl Session {
+    /// 元のファイルを一時ディレクトリに写す。ファイルがなければ空のファイルから始める
+    fn start(target: PathBuf) -> Result<Self> {
+        let content = if target.exists() {
+            let output = process::output(&ProcessSpec::new("sudo").args([
+                "cat",
+                "--",
+                &target.to_string_lossy(),
+            ]))?;
+            if !output.success() {
+                return Err(anyhow!(
+                    "Cannot read {}: {}",
+                    target.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            output.stdout
+        } else {
+            Vec::new()
+        };
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let dir =
+            std::env::temp_dir().join(format!("neovim-sudo-{}-{nanos:x}", std::process::id()));
+        create_private_dir(&dir)?;
+        // ファイルタイプの判定が効くよう、元のファイル名のまま置く
+        let name = target
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file", target.display()))?;
+        let copy = dir.join(name);
+        std::fs::write(&copy, &content)
+            .map_err(|e| anyhow!("Cannot write {}: {e}", copy.display()))?;
+
+        Ok(Self {
+            target,
+            dir,
+            copy,
+            written: content,
+            failed: None,
+        })
+    }
+
+    /// 一時ファイルが前回から変わっていれば `sudo cp` で書き戻す
+    ///
+    /// `cp` は既存のファイルの中身だけを書き換えるので、所有者とパーミッションはそのまま残る
+    fn sync(&mut self) -> Result<()> {
+        let content = std::fs::read(&self.copy)
+            .map_err(|e| anyhow!("Cannot read {}: {e}", self.copy.display()))?;
+        if content == self.written || self.failed.as_ref() == Some(&content) {
+            return Ok(());
+        }
+
+        let output = process::output(&ProcessSpec::new("sudo").args([
+            "cp",
+            "--",
+            &self.copy.to_string_lossy(),
+            &self.target.to_string_lossy(),
+        ]))?;
+        if !output.success() {
+            self.failed = Some(content);
+            return Err(anyhow!(
+                "Cannot write {}: {}",
+                self.target.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("Wrote {}", self.target.display());
+        self.written = content;
+        self.failed = None;
+        Ok(())
+    }
+
+    /// 最後に書き戻してから一時ディレクトリを消す。書き戻せなければ編集した内容を残して場所を伝える
+    fn finish(mut self) -> Result<()> {
+        self.failed = None;
+        if let Err(e) = self.sync() {
+            return Err(e.context(format!(
+                "The edited copy is kept at {}",
+                self.copy.display()
+            )));
+        }
+        std::fs::remove_dir_all(&self.dir)
+            .map_err(|e| anyhow!("Cannot remove {}: {e}", self.dir.display()))
+    }
+
+    /// インスタンスで開き、バッファが閉じられるまで保存のたびに書き戻す。閉じたときに未保存の変更があれば true
+    fn edit_in_instance(
+        &mut self,
+        server_address: &str,
+        options: &OpenFileOptions,
+    ) -> Result<bool> {
+        let mut nvim = NvimClient::connect(server_address)?;
+        let copy = self.copy.to_string_lossy().to_string();
+        nvim.eval(&utils::open_file_expr(&copy, options)?)?;
+        // 開いた直後なので、カレントバッファがコピーのバッファ
+        let buffer = nvim.eval("bufnr()")?;
+        info!(
+            "Editing {} as {copy} in {server_address}, waiting for it to close",
+            self.target.display()
+        );
+
+        let modified = loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if let Err(e) = self.sync() {
+                eprintln!("{e:#}");
+            }
+            let poll: Poll = nvim
+                .request_as(
+                    "nvim_exec_lua",
+                    vec![Value::from(POLL_LUA), Value::Array(vec![buffer.clone()])],
+                )
+                .map_err(|e| anyhow!("Lost the Neovim instance while waiting: {e}"))?;
+            if !poll.shown {
+                break poll.modified;
+            }
+        };
+
+        nvim.request(
+            "nvim_exec_lua",
+            vec![Value::from(CLEANUP_LUA), Value::Array(vec![buffer])],
+        )?;
+        Ok(modified)
+    }
+
+    /// インスタンスがない場合に、端末で `nvim` を実行する
+    fn edit_in_terminal(&self, options: &OpenFileOptions) -> Result<()> {
+        let mut spec = ProcessSpec::new("nvim").arg(self.copy.to_string_lossy());
+        if let Some(line) = options.line {
+            spec = spec.arg(format!(
+                "+call setcursorcharpos({line}, {})",
+                options.column.unwrap_or(1)
+            ));
+        }
+        process::status(&spec)?;
+        Ok(())
+    }
+}
+
+/// 自分だけが読み書きできるディレクトリを作る (既にあれば失敗する)
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(dir)
+        .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))
+}
+
+/// 書き戻し先の絶対パス。まだないファイルは親ディレクトリを解決してつなぐ
+fn target_path(file: &Path) -> Result<PathBuf> {
+    if file.exists() {
+        let path = identifier::canonical_path(file)?;
+        if path.is_dir() {
+            return Err(anyhow!("{} is a directory", path.display()));
+        }
+        return Ok(path);
+    }
+
+    let name = file
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", file.display()))?;
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let parent = identifier::canonical_path(parent)
+        .map_err(|e| anyhow!("Cannot open {}: {e}", file.display()))?;
+    Ok(parent.join(name))
+}
+
+/// `file` を編集し、終了コードを返す (閉じたときに未保存の変更があれば 1)
+pub async fn run(
+    file: &Path,
+    server_address: Option<String>,
+    options: &OpenFileOptions,
+) -> Result<i32> {
+    if cfg!(windows) {
+        return Err(anyhow!("--sudo is not supported on Windows"));
+    }
+    let target = target_path(file)?;
+
+    // 後の sudo が端末で尋ねずに済むよう、先に認証しておく
+    match process::status(&ProcessSpec::new("sudo").arg("-v"))? {
+        Some(0) => {}
+        _ => return Err(anyhow!("sudo failed")),
+    }
+
+    let mut session = Session::start(target)?;
+    if let Some(server_address) = &server_address {
+        if let Err(e) = utils::focus_nvim_instance(server_address).await {
+            warn!("{e:#}");
+        }
+    }
+
+    let options = *options;
+    tokio::task::spawn_blocking(move || {
+        let modified = match &server_address {
+            Some(server_address) => session.edit_in_instance(server_address, &options),
+            None => session.edit_in_terminal(&options).map(|()| false),
+        };
+        let target = session.target.clone();
+        let finished = session.finish();
+        let modified = modified?;
+        finished.with_context(|| format!("Failed to write back {}", target.display()))?;
+
+        if modified {
+            eprintln!(
+                "{} was closed without saving; unsaved changes were not written",
+                target.display()
+            );
+            return Ok(1);
+        }
+        Ok(0)
+    })
+    .await?
+}
@@ -0,0 +1,107 @@
+//! 名前付きのワークスペースのプロジェクトをまとめて開く (`--workspace <name>`)
+//!
+//! `[workspace.<name>]` の `projects` のディレクトリごとに `neovim-launcher <dir>` を起動し、
+//! すべて終了するまで待つ。既にインスタンスがあるプロジェクトは、それぞれの launcher が接続する。
+//! 端末を取り合わないよう、`.nvim-manager.toml` を信頼するかはここで 1 つずつ尋ねてから起動する。
+
+use anyhow::{anyhow, Result};
+use log::info;
+use neovim_manager::config::{self, Config};
+use neovim_manager::identifier;
+use neovim_manager::process::{self, ProcessSpec};
+use neovim_manager::tmux::{self, TmuxMode};
+use neovim_manager::utils;
+use std::path::{Path, PathBuf};
+
+use super::{print_log_tail, project, LauncherClient};
+
+/// それぞれの launcher の出力を残すファイル (identifier ごと、起動するたびに空にする)
+fn output_path(identifier: &str) -> PathBuf {
+    config::runtime_dir()
+        .join("workspace")
+        .join(format!("{}.log", utils::stable_hash(identifier.as_bytes())))
+}
+
+pub async fn run(
+    client: &LauncherClient,
+    config: &Config,
+    name: &str,
+    tmux_mode: Option<TmuxMode>,
+) -> Result<()> {
+    let workspace = config.workspace(name)?;
+    if tmux_mode.is_some() && !tmux::in_tmux() {
+        return Err(anyhow!(
+            "--tmux requires running inside a tmux session (TMUX is not set)"
+        ));
+    }
+    // 同時に起動する launcher がそれぞれマネージャーを起動しようとしないよう、先に起動しておく
+    client.list_instances().await?;
+
+    let launcher = std::env::current_exe()?;
+    let mut failed = 0;
+    let mut children = Vec::new();
+    for dir in &workspace.projects {
+        let identifier = match identifier::from_path(dir) {
+            Ok(identifier) if Path::new(&identifier).is_dir() => identifier,
+            _ => {
+                eprintln!("Skipping {}: not a directory", dir.display());
+                failed += 1;
+                continue;
+            }
+        };
+        // 信頼しなかった場合も、起動した launcher は端末がないので尋ねずに設定ファイルを使わない
+        if let Err(e) = project::load(Path::new(&identifier)) {
+            eprintln!("Skipping {identifier}: {e:#}");
+            failed += 1;
+            continue;
+        }
+
+        let output = output_path(&identifier);
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+        }
+        let mut spec = ProcessSpec::new(&launcher)
+            .arg(&identifier)
+            .stderr_file(&output);
+        if let Some(mode) = tmux_mode {
+            spec = spec.args(["--tmux".to_string(), mode.to_string()]);
+        }
+        info!("Launching {identifier} of workspace {name}");
+        eprintln!("Opening {identifier}");
+        match process::spawn(&spec) {
+            Ok(child) => children.push((identifier, output, child)),
+            Err(e) => {
+                eprintln!("Cannot launch {identifier}: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+
+    let launched = children.len();
+    let failed = failed
+        + tokio::task::spawn_blocking(move || {
+            let mut failed = 0;
+            for (identifier, output, mut child) in children {
+                match child.wait() {
+                    Ok(Some(0)) => continue,
+                    Ok(Some(code)) => eprintln!("{identifier} exited with code {code}"),
+                    Ok(None) => eprintln!("{identifier} was terminated by a signal"),
+                    Err(e) => eprintln!("Lost {identifier}: {e:#}"),
+                }
+                print_log_tail("neovim-launcher output", &output);
+                failed += 1;
+            }
+            failed
+        })
+        .await?;
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{failed} of {} projects in workspace '{name}' failed",
+            workspace.projects.len()
+        ));
+    }
+    info!("All {launched} projects of workspace {name} exited");
+    Ok(())
+}
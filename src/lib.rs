@@ -1,34 +1,465 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod clipboard;
+pub mod clock;
+#[cfg(feature = "client")]
+pub mod config;
+#[cfg(feature = "client")]
+pub mod direnv;
+pub mod duration;
+#[cfg(feature = "client")]
+pub mod gui;
+pub mod identifier;
+#[cfg(feature = "server")]
+pub mod manager;
+#[cfg(feature = "client")]
+pub mod notify;
+#[cfg(feature = "client")]
+pub mod nvim;
+#[cfg(feature = "client")]
+pub mod process;
+#[cfg(feature = "client")]
+pub mod project;
+#[cfg(feature = "client")]
+pub mod recent;
+#[cfg(feature = "client")]
+pub mod report;
+#[cfg(feature = "client")]
+pub mod retry;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "client")]
+pub mod stats;
+#[cfg(feature = "client")]
+pub mod tmux;
+#[cfg(feature = "client")]
+pub mod tunnel;
+#[cfg(feature = "client")]
+pub mod wsl;
+
 pub const DEFAULT_PORT: u16 = 57394;
 pub const DEFAULT_BIND_ADDR: &str = "127.0.0.1";
 
+/// JSON-RPC の互換性を表すバージョン。メソッドやパラメータを変えたら上げる
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// params に入れる、送り手のプロトコルバージョンのフィールド名
+pub const PROTOCOL_VERSION_FIELD: &str = "protocol_version";
+
+/// `health_status` を 4 状態で返すようになったプロトコルバージョン。これより古いクライアントには 2 状態で返す
+pub const HEALTH_STATUS_PROTOCOL_VERSION: u32 = 2;
+
+/// params に書かれた送り手のプロトコルバージョン (書いていない古いクライアントは 0)
+pub fn params_protocol_version(params: &serde_json::Value) -> u32 {
+    params
+        .get(PROTOCOL_VERSION_FIELD)
+        .and_then(serde_json::Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(0)
+}
+
+/// nvim サーバーの接続先 (`nvim --listen` / `--server` に渡すもの)
+///
+/// JSON では従来どおり文字列で表す。`&str` を受け取る関数にはそのまま渡せる。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ServerAddress {
+    /// `host:port` (IPv6 は `[::1]:port`)
+    Tcp(String),
+    /// Unix ドメインソケットのパス
+    Unix(String),
+    /// Windows の名前付きパイプ (`\\.\pipe\name`)
+    WindowsPipe(String),
+    /// SSH の先の nvim (`ssh://[user@]host[:port]/<host:port または /path>`)。ローカルフォワードを通して接続する
+    Ssh(String),
+}
+
+/// `ssh://` のアドレスの中身
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshTarget {
+    /// ssh に渡す接続先 (`user@host`。IPv6 の `[]` は外す)
+    pub destination: String,
+    /// ssh のポート (`-p`)
+    pub port: Option<u16>,
+    /// SSH の先から見た nvim のアドレス (TCP か絶対パスの Unix ソケット)
+    pub remote: String,
+}
+
+impl SshTarget {
+    fn parse(address: &str) -> Option<Self> {
+        let rest = address
+            .get(..6)?
+            .eq_ignore_ascii_case("ssh://")
+            .then(|| &address[6..])?;
+        let (authority, remote) = rest.split_once('/')?;
+
+        // `/path` は `ssh://host//path` と書く
+        let remote_is_tcp = matches!(remote.parse(), Ok(ServerAddress::Tcp(_)));
+        if !remote_is_tcp && !remote.starts_with('/') {
+            return None;
+        }
+
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed.split_once(']')?;
+                match after {
+                    "" => (host, None),
+                    _ => (host, Some(after.strip_prefix(':')?.parse().ok()?)),
+                }
+            }
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port.parse().ok()?)),
+                None => (host_port, None),
+            },
+        };
+        if host.is_empty()
+            || user.is_some_and(str::is_empty)
+            || authority.contains(char::is_whitespace)
+        {
+            return None;
+        }
+
+        Some(Self {
+            destination: match user {
+                Some(user) => format!("{user}@{host}"),
+                None => host.to_string(),
+            },
+            port,
+            remote: remote.to_string(),
+        })
+    }
+}
+
+/// [`ServerAddress`] として読めない文字列
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid server address: {0:?}")]
+pub struct InvalidServerAddress(pub String);
+
+impl ServerAddress {
+    pub fn tcp(host: &str, port: u16) -> Self {
+        if host.contains(':') && !host.starts_with('[') {
+            ServerAddress::Tcp(format!("[{host}]:{port}"))
+        } else {
+            ServerAddress::Tcp(format!("{host}:{port}"))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ServerAddress::Tcp(address)
+            | ServerAddress::Unix(address)
+            | ServerAddress::WindowsPipe(address)
+            | ServerAddress::Ssh(address) => address,
+        }
+    }
+
+    /// `ssh://` のアドレスなら、その中身
+    pub fn ssh_target(&self) -> Option<SshTarget> {
+        match self {
+            ServerAddress::Ssh(address) => SshTarget::parse(address),
+            _ => None,
+        }
+    }
+
+    pub fn is_tcp(&self) -> bool {
+        matches!(self, ServerAddress::Tcp(_))
+    }
+
+    /// TCP のポート
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            ServerAddress::Tcp(address) => address.rsplit_once(':')?.1.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// TCP のホスト (IPv6 の `[]` は外す)
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            ServerAddress::Tcp(address) => {
+                let host = address.rsplit_once(':')?.0;
+                Some(
+                    host.strip_prefix('[')
+                        .and_then(|host| host.strip_suffix(']'))
+                        .unwrap_or(host),
+                )
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ServerAddress {
+    type Err = InvalidServerAddress;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(InvalidServerAddress(s.to_string()));
+        }
+
+        let lower = s.to_ascii_lowercase();
+        if lower.starts_with(r"\\.\pipe\") || lower.starts_with("//./pipe/") {
+            return Ok(ServerAddress::WindowsPipe(s.to_string()));
+        }
+        if lower.starts_with("ssh://") {
+            return match SshTarget::parse(s) {
+                Some(_) => Ok(ServerAddress::Ssh(s.to_string())),
+                None => Err(InvalidServerAddress(s.to_string())),
+            };
+        }
+
+        // ホストに区切り文字を含むものはパス (`/tmp/nvim:1` など)
+        if let Some((host, port)) = s.rsplit_once(':') {
+            let is_host = !host.is_empty() && !host.contains(['/', '\\']);
+            if is_host && port.parse::<u16>().is_ok() {
+                return Ok(ServerAddress::Tcp(s.to_string()));
+            }
+        }
+
+        Ok(ServerAddress::Unix(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for ServerAddress {
+    type Error = InvalidServerAddress;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ServerAddress> for String {
+    fn from(address: ServerAddress) -> Self {
+        match address {
+            ServerAddress::Tcp(address)
+            | ServerAddress::Unix(address)
+            | ServerAddress::WindowsPipe(address)
+            | ServerAddress::Ssh(address) => address,
+        }
+    }
+}
+
+impl std::fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::ops::Deref for ServerAddress {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ServerAddress {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for ServerAddress {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<String> for ServerAddress {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ServerAddress {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ServerAddress {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "ServerAddress".into()
+    }
+
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "description": "host:port (TCP), a Unix socket path, a Windows named pipe (\\\\.\\pipe\\name) or ssh://[user@]host[:port]/<host:port or /path>",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct InstanceInfo {
     pub identifier: String,
-    pub server_address: String,
+    pub server_address: ServerAddress,
     pub registered_at: chrono::DateTime<chrono::Utc>,
     pub last_ping: chrono::DateTime<chrono::Utc>,
+    pub last_used: chrono::DateTime<chrono::Utc>,
     pub health_status: HealthStatus,
     pub last_health_check: chrono::DateTime<chrono::Utc>,
+    pub pinned: bool,
+    /// インスタンスの作業ディレクトリ (不明なら None)
+    pub cwd: Option<String>,
+    /// nvim サーバーのプロセス ID (不明なら None)
+    pub pid: Option<u32>,
+    /// グループ分け用のタグ (ソート済み・重複なし)
+    pub tags: Vec<String>,
+    /// nvim サーバーの `NVIM_APPNAME` (設定せずに起動した・不明なら None)
+    #[serde(default)]
+    pub appname: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// インスタンスの状態
+///
+/// JSON では `"Starting"` / `"Healthy"` / `{"Unhealthy": {"consecutive_failures": n}}` / `"Dead"` になる。
+/// 古いマネージャーが返す `"Unknown"` は `Starting` として読み、古いクライアントには
+/// [`downgrade_health_status`] で `"Unknown"` / `"Healthy"` にして返す
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum HealthStatus {
-    Unknown,
+    /// 登録直後で、まだヘルスチェックが通っていない
+    #[serde(alias = "Unknown")]
+    Starting,
     Healthy,
+    /// 直近のヘルスチェックが続けて失敗している (ピン留めされたものなど、削除されずに残っている)
+    Unhealthy {
+        consecutive_failures: u32,
+    },
+    /// プロセスが終了した (登録から外れたインスタンスの記録に残る)
+    Dead,
+}
+
+impl HealthStatus {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, HealthStatus::Healthy)
+    }
+
+    /// 連続したヘルスチェックの失敗回数 (Unhealthy 以外は 0)
+    pub fn consecutive_failures(&self) -> u32 {
+        match self {
+            HealthStatus::Unhealthy {
+                consecutive_failures,
+            } => *consecutive_failures,
+            _ => 0,
+        }
+    }
+
+    /// 失敗回数を除いた状態の名前 (メトリクスのラベルなどに使う)
+    pub fn name(&self) -> &'static str {
+        match self {
+            HealthStatus::Starting => "Starting",
+            HealthStatus::Healthy => "Healthy",
+            HealthStatus::Unhealthy { .. } => "Unhealthy",
+            HealthStatus::Dead => "Dead",
+        }
+    }
+
+    /// ヘルスチェックに失敗したあとの状態
+    pub fn failed(&self) -> Self {
+        HealthStatus::Unhealthy {
+            consecutive_failures: self.consecutive_failures() + 1,
+        }
+    }
+}
+
+/// 結果の中の `health_status` を、`Unknown` と `Healthy` しか知らない古いクライアントが読める形にする
+/// (`"Healthy"` 以外はすべて `"Unknown"`)
+pub fn downgrade_health_status(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == "health_status" {
+                    if value != "Healthy" {
+                        *value = "Unknown".into();
+                    }
+                } else {
+                    downgrade_health_status(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(downgrade_health_status),
+        _ => {}
+    }
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthStatus::Unhealthy {
+                consecutive_failures,
+            } => write!(f, "Unhealthy ({consecutive_failures})"),
+            _ => f.write_str(self.name()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct JsonRpcRequest {
     pub jsonrpc: String,
     pub method: String,
     pub params: serde_json::Value,
+    /// 通知 (応答不要) では省略する
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
     pub id: serde_json::Value,
 }
 
+impl JsonRpcRequest {
+    /// UUID の ID を付けたリクエスト
+    #[cfg(feature = "client")]
+    pub fn new(method: &str, params: serde_json::Value) -> Self {
+        Self::with_id(
+            method,
+            params,
+            serde_json::Value::String(uuid::Uuid::new_v4().to_string()),
+        )
+    }
+
+    /// params がオブジェクトなら [`PROTOCOL_VERSION_FIELD`] を付ける (既に書かれていればそのまま)
+    pub fn with_id(method: &str, params: serde_json::Value, id: serde_json::Value) -> Self {
+        let mut params = params;
+        if let Some(object) = params.as_object_mut() {
+            object
+                .entry(PROTOCOL_VERSION_FIELD)
+                .or_insert_with(|| PROTOCOL_VERSION.into());
+        }
+
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+            id,
+        }
+    }
+
+    /// ID を持たない通知。マネージャーは応答を返さない
+    pub fn notification(method: &str, params: serde_json::Value) -> Self {
+        Self::with_id(method, params, serde_json::Value::Null)
+    }
+
+    pub fn is_notification(&self) -> bool {
+        self.id.is_null()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct JsonRpcResponse {
     pub jsonrpc: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -38,7 +469,70 @@ pub struct JsonRpcResponse {
     pub id: serde_json::Value,
 }
 
+impl JsonRpcResponse {
+    pub fn success(id: serde_json::Value, result: serde_json::Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn failure(id: serde_json::Value, error: JsonRpcError) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// 1 本の接続に複数のリクエストを続けて送るときに、届いた応答を送ったリクエストと対応付ける
+///
+/// 応答は送った順に返るとは限らないので ID で引く。`T` には呼び出し側が応答と結び付けたい値を入れる
+#[derive(Debug)]
+pub struct PendingRequests<T> {
+    pending: HashMap<String, T>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PendingRequests<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 応答を待つリクエストとして登録する。通知は応答が来ないので登録しない
+    pub fn insert(&mut self, request: &JsonRpcRequest, value: T) {
+        if !request.is_notification() {
+            self.pending.insert(request.id.to_string(), value);
+        }
+    }
+
+    /// 応答に対応する値を取り出す。知らない ID なら None
+    pub fn resolve(&mut self, response: &JsonRpcResponse) -> Option<T> {
+        self.pending.remove(&response.id.to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct JsonRpcError {
     pub code: i32,
     pub message: String,
@@ -47,114 +541,1238 @@ pub struct JsonRpcError {
 }
 
 pub mod errors {
+    use crate::JsonRpcError;
+    use serde_json::{json, Value};
+
     pub const INSTANCE_ALREADY_EXISTS: i32 = -32001;
     pub const INSTANCE_NOT_FOUND: i32 = -32002;
     pub const HEALTH_CHECK_FAILED: i32 = -32003;
     pub const INTERNAL_ERROR: i32 = -32000;
+    pub const PARSE_ERROR: i32 = -32700;
+    pub const METHOD_NOT_FOUND: i32 = -32601;
+    pub const INVALID_PARAMS: i32 = -32602;
+
+    /// マネージャーの API が返すエラー。JSON-RPC のエラーオブジェクトと相互に変換できる
+    #[derive(Debug, Clone, PartialEq, thiserror::Error)]
+    pub enum ManagerError {
+        #[error("Instance already exists")]
+        InstanceAlreadyExists { identifier: String },
+        #[error("Instance not found")]
+        InstanceNotFound { identifier: String },
+        #[error("Health check failed")]
+        HealthCheckFailed { identifier: String },
+        #[error("Parse error")]
+        ParseError,
+        #[error("Method not found")]
+        MethodNotFound,
+        #[error("Invalid parameters: {0}")]
+        InvalidParams(String),
+        #[error("{0}")]
+        Internal(String),
+        /// マネージャーに接続できない・時間内に応答がない (クライアント側でのみ発生する)
+        #[error("{0}")]
+        Unreachable(String),
+        /// このバージョンが知らないエラーコード
+        #[error("{message} (code: {code})")]
+        Other {
+            code: i32,
+            message: String,
+            data: Option<Value>,
+        },
+    }
+
+    impl ManagerError {
+        pub fn code(&self) -> i32 {
+            match self {
+                ManagerError::InstanceAlreadyExists { .. } => INSTANCE_ALREADY_EXISTS,
+                ManagerError::InstanceNotFound { .. } => INSTANCE_NOT_FOUND,
+                ManagerError::HealthCheckFailed { .. } => HEALTH_CHECK_FAILED,
+                ManagerError::ParseError => PARSE_ERROR,
+                ManagerError::MethodNotFound => METHOD_NOT_FOUND,
+                ManagerError::InvalidParams(_) => INVALID_PARAMS,
+                ManagerError::Internal(_) | ManagerError::Unreachable(_) => INTERNAL_ERROR,
+                ManagerError::Other { code, .. } => *code,
+            }
+        }
+
+        /// 対象インスタンスの identifier (インスタンスに関するエラーのみ)
+        pub fn identifier(&self) -> Option<&str> {
+            match self {
+                ManagerError::InstanceAlreadyExists { identifier }
+                | ManagerError::InstanceNotFound { identifier }
+                | ManagerError::HealthCheckFailed { identifier } => Some(identifier),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<ManagerError> for JsonRpcError {
+        fn from(error: ManagerError) -> Self {
+            let code = error.code();
+            let data = match &error {
+                ManagerError::Other { data, .. } => data.clone(),
+                _ => error
+                    .identifier()
+                    .map(|identifier| json!({"identifier": identifier})),
+            };
+            let message = match error {
+                ManagerError::Other { message, .. } => message,
+                _ => error.to_string(),
+            };
+
+            JsonRpcError {
+                code,
+                message,
+                data,
+            }
+        }
+    }
+
+    impl From<JsonRpcError> for ManagerError {
+        fn from(error: JsonRpcError) -> Self {
+            let identifier = || {
+                error
+                    .data
+                    .as_ref()
+                    .and_then(|data| data.get("identifier"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+
+            match error.code {
+                INSTANCE_ALREADY_EXISTS => ManagerError::InstanceAlreadyExists {
+                    identifier: identifier(),
+                },
+                INSTANCE_NOT_FOUND => ManagerError::InstanceNotFound {
+                    identifier: identifier(),
+                },
+                HEALTH_CHECK_FAILED => ManagerError::HealthCheckFailed {
+                    identifier: identifier(),
+                },
+                PARSE_ERROR => ManagerError::ParseError,
+                METHOD_NOT_FOUND => ManagerError::MethodNotFound,
+                INVALID_PARAMS => ManagerError::InvalidParams(
+                    error
+                        .message
+                        .strip_prefix("Invalid parameters: ")
+                        .unwrap_or(&error.message)
+                        .to_string(),
+                ),
+                INTERNAL_ERROR => ManagerError::Internal(error.message),
+                code => ManagerError::Other {
+                    code,
+                    message: error.message,
+                    data: error.data,
+                },
+            }
+        }
+    }
 }
 
+pub use errors::ManagerError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct QueryInstanceParams {
     pub identifier: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RegisterInstanceParams {
     pub identifier: String,
-    pub server_address: String,
+    pub server_address: ServerAddress,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// nvim サーバーを起動したときの `NVIM_APPNAME` (設定していなければ None)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appname: Option<String>,
+    /// ピン留めした状態で登録する (`manager restart` や `import` で登録し直すときに引き継ぐ)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct UnregisterInstanceParams {
     pub identifier: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RenameInstanceParams {
+    pub identifier: String,
+    pub new_identifier: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PinInstanceParams {
+    pub identifier: String,
+    pub pinned: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TagInstanceParams {
+    pub identifier: String,
+    pub tag: String,
+    /// false ならタグを外す
+    pub tagged: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ListInstancesParams {
+    /// 指定するとこのタグを持つインスタンスだけを返す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WatchInstancesParams {
+    /// 前回の応答の `generation`。省略するか今と違えばすぐに返す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    /// 変わるまで待つ上限 (秒、省略時は 30、最大 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// `watch_instances` の結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InstancesUpdate {
+    /// 登録内容が変わるたびに増える世代 (次の `watch_instances` に渡す)
+    pub generation: u64,
+    /// identifier の順
+    pub instances: Vec<InstanceResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TouchInstanceParams {
+    pub identifier: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct InstanceResult {
     pub identifier: String,
-    pub server_address: String,
+    pub server_address: ServerAddress,
     pub health_status: HealthStatus,
     pub last_health_check: chrono::DateTime<chrono::Utc>,
+    pub registered_at: chrono::DateTime<chrono::Utc>,
+    pub last_used: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub appname: Option<String>,
+}
+
+impl From<&InstanceInfo> for InstanceResult {
+    fn from(instance: &InstanceInfo) -> Self {
+        Self {
+            identifier: instance.identifier.clone(),
+            server_address: instance.server_address.clone(),
+            health_status: instance.health_status.clone(),
+            last_health_check: instance.last_health_check,
+            registered_at: instance.registered_at,
+            last_used: instance.last_used,
+            pinned: instance.pinned,
+            cwd: instance.cwd.clone(),
+            pid: instance.pid,
+            tags: instance.tags.clone(),
+            appname: instance.appname.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SetInstanceCwdParams {
+    pub identifier: String,
+    pub cwd: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CheckInstanceParams {
+    pub identifier: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CheckInstanceResult {
+    pub identifier: String,
+    pub server_address: ServerAddress,
+    pub healthy: bool,
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PruneResult {
+    pub removed: Vec<InstanceResult>,
+    pub remaining: usize,
+}
+
+/// インスタンスが登録から外れた理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    Unregistered,
+    Unresponsive,
+}
+
+/// 登録から外れたインスタンスの記録 (`recent_instances` で返す)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Tombstone {
+    pub instance: InstanceResult,
+    pub closed_at: chrono::DateTime<chrono::Utc>,
+    pub reason: CloseReason,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HealthCheckStats {
+    pub runs: u64,
+    pub checks: u64,
+    pub failures: u64,
+    pub removed: u64,
+    pub last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_run_duration_ms: Option<u64>,
+    /// スリープからの復帰 (や時計の飛び) を見つけて確かめ直した回数
+    pub resumes: u64,
+    pub last_resume_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManagerStatus {
+    pub version: String,
+    /// 古いマネージャーは報告しないので 0 になる
+    #[serde(default)]
+    pub protocol_version: u32,
+    pub pid: u32,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub uptime_secs: u64,
+    pub bind_address: String,
+    pub instance_count: usize,
+    pub healthy_count: usize,
+    #[serde(default)]
+    pub health_checks: HealthCheckStats,
+}
+
+/// `control export` / `control import` で受け渡す登録情報のスナップショット
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RegistrySnapshot {
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub version: String,
+    pub instances: Vec<InstanceResult>,
+}
+
+/// マネージャーが書き出し続ける登録内容の写し (`manager.state_file`)
+///
+/// ステータスバーやプロンプトが、TCP で問い合わせずに (マネージャーを自動起動することもなく) 読めるようにする。
+/// 登録内容が変わるたびに一時ファイルに書いてから置き換えるので、書きかけを読むことはない。
+/// ヘルスチェックの時刻は含めないので、変化がなければ書き直さない
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateMirror {
+    pub protocol_version: u32,
+    /// 書き出したマネージャー (終了すると消すが、強制終了された場合は残るので生きているか確かめられるように)
+    pub manager_pid: u32,
+    pub bind_address: String,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// identifier の順
+    pub instances: Vec<MirroredInstance>,
+}
+
+/// [`StateMirror`] の各インスタンス ([`InstanceResult`] からヘルスチェックの時刻などを除いたもの)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MirroredInstance {
+    pub identifier: String,
+    pub server_address: ServerAddress,
+    pub health_status: HealthStatus,
+    pub last_used: chrono::DateTime<chrono::Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appname: Option<String>,
+}
+
+impl From<&InstanceInfo> for MirroredInstance {
+    fn from(instance: &InstanceInfo) -> Self {
+        Self {
+            identifier: instance.identifier.clone(),
+            server_address: instance.server_address.clone(),
+            health_status: instance.health_status.clone(),
+            last_used: instance.last_used,
+            pinned: instance.pinned,
+            cwd: instance.cwd.clone(),
+            tags: instance.tags.clone(),
+            appname: instance.appname.clone(),
+        }
+    }
+}
+
+/// `control snapshot` が書き出すセッションのマニフェスト (`manifest.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionManifest {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub version: String,
+    pub sessions: Vec<SessionEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionEntry {
+    /// マニフェストと同じディレクトリにあるセッションファイルの名前
+    pub session_file: String,
+    pub instance: InstanceResult,
 }
 
 pub type InstanceStorage = HashMap<String, InstanceInfo>;
 
+#[cfg(feature = "client")]
 pub mod utils {
     use anyhow::Result;
-    use std::process::Command;
 
-    pub fn check_nvim_instance(server_address: &str) -> Result<bool> {
-        let output = Command::new("nvim")
-            .args(["--server", server_address, "--remote-expr", "1"])
-            .output()?;
+    use crate::process::{self, ProcessOutput, ProcessSpec};
+    use crate::retry::Backoff;
+    use crate::ServerAddress;
 
-        Ok(output.status.success())
+    /// `nvim --server` で待つ上限。応答しない nvim でヘルスチェックや起動待ちが止まらないようにする
+    pub const NVIM_REMOTE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// `nvim --server <addr> ...` を実行する ([`NVIM_REMOTE_TIMEOUT`] を超えたら強制終了してエラー)
+    ///
+    /// `ssh://` のアドレスはローカルフォワード ([`crate::tunnel`]) を通す
+    async fn nvim_remote(server_address: &str, args: &[&str]) -> Result<ProcessOutput> {
+        let server_address = crate::tunnel::local_address_async(server_address).await?;
+        process::output_async(
+            &ProcessSpec::new("nvim")
+                .args(["--server", &server_address])
+                .args(args.iter().copied())
+                .timeout(NVIM_REMOTE_TIMEOUT),
+        )
+        .await
     }
 
-    pub fn focus_nvim_instance(server_address: &str) -> Result<()> {
-        Command::new("nvim")
-            .args([
-                "--server",
-                server_address,
-                "--remote-expr",
-                "execute('NeovideFocus')",
-            ])
-            .output()?;
+    /// 非同期版を現在のスレッドで完了まで実行する (`*_blocking` 用。tokio ランタイムの中からは呼ばない)
+    fn block_on<T>(future: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(future)
+    }
+
+    pub async fn check_nvim_instance(server_address: &str) -> Result<bool> {
+        // ソケットファイルや名前付きパイプが消えていれば nvim は終了している
+        match server_address.parse() {
+            Ok(ServerAddress::Unix(path)) if !std::path::Path::new(&path).exists() => {
+                return Ok(false);
+            }
+            Ok(ServerAddress::WindowsPipe(_)) if !pipe_exists(server_address) => return Ok(false),
+            _ => {}
+        }
+
+        let output = nvim_remote(server_address, &["--remote-expr", "1"]).await?;
+
+        Ok(output.success())
+    }
+
+    pub fn check_nvim_instance_blocking(server_address: &str) -> Result<bool> {
+        block_on(check_nvim_instance(server_address))
+    }
+
+    /// リモートで式を評価し、結果を文字列で返す
+    pub async fn eval_in_nvim_instance(server_address: &str, expr: &str) -> Result<String> {
+        let output = nvim_remote(server_address, &["--remote-expr", expr]).await?;
+
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to evaluate {expr} on {server_address}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    pub fn eval_in_nvim_instance_blocking(server_address: &str, expr: &str) -> Result<String> {
+        block_on(eval_in_nvim_instance(server_address, expr))
+    }
+
+    /// 名前付きパイプ (`\\.\pipe\<name>`) があるか
+    ///
+    /// パイプを開くと nvim の待ち受けを 1 つ使ってしまうので、`\\.\pipe\` の一覧から探す。
+    /// Windows 以外では調べられないので true
+    pub fn pipe_exists(address: &str) -> bool {
+        #[cfg(windows)]
+        {
+            let name = address
+                .get(r"\\.\pipe\".len()..)
+                .unwrap_or_default()
+                .to_lowercase();
+            let Ok(entries) = std::fs::read_dir(r"\\.\pipe\") else {
+                return true;
+            };
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)
+        }
+
+        #[cfg(not(windows))]
+        {
+            let _ = address;
+            true
+        }
+    }
+
+    /// `host:port` 形式 (TCP) のアドレスか。それ以外は Unix ソケットか名前付きパイプ
+    pub fn is_tcp_address(address: &str) -> bool {
+        address
+            .parse::<ServerAddress>()
+            .is_ok_and(|address| address.is_tcp())
+    }
+
+    /// Vim script の単一引用符文字列リテラルにする
+    pub fn vim_string_literal(value: &str) -> String {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+
+    /// `:cd` (tab が true なら `:tcd`) でリモートの作業ディレクトリを変え、変更後の getcwd() を返す
+    pub async fn change_nvim_directory(
+        server_address: &str,
+        dir: &str,
+        tab: bool,
+    ) -> Result<String> {
+        let command = if tab { "tcd" } else { "cd" };
+        eval_in_nvim_instance(
+            server_address,
+            &format!(
+                "execute('{command} ' . fnameescape({}))",
+                vim_string_literal(dir)
+            ),
+        )
+        .await?;
 
+        eval_in_nvim_instance(server_address, "getcwd()").await
+    }
+
+    /// リモートの `$NEOVIM_MANAGER_IDENTIFIER` を書き換える (rename の後で init-lua が新しい名前を使うように)
+    pub async fn set_nvim_identifier(server_address: &str, identifier: &str) -> Result<()> {
+        let command = format!("let ${IDENTIFIER_ENV} = {}", vim_string_literal(identifier));
+        eval_in_nvim_instance(
+            server_address,
+            &format!("execute({})", vim_string_literal(&command)),
+        )
+        .await?;
         Ok(())
     }
 
-    pub fn open_file_in_nvim_instance(server_address: &str, file_path: &str) -> Result<()> {
-        Command::new("nvim")
-            .args(["--server", server_address, "--remote", file_path])
-            .output()?;
+    pub async fn focus_nvim_instance(server_address: &str) -> Result<()> {
+        nvim_remote(
+            server_address,
+            &["--remote-expr", "execute('NeovideFocus')"],
+        )
+        .await?;
+
+        // macOS や Wayland では NeovideFocus で前面に出ないことが多いので、GUI のプロセスを直接前面に出す
+        // (対応していない環境では何もしない)。フォーカス要求は届いているので、失敗してもエラーにはしない
+        let server_address = server_address.to_string();
+        let _ = tokio::task::spawn_blocking(move || crate::gui::activate(&server_address)).await;
 
         Ok(())
     }
 
-    pub fn quit_nvim_instance(server_address: &str) -> Result<bool> {
-        let output = Command::new("nvim")
-            .args([
-                "--server",
-                server_address,
-                "--remote-expr",
-                "execute('quit')",
-            ])
-            .output()?;
+    pub fn focus_nvim_instance_blocking(server_address: &str) -> Result<()> {
+        block_on(focus_nvim_instance(server_address))
+    }
 
-        Ok(output.status.success())
+    /// リモートでファイルを開くときのコマンド
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum OpenMode {
+        /// `:edit` (現在のウィンドウで開く。未保存の変更があると失敗する)
+        Edit,
+        /// `:drop` (開いているウィンドウがあればそこへ移る。`nvim --remote` と同じ)
+        #[default]
+        Drop,
+        /// `:tabedit` (新しいタブで開く)
+        Tab,
+        /// `:split` (ウィンドウを分割して開く)
+        Split,
     }
 
-    pub fn quit_nvim_instance_with_retry(server_address: &str, max_retries: u32) -> Result<()> {
-        for attempt in 1..=max_retries {
-            match quit_nvim_instance(server_address) {
-                Ok(true) => {
-                    eprintln!("Successfully sent quit to {server_address}");
-                    return Ok(());
+    impl OpenMode {
+        pub const ALL: [OpenMode; 4] = [
+            OpenMode::Edit,
+            OpenMode::Drop,
+            OpenMode::Tab,
+            OpenMode::Split,
+        ];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                OpenMode::Edit => "edit",
+                OpenMode::Drop => "drop",
+                OpenMode::Tab => "tab",
+                OpenMode::Split => "split",
+            }
+        }
+
+        /// 実行する Ex コマンド
+        fn command(self) -> &'static str {
+            match self {
+                OpenMode::Edit => "edit",
+                OpenMode::Drop => "drop",
+                OpenMode::Tab => "tabedit",
+                OpenMode::Split => "split",
+            }
+        }
+    }
+
+    impl std::fmt::Display for OpenMode {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.name())
+        }
+    }
+
+    impl std::str::FromStr for OpenMode {
+        type Err = String;
+
+        fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+            OpenMode::ALL
+                .into_iter()
+                .find(|mode| mode.name() == s)
+                .ok_or_else(|| {
+                    let names: Vec<_> = OpenMode::ALL.iter().map(|mode| mode.name()).collect();
+                    format!(
+                        "unknown open mode '{s}' (expected one of: {})",
+                        names.join(", ")
+                    )
+                })
+        }
+    }
+
+    /// [`open_file_in_nvim_instance`] のオプション
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct OpenFileOptions {
+        pub mode: OpenMode,
+        /// 開いた後にカーソルを置く行 (1 始まり)
+        pub line: Option<u32>,
+        /// カーソルを置く列 (1 始まり・文字単位)。`line` と一緒に指定する
+        pub column: Option<u32>,
+    }
+
+    /// [`open_file_in_nvim_instance`] の失敗
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    pub enum OpenFileError {
+        /// 行・列が 0 か、列だけが指定された
+        #[error("Invalid position {line:?}:{column:?} (line and column are 1-based and column requires line)")]
+        InvalidPosition {
+            line: Option<u32>,
+            column: Option<u32>,
+        },
+        /// nvim に接続できない・時間内に応答がない
+        #[error("Cannot reach {server_address}: {reason}")]
+        Unreachable {
+            server_address: String,
+            reason: String,
+        },
+        /// nvim がコマンドを拒否した (未保存の変更がある `:edit` など)
+        #[error("Failed to open {path} on {server_address}: {message}")]
+        Rejected {
+            server_address: String,
+            path: String,
+            message: String,
+        },
+    }
+
+    /// 開くファイルとカーソル位置から、`--remote-expr` で評価する式を作る
+    ///
+    /// パスは Vim 側の `fnameescape()` でエスケープするので、空白や `%` `#` などを含んでいてもよい。
+    pub fn open_file_expr(
+        file_path: &str,
+        options: &OpenFileOptions,
+    ) -> std::result::Result<String, OpenFileError> {
+        let invalid = || OpenFileError::InvalidPosition {
+            line: options.line,
+            column: options.column,
+        };
+
+        let mut commands = vec![format!(
+            "'{} ' . fnameescape({})",
+            options.mode.command(),
+            vim_string_literal(file_path)
+        )];
+        match (options.line, options.column) {
+            (Some(0), _) | (_, Some(0)) | (None, Some(_)) => return Err(invalid()),
+            (Some(line), column) => commands.push(format!(
+                "'call setcursorcharpos({line}, {})'",
+                column.unwrap_or(1)
+            )),
+            (None, None) => {}
+        }
+
+        Ok(format!("execute([{}])", commands.join(", ")))
+    }
+
+    /// 起動済みのインスタンスでファイルを開く
+    pub async fn open_file_in_nvim_instance(
+        server_address: &str,
+        file_path: &str,
+        options: &OpenFileOptions,
+    ) -> std::result::Result<(), OpenFileError> {
+        let expr = open_file_expr(file_path, options)?;
+        let output = nvim_remote(server_address, &["--remote-expr", &expr])
+            .await
+            .map_err(|e| OpenFileError::Unreachable {
+                server_address: server_address.to_string(),
+                reason: e.to_string(),
+            })?;
+
+        if output.success() {
+            return Ok(());
+        }
+
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        // 接続できなかった場合も nvim --server は失敗で終わるので、メッセージで見分ける
+        if message.to_lowercase().contains("connect") {
+            Err(OpenFileError::Unreachable {
+                server_address: server_address.to_string(),
+                reason: message,
+            })
+        } else {
+            Err(OpenFileError::Rejected {
+                server_address: server_address.to_string(),
+                path: file_path.to_string(),
+                message,
+            })
+        }
+    }
+
+    /// ホスト側のパス `file_path` を、インスタンスから見たパスにする
+    ///
+    /// インスタンスの作業ディレクトリがいずれかの組のコンテナの中にあれば、コンテナの中のパスに直す。
+    /// 組がなければ問い合わせずにそのまま返す
+    pub async fn remote_path(
+        server_address: &str,
+        file_path: &str,
+        path_mappings: &[crate::identifier::PathMapping],
+    ) -> String {
+        if path_mappings.is_empty() {
+            return file_path.to_string();
+        }
+        let Ok(cwd) = eval_in_nvim_instance(server_address, "getcwd()").await else {
+            return file_path.to_string();
+        };
+        path_mappings
+            .iter()
+            .filter(|mapping| mapping.to_host(&cwd).is_some())
+            .find_map(|mapping| mapping.to_container(file_path))
+            .unwrap_or_else(|| file_path.to_string())
+    }
+
+    /// 現在の端末で `nvim --remote-ui` を実行し、切断されるまで待つ。終了コードを返す
+    pub fn attach_nvim_instance(server_address: &str) -> Result<Option<i32>> {
+        let server_address = crate::tunnel::local_address(server_address)?;
+        process::status(&ProcessSpec::new("nvim").args([
+            "--server",
+            &server_address,
+            "--remote-ui",
+        ]))
+    }
+
+    pub async fn quit_nvim_instance(server_address: &str) -> Result<bool> {
+        let output = nvim_remote(server_address, &["--remote-expr", "execute('quit')"]).await?;
+
+        Ok(output.success())
+    }
+
+    pub fn quit_nvim_instance_blocking(server_address: &str) -> Result<bool> {
+        block_on(quit_nvim_instance(server_address))
+    }
+
+    /// [`quit_all_nvim_instance`] が終了を待つ上限
+    const QUIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+    /// 全ウィンドウを閉じて終了させる。`force` の場合は未保存の変更を破棄する
+    ///
+    /// nvim は応答を返す前に終了することがあるので、`--remote-expr` の終了コードではなく、
+    /// 接続できなくなったかで判断する (未保存の変更で断られた場合は接続できたまま)
+    pub async fn quit_all_nvim_instance(server_address: &str, force: bool) -> Result<bool> {
+        let command = if force {
+            "execute('qall!')"
+        } else {
+            "execute('qall')"
+        };
+
+        nvim_remote(server_address, &["--remote-expr", command]).await?;
+
+        let exited = Backoff::exponential(
+            std::time::Duration::from_millis(50),
+            std::time::Duration::from_millis(500),
+        )
+        .deadline(QUIT_TIMEOUT)
+        .poll(|| async {
+            (!check_nvim_instance(server_address).await.unwrap_or(true)).then_some(())
+        })
+        .await;
+        Ok(exited.is_some())
+    }
+
+    pub fn quit_all_nvim_instance_blocking(server_address: &str, force: bool) -> Result<bool> {
+        block_on(quit_all_nvim_instance(server_address, force))
+    }
+
+    pub async fn quit_nvim_instance_with_retry(
+        server_address: &str,
+        max_retries: u32,
+    ) -> Result<()> {
+        Backoff::fixed(std::time::Duration::from_millis(500))
+            .max_attempts(max_retries)
+            .retry(|attempt| async move {
+                match quit_nvim_instance(server_address).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) => {
+                        eprintln!(
+                            "Quit command failed for {server_address} (attempt {attempt}/{max_retries})"
+                        );
+                        Err(())
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Error sending quit to {server_address} (attempt {attempt}/{max_retries}): {e}"
+                        );
+                        Err(())
+                    }
                 }
-                Ok(false) => {
-                    eprintln!(
-                        "Quit command failed for {server_address} (attempt {attempt}/{max_retries})"
-                    );
+            })
+            .await
+            .map_err(|()| {
+                anyhow::anyhow!("Failed to quit Neovim instance after {max_retries} attempts")
+            })?;
+
+        eprintln!("Successfully sent quit to {server_address}");
+        Ok(())
+    }
+
+    pub fn quit_nvim_instance_with_retry_blocking(
+        server_address: &str,
+        max_retries: u32,
+    ) -> Result<()> {
+        block_on(quit_nvim_instance_with_retry(server_address, max_retries))
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    pub struct NvimBuffer {
+        pub bufnr: u32,
+        pub name: String,
+        pub modified: bool,
+    }
+
+    /// インスタンスで開いているファイルバッファ (一覧に出るもののみ) を取得する
+    pub async fn list_nvim_buffers(server_address: &str) -> Result<Vec<NvimBuffer>> {
+        let server_address = server_address.to_string();
+        // NvimClient は同期なので、ブロッキング処理用のスレッドで実行する
+        tokio::task::spawn_blocking(move || list_nvim_buffers_blocking(&server_address)).await?
+    }
+
+    pub fn list_nvim_buffers_blocking(server_address: &str) -> Result<Vec<NvimBuffer>> {
+        crate::nvim::NvimClient::connect(server_address)?.list_buffers()
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct NvimServerProcess {
+        pub pid: u32,
+        pub listen_address: ServerAddress,
+    }
+
+    /// `nvim --headless --listen <addr>` で起動しているプロセスを列挙する
+    pub fn find_headless_nvim_servers() -> Result<Vec<NvimServerProcess>> {
+        let spec = if cfg!(windows) {
+            ProcessSpec::new("powershell").args([
+                "-NoProfile",
+                "-Command",
+                "Get-CimInstance Win32_Process -Filter \"Name='nvim.exe'\" | \
+                 ForEach-Object { \"$($_.ProcessId) $($_.CommandLine)\" }",
+            ])
+        } else {
+            ProcessSpec::new("ps").args(["-eo", "pid=,args="])
+        };
+        let output = process::output(&spec)?;
+
+        if !output.success() {
+            return Err(anyhow::anyhow!("Failed to list processes"));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let servers = stdout
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let pid = parts.next()?.parse::<u32>().ok()?;
+                let args: Vec<&str> = parts.collect();
+
+                let program = args.first()?.trim_matches('"');
+                let program_name = std::path::Path::new(program).file_stem()?.to_str()?;
+                if program_name != "nvim" || !args.contains(&"--headless") {
+                    return None;
                 }
-                Err(e) => {
-                    eprintln!(
-                        "Error sending quit to {server_address} (attempt {attempt}/{max_retries}): {e}"
-                    );
+
+                let listen_pos = args.iter().position(|arg| *arg == "--listen")?;
+                let listen_address = args.get(listen_pos + 1)?.parse().ok()?;
+
+                Some(NvimServerProcess {
+                    pid,
+                    listen_address,
+                })
+            })
+            .collect();
+
+        Ok(servers)
+    }
+
+    pub fn kill_process(pid: u32) -> Result<()> {
+        let spec = if cfg!(windows) {
+            ProcessSpec::new("taskkill").args(["/PID", &pid.to_string(), "/F"])
+        } else {
+            ProcessSpec::new("kill").arg(pid.to_string())
+        };
+        let output = process::output(&spec)?;
+
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to kill process {}: {}",
+                pid,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// プロセスがまだ存在するか。調べられない環境では None
+    pub fn process_exists(pid: u32) -> Option<bool> {
+        if cfg!(target_os = "linux") {
+            Some(std::path::Path::new(&format!("/proc/{pid}")).exists())
+        } else if cfg!(unix) {
+            // シグナル 0 は何も送らず、存在と権限だけを確かめる
+            process::output(&ProcessSpec::new("kill").args(["-0", &pid.to_string()]))
+                .ok()
+                .map(|output| output.success())
+        } else {
+            None
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct ProcessSample {
+        pid: u32,
+        ppid: u32,
+        cpu_secs: f64,
+        rss_kib: u64,
+        elapsed_secs: u64,
+    }
+
+    /// プロセスとその子孫 (LSP サーバーなど) を合計した資源使用量
+    #[derive(Debug, Clone, Default)]
+    pub struct ProcessTreeStats {
+        /// 起動からの累積 CPU 時間
+        pub cpu_secs: f64,
+        pub rss_kib: u64,
+        /// 根のプロセスの起動からの経過時間
+        pub uptime_secs: u64,
+        pub processes: usize,
+    }
+
+    /// Linux では /proc を直接読む (ps の CPU 時間は秒単位で粗すぎるため)
+    #[cfg(target_os = "linux")]
+    fn list_processes() -> Result<Vec<ProcessSample>> {
+        // USER_HZ と ページサイズは一般的な値を仮定する
+        const CLOCK_TICKS: f64 = 100.0;
+        const PAGE_KIB: u64 = 4;
+
+        let uptime: f64 = std::fs::read_to_string("/proc/uptime")?
+            .split_whitespace()
+            .next()
+            .and_then(|secs| secs.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Cannot parse /proc/uptime"))?;
+
+        let mut processes = Vec::new();
+        for entry in std::fs::read_dir("/proc")?.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            else {
+                continue;
+            };
+            // 終了済みのプロセスは読めないので飛ばす
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            // comm に空白や括弧が入りうるので、最後の ')' 以降を読む
+            let Some((_, rest)) = stat.rsplit_once(')') else {
+                continue;
+            };
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            let field = |index: usize| -> u64 {
+                fields
+                    .get(index)
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(0)
+            };
+
+            // fields[0] が state (stat の 3 番目の項目)
+            processes.push(ProcessSample {
+                pid,
+                ppid: field(1) as u32,
+                cpu_secs: (field(11) + field(12)) as f64 / CLOCK_TICKS,
+                rss_kib: field(21) * PAGE_KIB,
+                elapsed_secs: (uptime - field(19) as f64 / CLOCK_TICKS).max(0.0) as u64,
+            });
+        }
+
+        Ok(processes)
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn list_processes() -> Result<Vec<ProcessSample>> {
+        /// `[DD-][HH:]MM:SS[.ss]` を秒に直す
+        fn parse_duration(value: &str) -> f64 {
+            let (days, clock) = match value.split_once('-') {
+                Some((days, clock)) => (days.parse().unwrap_or(0.0), clock),
+                None => (0.0, value),
+            };
+            let clock = clock
+                .rsplit(':')
+                .zip([1.0, 60.0, 3600.0])
+                .map(|(part, unit)| part.parse::<f64>().unwrap_or(0.0) * unit)
+                .sum::<f64>();
+            days * 86400.0 + clock
+        }
+
+        let output = process::output(&ProcessSpec::new("ps").args([
+            "-A",
+            "-o",
+            "pid=,ppid=,rss=,time=,etime=",
+        ]))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(ProcessSample {
+                    pid: fields.next()?.parse().ok()?,
+                    ppid: fields.next()?.parse().ok()?,
+                    rss_kib: fields.next()?.parse().ok()?,
+                    cpu_secs: parse_duration(fields.next()?),
+                    elapsed_secs: parse_duration(fields.next()?) as u64,
+                })
+            })
+            .collect())
+    }
+
+    #[cfg(windows)]
+    fn list_processes() -> Result<Vec<ProcessSample>> {
+        Err(anyhow::anyhow!(
+            "Process statistics are not supported on Windows yet"
+        ))
+    }
+
+    /// 各 PID について、そのプロセスと子孫の資源使用量を合計する (存在しない PID は含まない)
+    pub fn process_tree_stats(
+        pids: &[u32],
+    ) -> Result<std::collections::HashMap<u32, ProcessTreeStats>> {
+        use std::collections::HashMap;
+
+        let processes = list_processes()?;
+        let mut children: HashMap<u32, Vec<&ProcessSample>> = HashMap::new();
+        for process in &processes {
+            children.entry(process.ppid).or_default().push(process);
+        }
+
+        let mut result = HashMap::new();
+        for &pid in pids {
+            let Some(root) = processes.iter().find(|process| process.pid == pid) else {
+                continue;
+            };
+
+            let mut stats = ProcessTreeStats {
+                uptime_secs: root.elapsed_secs,
+                ..Default::default()
+            };
+            let mut stack = vec![root];
+            while let Some(process) = stack.pop() {
+                stats.cpu_secs += process.cpu_secs;
+                stats.rss_kib += process.rss_kib;
+                stats.processes += 1;
+                if let Some(descendants) = children.get(&process.pid) {
+                    stack.extend(descendants.iter().copied());
                 }
             }
+            result.insert(pid, stats);
+        }
+
+        Ok(result)
+    }
+
+    /// プロセス起動時の環境変数を OS から読む (現状 Linux の /proc のみ対応)
+    pub fn process_environment(pid: u32) -> Result<Vec<(String, String)>> {
+        if !cfg!(target_os = "linux") {
+            return Err(anyhow::anyhow!(
+                "Reading another process's environment is only supported on Linux"
+            ));
+        }
 
-            if attempt < max_retries {
-                std::thread::sleep(std::time::Duration::from_millis(500));
+        let path = format!("/proc/{pid}/environ");
+        let raw = std::fs::read(&path).map_err(|e| anyhow::anyhow!("Cannot read {path}: {e}"))?;
+
+        Ok(raw
+            .split(|byte| *byte == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                let (name, value) = entry.split_once('=')?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect())
+    }
+
+    /// 誰も listen していない Neovim のソケットファイルを探す
+    ///
+    /// Neovim の既定の置き場所 (`$XDG_RUNTIME_DIR/nvim.*`, `/tmp/nvim.$USER/*/nvim.*`) に加えて
+    /// `candidates` に渡したアドレスも調べる。名前付きパイプは自動で消えるので Windows では常に空
+    pub fn find_stale_nvim_sockets(candidates: &[String]) -> Vec<std::path::PathBuf> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            use std::path::PathBuf;
+
+            fn nvim_sockets_in(dir: &std::path::Path) -> Vec<PathBuf> {
+                std::fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| name.starts_with("nvim."))
+                    })
+                    .collect()
+            }
+
+            let mut paths: Vec<PathBuf> = candidates.iter().map(PathBuf::from).collect();
+            if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+                paths.extend(nvim_sockets_in(std::path::Path::new(&runtime_dir)));
+            }
+            if let Some(user) = std::env::var_os("USER") {
+                let tmp_dir = std::env::temp_dir().join(format!("nvim.{}", user.to_string_lossy()));
+                for dir in std::fs::read_dir(tmp_dir).into_iter().flatten().flatten() {
+                    paths.extend(nvim_sockets_in(&dir.path()));
+                }
             }
+
+            paths.sort();
+            paths.dedup();
+            paths
+                .into_iter()
+                .filter(|path| {
+                    std::fs::symlink_metadata(path)
+                        .is_ok_and(|metadata| metadata.file_type().is_socket())
+                })
+                .filter(|path| {
+                    std::os::unix::net::UnixStream::connect(path)
+                        .is_err_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+                })
+                .collect()
         }
 
-        Err(anyhow::anyhow!(
-            "Failed to quit Neovim instance after {} attempts",
-            max_retries
-        ))
+        #[cfg(not(unix))]
+        {
+            let _ = candidates;
+            Vec::new()
+        }
+    }
+
+    /// `path` を含む git リポジトリのルート
+    pub fn git_root(path: &std::path::Path) -> Option<std::path::PathBuf> {
+        path.ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(std::path::Path::to_path_buf)
     }
 
+    /// `nvim --headless --listen <addr>` をバックグラウンドで起動する
+    ///
+    /// `appname` があれば `NVIM_APPNAME` にする (なければ呼び出し元の環境のまま)
+    pub fn spawn_headless_nvim(
+        identifier: &str,
+        server_address: &str,
+        cwd: Option<&std::path::Path>,
+        extra_args: &[String],
+        env: &crate::direnv::EnvChanges,
+        appname: Option<&str>,
+    ) -> Result<Box<dyn process::ChildProcess>> {
+        let mut spec = ProcessSpec::new("nvim")
+            .args(["--headless", "--listen", server_address])
+            .args(extra_args.iter().cloned())
+            .env(IDENTIFIER_ENV, identifier);
+        if let Some(cwd) = cwd {
+            spec = spec.current_dir(cwd);
+        }
+        if let Some(appname) = appname {
+            spec = spec.env(APPNAME_ENV, appname);
+        }
+
+        process::spawn(&crate::direnv::apply(spec, env))
+    }
+
+    /// 起動する nvim に identifier を伝える環境変数 (`control init-lua` の Lua が読む)
+    pub const IDENTIFIER_ENV: &str = "NEOVIM_MANAGER_IDENTIFIER";
+
+    /// Neovim の設定・データのディレクトリ名を切り替える環境変数 (`:help $NVIM_APPNAME`)
+    pub const APPNAME_ENV: &str = "NVIM_APPNAME";
+
+    /// ファイル名などに使うハッシュ (FNV-1a の 16 進数。プロセスや Rust のバージョンが違っても同じになる)
+    pub fn stable_hash(data: &[u8]) -> String {
+        let hash = data.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        });
+        format!("{hash:016x}")
+    }
+
+    /// OS に空いているポートを選ばせる
+    ///
+    /// 閉じてから nvim が listen するまでの間に他のプロセスに取られることがあるので、
+    /// サーバーの起動には [`start_nvim_server`] を使う。
     pub fn get_random_port() -> Result<u16> {
         use std::net::TcpListener;
 
@@ -165,28 +1783,165 @@ pub mod utils {
         Ok(addr.port())
     }
 
-    pub fn is_wsl() -> bool {
-        std::env::var("WSL_DISTRO_NAME").is_ok()
-            || std::fs::read_to_string("/proc/version")
-                .map(|content| content.contains("Microsoft"))
-                .unwrap_or(false)
+    /// [`start_nvim_server`] が 1 つのポートで応答を待つ上限
+    pub const NVIM_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+    /// [`start_nvim_server`] がアドレスを選び直す回数
+    pub const PORT_ALLOCATION_ATTEMPTS: u32 = 5;
+
+    /// 新しく起動する nvim の listen アドレス
+    ///
+    /// Windows では TCP のポートだとファイアウォールの確認が出たり予約済みの範囲とぶつかったりするので、
+    /// 名前付きパイプ `\\.\pipe\nvim-<ランダムな 16 桁の 16 進数>` にする。それ以外では空いている TCP ポート
+    pub fn new_server_address() -> Result<ServerAddress> {
+        if cfg!(windows) {
+            let id = uuid::Uuid::new_v4().simple().to_string();
+            return Ok(ServerAddress::WindowsPipe(format!(
+                r"\\.\pipe\nvim-{}",
+                &id[..16]
+            )));
+        }
+
+        Ok(ServerAddress::tcp("127.0.0.1", get_random_port()?))
     }
 
-    pub fn get_neovide_command() -> &'static str {
-        if is_wsl() || cfg!(windows) {
-            "neovide.exe"
-        } else {
-            "neovide"
+    /// [`new_server_address`] で nvim サーバーを起動し、応答するまで待つ。起動したプロセスとアドレスを返す
+    ///
+    /// `spawn` はアドレスを受け取って nvim を起動する。アドレスを選んでから listen するまでに
+    /// 他のプロセスに取られた場合 (nvim が応答する前に終了した・別のプロセスが応答した) は、
+    /// 起動したプロセスを止めて別のアドレスでやり直す。
+    pub async fn start_nvim_server(
+        mut spawn: impl FnMut(&str) -> Result<Box<dyn process::ChildProcess>>,
+    ) -> Result<(Box<dyn process::ChildProcess>, ServerAddress)> {
+        for attempt in 1..=PORT_ALLOCATION_ATTEMPTS {
+            let server_address = new_server_address()?;
+            let mut child = spawn(&server_address)?;
+
+            match wait_for_own_server(child.as_mut(), &server_address).await? {
+                ServerStart::Ready => return Ok((child, server_address)),
+                ServerStart::Exited(code) => log::warn!(
+                    "nvim exited with {code:?} before listening on {server_address} \
+                     (attempt {attempt}/{PORT_ALLOCATION_ATTEMPTS})"
+                ),
+                ServerStart::Taken(pid) => {
+                    log::warn!(
+                        "{server_address} is answered by another process (pid {pid}) \
+                         (attempt {attempt}/{PORT_ALLOCATION_ATTEMPTS})"
+                    );
+                    child.kill()?;
+                }
+                ServerStart::TimedOut => {
+                    child.kill()?;
+                    return Err(anyhow::anyhow!(
+                        "nvim did not start listening on {server_address} within {NVIM_START_TIMEOUT:?}"
+                    ));
+                }
+            }
         }
+
+        Err(anyhow::anyhow!(
+            "Failed to start nvim after {PORT_ALLOCATION_ATTEMPTS} attempts (addresses kept being taken)"
+        ))
+    }
+
+    enum ServerStart {
+        Ready,
+        Exited(Option<i32>),
+        /// 別のプロセス (PID) が応答した
+        Taken(u32),
+        TimedOut,
+    }
+
+    async fn wait_for_own_server(
+        child: &mut dyn process::ChildProcess,
+        server_address: &str,
+    ) -> Result<ServerStart> {
+        let child_pid = child.id();
+        let started = Backoff::exponential(
+            std::time::Duration::from_millis(50),
+            std::time::Duration::from_millis(500),
+        )
+        .deadline(NVIM_START_TIMEOUT)
+        .poll(|| {
+            let exited = child.try_wait();
+            async move {
+                match exited {
+                    Err(e) => return Some(Err(e)),
+                    Ok(Some(code)) => return Some(Ok(ServerStart::Exited(code))),
+                    Ok(None) => {}
+                }
+
+                if !check_nvim_instance(server_address).await.unwrap_or(false) {
+                    return None;
+                }
+                let pid = eval_in_nvim_instance(server_address, "getpid()")
+                    .await
+                    .ok()
+                    .and_then(|pid| pid.parse().ok());
+                // nvim がラッパースクリプト経由で起動されることもあるので子孫も自分のものとみなす
+                Some(Ok(match pid {
+                    Some(pid) if !is_same_or_descendant(pid, child_pid) => ServerStart::Taken(pid),
+                    _ => ServerStart::Ready,
+                }))
+            }
+        })
+        .await;
+
+        started.unwrap_or(Ok(ServerStart::TimedOut))
+    }
+
+    /// `pid` が `ancestor` 自身かその子孫か (プロセス一覧が取れない環境では true)
+    fn is_same_or_descendant(pid: u32, ancestor: u32) -> bool {
+        if pid == ancestor {
+            return true;
+        }
+        let Ok(processes) = list_processes() else {
+            return true;
+        };
+
+        let mut current = pid;
+        // 循環していても止まるように、たどる回数はプロセス数までにする
+        for _ in 0..processes.len() {
+            match processes.iter().find(|process| process.pid == current) {
+                Some(process) if process.ppid == ancestor => return true,
+                Some(process) if process.ppid != current => current = process.ppid,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// PATH から実行ファイルを探す (Windows では PATHEXT も考慮する)
+    pub fn find_in_path(program: &str) -> Option<std::path::PathBuf> {
+        let extensions: Vec<String> = if cfg!(windows) && !program.contains('.') {
+            std::env::var("PATHEXT")
+                .unwrap_or_else(|_| ".EXE;.CMD;.BAT".to_string())
+                .split(';')
+                .map(|ext| ext.to_string())
+                .collect()
+        } else {
+            vec![String::new()]
+        };
+
+        std::env::split_paths(&std::env::var_os("PATH")?).find_map(|dir| {
+            extensions.iter().find_map(|ext| {
+                let candidate = dir.join(format!("{program}{ext}"));
+                candidate.is_file().then_some(candidate)
+            })
+        })
     }
 
-    pub fn get_neovide_extra_args() -> Vec<String> {
-        let mut args = vec![];
+    pub fn nvim_version() -> Result<String> {
+        let output = process::output(&ProcessSpec::new("nvim").arg("--version"))?;
 
-        if cfg!(target_os = "macos") {
-            args.push("--no-vsync".to_string());
+        if !output.success() {
+            return Err(anyhow::anyhow!("nvim --version failed"));
         }
 
-        args
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string())
     }
 }
@@ -0,0 +1,1264 @@
+//! マネージャー本体 (JSON-RPC の受け付け・ディスパッチ・ヘルスチェック)
+//!
+//! `neovim-instance-manager` はこれを起動するだけなので、自前のデーモンに組み込むこともできる。
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Notify, RwLock};
+use tokio::task::JoinSet;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, ManagerHooks, Setting};
+use crate::identifier::PathMapping;
+use crate::notify::{self, NotifyLevel, Severity};
+use crate::process::{self, ProcessSpec};
+use crate::{
+    downgrade_health_status, identifier, params_protocol_version, tunnel, utils, wsl,
+    CheckInstanceParams, CheckInstanceResult, CloseReason, HealthCheckStats, HealthStatus,
+    InstanceInfo, InstanceResult, InstanceStorage, InstancesUpdate, JsonRpcRequest,
+    JsonRpcResponse, ListInstancesParams, ManagerError, ManagerStatus, MirroredInstance,
+    PinInstanceParams, PruneResult, QueryInstanceParams, RegisterInstanceParams,
+    RenameInstanceParams, ServerAddress, SetInstanceCwdParams, StateMirror, TagInstanceParams,
+    Tombstone, TouchInstanceParams, UnregisterInstanceParams, WatchInstancesParams,
+    HEALTH_STATUS_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+type SharedInstanceStorage = Arc<RwLock<InstanceStorage>>;
+
+/// 保持する墓標の上限 (古いものから捨てる)
+const MAX_TOMBSTONES: usize = 100;
+
+/// フックの終了を待つ上限 (超えたら強制終了する)
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `watch_instances` で変わるまで待つ時間の既定と上限
+const WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WATCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// スリープからの復帰を調べる間隔
+const RESUME_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// 壁時計と単調時計の進み方がこれ以上ずれたら復帰 (または時計の飛び) とみなす
+const RESUME_THRESHOLD: Duration = Duration::from_secs(10);
+/// 復帰の直後は応答しないインスタンスもすぐには削除せず、この間だけ待つ (ネットワークや SSH の再接続のため)
+const RESUME_GRACE: Duration = Duration::from_secs(10);
+
+struct InstanceManager {
+    instances: SharedInstanceStorage,
+    bind_address: String,
+    started_at: DateTime<Utc>,
+    stats: RwLock<HealthCheckStats>,
+    tombstones: RwLock<VecDeque<Tombstone>>,
+    clock: Arc<dyn Clock>,
+    /// `shutdown` リクエストを受けたら通知する
+    shutdown: Arc<Notify>,
+    /// 応答しなくなったインスタンスを削除したときのデスクトップ通知
+    notify_level: NotifyLevel,
+    /// 受け取ったコンテナの中のパスをホスト側のパスにそろえる
+    path_mappings: Vec<PathMapping>,
+    /// 登録・登録解除・応答しなくなったときに実行するシェルコマンド
+    hooks: ManagerHooks,
+    /// 登録内容が変わったかもしれない (要求を処理した・ヘルスチェックをした) ときに通知する
+    state_changed: Notify,
+    /// 登録内容 (写しに書く内容) が実際に変わるたびに増える世代
+    generation: watch::Sender<u64>,
+    /// スリープからの復帰を見つけたら通知する (すぐにヘルスチェックする)
+    resumed: Notify,
+    /// 復帰後の猶予の終わり。それまでは応答しないインスタンスを削除しない
+    resume_grace_until: RwLock<Option<Instant>>,
+}
+
+impl InstanceManager {
+    fn new(
+        bind_address: String,
+        notify_level: NotifyLevel,
+        path_mappings: Vec<PathMapping>,
+        hooks: ManagerHooks,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            instances: Arc::new(RwLock::new(HashMap::new())),
+            bind_address,
+            started_at: clock.now(),
+            clock,
+            stats: RwLock::new(HealthCheckStats::default()),
+            tombstones: RwLock::new(VecDeque::new()),
+            shutdown: Arc::new(Notify::new()),
+            notify_level,
+            path_mappings,
+            hooks,
+            state_changed: Notify::new(),
+            generation: watch::Sender::new(0),
+            resumed: Notify::new(),
+            resume_grace_until: RwLock::new(None),
+        }
+    }
+
+    /// 登録内容の写し
+    async fn state_mirror(&self) -> StateMirror {
+        StateMirror {
+            protocol_version: PROTOCOL_VERSION,
+            manager_pid: std::process::id(),
+            bind_address: self.bind_address.clone(),
+            updated_at: self.clock.now(),
+            instances: self.mirrored_instances().await,
+        }
+    }
+
+    /// identifier の順
+    async fn mirrored_instances(&self) -> Vec<MirroredInstance> {
+        let mut instances: Vec<MirroredInstance> = self
+            .instances
+            .read()
+            .await
+            .values()
+            .map(MirroredInstance::from)
+            .collect();
+        instances.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        instances
+    }
+
+    /// フックを待たずに実行し、インスタンスの JSON を標準入力に渡す (失敗してもログに残すだけ)
+    ///
+    /// `NEOVIM_MANAGER_EVENT` (`register` など) と `NEOVIM_MANAGER_IDENTIFIER` を設定する
+    fn run_hook(
+        &self,
+        select: impl FnOnce(&ManagerHooks) -> &Setting<Option<String>>,
+        event: &'static str,
+        instance: &InstanceResult,
+        env: &[(&str, &str)],
+    ) {
+        let Some(command) = select(&self.hooks).value.clone() else {
+            return;
+        };
+        let input = match serde_json::to_vec(instance) {
+            Ok(input) => input,
+            Err(e) => {
+                warn!(
+                    "Cannot serialize {} for the {event} hook: {e}",
+                    instance.identifier
+                );
+                return;
+            }
+        };
+        let shell = if cfg!(windows) {
+            ProcessSpec::new("cmd").args(["/C", &command])
+        } else {
+            ProcessSpec::new("sh").args(["-c", &command])
+        };
+        let mut spec = shell
+            .env("NEOVIM_MANAGER_EVENT", event)
+            .env(utils::IDENTIFIER_ENV, &instance.identifier)
+            .stdin(input)
+            .timeout(HOOK_TIMEOUT);
+        for (key, value) in env {
+            spec = spec.env(*key, *value);
+        }
+
+        info!(
+            "Running {event} hook for {}: {command}",
+            instance.identifier
+        );
+        tokio::spawn(async move {
+            match process::output_async(&spec).await {
+                Ok(output) if output.success() => {}
+                Ok(output) => warn!(
+                    "The {event} hook `{command}` exited with {:?}{}",
+                    output.code,
+                    match String::from_utf8_lossy(&output.stderr).trim() {
+                        "" => String::new(),
+                        stderr => format!(": {stderr}"),
+                    }
+                ),
+                Err(e) => warn!("The {event} hook `{command}` failed: {e:#}"),
+            }
+        });
+    }
+
+    /// 応答しなくなって削除したことをデスクトップ通知で知らせる
+    ///
+    /// 登録解除せずに終了しただけ (記録した PID がもうない) なら Info、
+    /// プロセスが残っている・確かめられないなら Warning
+    fn notify_removed(&self, instance: &InstanceInfo) {
+        let exited = instance
+            .pid
+            .and_then(utils::process_exists)
+            .is_some_and(|exists| !exists);
+        let path = identifier::display(&instance.identifier);
+        let (severity, body) = if exited {
+            (
+                Severity::Info,
+                format!("Neovim instance for {path} exited without unregistering"),
+            )
+        } else {
+            (
+                Severity::Warning,
+                format!("Neovim instance for {path} stopped responding and was removed"),
+            )
+        };
+        if !self.notify_level.allows(severity) {
+            return;
+        }
+
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = notify::send(severity, "Neovim instance removed", &body) {
+                warn!("{e:#}");
+            }
+        });
+    }
+
+    /// 登録から外れたインスタンスを記録する (新しいものが先頭)
+    async fn bury(&self, instance: &InstanceInfo, reason: CloseReason) {
+        let mut result = InstanceResult::from(instance);
+        // 応答しなくなったものはプロセスが終了している
+        if reason == CloseReason::Unresponsive {
+            result.health_status = HealthStatus::Dead;
+            self.notify_removed(instance);
+            self.run_hook(|hooks| &hooks.on_health_lost, "health_lost", &result, &[]);
+        }
+        let reason_name = match reason {
+            CloseReason::Unregistered => "unregistered",
+            CloseReason::Unresponsive => "unresponsive",
+        };
+        self.run_hook(
+            |hooks| &hooks.on_unregister,
+            "unregister",
+            &result,
+            &[("NEOVIM_MANAGER_REASON", reason_name)],
+        );
+        // `ssh://` のインスタンスのローカルフォワードはもう使わない
+        if let ServerAddress::Ssh(server_address) = &instance.server_address {
+            let server_address = server_address.clone();
+            tokio::task::spawn_blocking(move || match tunnel::close(&server_address) {
+                Ok(()) => info!("Closed the SSH forward for {server_address}"),
+                Err(e) => warn!("{e:#}"),
+            });
+        }
+
+        let mut tombstones = self.tombstones.write().await;
+        tombstones.push_front(Tombstone {
+            instance: result,
+            closed_at: self.clock.now(),
+            reason,
+        });
+        tombstones.truncate(MAX_TOMBSTONES);
+    }
+
+    async fn health_check_all(&self) -> Result<Vec<InstanceInfo>> {
+        let started = Instant::now();
+        let in_grace = self
+            .resume_grace_until
+            .read()
+            .await
+            .is_some_and(|until| started < until);
+
+        // 応答しない nvim を待つ間も他の要求を処理できるよう、ロックを持たずに並行して確かめる
+        let targets: Vec<_> = self
+            .instances
+            .read()
+            .await
+            .values()
+            .map(|instance| {
+                (
+                    instance.identifier.clone(),
+                    instance.server_address.clone(),
+                    instance.registered_at,
+                )
+            })
+            .collect();
+        let mut pending = JoinSet::new();
+        for (identifier, server_address, registered_at) in targets {
+            pending.spawn(async move {
+                let is_healthy = check_health(&server_address, in_grace).await;
+                (identifier, server_address, registered_at, is_healthy)
+            });
+        }
+        let mut results = Vec::new();
+        while let Some(joined) = pending.join_next().await {
+            match joined {
+                Ok(result) => results.push(result),
+                Err(e) => warn!("Health check task failed: {e}"),
+            }
+        }
+
+        let mut instances = self.instances.write().await;
+        let now = self.clock.now();
+        let mut to_remove = Vec::new();
+        let mut failures = 0;
+        let checks = results.len() as u64;
+
+        for (identifier, server_address, registered_at, is_healthy) in results {
+            // 確かめている間に登録解除・登録し直されたものは対象外
+            let Some(instance) = instances.get_mut(&identifier).filter(|instance| {
+                instance.server_address == server_address && instance.registered_at == registered_at
+            }) else {
+                continue;
+            };
+            instance.last_health_check = now;
+
+            if is_healthy {
+                if !instance.health_status.is_healthy() {
+                    info!("Instance {identifier} is now healthy");
+                }
+                instance.health_status = HealthStatus::Healthy;
+                instance.last_ping = now;
+            } else if instance.pinned {
+                // ピン留めされたものは自動削除せず、失敗回数だけ数える
+                failures += 1;
+                let was_healthy = instance.health_status.is_healthy();
+                instance.health_status = instance.health_status.failed();
+                if was_healthy {
+                    info!("Pinned instance {identifier} is not responding, keeping it");
+                    self.run_hook(
+                        |hooks| &hooks.on_health_lost,
+                        "health_lost",
+                        &InstanceResult::from(&*instance),
+                        &[],
+                    );
+                }
+            } else if in_grace {
+                // 猶予が終わった後のヘルスチェックでもまだ応答しなければ削除する
+                failures += 1;
+                instance.health_status = instance.health_status.failed();
+                info!("Instance {identifier} is not responding after resume, checking again later");
+            } else {
+                // ヘルスチェック失敗 = プロセス終了なので即座に削除
+                info!("Instance {identifier} is no longer responding, removing");
+                failures += 1;
+                to_remove.push(identifier.clone());
+            }
+        }
+
+        let mut removed = Vec::new();
+        for identifier in to_remove {
+            if let Some(instance) = instances.remove(&identifier) {
+                info!("Removed unresponsive instance: {identifier}");
+                removed.push(instance);
+            }
+        }
+
+        let mut stats = self.stats.write().await;
+        stats.runs += 1;
+        stats.checks += checks;
+        stats.failures += failures;
+        stats.removed += removed.len() as u64;
+        stats.last_run_at = Some(now);
+        stats.last_run_duration_ms = Some(started.elapsed().as_millis() as u64);
+
+        for instance in &removed {
+            self.bury(instance, CloseReason::Unresponsive).await;
+        }
+
+        Ok(removed)
+    }
+
+    async fn check_instance(&self, identifier: &str) -> Result<Option<CheckInstanceResult>> {
+        let server_address = match self.instances.read().await.get(identifier) {
+            Some(instance) => instance.server_address.clone(),
+            None => return Ok(None),
+        };
+
+        // 定期チェックを待たずにこのインスタンスだけ即座に疎通確認する
+        let started = Instant::now();
+        let healthy = utils::check_nvim_instance(&server_address)
+            .await
+            .unwrap_or(false);
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        let mut instances = self.instances.write().await;
+        let mut stats = self.stats.write().await;
+        stats.checks += 1;
+        if !healthy {
+            stats.failures += 1;
+        }
+
+        let mut removed = false;
+        if healthy {
+            if let Some(instance) = instances.get_mut(identifier) {
+                let now = self.clock.now();
+                if !instance.health_status.is_healthy() {
+                    info!("Instance {identifier} is now healthy");
+                }
+                instance.health_status = HealthStatus::Healthy;
+                instance.last_health_check = now;
+                instance.last_ping = now;
+            }
+        } else if let Some(instance) = instances
+            .get_mut(identifier)
+            .filter(|instance| instance.pinned)
+        {
+            let was_healthy = instance.health_status.is_healthy();
+            instance.health_status = instance.health_status.failed();
+            instance.last_health_check = self.clock.now();
+            if was_healthy {
+                self.run_hook(
+                    |hooks| &hooks.on_health_lost,
+                    "health_lost",
+                    &InstanceResult::from(&*instance),
+                    &[],
+                );
+            }
+        } else if let Some(instance) = instances.remove(identifier) {
+            info!("Removed unresponsive instance: {identifier}");
+            stats.removed += 1;
+            removed = true;
+            self.bury(&instance, CloseReason::Unresponsive).await;
+        }
+
+        Ok(Some(CheckInstanceResult {
+            identifier: identifier.to_string(),
+            server_address,
+            healthy,
+            latency_ms,
+            removed,
+        }))
+    }
+
+    async fn status(&self) -> Result<ManagerStatus> {
+        let instances = self.instances.read().await;
+        let healthy_count = instances
+            .values()
+            .filter(|instance| instance.health_status.is_healthy())
+            .count();
+
+        Ok(ManagerStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            pid: std::process::id(),
+            started_at: self.started_at,
+            uptime_secs: (self.clock.now() - self.started_at).num_seconds().max(0) as u64,
+            bind_address: self.bind_address.clone(),
+            instance_count: instances.len(),
+            healthy_count,
+            health_checks: self.stats.read().await.clone(),
+        })
+    }
+
+    async fn recent_instances(&self) -> Vec<Tombstone> {
+        self.tombstones.read().await.iter().cloned().collect()
+    }
+
+    async fn query_instance(&self, identifier: &str) -> Result<Option<InstanceResult>> {
+        // ヘルスチェックは別途実行するので、クエリ時は実行しない
+        // self.health_check_all().await?;
+
+        let instances = self.instances.read().await;
+        if let Some(instance) = instances.get(identifier) {
+            Ok(Some(InstanceResult::from(instance)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn list_instances(&self, tag: Option<&str>) -> Result<Vec<InstanceResult>> {
+        self.health_check_all().await?;
+
+        let instances = self.instances.read().await;
+        let results = instances
+            .values()
+            .filter(|instance| tag.is_none_or(|tag| instance.tags.iter().any(|t| t == tag)))
+            .map(InstanceResult::from)
+            .collect();
+
+        Ok(results)
+    }
+
+    /// 世代が `generation` から進むまで (最大 `timeout`) 待ち、その時点の一覧を返す
+    ///
+    /// 一覧を取り直すたびにヘルスチェックする `list_instances` と違い、記録済みの状態を返す
+    async fn watch_instances(&self, generation: Option<u64>, timeout: Duration) -> InstancesUpdate {
+        let mut changes = self.generation.subscribe();
+        if generation == Some(*changes.borrow_and_update()) {
+            let _ = tokio::time::timeout(timeout, changes.changed()).await;
+        }
+        let generation = *changes.borrow();
+
+        let mut instances: Vec<InstanceResult> = self
+            .instances
+            .read()
+            .await
+            .values()
+            .map(InstanceResult::from)
+            .collect();
+        instances.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        InstancesUpdate {
+            generation,
+            instances,
+        }
+    }
+
+    async fn prune_instances(&self) -> Result<PruneResult> {
+        let removed = self.health_check_all().await?;
+        let remaining = self.instances.read().await.len();
+
+        Ok(PruneResult {
+            removed: removed.iter().map(InstanceResult::from).collect(),
+            remaining,
+        })
+    }
+
+    async fn register_instance(&self, params: RegisterInstanceParams) -> Result<(), ManagerError> {
+        // WSL の manager からは Windows の名前付きパイプなどに接続できず、ヘルスチェックが必ず失敗する
+        if wsl::is_wsl() {
+            wsl::check_reachable_from_wsl(&params.server_address)
+                .map_err(|e| ManagerError::InvalidParams(e.to_string()))?;
+        }
+
+        let mut instances = self.instances.write().await;
+        let identifier = params.identifier;
+
+        if instances.contains_key(&identifier) {
+            return Err(ManagerError::InstanceAlreadyExists { identifier });
+        }
+
+        let now = self.clock.now();
+        let instance = InstanceInfo {
+            identifier: identifier.clone(),
+            server_address: params.server_address,
+            registered_at: now,
+            last_ping: now,
+            last_used: now,
+            health_status: HealthStatus::Starting,
+            last_health_check: now,
+            pinned: params.pinned,
+            cwd: params.cwd,
+            pid: params.pid,
+            tags: {
+                let mut tags = params.tags;
+                tags.sort();
+                tags.dedup();
+                tags
+            },
+            appname: params.appname,
+        };
+
+        let result = InstanceResult::from(&instance);
+        instances.insert(identifier.clone(), instance);
+        info!("Registered instance: {identifier}");
+        self.run_hook(|hooks| &hooks.on_register, "register", &result, &[]);
+
+        // 開き直されたものはもう「閉じた」扱いにしない
+        self.tombstones
+            .write()
+            .await
+            .retain(|tombstone| tombstone.instance.identifier != identifier);
+
+        Ok(())
+    }
+
+    async fn touch_instance(&self, identifier: &str) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        match instances.get_mut(identifier) {
+            Some(instance) => {
+                instance.last_used = self.clock.now();
+                Ok(())
+            }
+            None => Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            }),
+        }
+    }
+
+    async fn pin_instance(&self, identifier: &str, pinned: bool) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        match instances.get_mut(identifier) {
+            Some(instance) => {
+                instance.pinned = pinned;
+                info!("Set pinned={pinned} for instance: {identifier}");
+                Ok(())
+            }
+            None => Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            }),
+        }
+    }
+
+    async fn tag_instance(
+        &self,
+        identifier: &str,
+        tag: &str,
+        tagged: bool,
+    ) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        match instances.get_mut(identifier) {
+            Some(instance) => {
+                instance.tags.retain(|t| t != tag);
+                if tagged {
+                    instance.tags.push(tag.to_string());
+                    instance.tags.sort();
+                }
+                info!("Set tag {tag}={tagged} for instance: {identifier}");
+                Ok(())
+            }
+            None => Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            }),
+        }
+    }
+
+    async fn set_instance_cwd(&self, identifier: &str, cwd: String) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        match instances.get_mut(identifier) {
+            Some(instance) => {
+                info!("Set cwd={cwd} for instance: {identifier}");
+                instance.cwd = Some(cwd);
+                Ok(())
+            }
+            None => Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            }),
+        }
+    }
+
+    async fn unregister_instance(&self, identifier: &str) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        if let Some(instance) = instances.remove(identifier) {
+            info!("Unregistered instance: {identifier}");
+            self.bury(&instance, CloseReason::Unregistered).await;
+            Ok(())
+        } else {
+            Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            })
+        }
+    }
+
+    async fn rename_instance(
+        &self,
+        identifier: &str,
+        new_identifier: String,
+    ) -> Result<(), ManagerError> {
+        let mut instances = self.instances.write().await;
+
+        // 移動していないディレクトリに --follow-path したときなど
+        if new_identifier == identifier {
+            if instances.contains_key(identifier) {
+                return Ok(());
+            }
+            return Err(ManagerError::InstanceNotFound {
+                identifier: identifier.to_string(),
+            });
+        }
+
+        if instances.contains_key(&new_identifier) {
+            return Err(ManagerError::InstanceAlreadyExists {
+                identifier: new_identifier,
+            });
+        }
+
+        let mut instance =
+            instances
+                .remove(identifier)
+                .ok_or_else(|| ManagerError::InstanceNotFound {
+                    identifier: identifier.to_string(),
+                })?;
+
+        instance.identifier = new_identifier.clone();
+        // パスの identifier なら cwd も同じパスを指していたはず
+        if instance.cwd.as_deref() == Some(identifier) {
+            instance.cwd = Some(new_identifier.clone());
+        }
+        let server_address = instance.server_address.clone();
+        instances.insert(new_identifier.clone(), instance);
+        drop(instances);
+        info!("Renamed instance: {identifier} -> {new_identifier}");
+
+        // nvim 側の touch / cd --record-only / unregister が新しい名前を使うように
+        if let Err(e) = utils::set_nvim_identifier(&server_address, &new_identifier).await {
+            warn!(
+                "Failed to update {} in {new_identifier}: {e}",
+                utils::IDENTIFIER_ENV
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
+        let client_version = params_protocol_version(&request.params);
+        let result = self.dispatch(&request.method, request.params).await;
+        // 変わっていなければ書き出す側で捨てる
+        self.state_changed.notify_one();
+        match result {
+            Ok(mut result) => {
+                if client_version < HEALTH_STATUS_PROTOCOL_VERSION {
+                    downgrade_health_status(&mut result);
+                }
+                JsonRpcResponse::success(request.id, result)
+            }
+            Err(error) => JsonRpcResponse::failure(request.id, error.into()),
+        }
+    }
+
+    async fn dispatch(&self, method: &str, params: Value) -> Result<Value, ManagerError> {
+        match method {
+            "query_instance" => {
+                let params: QueryInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                let instance = self
+                    .query_instance(&params.identifier)
+                    .await
+                    .map_err(internal_error)?;
+                Ok(json!(instance))
+            }
+            "list_instances" => {
+                // 従来どおり params なし (null) でも受け付ける
+                let params: ListInstancesParams =
+                    serde_json::from_value(params).unwrap_or_default();
+                let instances = self
+                    .list_instances(params.tag.as_deref())
+                    .await
+                    .map_err(internal_error)?;
+                Ok(json!(instances))
+            }
+            "watch_instances" => {
+                let params: WatchInstancesParams =
+                    serde_json::from_value(params).unwrap_or_default();
+                let timeout = params
+                    .timeout_secs
+                    .map_or(WATCH_TIMEOUT, Duration::from_secs)
+                    .min(MAX_WATCH_TIMEOUT);
+                Ok(json!(
+                    self.watch_instances(params.generation, timeout).await
+                ))
+            }
+            "prune_instances" => {
+                let result = self.prune_instances().await.map_err(internal_error)?;
+                Ok(json!(result))
+            }
+            "register_instance" => {
+                let params: RegisterInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                self.register_instance(params).await?;
+                Ok(json!("registered"))
+            }
+            "unregister_instance" => {
+                let params: UnregisterInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                self.unregister_instance(&params.identifier).await?;
+                Ok(json!("unregistered"))
+            }
+            "rename_instance" => {
+                let params: RenameInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                self.rename_instance(&params.identifier, params.new_identifier)
+                    .await?;
+                Ok(json!("renamed"))
+            }
+            "touch_instance" => {
+                let params: TouchInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                self.touch_instance(&params.identifier).await?;
+                Ok(json!("touched"))
+            }
+            "pin_instance" => {
+                let params: PinInstanceParams = parse_params(method, params, &self.path_mappings)?;
+                self.pin_instance(&params.identifier, params.pinned).await?;
+                Ok(json!(if params.pinned { "pinned" } else { "unpinned" }))
+            }
+            "tag_instance" => {
+                let params: TagInstanceParams = parse_params(method, params, &self.path_mappings)?;
+                self.tag_instance(&params.identifier, &params.tag, params.tagged)
+                    .await?;
+                Ok(json!(if params.tagged { "tagged" } else { "untagged" }))
+            }
+            "set_instance_cwd" => {
+                let params: SetInstanceCwdParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                self.set_instance_cwd(&params.identifier, params.cwd)
+                    .await?;
+                Ok(json!("updated"))
+            }
+            "check_instance" => {
+                let params: CheckInstanceParams =
+                    parse_params(method, params, &self.path_mappings)?;
+                match self
+                    .check_instance(&params.identifier)
+                    .await
+                    .map_err(internal_error)?
+                {
+                    Some(result) => Ok(json!(result)),
+                    None => Err(ManagerError::InstanceNotFound {
+                        identifier: params.identifier,
+                    }),
+                }
+            }
+            "recent_instances" => Ok(json!(self.recent_instances().await)),
+            "ping" => Ok(json!("pong")),
+            "status" => {
+                let status = self.status().await.map_err(internal_error)?;
+                Ok(json!(status))
+            }
+            "shutdown" => {
+                info!("Shutdown requested");
+                // 応答を送り終えてから止める
+                let shutdown = Arc::clone(&self.shutdown);
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    shutdown.notify_one();
+                });
+                Ok(json!("shutting_down"))
+            }
+            _ => Err(ManagerError::MethodNotFound),
+        }
+    }
+}
+
+/// identifier と作業ディレクトリを指すパラメータ。パスの形のものは
+/// [`identifier::normalize_mapped`] でそろえる (コンテナの中のパスはホスト側のパスになる)
+const IDENTIFIER_FIELDS: [&str; 3] = ["identifier", "new_identifier", "cwd"];
+
+/// 知らないフィールドは無視する。読めない場合はメソッド名と、プロトコルのずれがあればその旨を添える
+fn parse_params<T: DeserializeOwned>(
+    method: &str,
+    mut params: Value,
+    path_mappings: &[PathMapping],
+) -> Result<T, ManagerError> {
+    let client_version = params_protocol_version(&params);
+    for field in IDENTIFIER_FIELDS {
+        if let Some(value) = params.get_mut(field) {
+            if let Some(normalized) = value
+                .as_str()
+                .map(|id| identifier::normalize_mapped(id, path_mappings))
+            {
+                *value = Value::String(normalized);
+            }
+        }
+    }
+    serde_json::from_value(params).map_err(|e| {
+        let hint = match client_version.cmp(&PROTOCOL_VERSION) {
+            Ordering::Greater => format!(
+                " (the client speaks protocol {client_version} but the manager only \
+                 {PROTOCOL_VERSION}; restart the manager to upgrade it)"
+            ),
+            Ordering::Less => format!(
+                " (the client speaks protocol {client_version} but the manager \
+                 {PROTOCOL_VERSION}; update the client)"
+            ),
+            Ordering::Equal => String::new(),
+        };
+        ManagerError::InvalidParams(format!("{method}: {e}{hint}"))
+    })
+}
+
+fn internal_error(e: anyhow::Error) -> ManagerError {
+    ManagerError::Internal(e.to_string())
+}
+
+async fn handle_client<S>(stream: S, manager: Arc<InstanceManager>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let bytes_read = reader.read_line(&mut line).await?;
+
+        if bytes_read == 0 {
+            // Client disconnected
+            break;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        info!("Received request: {trimmed}");
+
+        let response = match serde_json::from_str::<JsonRpcRequest>(trimmed) {
+            // 通知には応答しない
+            Ok(request) if request.is_notification() => {
+                manager.handle_request(request).await;
+                continue;
+            }
+            Ok(request) => manager.handle_request(request).await,
+            Err(e) => {
+                error!("Failed to parse JSON-RPC request: {e}");
+                JsonRpcResponse::failure(Value::Null, ManagerError::ParseError.into())
+            }
+        };
+
+        let response_json = serde_json::to_string(&response)?;
+        info!("Sending response: {response_json}");
+
+        writer.write_all(response_json.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// 接続を受け付けるもの (TCP と Unix ソケット)
+trait Accept {
+    type Stream: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// 接続と、ログに出す接続元
+    fn accept(&self) -> impl Future<Output = std::io::Result<(Self::Stream, String)>> + Send;
+}
+
+impl Accept for TcpListener {
+    type Stream = tokio::net::TcpStream;
+
+    async fn accept(&self) -> std::io::Result<(Self::Stream, String)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, addr.to_string()))
+    }
+}
+
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept(&self) -> std::io::Result<(Self::Stream, String)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, "unix socket".to_string()))
+    }
+}
+
+/// 設定のアドレス (`manager.bind_address:manager.port`) で待ち受け、
+/// `shutdown_signal` が完了するか `shutdown` リクエストを受けるまで動かす
+///
+/// systemd のソケットアクティベーションで起動された場合は、渡されたソケットで待ち受ける
+pub async fn run(config: &Config, shutdown_signal: impl Future<Output = ()>) -> Result<()> {
+    let listener = match activated_listener()? {
+        Some(listener) => {
+            info!("Using the socket passed by systemd");
+            TcpListener::from_std(listener)?
+        }
+        None => {
+            let addr = config.manager.address();
+            TcpListener::bind(&addr)
+                .await
+                .with_context(|| format!("Cannot listen on {addr}"))?
+        }
+    };
+    serve(listener, config, shutdown_signal).await
+}
+
+/// systemd から渡された待ち受け中のソケット (`LISTEN_PID` が自分で `LISTEN_FDS` が 1 以上なら fd 3)
+#[cfg(unix)]
+fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let var = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+    if var("LISTEN_PID") != Some(std::process::id()) || var("LISTEN_FDS").unwrap_or(0) == 0 {
+        return Ok(None);
+    }
+    // フックなどの子プロセスに引き継がせない
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    // SAFETY: systemd は fd 3 から LISTEN_FDS 個のソケットを渡し、ほかに fd 3 を使うものはない
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(3) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// [`run`] と同じだが、bind 済みの TCP リスナーで待ち受ける (ポート 0 で空きポートを使う場合など)
+pub async fn serve(
+    listener: TcpListener,
+    config: &Config,
+    shutdown_signal: impl Future<Output = ()>,
+) -> Result<()> {
+    serve_with_clock(listener, config, Arc::new(SystemClock), shutdown_signal).await
+}
+
+/// [`serve`] と同じだが、登録・最終使用・ヘルスチェックの時刻やスリープからの復帰の判定に `clock` を使う
+/// (テストで [`ManualClock`](crate::clock::ManualClock) を渡して時刻を進める)
+pub async fn serve_with_clock(
+    listener: TcpListener,
+    config: &Config,
+    clock: Arc<dyn Clock>,
+    shutdown_signal: impl Future<Output = ()>,
+) -> Result<()> {
+    let addr = listener.local_addr()?.to_string();
+    info!("Neovim Instance Manager listening on {addr}");
+    serve_with(listener, addr, config, clock, shutdown_signal).await
+}
+
+/// TCP ポートの代わりに Unix ソケットで待ち受ける (SSH のソケット転送などで使う)
+///
+/// 終了時にソケットファイルを消す
+#[cfg(unix)]
+pub async fn run_unix(
+    socket: &Path,
+    config: &Config,
+    shutdown_signal: impl Future<Output = ()>,
+) -> Result<()> {
+    // 前回の終了時に残ったソケットファイルは、誰も listen していなければ消す
+    if socket.exists() && std::os::unix::net::UnixStream::connect(socket).is_err() {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket)
+        .with_context(|| format!("Cannot listen on {}", socket.display()))?;
+    info!("Neovim Instance Manager listening on {}", socket.display());
+
+    let result = serve_with(
+        listener,
+        socket.display().to_string(),
+        config,
+        Arc::new(SystemClock),
+        shutdown_signal,
+    )
+    .await;
+    let _ = std::fs::remove_file(socket);
+    result
+}
+
+#[cfg(not(unix))]
+pub async fn run_unix(
+    _socket: &Path,
+    _config: &Config,
+    _shutdown_signal: impl Future<Output = ()>,
+) -> Result<()> {
+    Err(anyhow::anyhow!("Unix sockets are only supported on Unix"))
+}
+
+/// 接続の受け付けとヘルスチェックを回す。戻るときに接続中のクライアントのタスクも止める
+async fn serve_with<L: Accept>(
+    listener: L,
+    bind_address: String,
+    config: &Config,
+    clock: Arc<dyn Clock>,
+    shutdown_signal: impl Future<Output = ()>,
+) -> Result<()> {
+    let manager = Arc::new(InstanceManager::new(
+        bind_address,
+        config.manager.notify.value,
+        config.manager.path_mappings.value.clone(),
+        config.manager.hooks.clone(),
+        clock,
+    ));
+    let mut tasks = JoinSet::new();
+    let state_file = config.manager.state_file.value.clone();
+    if let Some(path) = &state_file {
+        tasks.spawn(write_state(Arc::clone(&manager), path.clone()));
+    }
+    tasks.spawn(track_changes(Arc::clone(&manager)));
+    tasks.spawn(resume_watch(Arc::clone(&manager)));
+    tasks.spawn(health_checks(
+        Arc::clone(&manager),
+        config
+            .manager
+            .health_check_interval
+            .value
+            .max(Duration::from_millis(100)),
+    ));
+
+    let shutdown = Arc::clone(&manager.shutdown);
+    tokio::pin!(shutdown_signal);
+    loop {
+        tokio::select! {
+            () = &mut shutdown_signal => {
+                info!("Shutdown signal received");
+                break;
+            }
+            () = shutdown.notified() => {
+                info!("Shutdown requested");
+                break;
+            }
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    info!("New client connected from: {peer}");
+                    let manager = Arc::clone(&manager);
+                    tasks.spawn(async move {
+                        if let Err(e) = handle_client(stream, manager).await {
+                            error!("Error handling client: {e}");
+                        }
+                        info!("Client {peer} disconnected");
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to accept connection: {e}");
+                }
+            },
+        }
+
+        // 終わった接続のタスクを片付ける
+        while tasks.try_join_next().is_some() {}
+    }
+
+    tasks.shutdown().await;
+    // 残っていると、読む側がマネージャーが動いていると思ってしまう
+    if let Some(path) = &state_file {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// 変わったかもしれないと通知されるたびに登録内容を比べ、変わっていれば世代を進める
+async fn track_changes(manager: Arc<InstanceManager>) {
+    let mut last: Option<Vec<MirroredInstance>> = None;
+    loop {
+        let instances = manager.mirrored_instances().await;
+        if last.as_ref() != Some(&instances) {
+            last = Some(instances);
+            manager
+                .generation
+                .send_modify(|generation| *generation += 1);
+        }
+        manager.state_changed.notified().await;
+    }
+}
+
+/// 登録内容が変わるたびに写しを `path` に書き出す (起動時にも空の一覧を書く)
+async fn write_state(manager: Arc<InstanceManager>, path: PathBuf) {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = tokio::fs::create_dir_all(dir).await {
+            warn!("Cannot create {}: {e}", dir.display());
+        }
+    }
+
+    let mut changes = manager.generation.subscribe();
+    let mut written: Option<Vec<MirroredInstance>> = None;
+    loop {
+        let state = manager.state_mirror().await;
+        if written.as_ref() != Some(&state.instances) {
+            match write_atomically(&path, &state).await {
+                Ok(()) => written = Some(state.instances),
+                Err(e) => warn!("Cannot write the state file {}: {e:#}", path.display()),
+            }
+        }
+        if changes.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// 読む側が書きかけを読まないよう、一時ファイルに書いてから置き換える
+async fn write_atomically(path: &Path, state: &StateMirror) -> Result<()> {
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    let mut content = serde_json::to_vec(state)?;
+    content.push(b'\n');
+    tokio::fs::write(&tmp, content)
+        .await
+        .with_context(|| format!("Cannot write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Cannot replace {}", path.display()))
+}
+
+/// 1 つのインスタンスの疎通確認
+///
+/// 復帰後の猶予中は SSH のフォワードが切れているかもしれないので、作り直してもう一度確かめる
+async fn check_health(server_address: &ServerAddress, in_grace: bool) -> bool {
+    let is_healthy = utils::check_nvim_instance(server_address)
+        .await
+        .unwrap_or(false);
+    let ServerAddress::Ssh(ssh_address) = server_address else {
+        return is_healthy;
+    };
+    if is_healthy || !in_grace {
+        return is_healthy;
+    }
+    let ssh_address = ssh_address.clone();
+    if let Ok(Err(e)) = tokio::task::spawn_blocking(move || tunnel::close(&ssh_address)).await {
+        warn!("{e:#}");
+    }
+    utils::check_nvim_instance(server_address)
+        .await
+        .unwrap_or(false)
+}
+
+/// 定期的なヘルスチェック (スリープから復帰したときは周期を待たずに)
+async fn health_checks(manager: Arc<InstanceManager>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        let resumed = tokio::select! {
+            _ = interval.tick() => false,
+            () = manager.resumed.notified() => true,
+        };
+        if let Err(e) = manager.health_check_all().await {
+            error!("Health check failed: {e}");
+        }
+        manager.state_changed.notify_one();
+        if resumed {
+            // 猶予が終わったらすぐに確かめ直し、まだ応答しないものを削除する
+            interval.reset_after(RESUME_GRACE);
+        }
+    }
+}
+
+/// スリープからの復帰を見つける
+///
+/// 壁時計 (`Clock`) はスリープ中も進むが、単調時計は Linux・macOS では止まる。
+/// 前回から壁時計と単調時計の進み方がずれた、または単調時計でもタイマーが大きく遅れた (Windows) ときに復帰とみなす。
+/// 壁時計を合わせ直しただけでも見つかるが、その場合も確かめ直すだけなので区別しない
+struct ResumeDetector {
+    wall: DateTime<Utc>,
+    monotonic: tokio::time::Instant,
+}
+
+impl ResumeDetector {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            wall: now,
+            monotonic: tokio::time::Instant::now(),
+        }
+    }
+
+    /// 前回から飛んだ時間。閾値に満たなければ None
+    fn check(&mut self, now: DateTime<Utc>) -> Option<Duration> {
+        let monotonic = tokio::time::Instant::now();
+        let elapsed = monotonic - self.monotonic;
+        // 壁時計が戻った場合は 0 とみなす
+        let wall_elapsed = (now - self.wall).to_std().unwrap_or_default();
+        self.wall = now;
+        self.monotonic = monotonic;
+
+        let jump = wall_elapsed
+            .abs_diff(elapsed)
+            .max(elapsed.saturating_sub(RESUME_POLL_INTERVAL));
+        (jump >= RESUME_THRESHOLD).then_some(jump)
+    }
+}
+
+/// スリープからの復帰を見つけたら、猶予を付けてすぐにヘルスチェックさせる
+async fn resume_watch(manager: Arc<InstanceManager>) {
+    let mut detector = ResumeDetector::new(manager.clock.now());
+    loop {
+        tokio::time::sleep(RESUME_POLL_INTERVAL).await;
+        let Some(jump) = detector.check(manager.clock.now()) else {
+            continue;
+        };
+        info!(
+            "Detected a suspend/resume or clock jump of {}s, re-verifying all instances",
+            jump.as_secs()
+        );
+        *manager.resume_grace_until.write().await = Some(Instant::now() + RESUME_GRACE);
+        let mut stats = manager.stats.write().await;
+        stats.resumes += 1;
+        stats.last_resume_at = Some(manager.clock.now());
+        drop(stats);
+        manager.resumed.notify_one();
+    }
+}
@@ -0,0 +1,135 @@
+//! デスクトップ通知
+//!
+//! マネージャーがインスタンスを勝手に削除したときなど、黙って消えると分かりにくい出来事を OS の通知で知らせる。
+//! 通知には OS に付属のコマンドを使う (Linux などは `notify-send`、macOS は `osascript`、Windows は PowerShell)。
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::process::{self, ProcessSpec};
+
+const APP_NAME: &str = "Neovim Manager";
+
+/// 通知を出すコマンドを待つ上限 (Windows のバルーンは表示している間コマンドが終わらない)
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 通知の重要度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// 知っておくとよい (PID が終了していた、など)
+    Info,
+    /// 予期しない (プロセスは残っているのに応答しない、など)
+    Warning,
+}
+
+/// どの重要度から通知するか (`manager.notify`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyLevel {
+    /// 通知しない
+    Off,
+    #[default]
+    Warning,
+    /// すべて通知する
+    Info,
+}
+
+impl NotifyLevel {
+    pub const ALL: [NotifyLevel; 3] = [NotifyLevel::Off, NotifyLevel::Warning, NotifyLevel::Info];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotifyLevel::Off => "off",
+            NotifyLevel::Warning => "warning",
+            NotifyLevel::Info => "info",
+        }
+    }
+
+    /// `severity` の通知を出すか
+    pub fn allows(self, severity: Severity) -> bool {
+        match self {
+            NotifyLevel::Off => false,
+            NotifyLevel::Warning => severity >= Severity::Warning,
+            NotifyLevel::Info => true,
+        }
+    }
+}
+
+impl fmt::Display for NotifyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for NotifyLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        NotifyLevel::ALL
+            .into_iter()
+            .find(|level| level.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = NotifyLevel::ALL.iter().map(|level| level.name()).collect();
+                format!(
+                    "unknown notify level '{s}' (expected one of: {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// 通知を出す (表示されるまで待つので、非同期の処理からは spawn_blocking で呼ぶ)
+pub fn send(severity: Severity, summary: &str, body: &str) -> Result<()> {
+    let output = process::output(&spec(severity, summary, body).timeout(NOTIFY_TIMEOUT))?;
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to show a notification: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+fn spec(severity: Severity, summary: &str, body: &str) -> ProcessSpec {
+    if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {} subtitle {}",
+            applescript_string(body),
+            applescript_string(APP_NAME),
+            applescript_string(summary)
+        );
+        ProcessSpec::new("osascript").args(["-e", &script])
+    } else if cfg!(windows) {
+        let icon = match severity {
+            Severity::Info => "Info",
+            Severity::Warning => "Warning",
+        };
+        let script = format!(
+            "Add-Type -AssemblyName System.Windows.Forms; \
+             $n = New-Object System.Windows.Forms.NotifyIcon; \
+             $n.Icon = [System.Drawing.SystemIcons]::{icon}; $n.Visible = $true; \
+             $n.ShowBalloonTip(10000, {}, {}, '{icon}'); Start-Sleep -Seconds 10; $n.Dispose()",
+            powershell_string(summary),
+            powershell_string(body)
+        );
+        ProcessSpec::new("powershell").args(["-NoProfile", "-Command", &script])
+    } else {
+        let urgency = match severity {
+            Severity::Info => "low",
+            Severity::Warning => "normal",
+        };
+        ProcessSpec::new("notify-send").args(["-a", APP_NAME, "-u", urgency, "--", summary, body])
+    }
+}
+
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn powershell_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,228 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::utils::NvimBuffer;
+use crate::ServerAddress;
+
+pub use rmpv::Value;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// msgpack-RPC のメッセージ種別
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+
+trait NvimStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> NvimStream for T {}
+
+/// `nvim_list_uis()` の要素 (使うものだけ)
+#[derive(Debug, Clone, Deserialize)]
+pub struct NvimUi {
+    pub width: u64,
+    pub height: u64,
+    #[serde(default)]
+    pub rgb: bool,
+    /// 接続しているチャンネル (古い Neovim では返らない)
+    #[serde(default)]
+    pub chan: Option<u64>,
+}
+
+/// Neovim サーバーへの msgpack-RPC 接続
+///
+/// `nvim --server ... --remote-expr` を毎回起動する代わりに、1 本の接続で続けて要求を送る。
+pub struct NvimClient {
+    address: String,
+    stream: BufReader<Box<dyn NvimStream>>,
+    next_id: u32,
+}
+
+impl NvimClient {
+    pub fn connect(address: &str) -> Result<Self> {
+        Self::connect_timeout(address, DEFAULT_TIMEOUT)
+    }
+
+    /// [`ServerAddress`] の種類に応じて TCP・Unix ソケット・名前付きパイプで接続する
+    ///
+    /// `ssh://` のアドレスはローカルフォワード ([`crate::tunnel`]) を通す
+    pub fn connect_timeout(address: &str, timeout: Duration) -> Result<Self> {
+        let local = crate::tunnel::local_address(address)?;
+        let stream: Box<dyn NvimStream> = match local.parse::<ServerAddress>()? {
+            ServerAddress::Tcp(_) => {
+                let addr = local
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| anyhow!("Cannot resolve {address}"))?;
+                let stream = TcpStream::connect_timeout(&addr, timeout)
+                    .map_err(|e| anyhow!("Cannot connect to {address}: {e}"))?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Box::new(stream)
+            }
+            ServerAddress::Unix(_) => connect_unix(&local, timeout)?,
+            ServerAddress::WindowsPipe(_) => connect_pipe(&local, timeout)?,
+            ServerAddress::Ssh(_) => {
+                return Err(anyhow!("Cannot connect to {address} through SSH"))
+            }
+        };
+
+        Ok(Self {
+            address: address.to_string(),
+            stream: BufReader::new(stream),
+            next_id: 0,
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// API 関数を呼び、応答を待つ。途中に届いた通知は読み飛ばす
+    pub fn request(&mut self, method: &str, args: Vec<Value>) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let message = Value::Array(vec![
+            Value::from(REQUEST),
+            Value::from(id),
+            Value::from(method),
+            Value::Array(args),
+        ]);
+        let mut buffer = Vec::new();
+        rmpv::encode::write_value(&mut buffer, &message)?;
+        let stream = self.stream.get_mut();
+        stream.write_all(&buffer)?;
+        stream.flush()?;
+
+        loop {
+            let message = rmpv::decode::read_value(&mut self.stream)
+                .map_err(|e| anyhow!("Failed to read response from {}: {e}", self.address))?;
+
+            match message.as_array().map(Vec::as_slice) {
+                Some([kind, msgid, error, result])
+                    if kind.as_u64() == Some(RESPONSE) && msgid.as_u64() == Some(id.into()) =>
+                {
+                    if error.is_nil() {
+                        return Ok(result.clone());
+                    }
+                    return Err(anyhow!("{method} failed: {}", error_message(error)));
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// 応答を serde で受け取りたい型に変換する
+    pub fn request_as<T: DeserializeOwned>(&mut self, method: &str, args: Vec<Value>) -> Result<T> {
+        let value = self.request(method, args)?;
+        rmpv::ext::from_value(value)
+            .map_err(|e| anyhow!("Unexpected response to {method} from {}: {e}", self.address))
+    }
+
+    pub fn eval(&mut self, expr: &str) -> Result<Value> {
+        self.request("nvim_eval", vec![Value::from(expr)])
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<()> {
+        self.request("nvim_command", vec![Value::from(command)])?;
+        Ok(())
+    }
+
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        self.request(
+            "nvim_call_function",
+            vec![Value::from(name), Value::Array(args)],
+        )
+    }
+
+    pub fn exec_lua(&mut self, code: &str, args: Vec<Value>) -> Result<Value> {
+        self.request("nvim_exec_lua", vec![Value::from(code), Value::Array(args)])
+    }
+
+    /// `:edit` でファイルを開く (パスはエスケープ不要)
+    pub fn open_file(&mut self, path: &str) -> Result<()> {
+        let command = Value::Map(vec![
+            (Value::from("cmd"), Value::from("edit")),
+            (Value::from("args"), Value::Array(vec![Value::from(path)])),
+        ]);
+        self.request("nvim_cmd", vec![command, Value::Map(Vec::new())])?;
+        Ok(())
+    }
+
+    /// 開いているファイルバッファ (一覧に出るもののみ)
+    pub fn list_buffers(&mut self) -> Result<Vec<NvimBuffer>> {
+        let value = self.eval(
+            "map(filter(getbufinfo({'buflisted': 1}), \
+             {_, b -> b.name !=# '' && getbufvar(b.bufnr, '&buftype') ==# ''}), \
+             {_, b -> {'bufnr': b.bufnr, 'name': b.name, 'modified': b.changed ? v:true : v:false}})",
+        )?;
+
+        rmpv::ext::from_value(value)
+            .map_err(|e| anyhow!("Unexpected buffer list from {}: {e}", self.address))
+    }
+
+    /// 接続中の UI (Neovide や `--remote-ui` の端末など)
+    pub fn attached_uis(&mut self) -> Result<Vec<NvimUi>> {
+        self.request_as("nvim_list_uis", Vec::new())
+    }
+}
+
+/// エラーは `[種別, メッセージ]` の形で返る
+fn error_message(error: &Value) -> String {
+    match error.as_array().map(Vec::as_slice) {
+        Some([_, message]) if message.is_str() => message.as_str().unwrap_or_default().to_string(),
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(address: &str, timeout: Duration) -> Result<Box<dyn NvimStream>> {
+    let stream = std::os::unix::net::UnixStream::connect(address)
+        .map_err(|e| anyhow!("Cannot connect to {address}: {e}"))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(address: &str, _timeout: Duration) -> Result<Box<dyn NvimStream>> {
+    Err(anyhow!(
+        "Cannot connect to {address}: Unix sockets are not supported on this platform"
+    ))
+}
+
+#[cfg(windows)]
+fn connect_pipe(address: &str, timeout: Duration) -> Result<Box<dyn NvimStream>> {
+    /// 待ち受けがすべて使用中 (nvim が次の待ち受けを作るまでの間)
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    // 読み書きにはタイムアウトを設定できないので、接続できるまでの待ち時間にだけ使う
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(address)
+        {
+            Ok(pipe) => return Ok(Box::new(pipe)),
+            Err(e)
+                if e.raw_os_error() == Some(ERROR_PIPE_BUSY)
+                    && std::time::Instant::now() < deadline =>
+            {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => return Err(anyhow!("Cannot connect to {address}: {e}")),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn connect_pipe(address: &str, _timeout: Duration) -> Result<Box<dyn NvimStream>> {
+    Err(anyhow!(
+        "Cannot connect to {address}: named pipes are only available on Windows"
+    ))
+}
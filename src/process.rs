@@ -0,0 +1,383 @@
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// プロセスを起動できなかった (実行ファイルがない・権限がないなど)
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to run {}: {error}", program.display())]
+pub struct SpawnError {
+    pub program: PathBuf,
+    pub error: std::io::Error,
+}
+
+impl SpawnError {
+    fn new(spec: &ProcessSpec, error: std::io::Error) -> Self {
+        Self {
+            program: spec.program.clone(),
+            error,
+        }
+    }
+}
+
+/// 起動する外部プロセスの内容
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessSpec {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// 親プロセスから引き継がない環境変数
+    pub env_remove: Vec<String>,
+    /// 標準入出力を引き継ぐ (既定では捨てる)。[`ProcessRunner::output`] では標準エラー出力だけを引き継ぐ
+    /// (fzf のように端末に画面を出しつつ、選んだものを標準出力に書くコマンド用)
+    pub inherit_stdio: bool,
+    /// [`ProcessRunner::output`] で待つ上限。超えたら強制終了してエラーにする
+    pub timeout: Option<Duration>,
+    /// [`ProcessRunner::output`] と [`ProcessRunner::output_async`] で標準入力に書き込む内容 (既定では何も渡さない)
+    pub stdin: Option<Vec<u8>>,
+    /// [`ProcessRunner::spawn`] で標準エラー出力を書き込むファイル (既定では捨てる)。起動するたびに空にする
+    pub stderr_file: Option<PathBuf>,
+}
+
+impl ProcessSpec {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn env_remove(mut self, key: impl Into<String>) -> Self {
+        self.env_remove.push(key.into());
+        self
+    }
+
+    pub fn inherit_stdio(mut self) -> Self {
+        self.inherit_stdio = true;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn stdin(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn stderr_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stderr_file = Some(path.into());
+        self
+    }
+
+    /// ログ表示用のコマンドライン
+    pub fn display(&self) -> String {
+        std::iter::once(self.program.to_string_lossy().to_string())
+            .chain(self.args.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// 出力を集めるときの標準エラー出力
+    fn stderr_stdio(&self) -> Stdio {
+        if self.inherit_stdio {
+            Stdio::inherit()
+        } else {
+            Stdio::piped()
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        for key in &self.env_remove {
+            command.env_remove(key);
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command
+    }
+}
+
+/// 終了まで待ったプロセスの結果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessOutput {
+    /// 終了コード (シグナルで終了した場合は None)
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ProcessOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// バックグラウンドで起動したプロセス
+pub trait ChildProcess: Send {
+    fn id(&self) -> u32;
+
+    /// 終了を待ち、終了コードを返す (シグナルで終了した場合は None)
+    fn wait(&mut self) -> Result<Option<i32>>;
+
+    /// 待たずに終了しているか調べる。終了していれば `Some(終了コード)`
+    fn try_wait(&mut self) -> Result<Option<Option<i32>>>;
+
+    /// 強制終了する
+    fn kill(&mut self) -> Result<()>;
+}
+
+impl ChildProcess for std::process::Child {
+    fn id(&self) -> u32 {
+        std::process::Child::id(self)
+    }
+
+    fn wait(&mut self) -> Result<Option<i32>> {
+        Ok(std::process::Child::wait(self)?.code())
+    }
+
+    fn try_wait(&mut self) -> Result<Option<Option<i32>>> {
+        Ok(std::process::Child::try_wait(self)?.map(|status| status.code()))
+    }
+
+    fn kill(&mut self) -> Result<()> {
+        std::process::Child::kill(self)?;
+        // ゾンビを残さない
+        std::process::Child::wait(self)?;
+        Ok(())
+    }
+}
+
+/// nvim・Neovide・マネージャーなど外部プロセスの起動をまとめたもの
+///
+/// テストでは [`set_runner`] で差し替えて、実際のプロセスを起動せずに済ませる
+pub trait ProcessRunner: Send + Sync {
+    /// 実行して終了を待ち、出力を集める
+    fn output(&self, spec: &ProcessSpec) -> Result<ProcessOutput>;
+
+    /// 待たずに起動する。Windows ではコンソールウィンドウを出さない
+    fn spawn(&self, spec: &ProcessSpec) -> Result<Box<dyn ChildProcess>>;
+
+    /// [`output`](ProcessRunner::output) の非同期版。future を捨てるとプロセスも終了させる
+    ///
+    /// 既定ではその場で `output` を呼ぶ (テスト用のランナーはこれで十分)
+    fn output_async<'a>(&'a self, spec: &'a ProcessSpec) -> OutputFuture<'a> {
+        Box::pin(async move { self.output(spec) })
+    }
+}
+
+pub type OutputFuture<'a> = Pin<Box<dyn Future<Output = Result<ProcessOutput>> + Send + 'a>>;
+
+/// 実際に OS のプロセスを起動する
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl ProcessRunner for SystemRunner {
+    fn output(&self, spec: &ProcessSpec) -> Result<ProcessOutput> {
+        if spec.timeout.is_none() && spec.stdin.is_none() {
+            let output = spec
+                .command()
+                .stdin(Stdio::null())
+                .stderr(spec.stderr_stdio())
+                .output()
+                .map_err(|error| SpawnError::new(spec, error))?;
+
+            return Ok(ProcessOutput {
+                code: output.status.code(),
+                stdout: output.stdout,
+                stderr: output.stderr,
+            });
+        }
+
+        let mut child = spec
+            .command()
+            .stdin(if spec.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(spec.stderr_stdio())
+            .spawn()
+            .map_err(|error| SpawnError::new(spec, error))?;
+
+        // パイプが詰まらないように、終了を待つ間も別スレッドで読み続ける
+        let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut buffer = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buffer);
+                }
+                buffer
+            })
+        };
+        let stdout = read_all(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+        let stderr = read_all(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+        // 読まずに終了するコマンドもあるので、書き込みの失敗は無視する
+        if let (Some(input), Some(mut pipe)) = (spec.stdin.clone(), child.stdin.take()) {
+            std::thread::spawn(move || {
+                let _ = pipe.write_all(&input);
+            });
+        }
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if let Some(timeout) = spec.timeout.filter(|timeout| started.elapsed() >= *timeout) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!("{} timed out after {timeout:?}", spec.display()));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        Ok(ProcessOutput {
+            code: status.code(),
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    fn spawn(&self, spec: &ProcessSpec) -> Result<Box<dyn ChildProcess>> {
+        let mut command = spec.command();
+        if !spec.inherit_stdio {
+            let stderr = match &spec.stderr_file {
+                Some(path) => Stdio::from(
+                    std::fs::File::create(path)
+                        .map_err(|e| anyhow!("Cannot create {}: {e}", path.display()))?,
+                ),
+                None => Stdio::null(),
+            };
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(stderr);
+
+            #[cfg(windows)]
+            {
+                use std::os::windows::process::CommandExt;
+                command.creation_flags(0x08000000);
+            }
+        }
+
+        let child = command
+            .spawn()
+            .map_err(|error| SpawnError::new(spec, error))?;
+        Ok(Box::new(child))
+    }
+
+    fn output_async<'a>(&'a self, spec: &'a ProcessSpec) -> OutputFuture<'a> {
+        Box::pin(async move {
+            use tokio::io::AsyncWriteExt;
+
+            let mut command = tokio::process::Command::from(spec.command());
+            command
+                .stdin(if spec.stdin.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(spec.stderr_stdio())
+                .kill_on_drop(true);
+
+            let mut child = command
+                .spawn()
+                .map_err(|error| SpawnError::new(spec, error))?;
+            // 読まずに終了するコマンドもあるので、書き込みの失敗は無視する
+            if let (Some(input), Some(mut pipe)) = (spec.stdin.clone(), child.stdin.take()) {
+                tokio::spawn(async move {
+                    let _ = pipe.write_all(&input).await;
+                });
+            }
+
+            let output = child.wait_with_output();
+            let output = match spec.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, output)
+                    .await
+                    .map_err(|_| anyhow!("{} timed out after {timeout:?}", spec.display()))?,
+                None => output.await,
+            }
+            .map_err(|error| SpawnError::new(spec, error))?;
+
+            Ok(ProcessOutput {
+                code: output.status.code(),
+                stdout: output.stdout,
+                stderr: output.stderr,
+            })
+        })
+    }
+}
+
+static RUNNER: RwLock<Option<Arc<dyn ProcessRunner>>> = RwLock::new(None);
+
+/// 現在のランナー (差し替えていなければ [`SystemRunner`])
+pub fn runner() -> Arc<dyn ProcessRunner> {
+    RUNNER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(SystemRunner))
+}
+
+/// プロセス全体のランナーを差し替える
+pub fn set_runner(runner: Arc<dyn ProcessRunner>) {
+    *RUNNER.write().unwrap_or_else(|e| e.into_inner()) = Some(runner);
+}
+
+/// 出力を集めて実行する ([`runner`] を使う)
+pub fn output(spec: &ProcessSpec) -> Result<ProcessOutput> {
+    runner().output(spec)
+}
+
+/// 出力を集めて実行する非同期版 ([`runner`] を使う)
+pub async fn output_async(spec: &ProcessSpec) -> Result<ProcessOutput> {
+    let runner = runner();
+    runner.output_async(spec).await
+}
+
+/// バックグラウンドで起動する ([`runner`] を使う)
+pub fn spawn(spec: &ProcessSpec) -> Result<Box<dyn ChildProcess>> {
+    runner().spawn(spec)
+}
+
+/// 標準入出力を引き継いで実行し、終了コードを返す ([`runner`] を使う)
+pub fn status(spec: &ProcessSpec) -> Result<Option<i32>> {
+    runner().spawn(&spec.clone().inherit_stdio())?.wait()
+}
@@ -0,0 +1,193 @@
+//! プロジェクトごとの設定 (`.nvim-manager.toml`)
+//!
+//! launcher が新しく開くプロジェクトのディレクトリ (から git の作業ツリーの先頭まで) にあれば読み、
+//! identifier の決め方・接続に使うフロントエンド・nvim の引数・フック・セッションをそのプロジェクトだけ変える。
+//! フックや nvim の引数で任意のコマンドを実行できるので、初めて読む (内容が変わった) ファイルは
+//! 信頼するか尋ね、信頼した内容のハッシュを状態ディレクトリの `trusted-projects.json` に記録する。
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, LauncherConfig};
+use crate::tmux::TmuxMode;
+use crate::utils;
+
+/// 設定ファイルの名前
+pub const FILE_NAME: &str = ".nvim-manager.toml";
+
+/// identifier の決め方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdentifierStrategy {
+    /// 開く対象のディレクトリ (既定)
+    #[default]
+    Target,
+    /// 開く対象を含む git の作業ツリーの先頭
+    GitRoot,
+}
+
+/// 新しいインスタンスに接続するもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Frontend {
+    /// Neovide などの GUI (`launcher.neovide_command`)
+    Gui,
+    TmuxWindow,
+    TmuxSplit,
+}
+
+impl Frontend {
+    pub fn tmux_mode(self) -> Option<TmuxMode> {
+        match self {
+            Frontend::Gui => None,
+            Frontend::TmuxWindow => Some(TmuxMode::Window),
+            Frontend::TmuxSplit => Some(TmuxMode::Split),
+        }
+    }
+}
+
+/// セッションの扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionMode {
+    /// 何もしない (既定)
+    #[default]
+    None,
+    /// 終了するときに保存し、次に起動するときに読み込む
+    Auto,
+}
+
+/// launcher が実行するシェルコマンド (プロジェクトのディレクトリで、待たずに実行する)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// 新しいインスタンスを起動して登録した後
+    pub on_start: Option<String>,
+    /// 既存のインスタンスにフォーカスした後
+    pub on_attach: Option<String>,
+    /// launcher が起動したインスタンスが終了した後
+    pub on_exit: Option<String>,
+}
+
+/// `.nvim-manager.toml` の内容 (書かなかった項目は全体の設定のまま)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub identifier: IdentifierStrategy,
+    pub frontend: Option<Frontend>,
+    pub neovide_command: Option<String>,
+    pub neovide_args: Option<Vec<String>>,
+    pub direnv: Option<bool>,
+    /// headless の nvim の `NVIM_APPNAME` (空なら全体の設定も使わず launcher の環境のまま)
+    pub nvim_appname: Option<String>,
+    /// headless の nvim に加える引数
+    pub nvim_args: Vec<String>,
+    pub session: SessionMode,
+    pub hooks: Hooks,
+}
+
+/// 見つけた設定ファイル
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub file: PathBuf,
+    /// 設定ファイルのあるディレクトリ (フックの作業ディレクトリ)
+    pub root: PathBuf,
+    pub content: String,
+}
+
+impl Project {
+    pub fn parse(&self) -> Result<ProjectConfig> {
+        toml::from_str(&self.content)
+            .map_err(|e| anyhow!("Invalid project config {}: {e}", self.file.display()))
+    }
+
+    /// この内容を信頼したことがあるか
+    pub fn is_trusted(&self) -> bool {
+        read_trusted()
+            .get(self.file.to_string_lossy().as_ref())
+            .is_some_and(|hash| *hash == utils::stable_hash(self.content.as_bytes()))
+    }
+
+    /// この内容を信頼したことを記録する (内容が変わればまた尋ねる)
+    pub fn trust(&self) -> Result<()> {
+        let path = trusted_path().ok_or_else(|| anyhow!("Cannot determine the state directory"))?;
+        let mut trusted = read_trusted();
+        trusted.insert(
+            self.file.to_string_lossy().into_owned(),
+            utils::stable_hash(self.content.as_bytes()),
+        );
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+        }
+        // 同時に起動した launcher が書きかけを読まないよう、一時ファイルに書いてから置き換える
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string_pretty(&trusted)?)
+            .map_err(|e| anyhow!("Cannot write {}: {e}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))
+    }
+}
+
+impl ProjectConfig {
+    /// GUI・direnv・`NVIM_APPNAME` の設定を上書きする (由来は設定ファイル)
+    pub fn apply(&self, launcher: &mut LauncherConfig, file: &Path) {
+        launcher
+            .neovide_command
+            .apply_file(self.neovide_command.clone(), file);
+        launcher
+            .neovide_args
+            .apply_file(self.neovide_args.clone(), file);
+        launcher.direnv.apply_file(self.direnv, file);
+        launcher
+            .nvim_appname
+            .apply_file(self.nvim_appname.clone().map(config::appname), file);
+    }
+}
+
+/// `dir` から上に向かって設定ファイルを探す。git の作業ツリーの中なら、その先頭より上は見ない
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    let top = utils::git_root(dir).unwrap_or_else(|| dir.to_path_buf());
+    dir.ancestors()
+        .take_while(|ancestor| ancestor.starts_with(&top))
+        .map(|ancestor| ancestor.join(FILE_NAME))
+        .find(|file| file.is_file())
+}
+
+/// `dir` の設定ファイルを読む (信頼するかどうかは見ない)。なければ None
+pub fn load(dir: &Path) -> Result<Option<Project>> {
+    let Some(file) = find(dir) else {
+        return Ok(None);
+    };
+    let content = std::fs::read_to_string(&file)
+        .map_err(|e| anyhow!("Cannot read {}: {e}", file.display()))?;
+    let root = file.parent().unwrap_or(dir).to_path_buf();
+    Ok(Some(Project {
+        file,
+        root,
+        content,
+    }))
+}
+
+/// `session = "auto"` のセッションファイル (状態ディレクトリの `sessions/projects/<identifier のハッシュ>.vim`)
+pub fn session_path(identifier: &str) -> Option<PathBuf> {
+    config::session_dir().map(|dir| {
+        dir.join("projects")
+            .join(format!("{}.vim", utils::stable_hash(identifier.as_bytes())))
+    })
+}
+
+/// 信頼した設定ファイルの記録 (状態ディレクトリの `trusted-projects.json`)
+fn trusted_path() -> Option<PathBuf> {
+    config::state_dir().map(|dir| dir.join("trusted-projects.json"))
+}
+
+/// 設定ファイルのパスから内容のハッシュへの対応。ファイルがない・読めない場合は空
+fn read_trusted() -> BTreeMap<String, String> {
+    trusted_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,58 @@
+//! launcher で開いたプロジェクトの履歴
+//!
+//! マネージャーの `recent_instances` はメモリー上にしかないので、再起動しても残るように
+//! launcher が開くたびに identifier を状態ディレクトリの `recent-projects.json` に記録する。
+//! Windows のジャンプリストはこれから作る。
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::identifier;
+
+/// 残す件数
+pub const MAX_RECENT: usize = 20;
+
+/// 履歴のファイル (状態ディレクトリの `recent-projects.json`)
+pub fn path() -> Option<PathBuf> {
+    config::state_dir().map(|dir| dir.join("recent-projects.json"))
+}
+
+/// 新しい順の identifier。ファイルがない・読めない場合は空
+pub fn load() -> Vec<String> {
+    path().map(|path| read(&path)).unwrap_or_default()
+}
+
+/// `identifier` を先頭に移して保存し、新しい履歴を返す
+///
+/// パスでない identifier (リモートモードのもの) は記録しない
+pub fn record(identifier: &str) -> Result<Vec<String>> {
+    let path = path().ok_or_else(|| anyhow!("Cannot determine the state directory"))?;
+    let mut recent = read(&path);
+    if !identifier::is_path(identifier) {
+        return Ok(recent);
+    }
+
+    recent.retain(|recorded| recorded != identifier);
+    recent.insert(0, identifier.to_string());
+    recent.truncate(MAX_RECENT);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    }
+    // 同時に起動した launcher が書きかけを読まないよう、一時ファイルに書いてから置き換える
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    std::fs::write(&tmp, serde_json::to_string_pretty(&recent)?)
+        .map_err(|e| anyhow!("Cannot write {}: {e}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+
+    Ok(recent)
+}
+
+fn read(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
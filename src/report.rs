@@ -0,0 +1,129 @@
+//! バイナリ共通のエラー表示
+//!
+//! anyhow のエラーを、何をしようとしたか (最上位のメッセージ)・原因・直し方の提案の順に表示する。
+//!
+//! ```text
+//! error: `list` failed
+//!   caused by: Cannot connect to manager at 127.0.0.1:57394
+//!   hint: is the manager running, or is another program using this port? try `neovim-instance-manager-control doctor`
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::errors::ManagerError;
+use crate::process::SpawnError;
+use crate::utils::OpenFileError;
+use crate::InvalidServerAddress;
+
+const CONTROL: &str = "neovim-instance-manager-control";
+
+/// [`fmt::Display`] でエラーを表示する
+pub struct Report<'a>(pub &'a anyhow::Error);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chain = self.0.chain();
+        if let Some(error) = chain.next() {
+            write!(f, "error: {error}")?;
+        }
+        for cause in chain {
+            write!(f, "\n  caused by: {cause}")?;
+        }
+        if let Some(hint) = hint(self.0) {
+            write!(f, "\n  hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 標準エラー出力に表示する
+pub fn eprint(error: &anyhow::Error) {
+    eprintln!("{}", Report(error));
+}
+
+/// 表示して `code` で終了する
+pub fn exit(code: i32, error: &anyhow::Error) -> ! {
+    eprint(error);
+    std::process::exit(code);
+}
+
+/// 原因の連鎖から、最初に見つかった直し方の提案
+pub fn hint(error: &anyhow::Error) -> Option<String> {
+    error.chain().find_map(hint_for)
+}
+
+/// 1 つのエラーに対する直し方の提案
+pub fn hint_for(error: &(dyn Error + 'static)) -> Option<String> {
+    if let Some(error) = error.downcast_ref::<ManagerError>() {
+        return manager_hint(error);
+    }
+
+    if let Some(error) = error.downcast_ref::<OpenFileError>() {
+        return match error {
+            OpenFileError::Unreachable { .. } => Some(format!(
+                "the instance may have exited; `{CONTROL} prune` removes instances that no longer respond"
+            )),
+            _ => None,
+        };
+    }
+
+    if let Some(error) = error.downcast_ref::<SpawnError>() {
+        let program = error.program.display();
+        return match error.error.kind() {
+            io::ErrorKind::NotFound => Some(format!(
+                "`{program}` was not found; install it or add its directory to PATH \
+                 (`{CONTROL} doctor` shows what is found)"
+            )),
+            io::ErrorKind::PermissionDenied => Some(format!(
+                "`{program}` is not executable; check its permissions"
+            )),
+            _ => None,
+        };
+    }
+
+    if error.downcast_ref::<InvalidServerAddress>().is_some() {
+        return Some(
+            r"use host:port (e.g. 127.0.0.1:6666), a Unix socket path or \\.\pipe\<name>"
+                .to_string(),
+        );
+    }
+
+    if let Some(error) = error.downcast_ref::<io::Error>() {
+        return match error.kind() {
+            io::ErrorKind::AddrInUse => Some(
+                "another program is using this address; choose another port with \
+                 NEOVIM_MANAGER_PORT or `manager.port` in config.toml"
+                    .to_string(),
+            ),
+            io::ErrorKind::PermissionDenied => Some(format!(
+                "check the permissions of the file or directory (`{CONTROL} doctor` checks the usual ones)"
+            )),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+fn manager_hint(error: &ManagerError) -> Option<String> {
+    match error {
+        ManagerError::Unreachable(_) => Some(format!(
+            "is the manager running, or is another program using this port? try `{CONTROL} doctor`"
+        )),
+        ManagerError::InstanceNotFound { .. } => {
+            Some(format!("`{CONTROL} list` shows the registered identifiers"))
+        }
+        ManagerError::InstanceAlreadyExists { identifier } => Some(format!(
+            "unregister it first (`{CONTROL} unregister {identifier}`) or choose another identifier"
+        )),
+        ManagerError::HealthCheckFailed { identifier } => Some(format!(
+            "the instance is not responding; `{CONTROL} kill {identifier}` stops it"
+        )),
+        ManagerError::MethodNotFound => Some(format!(
+            "the manager may be older than this client; `{CONTROL} manager restart` replaces it"
+        )),
+        _ => None,
+    }
+}
@@ -0,0 +1,149 @@
+//! 再試行と待機の間隔
+//!
+//! nvim の終了要求・マネージャーの起動待ち・インスタンスの起動待ちなどは、
+//! 固定の sleep で回す代わりに [`Backoff`] で回数・間隔・全体の上限を決める。
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+/// 再試行の方針
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// 最初の試行を含めた試行回数の上限 (None なら `deadline` まで)
+    pub max_attempts: Option<u32>,
+    /// 1 回目の失敗の後に待つ時間
+    pub base_delay: Duration,
+    /// 失敗するたびに間隔に掛ける倍率 (1.0 なら一定間隔)
+    pub multiplier: f64,
+    /// 間隔の上限
+    pub max_delay: Duration,
+    /// 間隔をこの割合までランダムに短くする (0.0 から 1.0)。同時に再試行する複数のプロセスをずらす
+    pub jitter: f64,
+    /// 最初の試行からの全体の上限
+    pub deadline: Option<Duration>,
+}
+
+impl Backoff {
+    /// 一定間隔で再試行する
+    pub const fn fixed(delay: Duration) -> Self {
+        Self {
+            max_attempts: None,
+            base_delay: delay,
+            multiplier: 1.0,
+            max_delay: delay,
+            jitter: 0.0,
+            deadline: None,
+        }
+    }
+
+    /// `base` から倍々にして `max` までの間隔で再試行する
+    pub const fn exponential(base: Duration, max: Duration) -> Self {
+        Self {
+            max_attempts: None,
+            base_delay: base,
+            multiplier: 2.0,
+            max_delay: max,
+            jitter: 0.0,
+            deadline: None,
+        }
+    }
+
+    pub const fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub const fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub const fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// `failures` 回目の失敗の後に待つ時間 (1 始まり)
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self
+            .base_delay
+            .mul_f64(self.multiplier.max(1.0).powi(exponent).min(u32::MAX as f64))
+            .min(self.max_delay);
+
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        // 乱数の crate は使わず、プロセスごとに異なる RandomState のハッシュでばらつかせる
+        let random = RandomState::new().hash_one(failures) as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - self.jitter.min(1.0) * random)
+    }
+
+    /// `op` が Ok を返すまで再試行する。打ち切ったら最後のエラーを返す
+    ///
+    /// `op` には何回目の試行か (1 始まり) を渡す
+    pub async fn retry<T, E, F, Fut>(&self, op: F) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.retry_if(op, |_| true).await
+    }
+
+    /// [`retry`](Backoff::retry) と同じだが、`should_retry` が false を返すエラーはすぐに返す
+    pub async fn retry_if<T, E, F, Fut>(
+        &self,
+        mut op: F,
+        should_retry: impl Fn(&E) -> bool,
+    ) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            match op(attempt).await {
+                Ok(value) => return Ok(value),
+                Err(e) if !should_retry(&e) => return Err(e),
+                Err(e) => match self.next_delay(started, attempt) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(e),
+                },
+            }
+            attempt += 1;
+        }
+    }
+
+    /// `check` が Some を返すまで待つ。打ち切ったら None
+    pub async fn poll<T, F, Fut>(&self, mut check: F) -> Option<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Option<T>>,
+    {
+        self.retry(|_| {
+            let check = check();
+            async move { check.await.ok_or(()) }
+        })
+        .await
+        .ok()
+    }
+
+    /// `attempt` 回目が失敗した後に待つ時間。もう試行しないなら None
+    fn next_delay(&self, started: Instant, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let delay = self.delay(attempt);
+        match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.checked_sub(started.elapsed())?;
+                (!remaining.is_zero()).then(|| delay.min(remaining))
+            }
+            None => Some(delay),
+        }
+    }
+}
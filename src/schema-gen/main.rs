@@ -0,0 +1,33 @@
+//! プロトコルの JSON Schema を書き出す
+//!
+//! 使い方: `neovim-manager-schema [出力ディレクトリ]` (省略時は `schema`)
+
+use neovim_manager::schema::{method_schemas, type_schemas};
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = match std::env::args_os().nth(1) {
+        Some(arg) if arg == "-h" || arg == "--help" => {
+            println!("Usage: neovim-manager-schema [OUT_DIR]");
+            println!("Write a JSON Schema per protocol type and methods.json to OUT_DIR (default: schema)");
+            return Ok(());
+        }
+        Some(arg) => PathBuf::from(arg),
+        None => PathBuf::from("schema"),
+    };
+    std::fs::create_dir_all(&out_dir)?;
+
+    let write = |name: &str, value: &serde_json::Value| -> std::io::Result<()> {
+        let path = out_dir.join(format!("{name}.json"));
+        std::fs::write(&path, serde_json::to_string_pretty(value)? + "\n")?;
+        println!("{}", path.display());
+        Ok(())
+    };
+
+    for (name, schema) in type_schemas() {
+        write(name, schema.as_value())?;
+    }
+    write("methods", &method_schemas())?;
+
+    Ok(())
+}
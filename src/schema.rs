@@ -0,0 +1,111 @@
+//! プロトコルの型の JSON Schema (`schema` feature)
+//!
+//! Rust 以外のクライアント (Lua / TypeScript など) が通信内容を検証できるように書き出す。
+
+use schemars::{schema_for, Schema};
+use serde_json::{json, Value};
+
+use crate::*;
+
+/// 型名とそのスキーマ。`<型名>.json` として書き出す
+pub fn type_schemas() -> Vec<(&'static str, Schema)> {
+    macro_rules! schemas {
+        ($($ty:ident),* $(,)?) => {
+            vec![$((stringify!($ty), schema_for!($ty))),*]
+        };
+    }
+
+    schemas![
+        JsonRpcRequest,
+        JsonRpcResponse,
+        JsonRpcError,
+        HealthStatus,
+        InstanceResult,
+        QueryInstanceParams,
+        ListInstancesParams,
+        WatchInstancesParams,
+        InstancesUpdate,
+        RegisterInstanceParams,
+        UnregisterInstanceParams,
+        RenameInstanceParams,
+        TouchInstanceParams,
+        PinInstanceParams,
+        TagInstanceParams,
+        SetInstanceCwdParams,
+        CheckInstanceParams,
+        CheckInstanceResult,
+        PruneResult,
+        CloseReason,
+        Tombstone,
+        HealthCheckStats,
+        ManagerStatus,
+        RegistrySnapshot,
+        StateMirror,
+        MirroredInstance,
+        SessionManifest,
+        SessionEntry,
+    ]
+}
+
+/// メソッドごとの params と result のスキーマ (`methods.json`)
+///
+/// 型は [`type_schemas`] で書き出したファイルを `$ref` で参照する
+pub fn method_schemas() -> Value {
+    let reference = |name: &str| json!({ "$ref": format!("{name}.json") });
+    let array = |name: &str| json!({ "type": "array", "items": reference(name) });
+    let nullable = |schema: Value| json!({ "anyOf": [schema, { "type": "null" }] });
+    let constant = |values: &[&str]| json!({ "type": "string", "enum": values });
+    let none = json!({ "type": ["object", "null"] });
+
+    json!({
+        "query_instance": {
+            "params": reference("QueryInstanceParams"),
+            "result": nullable(reference("InstanceResult")),
+        },
+        "list_instances": {
+            "params": nullable(reference("ListInstancesParams")),
+            "result": array("InstanceResult"),
+        },
+        "watch_instances": {
+            "params": nullable(reference("WatchInstancesParams")),
+            "result": reference("InstancesUpdate"),
+        },
+        "prune_instances": { "params": none, "result": reference("PruneResult") },
+        "register_instance": {
+            "params": reference("RegisterInstanceParams"),
+            "result": constant(&["registered"]),
+        },
+        "unregister_instance": {
+            "params": reference("UnregisterInstanceParams"),
+            "result": constant(&["unregistered"]),
+        },
+        "rename_instance": {
+            "params": reference("RenameInstanceParams"),
+            "result": constant(&["renamed"]),
+        },
+        "touch_instance": {
+            "params": reference("TouchInstanceParams"),
+            "result": constant(&["touched"]),
+        },
+        "pin_instance": {
+            "params": reference("PinInstanceParams"),
+            "result": constant(&["pinned", "unpinned"]),
+        },
+        "tag_instance": {
+            "params": reference("TagInstanceParams"),
+            "result": constant(&["tagged", "untagged"]),
+        },
+        "set_instance_cwd": {
+            "params": reference("SetInstanceCwdParams"),
+            "result": constant(&["updated"]),
+        },
+        "check_instance": {
+            "params": reference("CheckInstanceParams"),
+            "result": reference("CheckInstanceResult"),
+        },
+        "recent_instances": { "params": none, "result": array("Tombstone") },
+        "ping": { "params": none, "result": constant(&["pong"]) },
+        "status": { "params": none, "result": reference("ManagerStatus") },
+        "shutdown": { "params": none, "result": constant(&["shutting_down"]) },
+    })
+}
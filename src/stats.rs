@@ -0,0 +1,147 @@
+//! 使用状況の統計 (`launcher.stats = true` のときだけ記録する)
+//!
+//! launcher が既存のインスタンスに接続した・新しく起動した・起動したインスタンスが終了した、を
+//! 状態ディレクトリの `stats.jsonl` に 1 行ずつ追記する。手元に残すだけで、どこにも送らない。
+//! `neovim-control stats` が identifier ごとに集計して表示する。
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config;
+
+/// 記録する出来事
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// 新しいインスタンスを起動した
+    Create,
+    /// 既存のインスタンスに接続した (ファイルを開いた)
+    Attach,
+    /// launcher が起動したインスタンスが終了した (`duration_secs` は起動からの時間)
+    Exit,
+}
+
+/// `stats.jsonl` の 1 行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    pub identifier: String,
+    pub kind: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+}
+
+/// identifier ごとの集計
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProjectStats {
+    pub identifier: String,
+    /// 起動と接続を合わせた回数
+    pub launches: u64,
+    pub creates: u64,
+    pub attaches: u64,
+    /// 終了まで記録できたセッションの数と、その長さの合計
+    pub sessions: u64,
+    pub total_duration_secs: u64,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ProjectStats {
+    /// 起動と接続のうち、接続の割合 (0.0〜1.0)
+    pub fn attach_ratio(&self) -> f64 {
+        if self.launches == 0 {
+            0.0
+        } else {
+            self.attaches as f64 / self.launches as f64
+        }
+    }
+}
+
+/// 記録のファイル (状態ディレクトリの `stats.jsonl`)
+pub fn path() -> Option<PathBuf> {
+    config::state_dir().map(|dir| dir.join("stats.jsonl"))
+}
+
+/// 1 行追記する
+///
+/// 同時に動く launcher の行が混ざらないよう、追記モードで 1 行を 1 回で書く
+pub fn record(identifier: &str, kind: EventKind, duration: Option<Duration>) -> Result<()> {
+    let path = path().ok_or_else(|| anyhow!("Cannot determine the state directory"))?;
+    let event = Event {
+        at: Utc::now(),
+        identifier: identifier.to_string(),
+        kind,
+        duration_secs: duration.map(|duration| duration.as_secs()),
+    };
+    let mut line = serde_json::to_string(&event)?;
+    line.push('\n');
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Cannot create {}: {e}", dir.display()))?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))
+}
+
+/// `since` 以降の記録。ファイルがなければ空、読めない行は飛ばす
+pub fn load(since: Option<DateTime<Utc>>) -> Result<Vec<Event>> {
+    let Some(path) = path() else {
+        return Ok(Vec::new());
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Cannot read {}: {e}", path.display())),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Event>(line).ok())
+        .filter(|event| since.is_none_or(|since| event.at >= since))
+        .collect())
+}
+
+/// identifier ごとに集計する (起動と接続の多い順、同じなら使った時間の長い順)
+pub fn summarize(events: &[Event]) -> Vec<ProjectStats> {
+    let mut projects: HashMap<&str, ProjectStats> = HashMap::new();
+    for event in events {
+        let stats = projects
+            .entry(&event.identifier)
+            .or_insert_with(|| ProjectStats {
+                identifier: event.identifier.clone(),
+                ..ProjectStats::default()
+            });
+        match event.kind {
+            EventKind::Create => {
+                stats.creates += 1;
+                stats.launches += 1;
+            }
+            EventKind::Attach => {
+                stats.attaches += 1;
+                stats.launches += 1;
+            }
+            EventKind::Exit => {
+                stats.sessions += 1;
+                stats.total_duration_secs += event.duration_secs.unwrap_or(0);
+            }
+        }
+        stats.last_used = stats.last_used.max(Some(event.at));
+    }
+
+    let mut projects: Vec<_> = projects.into_values().collect();
+    projects.sort_by(|a, b| {
+        b.launches
+            .cmp(&a.launches)
+            .then(b.total_duration_secs.cmp(&a.total_duration_secs))
+            .then_with(|| a.identifier.cmp(&b.identifier))
+    });
+    projects
+}
@@ -0,0 +1,163 @@
+//! tmux の中でインスタンスに接続する
+//!
+//! GUI の代わりに、tmux の新しいウィンドウまたはペインで `nvim --server <addr> --remote-ui` を実行する。
+//! 作ったウィンドウ・ペインにはユーザーオプション `@neovim-manager-identifier` で identifier を記録しておき、
+//! 同じ identifier を開くときは新しく作らずにそこへ移る。
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::process::{self, ProcessSpec};
+use crate::{tunnel, utils};
+
+/// ウィンドウ・ペインに identifier を記録するユーザーオプション
+const IDENTIFIER_OPTION: &str = "@neovim-manager-identifier";
+
+/// 接続をどこに開くか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TmuxMode {
+    /// 新しいウィンドウ (`tmux new-window`)
+    Window,
+    /// 現在のウィンドウを分割したペイン (`tmux split-window`)
+    Split,
+}
+
+impl TmuxMode {
+    pub const ALL: [TmuxMode; 2] = [TmuxMode::Window, TmuxMode::Split];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TmuxMode::Window => "window",
+            TmuxMode::Split => "split",
+        }
+    }
+}
+
+impl fmt::Display for TmuxMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TmuxMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        TmuxMode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = TmuxMode::ALL.iter().map(|mode| mode.name()).collect();
+                format!(
+                    "unknown tmux mode '{s}' (expected one of: {})",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// tmux のセッションの中で動いているか
+pub fn in_tmux() -> bool {
+    std::env::var_os("TMUX").is_some_and(|value| !value.is_empty())
+}
+
+/// ウィンドウ・ペインの名前 (identifier の最後の要素。`/home/me/project` なら `project`)
+pub fn window_name(identifier: &str) -> String {
+    identifier
+        .trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\'])
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(identifier)
+        .to_string()
+}
+
+/// `identifier` の接続を開く。既に開いているウィンドウ・ペインがあればそこへ移る
+///
+/// 新しく作った場合は true
+pub fn open(mode: TmuxMode, identifier: &str, server_address: &str) -> Result<bool> {
+    if !in_tmux() {
+        return Err(anyhow!(
+            "Not running inside tmux (TMUX is not set); run this from a tmux session or drop --tmux"
+        ));
+    }
+
+    if let Some(pane) = find_pane(identifier)? {
+        // 別のセッションにある場合だけ switch-client が必要になる。同じセッションなら失敗しても構わない
+        let _ = tmux(&["switch-client", "-t", &pane.pane_id]);
+        tmux(&["select-window", "-t", &pane.window_id])?;
+        tmux(&["select-pane", "-t", &pane.pane_id])?;
+        return Ok(false);
+    }
+
+    // nvim がすぐに終了してもウィンドウが消える前に記録できるよう、1 回の tmux の呼び出しで続けて実行する
+    let name = window_name(identifier);
+    let mut args = match mode {
+        TmuxMode::Window => vec!["new-window", "-n", &name],
+        TmuxMode::Split => vec!["split-window"],
+    };
+    let server_address = tunnel::local_address(server_address)?;
+    args.extend([
+        "--",
+        "nvim",
+        "--server",
+        &server_address,
+        "--remote-ui",
+        ";",
+    ]);
+    match mode {
+        TmuxMode::Window => args.extend(["set-option", "-w", IDENTIFIER_OPTION, identifier]),
+        TmuxMode::Split => args.extend([
+            "set-option",
+            "-p",
+            IDENTIFIER_OPTION,
+            identifier,
+            ";",
+            "select-pane",
+            "-T",
+            &name,
+        ]),
+    }
+    tmux(&args)?;
+
+    Ok(true)
+}
+
+struct Pane {
+    pane_id: String,
+    window_id: String,
+}
+
+/// `identifier` を記録したペインを探す (ウィンドウに記録したものはその中のペインすべてが該当する)
+fn find_pane(identifier: &str) -> Result<Option<Pane>> {
+    let format = format!("#{{pane_id}}\t#{{window_id}}\t#{{{IDENTIFIER_OPTION}}}");
+    let panes = tmux(&["list-panes", "-a", "-F", &format])?;
+
+    Ok(panes.lines().find_map(|line| {
+        let mut fields = line.splitn(3, '\t');
+        let (pane_id, window_id, tagged) = (fields.next()?, fields.next()?, fields.next()?);
+        (tagged == identifier).then(|| Pane {
+            pane_id: pane_id.to_string(),
+            window_id: window_id.to_string(),
+        })
+    }))
+}
+
+/// tmux を実行して標準出力を返す
+fn tmux(args: &[&str]) -> Result<String> {
+    let output = process::output(
+        &ProcessSpec::new("tmux")
+            .args(args.iter().copied())
+            .timeout(utils::NVIM_REMOTE_TIMEOUT),
+    )?;
+    if !output.success() {
+        return Err(anyhow!(
+            "tmux {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,317 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use log::{info, warn};
+use neovim_manager::client::{InstanceWatcher, ManagerClient};
+use neovim_manager::config::Config;
+use neovim_manager::gui::GuiCommand;
+use neovim_manager::{duration, report, utils, InstanceResult};
+use std::collections::HashMap;
+use std::time::Duration;
+use tao::event::{Event, StartCause};
+use tao::event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy};
+use tokio::sync::mpsc;
+use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem, Submenu};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+/// アイコンの一辺 (ピクセル)
+const ICON_SIZE: u32 = 32;
+
+#[derive(Parser)]
+#[command(name = "neovim-manager-tray")]
+#[command(about = "System tray icon listing the Neovim instances")]
+struct Cli {
+    #[arg(
+        long,
+        default_value = "2s",
+        value_parser = duration::parse,
+        help = "How long to wait before retrying when the manager cannot be reached, \
+                or how often to refresh when it is too old to report changes (e.g. 2s, 500ms)"
+    )]
+    interval: Duration,
+}
+
+/// イベントループに送るもの
+enum UserEvent {
+    /// 一覧を取得した (マネージャーに接続できなければ Err)
+    Instances(Result<Vec<InstanceResult>, String>),
+    Menu(MenuEvent),
+}
+
+/// メニューから選ばれた操作 (非同期側で実行する)
+#[derive(Debug, Clone)]
+enum Action {
+    Focus(InstanceResult),
+    Open(InstanceResult),
+    Quit(InstanceResult),
+    QuitAll,
+}
+
+/// 現在のメニューと、項目から操作への対応
+struct TrayMenu {
+    menu: Menu,
+    actions: HashMap<MenuId, Action>,
+    quit_tray: MenuId,
+}
+
+impl TrayMenu {
+    fn build(instances: &Result<Vec<InstanceResult>, String>) -> Result<Self> {
+        let menu = Menu::new();
+        let mut actions = HashMap::new();
+
+        match instances {
+            Ok(instances) => {
+                menu.append(&MenuItem::new(
+                    format!("Neovim instances: {}", instances.len()),
+                    false,
+                    None,
+                ))?;
+                menu.append(&PredefinedMenuItem::separator())?;
+
+                for instance in instances {
+                    let label = if instance.health_status.is_healthy() {
+                        instance.identifier.clone()
+                    } else {
+                        format!("{} (not responding)", instance.identifier)
+                    };
+                    let submenu = Submenu::new(label, true);
+                    for (text, action) in [
+                        ("Focus", Action::Focus(instance.clone())),
+                        ("Open in a new window", Action::Open(instance.clone())),
+                        ("Quit", Action::Quit(instance.clone())),
+                    ] {
+                        let item = MenuItem::new(text, true, None);
+                        actions.insert(item.id().clone(), action);
+                        submenu.append(&item)?;
+                    }
+                    menu.append(&submenu)?;
+                }
+
+                menu.append(&PredefinedMenuItem::separator())?;
+                let quit_all = MenuItem::new("Quit all instances", !instances.is_empty(), None);
+                actions.insert(quit_all.id().clone(), Action::QuitAll);
+                menu.append(&quit_all)?;
+            }
+            Err(e) => {
+                menu.append(&MenuItem::new("Manager is not running", false, None))?;
+                menu.append(&MenuItem::new(e, false, None))?;
+            }
+        }
+
+        menu.append(&PredefinedMenuItem::separator())?;
+        let quit_tray = MenuItem::new("Quit tray", true, None);
+        menu.append(&quit_tray)?;
+
+        Ok(Self {
+            menu,
+            actions,
+            quit_tray: quit_tray.id().clone(),
+        })
+    }
+}
+
+/// 一覧のうちメニューに出す部分 (変わったときだけメニューを作り直す)
+fn menu_key(
+    instances: &Result<Vec<InstanceResult>, String>,
+) -> Result<Vec<(String, bool)>, String> {
+    instances.as_ref().map_err(Clone::clone).map(|instances| {
+        instances
+            .iter()
+            .map(|instance| {
+                (
+                    instance.identifier.clone(),
+                    instance.health_status.is_healthy(),
+                )
+            })
+            .collect()
+    })
+}
+
+/// 丸いアイコン。インスタンスがあれば Neovim の緑、なければ灰色
+fn icon(active: bool) -> Result<Icon> {
+    let color = if active {
+        [0x57, 0xa1, 0x43]
+    } else {
+        [0x80, 0x80, 0x80]
+    };
+    let center = (ICON_SIZE as f64 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f64 / 2.0 - 1.0;
+
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f64 - center).hypot(y as f64 - center);
+            let alpha = if distance <= radius { 0xff } else { 0 };
+            rgba.extend(color);
+            rgba.push(alpha);
+        }
+    }
+
+    Ok(Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)?)
+}
+
+fn tooltip(instances: &Result<Vec<InstanceResult>, String>) -> String {
+    match instances {
+        Ok(instances) => format!("Neovim: {} instance(s)", instances.len()),
+        Err(_) => "Neovim: manager is not running".to_string(),
+    }
+}
+
+/// 一覧の取得とメニューの操作を行う (イベントループとは別のスレッドで動かす)
+async fn worker(
+    config: Config,
+    interval: Duration,
+    proxy: EventLoopProxy<UserEvent>,
+    mut actions: mpsc::UnboundedReceiver<Action>,
+) {
+    let client = ManagerClient::new(&config);
+    let mut watcher = InstanceWatcher::new(client.clone(), interval);
+
+    loop {
+        let instances = tokio::select! {
+            instances = watcher.next() => Some(instances),
+            action = actions.recv() => {
+                let Some(action) = action else {
+                    return;
+                };
+                if let Err(e) = perform(&client, &config, action).await {
+                    warn!("{e:#}");
+                }
+                None
+            }
+        };
+
+        match instances {
+            Some(instances) => {
+                let instances = instances.map_err(|e| format!("{e:#}"));
+                if proxy.send_event(UserEvent::Instances(instances)).is_err() {
+                    return;
+                }
+            }
+            // 操作の結果をすぐに反映する
+            None => watcher.refresh(),
+        }
+    }
+}
+
+async fn perform(client: &ManagerClient, config: &Config, action: Action) -> Result<()> {
+    match action {
+        Action::Focus(instance) => {
+            info!("Focusing {}", instance.identifier);
+            utils::focus_nvim_instance(&instance.server_address).await?;
+            client.touch(&instance.identifier).await?;
+        }
+        Action::Open(instance) => {
+            info!("Opening a GUI for {}", instance.identifier);
+            GuiCommand::resolve(&config.launcher).spawn(&instance.server_address)?;
+            client.touch(&instance.identifier).await?;
+        }
+        Action::Quit(instance) => quit(client, &instance).await?,
+        Action::QuitAll => {
+            for instance in client.list(None).await? {
+                if let Err(e) = quit(client, &instance).await {
+                    warn!("{e:#}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 終了させて登録を解除する。未保存の変更がある場合は終了させない
+async fn quit(client: &ManagerClient, instance: &InstanceResult) -> Result<()> {
+    info!("Quitting {}", instance.identifier);
+    if !utils::quit_all_nvim_instance(&instance.server_address, false).await? {
+        return Err(anyhow!(
+            "Failed to quit {} (unsaved changes?)",
+            instance.identifier
+        ));
+    }
+    // 次のヘルスチェックを待たずに登録も解除しておく
+    client.unregister(&instance.identifier).await
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = run(Cli::parse()) {
+        report::exit(1, &e.context("The tray stopped with an error"));
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let config = Config::load()?;
+
+    // GUI のイベントループはメインスレッドで回す必要があるので、tokio は別のスレッドで動かす
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+    let (action_tx, action_rx) = mpsc::unbounded_channel();
+
+    let proxy = event_loop.create_proxy();
+    MenuEvent::set_event_handler(Some(move |event| {
+        let _ = proxy.send_event(UserEvent::Menu(event));
+    }));
+
+    let proxy = event_loop.create_proxy();
+    let runtime = tokio::runtime::Runtime::new()?;
+    std::thread::spawn(move || runtime.block_on(worker(config, cli.interval, proxy, action_rx)));
+
+    let initial: Result<Vec<InstanceResult>, String> = Ok(Vec::new());
+    let mut tray: Option<TrayIcon> = None;
+    let mut menu = TrayMenu::build(&initial)?;
+    let mut shown = menu_key(&initial);
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+
+        match event {
+            // macOS ではイベントループが始まってからでないとアイコンを作れない
+            Event::NewEvents(StartCause::Init) => {
+                let built = icon(false).and_then(|icon| {
+                    Ok(TrayIconBuilder::new()
+                        .with_menu(Box::new(menu.menu.clone()))
+                        .with_tooltip(tooltip(&initial))
+                        .with_icon(icon)
+                        .build()?)
+                });
+                match built {
+                    Ok(built) => tray = Some(built),
+                    Err(e) => report::exit(1, &e.context("Cannot create the tray icon")),
+                }
+            }
+            Event::UserEvent(UserEvent::Instances(instances)) => {
+                let key = menu_key(&instances);
+                if key == shown {
+                    return;
+                }
+                let Some(tray) = &tray else {
+                    return;
+                };
+
+                match TrayMenu::build(&instances) {
+                    Ok(rebuilt) => {
+                        tray.set_menu(Some(Box::new(rebuilt.menu.clone())));
+                        menu = rebuilt;
+                    }
+                    Err(e) => warn!("Cannot rebuild the tray menu: {e:#}"),
+                }
+                let active = instances
+                    .as_ref()
+                    .is_ok_and(|instances| !instances.is_empty());
+                if let Err(e) = icon(active).and_then(|icon| Ok(tray.set_icon(Some(icon))?)) {
+                    warn!("Cannot update the tray icon: {e:#}");
+                }
+                let _ = tray.set_tooltip(Some(tooltip(&instances)));
+                shown = key;
+            }
+            Event::UserEvent(UserEvent::Menu(event)) => {
+                if event.id == menu.quit_tray {
+                    tray = None;
+                    *control_flow = ControlFlow::Exit;
+                } else if let Some(action) = menu.actions.get(&event.id) {
+                    let _ = action_tx.send(action.clone());
+                }
+            }
+            _ => {}
+        }
+    })
+}
@@ -0,0 +1,194 @@
+//! `ssh://` のアドレスのインスタンスに接続するための SSH のローカルフォワード
+//!
+//! 接続するときに `ssh -N -L <ローカル>:<リモート>` を起動し、以降はそのフォワードを使い回す。
+//! どのプロセスから接続しても同じフォワードを使えるよう、アドレスごとの状態を
+//! ランタイムディレクトリの `tunnels/` に置く。インスタンスが登録から外れたらマネージャーが閉じる。
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::process::{self, ProcessSpec};
+use crate::utils;
+use crate::ServerAddress;
+
+/// フォワードが使えるようになるまで待つ上限 (SSH の接続と認証を含む)
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 使えるか調べるときの接続のタイムアウト
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 起動したフォワード (`tunnels/<key>.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Tunnel {
+    server_address: String,
+    local_address: String,
+    pid: u32,
+}
+
+/// 状態を置くディレクトリ
+pub fn dir() -> PathBuf {
+    config::runtime_dir().join("tunnels")
+}
+
+/// アドレスごとのファイル名
+fn key(server_address: &str) -> String {
+    utils::stable_hash(server_address.as_bytes())
+}
+
+fn state_path(server_address: &str) -> PathBuf {
+    dir().join(format!("{}.json", key(server_address)))
+}
+
+fn read_state(server_address: &str) -> Option<Tunnel> {
+    let content = std::fs::read_to_string(state_path(server_address)).ok()?;
+    serde_json::from_str(&content)
+        .ok()
+        .filter(|tunnel: &Tunnel| tunnel.server_address == server_address)
+}
+
+/// ローカル側のアドレス
+///
+/// Unix ではアドレスごとに決まったソケットにする (同時に起動しても片方の ssh が失敗するだけで済む)。
+/// Windows と、Windows の GUI から接続する WSL では空いている TCP ポート
+fn new_local_address(server_address: &str) -> Result<ServerAddress> {
+    if cfg!(unix) && !crate::wsl::is_wsl() {
+        let path = dir().join(format!("{}.sock", key(server_address)));
+        return Ok(ServerAddress::Unix(path.to_string_lossy().into_owned()));
+    }
+    Ok(ServerAddress::tcp("127.0.0.1", utils::get_random_port()?))
+}
+
+/// ローカル側に接続できるか (接続するとフォワードの先まで届くが、すぐ閉じるので nvim には影響しない)
+fn is_reachable(local_address: &str) -> bool {
+    match local_address.parse() {
+        Ok(ServerAddress::Tcp(address)) => address
+            .parse()
+            .is_ok_and(|addr| std::net::TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok()),
+        #[cfg(unix)]
+        Ok(ServerAddress::Unix(path)) => std::os::unix::net::UnixStream::connect(path).is_ok(),
+        _ => false,
+    }
+}
+
+/// 接続に使うアドレス。`ssh://` ならフォワードを (なければ起動して) 通したローカルのアドレス、
+/// それ以外はそのまま
+pub fn local_address(server_address: &str) -> Result<String> {
+    let Ok(address @ ServerAddress::Ssh(_)) = server_address.parse::<ServerAddress>() else {
+        return Ok(server_address.to_string());
+    };
+    let target = address
+        .ssh_target()
+        .ok_or_else(|| anyhow!("Invalid SSH address {server_address}"))?;
+
+    if let Some(tunnel) = read_state(server_address) {
+        if is_reachable(&tunnel.local_address) {
+            return Ok(tunnel.local_address);
+        }
+    }
+    // 残っていた古いフォワード (ssh が終了した・接続が切れた) は片付けてから作り直す
+    close(server_address)?;
+
+    let local_address = new_local_address(server_address)?;
+    std::fs::create_dir_all(dir())
+        .map_err(|e| anyhow!("Cannot create {}: {e}", dir().display()))?;
+    // 接続できないソケットファイルが残っていると ssh が listen できない
+    if let ServerAddress::Unix(socket) = &local_address {
+        let _ = std::fs::remove_file(socket);
+    }
+
+    let forward = match &local_address {
+        ServerAddress::Tcp(_) => format!(
+            "{}:{}:{}",
+            local_address.host().unwrap_or("127.0.0.1"),
+            local_address.port().unwrap_or_default(),
+            target.remote
+        ),
+        _ => format!("{local_address}:{}", target.remote),
+    };
+    let mut spec = ProcessSpec::new("ssh").args([
+        "-N",
+        // 端末がないので、パスワードなどを尋ねずに失敗させる
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        "ExitOnForwardFailure=yes",
+        "-o",
+        "ServerAliveInterval=30",
+        "-L",
+        &forward,
+    ]);
+    if let Some(port) = target.port {
+        spec = spec.args(["-p".to_string(), port.to_string()]);
+    }
+    let mut child = process::spawn(&spec.arg(&target.destination))?;
+    let tunnel = Tunnel {
+        server_address: server_address.to_string(),
+        local_address: local_address.to_string(),
+        pid: child.id(),
+    };
+
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        if is_reachable(&tunnel.local_address) {
+            break;
+        }
+        if let Some(code) = child.try_wait()? {
+            // 同時に起動した別のプロセスのフォワードが先にソケットを作っていれば、それを使う
+            if is_reachable(&tunnel.local_address) {
+                return Ok(tunnel.local_address);
+            }
+            return Err(anyhow!(
+                "ssh exited with {code:?} before forwarding {} to {} \
+                 (check that `ssh {}` works without a password prompt)",
+                target.remote,
+                target.destination,
+                target.destination
+            ));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            return Err(anyhow!(
+                "ssh did not forward {} from {} within {CONNECT_TIMEOUT:?}",
+                target.remote,
+                target.destination
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let path = state_path(server_address);
+    std::fs::write(&path, serde_json::to_string(&tunnel)?)
+        .map_err(|e| anyhow!("Cannot write {}: {e}", path.display()))?;
+
+    Ok(tunnel.local_address)
+}
+
+/// [`local_address`] の非同期版。`ssh://` のときだけ別スレッドで待つ
+pub async fn local_address_async(server_address: &str) -> Result<String> {
+    if !matches!(server_address.parse(), Ok(ServerAddress::Ssh(_))) {
+        return Ok(server_address.to_string());
+    }
+    let server_address = server_address.to_string();
+    tokio::task::spawn_blocking(move || local_address(&server_address)).await?
+}
+
+/// `server_address` のフォワードがあれば ssh を止めて片付ける
+pub fn close(server_address: &str) -> Result<()> {
+    let path = state_path(server_address);
+    let Some(tunnel) = read_state(server_address) else {
+        let _ = std::fs::remove_file(&path);
+        return Ok(());
+    };
+
+    if utils::process_exists(tunnel.pid) != Some(false) {
+        // 既に終了していれば失敗するだけなので無視する
+        let _ = utils::kill_process(tunnel.pid);
+    }
+    if let Ok(ServerAddress::Unix(socket)) = tunnel.local_address.parse() {
+        let _ = std::fs::remove_file(socket);
+    }
+    std::fs::remove_file(&path).map_err(|e| anyhow!("Cannot remove {}: {e}", path.display()))
+}
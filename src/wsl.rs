@@ -0,0 +1,162 @@
+//! WSL (Windows Subsystem for Linux) との相互運用
+//!
+//! WSL では Windows 版の Neovide (`neovide.exe`) から WSL 内の nvim に接続する。
+//! Windows 側のプログラムは Unix ソケットに接続できず、WSL2 の NAT モードでは
+//! localhost の転送を経由することになるので、接続先のアドレスをここで確かめる。
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::process::{self, ProcessSpec};
+use crate::utils;
+use crate::ServerAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WslVersion {
+    Wsl1,
+    Wsl2,
+}
+
+/// Windows と WSL の間のネットワーク
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkingMode {
+    /// WSL1: Windows とネットワークを共有する
+    Shared,
+    /// WSL2 の既定: 別の仮想ネットワーク。Windows の localhost は WSL に転送される
+    Nat,
+    /// WSL2 の `networkingMode=mirrored`: Windows と同じアドレスを使う
+    Mirrored,
+}
+
+impl fmt::Display for WslVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WslVersion::Wsl1 => write!(f, "WSL1"),
+            WslVersion::Wsl2 => write!(f, "WSL2"),
+        }
+    }
+}
+
+impl fmt::Display for NetworkingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkingMode::Shared => write!(f, "shared"),
+            NetworkingMode::Nat => write!(f, "NAT"),
+            NetworkingMode::Mirrored => write!(f, "mirrored"),
+        }
+    }
+}
+
+pub fn is_wsl() -> bool {
+    version().is_some()
+}
+
+/// WSL の中で動いていればそのバージョン
+pub fn version() -> Option<WslVersion> {
+    let kernel = std::fs::read_to_string("/proc/version")
+        .unwrap_or_default()
+        .to_lowercase();
+
+    // WSL2 のカーネルは `microsoft-standard-WSL2`、WSL1 は `Microsoft` を含む
+    if kernel.contains("wsl2") {
+        Some(WslVersion::Wsl2)
+    } else if kernel.contains("microsoft") {
+        Some(WslVersion::Wsl1)
+    } else if std::env::var_os("WSL_DISTRO_NAME").is_some() {
+        // カーネルから判別できない場合は現在の既定の WSL2 とみなす
+        Some(WslVersion::Wsl2)
+    } else {
+        None
+    }
+}
+
+/// WSL の中で動いていればネットワークのモード
+///
+/// WSL2 は `wslinfo --networking-mode` で調べる (古い WSL にはないので NAT とみなす)
+pub fn networking_mode() -> Option<NetworkingMode> {
+    match version()? {
+        WslVersion::Wsl1 => Some(NetworkingMode::Shared),
+        WslVersion::Wsl2 => {
+            let mirrored = process::output(
+                &ProcessSpec::new("wslinfo")
+                    .arg("--networking-mode")
+                    .timeout(utils::NVIM_REMOTE_TIMEOUT),
+            )
+            .is_ok_and(|output| {
+                output.success() && String::from_utf8_lossy(&output.stdout).trim() == "mirrored"
+            });
+            Some(if mirrored {
+                NetworkingMode::Mirrored
+            } else {
+                NetworkingMode::Nat
+            })
+        }
+    }
+}
+
+/// WSL のパスを Windows のパスにする (`wslpath -w`)
+pub fn to_windows_path(path: &Path) -> Result<String> {
+    wslpath("-w", &path.to_string_lossy())
+}
+
+/// Windows のパス (`C:\Users\...`) を WSL のパスにする (`wslpath -u`)
+pub fn to_wsl_path(path: &str) -> Result<PathBuf> {
+    wslpath("-u", path).map(PathBuf::from)
+}
+
+fn wslpath(flag: &str, path: &str) -> Result<String> {
+    let output = process::output(&ProcessSpec::new("wslpath").args([flag, path]))?;
+    if !output.success() {
+        return Err(anyhow!(
+            "wslpath {flag} {path} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// WSL 内の nvim のアドレスを、Windows 側の GUI が接続できるアドレスにする
+///
+/// - Unix ソケットには Windows 側から接続できないのでエラー
+/// - `0.0.0.0` / `[::]` では接続できないので localhost にする
+/// - NAT モードでは Windows の localhost が WSL に転送されるので、localhost のままでよい
+pub fn windows_gui_address(server_address: &str) -> Result<String> {
+    let address: ServerAddress = server_address.parse()?;
+    let (Some(host), Some(port)) = (address.host(), address.port()) else {
+        return Err(anyhow!(
+            "Windows programs cannot connect to the Unix socket {server_address}; \
+             start nvim with a TCP address such as 127.0.0.1:<port>"
+        ));
+    };
+
+    Ok(match host {
+        "0.0.0.0" | "::" => ServerAddress::tcp("127.0.0.1", port).into(),
+        _ => address.into(),
+    })
+}
+
+/// WSL 内から接続できないアドレス (Windows の名前付きパイプ・ドライブ上のパス) ならエラー
+pub fn check_reachable_from_wsl(server_address: &str) -> Result<()> {
+    if let Ok(ServerAddress::WindowsPipe(_)) = server_address.parse() {
+        return Err(anyhow!(
+            "{server_address} is a Windows named pipe, which cannot be reached from WSL; \
+             start the Windows nvim with --listen 127.0.0.1:<port> instead"
+        ));
+    }
+
+    let bytes = server_address.as_bytes();
+    if bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/')
+    {
+        return Err(anyhow!(
+            "{server_address} is a Windows path; use the WSL path (see `wslpath -u`) \
+             or a TCP address"
+        ));
+    }
+
+    Ok(())
+}
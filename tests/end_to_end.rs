@@ -0,0 +1,1035 @@
+//! マネージャー・control・ランチャーを偽 nvim (tests/fixtures/fake_nvim.rs) で動かす結合テスト
+//!
+//! テストごとに一時ディレクトリ・ポート・マネージャーを用意するので、並列に実行できる。
+
+use neovim_manager::client::ManagerClient;
+use neovim_manager::config::Config;
+use neovim_manager::{manager, InstanceResult, StateMirror};
+use std::ffi::OsString;
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
+
+const FAKE_NVIM: &str = env!("CARGO_BIN_EXE_fake-nvim");
+const MANAGER: &str = env!("CARGO_BIN_EXE_neovim-instance-manager");
+const CONTROL: &str = env!("CARGO_BIN_EXE_neovim-instance-manager-control");
+const LAUNCHER: &str = env!("CARGO_BIN_EXE_neovim-launcher");
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("cannot allocate a port")
+        .port()
+}
+
+/// `condition` が Some を返すまで待つ
+fn wait_for<T>(what: &str, mut condition: impl FnMut() -> Option<T>) -> T {
+    let started = Instant::now();
+    loop {
+        if let Some(value) = condition() {
+            return value;
+        }
+        assert!(started.elapsed() < TIMEOUT, "timed out waiting for {what}");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn wait_exit(child: &mut Child, what: &str) -> ExitStatus {
+    wait_for(what, || child.try_wait().expect("try_wait failed"))
+}
+
+/// 1 つのテスト用の環境 (一時ディレクトリ・PATH 上の偽 nvim・専用ポートのマネージャー)
+struct Harness {
+    root: PathBuf,
+    bin_dir: PathBuf,
+    port: u16,
+    manager: Option<Child>,
+}
+
+impl Harness {
+    fn new(name: &str) -> Self {
+        Self::with_config(name, "")
+    }
+
+    /// マネージャーを起動する前に設定ファイル (`config.toml`) を書いておく
+    fn with_config(name: &str, config: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("neovim-manager-e2e-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let bin_dir = root.join("bin");
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        std::fs::write(root.join("config.toml"), config).unwrap();
+        std::fs::copy(
+            FAKE_NVIM,
+            bin_dir.join(format!("nvim{}", std::env::consts::EXE_SUFFIX)),
+        )
+        .unwrap();
+
+        let mut harness = Self {
+            root,
+            bin_dir,
+            port: free_port(),
+            manager: None,
+        };
+        harness.manager = Some(
+            harness
+                .command(MANAGER)
+                .arg("--foreground")
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("cannot start the manager"),
+        );
+
+        let port = harness.port;
+        wait_for("the manager to listen", || {
+            std::net::TcpStream::connect(("127.0.0.1", port)).ok()
+        });
+        harness
+    }
+
+    fn nvim_path(&self) -> PathBuf {
+        self.bin_dir
+            .join(format!("nvim{}", std::env::consts::EXE_SUFFIX))
+    }
+
+    /// 偽 nvim が PATH の先頭にあり、設定やキャッシュが一時ディレクトリに閉じた環境でのコマンド
+    fn command(&self, program: impl AsRef<Path>) -> Command {
+        let mut paths = vec![self.bin_dir.clone()];
+        paths.extend(std::env::split_paths(
+            &std::env::var_os("PATH").unwrap_or_default(),
+        ));
+        let path: OsString = std::env::join_paths(paths).unwrap();
+
+        let mut command = Command::new(program.as_ref());
+        command
+            .env("PATH", path)
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_CACHE_HOME", self.root.join("cache"))
+            .env("XDG_RUNTIME_DIR", self.root.join("run"))
+            .env("NEOVIM_MANAGER_CONFIG", self.root.join("config.toml"))
+            .env("NEOVIM_MANAGER_PORT", self.port.to_string())
+            .env("NEOVIM_MANAGER_HEALTH_CHECK_INTERVAL", "1")
+            .env("NEOVIM_MANAGER_LOG_FILE", self.root.join("manager.log"))
+            .env("NEOVIM_MANAGER_STATE_FILE", self.state_file())
+            .env("NEOVIM_MANAGER_NEOVIDE", self.nvim_path())
+            .env("NEOVIM_MANAGER_NEOVIDE_ARGS", "")
+            .env("NEOVIM_MANAGER_NOTIFY", "off")
+            .env("FAKE_NVIM_UI_LOG", self.ui_log())
+            .env_remove("NEOVIM_MANAGER_DEBUG")
+            .env_remove("NEOVIM_MANAGER_SOCKET")
+            .stdin(Stdio::null());
+        command
+    }
+
+    fn state_file(&self) -> PathBuf {
+        self.root.join("state.json")
+    }
+
+    /// マネージャーが書き出した登録内容の写し (まだなければ None)
+    fn state(&self) -> Option<StateMirror> {
+        serde_json::from_slice(&std::fs::read(self.state_file()).ok()?).ok()
+    }
+
+    /// 偽 Neovide が起動された接続先の記録
+    fn ui_log(&self) -> PathBuf {
+        self.root.join("ui.log")
+    }
+
+    /// ランチャーが `address` に Neovide を起動するまで待つ (起動処理が終わった目安)
+    fn wait_ui_launch(&self, address: &str) {
+        wait_for(&format!("Neovide to be launched for {address}"), || {
+            std::fs::read_to_string(self.ui_log())
+                .ok()?
+                .lines()
+                .any(|line| line == address)
+                .then_some(())
+        });
+    }
+
+    fn control(&self, args: &[&str]) -> Output {
+        self.command(CONTROL).args(args).output().unwrap()
+    }
+
+    fn nvim(&self, args: &[&str]) -> Output {
+        self.command(self.nvim_path()).args(args).output().unwrap()
+    }
+
+    fn list(&self) -> Vec<InstanceResult> {
+        let output = self.control(&["list", "--json"]);
+        assert!(
+            output.status.success(),
+            "list failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    fn find(&self, identifier: &str) -> Option<InstanceResult> {
+        self.list()
+            .into_iter()
+            .find(|instance| instance.identifier == identifier)
+    }
+
+    fn wait_healthy(&self, identifier: &str) -> InstanceResult {
+        wait_for(&format!("{identifier} to become healthy"), || {
+            self.find(identifier)
+                .filter(|instance| instance.health_status.is_healthy())
+        })
+    }
+
+    /// 偽 nvim のサーバーを起動し、応答するまで待つ
+    fn spawn_nvim_server(&self) -> (Child, String) {
+        let address = format!("127.0.0.1:{}", free_port());
+        let child = self
+            .command(self.nvim_path())
+            .args(["--headless", "--listen", &address])
+            .spawn()
+            .unwrap();
+        wait_for("the nvim server to listen", || {
+            self.nvim(&["--server", &address, "--remote-expr", "1"])
+                .status
+                .success()
+                .then_some(())
+        });
+        (child, address)
+    }
+
+    fn remote_expr(&self, address: &str, expr: &str) -> Output {
+        self.nvim(&["--server", address, "--remote-expr", expr])
+    }
+
+    fn spawn_launcher(&self, target: &Path) -> Child {
+        self.command(LAUNCHER)
+            .arg(target)
+            .current_dir(target)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    fn project_dir(&self, name: &str) -> (PathBuf, String) {
+        let dir = self.root.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let identifier = dir.canonicalize().unwrap().to_string_lossy().to_string();
+        (dir, identifier)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // 失敗したテストが残したサーバーも止める
+        if let Ok(output) = self.command(CONTROL).args(["list", "--json"]).output() {
+            let instances: Vec<InstanceResult> =
+                serde_json::from_slice(&output.stdout).unwrap_or_default();
+            for instance in instances {
+                let _ = self.remote_expr(&instance.server_address, "execute('qall!')");
+            }
+        }
+        if let Some(manager) = &mut self.manager {
+            let _ = manager.kill();
+            let _ = manager.wait();
+        }
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[test]
+fn fake_nvim_answers_and_exits_on_command() {
+    let harness = Harness::new("fake-nvim");
+    let (mut server, address) = harness.spawn_nvim_server();
+
+    let output = harness.remote_expr(&address, "getpid()");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        server.id().to_string()
+    );
+    assert!(!harness.remote_expr(&address, "bogus(").status.success());
+
+    harness.remote_expr(&address, "execute('cquit 7')");
+    assert_eq!(
+        wait_exit(&mut server, "the nvim server to exit").code(),
+        Some(7)
+    );
+    assert!(!harness.remote_expr(&address, "1").status.success());
+}
+
+#[test]
+fn registered_instance_is_removed_when_nvim_exits() {
+    let harness = Harness::new("register");
+    let (mut server, address) = harness.spawn_nvim_server();
+
+    let output = harness.control(&["register", "project", &address]);
+    assert!(
+        output.status.success(),
+        "register failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let instance = harness.wait_healthy("project");
+    assert_eq!(instance.server_address, address);
+    let mirrored = wait_for("the state file to have the instance", || {
+        harness.state()?.instances.into_iter().next()
+    });
+    assert_eq!(mirrored.identifier, "project");
+    assert_eq!(mirrored.server_address, address);
+
+    // 同じ identifier の二重登録は失敗する
+    assert!(!harness
+        .control(&["register", "project", &address])
+        .status
+        .success());
+
+    harness.remote_expr(&address, "execute('qall')");
+    assert!(wait_exit(&mut server, "the nvim server to exit").success());
+
+    wait_for("the instance to be removed", || {
+        harness.find("project").is_none().then_some(())
+    });
+    let output = harness.control(&["query", "project", "--json"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "null");
+    wait_for("the state file to be emptied", || {
+        harness.state().filter(|state| state.instances.is_empty())
+    });
+}
+
+#[test]
+fn prompt_reads_the_state_file_without_asking_the_manager() {
+    let harness = Harness::new("prompt");
+    let (dir, identifier) = harness.project_dir("project");
+    let (mut server, address) = harness.spawn_nvim_server();
+    assert!(harness
+        .control(&["register", &identifier, &address])
+        .status
+        .success());
+    wait_for("the state file to have the healthy instance", || {
+        harness
+            .state()?
+            .instances
+            .into_iter()
+            .find(|instance| instance.health_status.is_healthy())
+    });
+
+    // 誰も待ち受けていないポートを指定しても、写しから状態を出す
+    let port = free_port().to_string();
+    let output = harness
+        .command(CONTROL)
+        .args(["--port", &port, "prompt", "--starship"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "~/project");
+
+    // 写しがなければ TCP で問い合わせる (つながらなければ何も出さない)
+    let output = harness
+        .command(CONTROL)
+        .args(["--port", &port, "prompt", "--starship"])
+        .env("NEOVIM_MANAGER_STATE_FILE", "")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    harness.remote_expr(&address, "execute('qall')");
+    assert!(wait_exit(&mut server, "the nvim server to exit").success());
+}
+
+#[test]
+fn quit_all_succeeds_when_nvim_exits_before_replying() {
+    let harness = Harness::new("quit-all");
+    let address = format!("127.0.0.1:{}", free_port());
+    let mut server = harness
+        .command(harness.nvim_path())
+        .args(["--headless", "--listen", &address])
+        .env("FAKE_NVIM_EXIT_WITHOUT_REPLY", "1")
+        .spawn()
+        .unwrap();
+    assert!(harness
+        .control(&["register", "project", &address])
+        .status
+        .success());
+    harness.wait_healthy("project");
+
+    let output = harness.control(&["quit-all", "-y"]);
+    assert!(
+        output.status.success(),
+        "quit-all failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Quit: project"));
+    assert!(wait_exit(&mut server, "the nvim server to exit").success());
+    assert!(harness.list().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn manager_runs_hooks_with_the_instance_json() {
+    // マネージャーの HOME はテストの一時ディレクトリ
+    let harness = Harness::with_config(
+        "hooks",
+        r#"
+[manager.hooks]
+on_register = 'cat > "$HOME/register.json"'
+on_unregister = 'echo "$NEOVIM_MANAGER_REASON" > "$HOME/reason"; cat > "$HOME/unregister.json"'
+on_health_lost = 'echo "$NEOVIM_MANAGER_EVENT $NEOVIM_MANAGER_IDENTIFIER" > "$HOME/health_lost"'
+"#,
+    );
+    let read_instance = |name: &str| -> Option<InstanceResult> {
+        serde_json::from_slice(&std::fs::read(harness.root.join(name)).ok()?).ok()
+    };
+    let read_line = |name: &str| -> Option<String> {
+        let content = std::fs::read_to_string(harness.root.join(name)).ok()?;
+        content.ends_with('\n').then(|| content.trim().to_string())
+    };
+
+    let (mut server, address) = harness.spawn_nvim_server();
+    assert!(harness
+        .control(&["register", "project", &address])
+        .status
+        .success());
+    let registered = wait_for("the on_register hook", || read_instance("register.json"));
+    assert_eq!(registered.identifier, "project");
+    assert_eq!(registered.server_address, address);
+
+    // 登録解除せずに終了すると、応答しなくなったものとして両方のフックが実行される
+    harness.remote_expr(&address, "execute('qall')");
+    assert!(wait_exit(&mut server, "the nvim server to exit").success());
+    assert_eq!(
+        wait_for("the on_health_lost hook", || read_line("health_lost")),
+        "health_lost project"
+    );
+    let unregistered = wait_for("the on_unregister hook", || {
+        read_instance("unregister.json")
+    });
+    assert_eq!(unregistered.identifier, "project");
+    assert_eq!(
+        unregistered.health_status,
+        neovim_manager::HealthStatus::Dead
+    );
+    assert_eq!(read_line("reason").as_deref(), Some("unresponsive"));
+}
+
+#[test]
+fn old_clients_read_the_health_status_in_two_states() {
+    use neovim_manager::HealthStatus;
+    use std::io::{BufRead, BufReader, Write};
+
+    let harness = Harness::new("legacy-health");
+    let (mut live, live_address) = harness.spawn_nvim_server();
+    let (mut dead, dead_address) = harness.spawn_nvim_server();
+    for (identifier, address) in [("live", &live_address), ("dead", &dead_address)] {
+        assert!(harness
+            .control(&["register", identifier, address])
+            .status
+            .success());
+        harness.wait_healthy(identifier);
+    }
+    // ピン留めしたものは応答しなくなっても Unhealthy で残る
+    assert!(harness.control(&["pin", "dead"]).status.success());
+    harness.remote_expr(&dead_address, "execute('qall')");
+    assert!(wait_exit(&mut dead, "the nvim server to exit").success());
+    wait_for("the pinned instance to become unhealthy", || {
+        matches!(
+            harness.find("dead")?.health_status,
+            HealthStatus::Unhealthy { .. }
+        )
+        .then_some(())
+    });
+
+    // protocol_version を送らない古いクライアントには "Unknown" / "Healthy" だけを返す
+    let list = |params: &str| -> Vec<(String, serde_json::Value)> {
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", harness.port)).unwrap();
+        writeln!(
+            stream,
+            r#"{{"jsonrpc":"2.0","method":"list_instances","params":{params},"id":1}}"#
+        )
+        .unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let mut statuses: Vec<_> = response["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|instance| {
+                (
+                    instance["identifier"].as_str().unwrap().to_string(),
+                    instance["health_status"].clone(),
+                )
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        statuses
+    };
+    assert_eq!(
+        list("{}"),
+        [
+            ("dead".to_string(), serde_json::json!("Unknown")),
+            ("live".to_string(), serde_json::json!("Healthy")),
+        ]
+    );
+    let current = list(r#"{"protocol_version":2}"#);
+    assert!(matches!(
+        serde_json::from_value(current[0].1.clone()).unwrap(),
+        HealthStatus::Unhealthy { .. }
+    ));
+
+    // 新しいクライアントは古いマネージャーの "Unknown" を Starting として読む
+    assert_eq!(
+        serde_json::from_str::<HealthStatus>(r#""Unknown""#).unwrap(),
+        HealthStatus::Starting
+    );
+
+    harness.remote_expr(&live_address, "execute('qall')");
+    assert!(wait_exit(&mut live, "the nvim server to exit").success());
+}
+
+#[test]
+fn launcher_attaches_to_an_existing_instance() {
+    let harness = Harness::new("attach");
+    let (dir, identifier) = harness.project_dir("project");
+
+    let mut first = harness
+        .command(LAUNCHER)
+        .arg(&dir)
+        .current_dir(&dir)
+        .env("NEOVIM_MANAGER_NVIM_APPNAME", "nvim-work")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+    assert_eq!(instance.cwd.as_deref(), Some(identifier.as_str()));
+    assert_eq!(instance.appname.as_deref(), Some("nvim-work"));
+
+    // 2 回目は新しいサーバーを起動せず、既存のインスタンスを使う
+    let mut second = harness.spawn_launcher(&dir);
+    std::thread::sleep(Duration::from_secs(2));
+    assert!(second.try_wait().unwrap().is_none());
+    let instances = harness.list();
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].server_address, instance.server_address);
+
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+    assert!(wait_exit(&mut second, "the attached launcher to exit").success());
+    assert!(wait_exit(&mut first, "the launcher to exit").success());
+    assert!(harness.list().is_empty());
+}
+
+#[test]
+fn launcher_opens_every_project_of_a_workspace() {
+    // HOME はテストの一時ディレクトリ
+    let harness = Harness::with_config(
+        "workspace",
+        r#"
+[workspace.web]
+projects = ["~/api", "~/notes"]
+"#,
+    );
+    let (api_dir, api) = harness.project_dir("api");
+    let (_, notes) = harness.project_dir("notes");
+
+    // 既にあるインスタンスには接続し、ないものは起動する
+    let mut first = harness.spawn_launcher(&api_dir);
+    let existing = harness.wait_healthy(&api);
+    harness.wait_ui_launch(&existing.server_address);
+
+    let mut workspace = harness
+        .command(LAUNCHER)
+        .args(["--workspace", "web"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = harness.wait_healthy(&notes);
+    harness.wait_ui_launch(&started.server_address);
+    assert_eq!(harness.list().len(), 2);
+    assert_eq!(
+        harness.find(&api).unwrap().server_address,
+        existing.server_address
+    );
+    assert!(harness
+        .control(&["workspace", "status", "web"])
+        .status
+        .success());
+
+    harness.remote_expr(&started.server_address, "execute('qall')");
+    harness.remote_expr(&existing.server_address, "execute('qall')");
+    assert!(wait_exit(&mut workspace, "the workspace launcher to exit").success());
+    assert!(wait_exit(&mut first, "the launcher to exit").success());
+    assert!(!harness
+        .control(&["workspace", "status", "web"])
+        .status
+        .success());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_and_trailing_slash_paths_share_one_instance() {
+    let harness = Harness::new("identifier");
+    let (dir, identifier) = harness.project_dir("project");
+    let link = harness.root.join("link");
+    std::os::unix::fs::symlink(&dir, &link).unwrap();
+
+    let mut first = harness.spawn_launcher(&dir);
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    // シンボリックリンク経由でも同じインスタンスを使う
+    let mut second = harness.spawn_launcher(&link);
+    std::thread::sleep(Duration::from_secs(2));
+    assert!(second.try_wait().unwrap().is_none());
+    assert_eq!(harness.list().len(), 1);
+
+    // 末尾に区切り文字が付いた identifier でも見つかる
+    let output = harness.control(&["query", &format!("{identifier}/"), "--json"]);
+    let found: Option<InstanceResult> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(found.map(|found| found.identifier), Some(identifier));
+
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+    assert!(wait_exit(&mut second, "the attached launcher to exit").success());
+    assert!(wait_exit(&mut first, "the launcher to exit").success());
+}
+
+#[test]
+fn rename_follows_a_moved_project_into_nvim_and_the_launcher() {
+    let harness = Harness::new("rename");
+    let (dir, identifier) = harness.project_dir("project");
+
+    let mut launcher = harness.spawn_launcher(&dir);
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    let moved = harness.root.join("moved");
+    std::fs::rename(&dir, &moved).unwrap();
+    let new_identifier = moved.canonicalize().unwrap().to_string_lossy().to_string();
+    let output = harness.control(&["rename", &identifier, &new_identifier, "--follow-path"]);
+    assert!(
+        output.status.success(),
+        "rename failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // nvim の identifier と作業ディレクトリも移動先になる
+    let address = &instance.server_address;
+    let output = harness.remote_expr(address, "$NEOVIM_MANAGER_IDENTIFIER");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        new_identifier
+    );
+    let output = harness.remote_expr(address, "getcwd()");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        new_identifier
+    );
+    let renamed = harness.find(&new_identifier).unwrap();
+    assert_eq!(renamed.cwd.as_deref(), Some(new_identifier.as_str()));
+
+    // 移動していなければ何もせずに成功する
+    let output = harness.control(&["rename", &new_identifier, &new_identifier, "--follow-path"]);
+    assert!(output.status.success());
+
+    // ランチャーは新しい identifier を見張り続ける
+    std::thread::sleep(Duration::from_secs(2));
+    assert!(launcher.try_wait().unwrap().is_none());
+
+    harness.remote_expr(address, "execute('qall')");
+    assert!(wait_exit(&mut launcher, "the launcher to exit").success());
+}
+
+#[test]
+fn launcher_opens_files_with_special_characters_in_an_existing_instance() {
+    let harness = Harness::new("open");
+    let (dir, identifier) = harness.project_dir("project");
+    let file = dir.join("a file #1 %.txt");
+    std::fs::write(&file, "").unwrap();
+    let file = file.canonicalize().unwrap().to_string_lossy().to_string();
+
+    let mut first = harness.spawn_launcher(&dir);
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    let mut second = harness
+        .command(LAUNCHER)
+        .args([file.as_str(), "--open-mode", "tab", "--line", "3"])
+        .current_dir(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for("the file to be opened", || {
+        let output = harness.control(&["buffers", &identifier, "--json"]);
+        let buffers: Vec<neovim_manager::utils::NvimBuffer> =
+            serde_json::from_slice(&output.stdout).ok()?;
+        buffers
+            .iter()
+            .any(|buffer| buffer.name == file)
+            .then_some(())
+    });
+
+    // 列だけの指定は受け付けない
+    let output = harness
+        .command(LAUNCHER)
+        .args([file.as_str(), "--column", "2"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+    assert!(wait_exit(&mut second, "the attached launcher to exit").success());
+    assert!(wait_exit(&mut first, "the launcher to exit").success());
+}
+
+#[test]
+fn handler_opens_files_in_the_containing_instance_and_exits() {
+    let harness = Harness::new("handler");
+    let (dir, identifier) = harness.project_dir("project");
+    let nested = dir.join("src");
+    std::fs::create_dir_all(&nested).unwrap();
+    let file = nested.join("main.rs");
+    std::fs::write(&file, "").unwrap();
+    let file = file.canonicalize().unwrap().to_string_lossy().to_string();
+
+    let mut first = harness.spawn_launcher(&dir);
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    // 下のディレクトリのファイルは既存のインスタンスで開き、待たずに終了する
+    let output = harness
+        .command(LAUNCHER)
+        .args(["--handler", file.as_str()])
+        .current_dir(&harness.root)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "handler failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = harness.control(&["buffers", &identifier, "--json"]);
+    let buffers: Vec<neovim_manager::utils::NvimBuffer> =
+        serde_json::from_slice(&output.stdout).unwrap();
+    assert!(buffers.iter().any(|buffer| buffer.name == file));
+    assert_eq!(harness.list().len(), 1);
+
+    // どのインスタンスにも含まれないファイルは、そのディレクトリで新しく起動する
+    let (other, other_identifier) = harness.project_dir("other");
+    let other_file = other.join("notes.txt");
+    std::fs::write(&other_file, "").unwrap();
+    let output = harness
+        .command(LAUNCHER)
+        .args(["--handler".as_ref(), other_file.as_os_str()])
+        .current_dir(&harness.root)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let started = harness.wait_healthy(&other_identifier);
+    assert_eq!(started.cwd.as_deref(), Some(other_identifier.as_str()));
+
+    // 開いたプロジェクトは新しい順に履歴に残る
+    let recent: Vec<String> = serde_json::from_str(
+        &std::fs::read_to_string(
+            harness
+                .root
+                .join("cache/neovim-instance-manager/recent-projects.json"),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(recent, [other_identifier.clone(), identifier.clone()]);
+
+    harness.remote_expr(&started.server_address, "execute('qall')");
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+    assert!(wait_exit(&mut first, "the launcher to exit").success());
+}
+
+#[test]
+fn toggle_scratch_starts_the_scratch_instance_and_reopens_a_closed_window() {
+    let harness = Harness::new("scratch");
+    let toggle = || {
+        let output = harness
+            .command(LAUNCHER)
+            .arg("--toggle-scratch")
+            .current_dir(&harness.root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "toggle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    // なければ状態ディレクトリの scratch で起動し、待たずに終了する
+    toggle();
+    let identifier = harness
+        .root
+        .join("cache/neovim-instance-manager/scratch")
+        .canonicalize()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    // 偽 Neovide はすぐに終了するので、接続している GUI がないものとして開き直す
+    toggle();
+    let launches = std::fs::read_to_string(harness.ui_log()).unwrap();
+    assert_eq!(
+        launches
+            .lines()
+            .filter(|line| instance.server_address == *line)
+            .count(),
+        2
+    );
+    assert_eq!(harness.list().len(), 1);
+
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+}
+
+#[test]
+fn launcher_retries_on_another_port_when_the_port_is_taken() {
+    let harness = Harness::new("port");
+    let (dir, identifier) = harness.project_dir("project");
+    let marker = harness.root.join("taken");
+
+    let mut launcher = harness
+        .command(LAUNCHER)
+        .arg(&dir)
+        .current_dir(&dir)
+        .env("FAKE_NVIM_TAKEN_MARKER", &marker)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+    // 最初の nvim は listen に失敗している
+    assert!(marker.exists());
+
+    harness.remote_expr(&instance.server_address, "execute('qall')");
+    assert!(wait_exit(&mut launcher, "the launcher to exit").success());
+}
+
+#[test]
+fn launcher_restarts_nvim_that_exits_with_code_2() {
+    let harness = Harness::new("restart");
+    let (dir, identifier) = harness.project_dir("project");
+
+    let mut launcher = harness.spawn_launcher(&dir);
+    let instance = harness.wait_healthy(&identifier);
+    harness.wait_ui_launch(&instance.server_address);
+
+    harness.remote_expr(&instance.server_address, "execute('cquit 2')");
+    let restarted = wait_for("the instance to be restarted", || {
+        harness
+            .find(&identifier)
+            .filter(|restarted| restarted.server_address != instance.server_address)
+    });
+    harness.wait_ui_launch(&restarted.server_address);
+    assert!(launcher.try_wait().unwrap().is_none());
+
+    // 終了コード 0 なら再起動せずに終わる
+    harness.remote_expr(&restarted.server_address, "execute('qall')");
+    assert!(wait_exit(&mut launcher, "the launcher to exit").success());
+    assert!(harness.find(&identifier).is_none());
+}
+
+#[test]
+fn launcher_stops_restarting_nvim_that_keeps_exiting_with_code_2() {
+    let harness = Harness::new("crash-loop");
+    let (dir, identifier) = harness.project_dir("project");
+
+    let mut launcher = harness
+        .command(LAUNCHER)
+        .arg(&dir)
+        .current_dir(&dir)
+        .env("FAKE_NVIM_STDERR", "E5113: Error while calling lua chunk")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // 起動直後に終了コード 2 で終了し続ける
+    let mut previous = None;
+    for _ in 0..3 {
+        let instance = wait_for("the instance to be (re)started", || {
+            harness.find(&identifier).filter(|instance| {
+                instance.health_status.is_healthy()
+                    && previous.as_ref() != Some(&instance.server_address)
+            })
+        });
+        harness.wait_ui_launch(&instance.server_address);
+        harness.remote_expr(&instance.server_address, "execute('cquit 2')");
+        previous = Some(instance.server_address);
+    }
+
+    assert_eq!(
+        wait_exit(&mut launcher, "the launcher to give up").code(),
+        Some(7)
+    );
+    let mut stderr = String::new();
+    launcher
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(stderr.contains("not restarting it again"), "{stderr}");
+    assert!(
+        stderr.contains("E5113: Error while calling lua chunk"),
+        "{stderr}"
+    );
+    assert!(harness.find(&identifier).is_none());
+}
+
+#[tokio::test]
+async fn embedded_manager_serves_until_the_shutdown_signal() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = Config::load().unwrap();
+    config.manager.port.value = listener.local_addr().unwrap().port();
+    // 普段使っているマネージャーの写しを上書きしない
+    config.manager.state_file.value = None;
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn({
+        let config = config.clone();
+        async move {
+            manager::serve(listener, &config, async {
+                let _ = stopped.await;
+            })
+            .await
+        }
+    });
+
+    let client = ManagerClient::new(&config);
+    let status = client.status().await.unwrap();
+    assert_eq!(status.pid, std::process::id());
+    assert!(client.list(None).await.unwrap().is_empty());
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert!(!client.is_manager_running().await);
+}
+
+#[tokio::test]
+async fn watch_instances_returns_when_the_registry_changes() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = Config::load().unwrap();
+    config.manager.port.value = listener.local_addr().unwrap().port();
+    config.manager.state_file.value = None;
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn({
+        let config = config.clone();
+        async move {
+            manager::serve(listener, &config, async {
+                let _ = stopped.await;
+            })
+            .await
+        }
+    });
+
+    let client = ManagerClient::new(&config);
+    let first = client.watch(None, Duration::ZERO).await.unwrap();
+    assert!(first.instances.is_empty());
+
+    // 変わらなければ待ち時間いっぱいまで待って同じ世代を返す
+    let started = Instant::now();
+    let unchanged = client
+        .watch(Some(first.generation), Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    assert_eq!(unchanged.generation, first.generation);
+
+    let watching = tokio::spawn({
+        let client = client.clone();
+        async move {
+            client
+                .watch(Some(first.generation), Duration::from_secs(30))
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client
+        .register(neovim_manager::RegisterInstanceParams {
+            identifier: "project".to_string(),
+            server_address: "127.0.0.1:1".parse().unwrap(),
+            cwd: None,
+            pid: None,
+            tags: Vec::new(),
+            appname: None,
+            // 応答しなくても消されないように
+            pinned: true,
+        })
+        .await
+        .unwrap();
+    let changed = tokio::time::timeout(Duration::from_secs(10), watching)
+        .await
+        .expect("watch_instances did not return on the change")
+        .unwrap()
+        .unwrap();
+    assert!(changed.generation > first.generation);
+    assert_eq!(changed.instances.len(), 1);
+    assert_eq!(changed.instances[0].identifier, "project");
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn embedded_manager_detects_a_clock_jump_as_a_resume() {
+    use neovim_manager::clock::{Clock, ManualClock};
+    use std::sync::Arc;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = Config::load().unwrap();
+    config.manager.port.value = listener.local_addr().unwrap().port();
+    config.manager.state_file.value = None;
+
+    let clock = Arc::new(ManualClock::default());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn({
+        let config = config.clone();
+        let clock: Arc<dyn Clock> = clock.clone();
+        async move {
+            manager::serve_with_clock(listener, &config, clock, async {
+                let _ = stopped.await;
+            })
+            .await
+        }
+    });
+
+    let client = ManagerClient::new(&config);
+    let status = client.status().await.unwrap();
+    assert_eq!(status.uptime_secs, 0);
+    assert_eq!(status.health_checks.resumes, 0);
+
+    // 単調時計は進まずに壁時計だけが 1 時間進む (スリープから復帰したのと同じ)
+    clock.advance(chrono::Duration::hours(1));
+    let deadline = Instant::now() + Duration::from_secs(15);
+    let status = loop {
+        let status = client.status().await.unwrap();
+        if status.health_checks.resumes > 0 {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "the resume was not detected");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    };
+    assert_eq!(status.health_checks.resumes, 1);
+    assert_eq!(status.health_checks.last_resume_at, Some(clock.now()));
+    assert_eq!(status.uptime_secs, 3600);
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
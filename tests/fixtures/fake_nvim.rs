@@ -0,0 +1,524 @@
+//! 結合テスト用の偽 nvim
+//!
+//! 本物の Neovim がなくてもマネージャー・control・ランチャーを動かせるように、
+//! 使っている機能だけを真似する。
+//!
+//! - `--listen <addr> --headless [file]`: msgpack-RPC サーバーとして待ち受ける
+//! - `--server <addr> --remote-expr <expr>` / `--remote <file>` / `--remote-ui`: クライアントとして要求を送る
+//! - `--server <addr>` のみ: Neovide の代わり。`$FAKE_NVIM_UI_LOG` にアドレスを追記して終了する
+//! - `--version`
+//!
+//! サーバーは `qall` などで終了コード 0、`cquit N` で終了コード N で終了する。
+//! `$FAKE_NVIM_TAKEN_MARKER` のファイルがまだなければ、作成してからポートを取られたときのように失敗する。
+//! `$FAKE_NVIM_STDERR` があれば、サーバーとして起動したときにその内容を標準エラー出力に書く。
+//! `$FAKE_NVIM_EXIT_WITHOUT_REPLY` があれば、終了するコマンドには応答せずに終了する。
+
+use neovim_manager::nvim::{NvimClient, Value};
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Neovide として起動されたときに接続先を追記するファイル
+const UI_LOG_ENV: &str = "FAKE_NVIM_UI_LOG";
+
+/// 最初の 1 回だけ listen に失敗させるための目印のファイル
+const TAKEN_MARKER_ENV: &str = "FAKE_NVIM_TAKEN_MARKER";
+
+/// サーバーとして起動したときに標準エラー出力に書く内容 (init.lua のエラーの代わり)
+const STDERR_ENV: &str = "FAKE_NVIM_STDERR";
+
+/// 終了するコマンドに応答を返さずに終了する (応答より先に終了する本物の nvim の代わり)
+const EXIT_WITHOUT_REPLY_ENV: &str = "FAKE_NVIM_EXIT_WITHOUT_REPLY";
+
+#[derive(Default)]
+struct Args {
+    version: bool,
+    listen: Option<String>,
+    server: Option<String>,
+    remote_expr: Option<String>,
+    remote: Option<String>,
+    remote_ui: bool,
+    files: Vec<String>,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--version" => args.version = true,
+            "--listen" => args.listen = iter.next(),
+            "--server" => args.server = iter.next(),
+            "--remote-expr" => args.remote_expr = iter.next(),
+            "--remote" => args.remote = iter.next(),
+            "--remote-ui" => args.remote_ui = true,
+            "--headless" => {}
+            // Neovide の引数や `+cmd` などは無視する
+            _ if arg.starts_with('-') || arg.starts_with('+') => {}
+            _ => args.files.push(arg),
+        }
+    }
+    args
+}
+
+fn main() {
+    let args = parse_args();
+
+    if args.version {
+        println!("NVIM v0.10.0-fake");
+        return;
+    }
+
+    let code = match (&args.listen, &args.server) {
+        (Some(address), _) => serve(address, &args.files),
+        (None, Some(address)) => remote(address, &args),
+        (None, None) => {
+            eprintln!("fake-nvim: either --listen or --server is required");
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+/// `--server` 付きで起動されたとき
+fn remote(address: &str, args: &Args) -> i32 {
+    // Neovide として起動された場合は接続せず、起動されたことだけ記録する
+    if args.remote_expr.is_none() && args.remote.is_none() && !args.remote_ui {
+        return match std::env::var_os(UI_LOG_ENV) {
+            Some(path) => match log_ui_launch(&path, address) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("fake-nvim: cannot write {}: {e}", path.to_string_lossy());
+                    1
+                }
+            },
+            None => 0,
+        };
+    }
+
+    let mut client = match NvimClient::connect_timeout(address, CLIENT_TIMEOUT) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("fake-nvim: {e}");
+            return 1;
+        }
+    };
+
+    let result = if let Some(expr) = &args.remote_expr {
+        client
+            .eval(expr)
+            .map(|value| println!("{}", display(&value)))
+    } else if let Some(file) = &args.remote {
+        client.open_file(file)
+    } else {
+        client.attached_uis().map(drop)
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("fake-nvim: {e}");
+            1
+        }
+    }
+}
+
+fn log_ui_launch(path: &std::ffi::OsStr, address: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{address}")
+}
+
+/// `--remote-expr` の結果の表示。文字列と数値はそのまま、それ以外は JSON にする
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.as_str().unwrap_or_default().to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Nil => String::new(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+struct State {
+    cwd: String,
+    buffers: Vec<String>,
+    /// `:let $NAME = ...` で設定した環境変数
+    env: HashMap<String, String>,
+}
+
+/// コマンドの実行結果
+enum Outcome {
+    Continue,
+    Exit(i32),
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// `--listen` 付きで起動されたとき。終了コマンドを受け取るまで戻らない
+fn serve(address: &str, files: &[String]) -> i32 {
+    let cwd = std::env::current_dir()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let state = Arc::new(Mutex::new(State {
+        cwd,
+        buffers: files.to_vec(),
+        env: HashMap::new(),
+    }));
+
+    if let Ok(message) = std::env::var(STDERR_ENV) {
+        eprintln!("{message}");
+    }
+
+    if let Some(marker) = std::env::var_os(TAKEN_MARKER_ENV) {
+        if std::fs::File::create_new(&marker).is_ok() {
+            eprintln!("fake-nvim: cannot listen on {address}: address already in use");
+            return 1;
+        }
+    }
+
+    let accept = match listen(address) {
+        Ok(accept) => accept,
+        Err(e) => {
+            eprintln!("fake-nvim: cannot listen on {address}: {e}");
+            return 1;
+        }
+    };
+
+    loop {
+        match accept() {
+            Ok(stream) => {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || handle_connection(stream, &state));
+            }
+            Err(e) => eprintln!("fake-nvim: accept failed: {e}"),
+        }
+    }
+}
+
+type Accept = Box<dyn Fn() -> std::io::Result<Box<dyn Stream>>>;
+
+fn listen(address: &str) -> std::io::Result<Accept> {
+    if neovim_manager::utils::is_tcp_address(address) {
+        let listener = std::net::TcpListener::bind(address)?;
+        return Ok(Box::new(move || {
+            Ok(Box::new(listener.accept()?.0) as Box<dyn Stream>)
+        }));
+    }
+
+    #[cfg(unix)]
+    {
+        let listener = std::os::unix::net::UnixListener::bind(address)?;
+        Ok(Box::new(move || {
+            Ok(Box::new(listener.accept()?.0) as Box<dyn Stream>)
+        }))
+    }
+
+    #[cfg(not(unix))]
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "only TCP addresses are supported",
+    ))
+}
+
+fn handle_connection(stream: Box<dyn Stream>, state: &Mutex<State>) {
+    let mut stream = BufReader::new(stream);
+
+    // 切断されるかデコードできなくなったら終わる
+    while let Ok(message) = rmpv::decode::read_value(&mut stream) {
+        let Some([kind, msgid, method, params]) = message.as_array().map(Vec::as_slice) else {
+            continue;
+        };
+        // 通知には応答しない
+        if kind.as_u64() != Some(REQUEST) {
+            continue;
+        }
+
+        let method = method.as_str().unwrap_or_default();
+        let params = params.as_array().cloned().unwrap_or_default();
+        let (result, outcome) = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            match call(&mut state, method, &params) {
+                Ok((value, outcome)) => (Ok(value), outcome),
+                Err(message) => (Err(message), Outcome::Continue),
+            }
+        };
+
+        if let Outcome::Exit(code) = outcome {
+            if std::env::var_os(EXIT_WITHOUT_REPLY_ENV).is_some() {
+                std::process::exit(code);
+            }
+        }
+
+        let response = match result {
+            Ok(value) => Value::Array(vec![
+                Value::from(RESPONSE),
+                msgid.clone(),
+                Value::Nil,
+                value,
+            ]),
+            Err(message) => Value::Array(vec![
+                Value::from(RESPONSE),
+                msgid.clone(),
+                Value::Array(vec![Value::from(0), Value::from(message)]),
+                Value::Nil,
+            ]),
+        };
+
+        let mut buffer = Vec::new();
+        if rmpv::encode::write_value(&mut buffer, &response).is_err() {
+            return;
+        }
+        let stream = stream.get_mut();
+        if stream
+            .write_all(&buffer)
+            .and_then(|()| stream.flush())
+            .is_err()
+        {
+            return;
+        }
+
+        // 応答を返してから終了する
+        if let Outcome::Exit(code) = outcome {
+            std::process::exit(code);
+        }
+    }
+}
+
+fn call(state: &mut State, method: &str, params: &[Value]) -> Result<(Value, Outcome), String> {
+    let string = |index: usize| {
+        params
+            .get(index)
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("{method}: expected a string argument"))
+    };
+
+    match method {
+        "nvim_eval" => eval(state, string(0)?),
+        "nvim_command" => execute(state, string(0)?).map(|outcome| (Value::Nil, outcome)),
+        "nvim_cmd" => {
+            let command = params
+                .first()
+                .and_then(Value::as_map)
+                .ok_or("nvim_cmd: expected a dict")?;
+            let field = |name: &str| {
+                command
+                    .iter()
+                    .find(|(key, _)| key.as_str() == Some(name))
+                    .map(|(_, value)| value)
+            };
+            let name = field("cmd").and_then(Value::as_str).unwrap_or_default();
+            let args: Vec<&str> = field("args")
+                .and_then(Value::as_array)
+                .map(|args| args.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            let line = std::iter::once(name)
+                .chain(args)
+                .collect::<Vec<_>>()
+                .join(" ");
+            execute(state, &line).map(|outcome| (Value::from(""), outcome))
+        }
+        "nvim_call_function" => {
+            let expr = format!("{}()", string(0)?);
+            eval(state, &expr)
+        }
+        "nvim_exec_lua" => Ok((Value::Nil, Outcome::Continue)),
+        "nvim_list_uis" => Ok((Value::Array(Vec::new()), Outcome::Continue)),
+        _ => Err(format!("Invalid method: {method}")),
+    }
+}
+
+/// 使われている式だけを評価する
+fn eval(state: &mut State, expr: &str) -> Result<(Value, Outcome), String> {
+    let expr = expr.trim();
+
+    if let Ok(number) = expr.parse::<i64>() {
+        return Ok((Value::from(number), Outcome::Continue));
+    }
+
+    match expr {
+        "getcwd()" => return Ok((Value::from(state.cwd.as_str()), Outcome::Continue)),
+        "getpid()" => return Ok((Value::from(std::process::id()), Outcome::Continue)),
+        _ => {}
+    }
+
+    if let Some(name) = expr.strip_prefix('$') {
+        let value = state
+            .env
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .unwrap_or_default();
+        return Ok((Value::from(value), Outcome::Continue));
+    }
+
+    // execute() は文字列 (またはそのリスト) を受け付ける
+    if let Some(commands) = expr
+        .strip_prefix("execute(")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .and_then(string_list)
+    {
+        for command in commands {
+            if let Outcome::Exit(code) = execute(state, &command)? {
+                return Ok((Value::from(""), Outcome::Exit(code)));
+            }
+        }
+        return Ok((Value::from(""), Outcome::Continue));
+    }
+
+    // NvimClient::list_buffers の式
+    if expr.starts_with("map(filter(getbufinfo(") {
+        let buffers = state
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                Value::Map(vec![
+                    (Value::from("bufnr"), Value::from(index + 1)),
+                    (Value::from("name"), Value::from(name.as_str())),
+                    (Value::from("modified"), Value::from(false)),
+                ])
+            })
+            .collect();
+        return Ok((Value::Array(buffers), Outcome::Continue));
+    }
+
+    Err(format!("E15: Invalid expression: \"{expr}\""))
+}
+
+/// `'...'` を文字列にする (`''` は `'`)
+fn string_literal(literal: &str) -> Option<String> {
+    let inner = literal.trim().strip_prefix('\'')?.strip_suffix('\'')?;
+    Some(inner.replace("''", "'"))
+}
+
+/// 文字列の式か、そのリスト `[a, b]`
+fn string_list(expr: &str) -> Option<Vec<String>> {
+    match expr.trim().strip_prefix('[') {
+        Some(rest) => split_top_level(rest.strip_suffix(']')?, ',')
+            .into_iter()
+            .map(string_expr)
+            .collect(),
+        None => string_expr(expr).map(|string| vec![string]),
+    }
+}
+
+/// 文字列リテラルと `fnameescape(...)` を `.` でつないだ式
+fn string_expr(expr: &str) -> Option<String> {
+    split_top_level(expr, '.')
+        .into_iter()
+        .map(|part| {
+            let part = part.trim();
+            match part
+                .strip_prefix("fnameescape(")
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                Some(inner) => string_expr(inner).map(|name| fnameescape(&name)),
+                None => string_literal(part),
+            }
+        })
+        .collect()
+}
+
+/// 文字列リテラルと括弧の外にある `separator` で分ける
+fn split_top_level(expr: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut depth, mut quoted) = (0, 0, false);
+    for (index, c) in expr.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted => depth -= 1,
+            _ if c == separator && !quoted && depth == 0 => {
+                parts.push(&expr[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&expr[start..]);
+    parts
+}
+
+const FNAME_SPECIAL: &str = " \t\n*?[{`$\\%#'\"|!<";
+
+fn fnameescape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if FNAME_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// [`fnameescape`] の逆
+fn fnameunescape(name: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn execute(state: &mut State, command: &str) -> Result<Outcome, String> {
+    let command = command.trim().trim_start_matches(':');
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+
+    // `2cquit` のような前置きの数
+    let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (count, name) = name.split_at(digits);
+
+    match name.trim_end_matches('!') {
+        "q" | "quit" | "qa" | "qall" | "quitall" | "wqa" | "wqall" | "xa" | "xall" => {
+            Ok(Outcome::Exit(0))
+        }
+        "cq" | "cquit" => {
+            let code = [count, arg]
+                .iter()
+                .find_map(|value| value.parse().ok())
+                .unwrap_or(1);
+            Ok(Outcome::Exit(code))
+        }
+        "e" | "edit" | "drop" | "tabe" | "tabedit" | "sp" | "split" => {
+            let arg = fnameunescape(arg);
+            if !arg.is_empty() && !state.buffers.contains(&arg) {
+                state.buffers.push(arg);
+            }
+            Ok(Outcome::Continue)
+        }
+        "call" => Ok(Outcome::Continue),
+        "cd" | "tcd" | "lcd" => {
+            state.cwd = fnameunescape(arg);
+            Ok(Outcome::Continue)
+        }
+        "NeovideFocus" => Ok(Outcome::Continue),
+        "let" => {
+            let (name, value) = arg
+                .strip_prefix('$')
+                .and_then(|arg| arg.split_once('='))
+                .ok_or_else(|| format!("E15: Invalid expression: \"{arg}\""))?;
+            let value = string_expr(value)
+                .ok_or_else(|| format!("E15: Invalid expression: \"{value}\""))?;
+            state.env.insert(name.trim().to_string(), value);
+            Ok(Outcome::Continue)
+        }
+        _ => Err(format!("E492: Not an editor command: {command}")),
+    }
+}